@@ -0,0 +1,191 @@
+//! Capture of raw bridge traffic, and dissection of captures.
+//!
+//! Usage:
+//! - `flick-capture udp [--listen ADDR] [--vehicle ADDR] FILE` relays UDP datagrams between ground
+//!   tools and a vehicle, recording both directions.
+//! - `flick-capture serial PORT FILE` relays the bytes of a serial port, which must already be
+//!   configured (for example with `stty raw`), to a pseudo-terminal that ground tools open instead,
+//!   recording both directions. The path of the pseudo-terminal is printed.
+//! - `flick-capture dissect FILE` decodes a capture.
+
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::process::exit;
+use std::thread::sleep;
+use std::time::Duration;
+
+use flick_bridge::host::capture::dissect::Dissector;
+use flick_bridge::host::capture::{CaptureReader, CaptureWriter, Direction, Layer};
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("udp") => udp(&args[1..]),
+        Some("serial") if args.len() == 3 => serial(&args[1], &args[2]),
+        Some("dissect") if args.len() == 2 => dissect(&args[1]),
+        _ => usage(),
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        exit(1);
+    }
+}
+
+/// Relay datagrams between ground tools and the vehicle. Traffic from the ground is recorded as
+/// transmitted, and traffic from the vehicle as received.
+fn udp(args: &[String]) -> io::Result<()> {
+    let mut listen: SocketAddr = SocketAddr::from(([0, 0, 0, 0], 4700));
+    let mut vehicle: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 4701));
+    let mut path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--listen" => listen = parse(value()),
+            "--vehicle" => vehicle = parse(value()),
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+
+    let mut capture = CaptureWriter::new(File::create(path)?)?;
+    let ground_socket = UdpSocket::bind(listen)?;
+    ground_socket.set_nonblocking(true)?;
+    let vehicle_socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0)))?;
+    vehicle_socket.set_nonblocking(true)?;
+    // Address of the ground tool, learned from its first datagram.
+    let mut ground = None;
+
+    let mut buf = [0; 65_535];
+    loop {
+        let mut idle = true;
+        if let Some((len, from)) = receive(&ground_socket, &mut buf)? {
+            ground = Some(from);
+            capture.record(Direction::Transmitted, Layer::Message, &buf[..len])?;
+            vehicle_socket.send_to(&buf[..len], vehicle)?;
+            idle = false;
+        }
+        if let Some((len, _)) = receive(&vehicle_socket, &mut buf)? {
+            capture.record(Direction::Received, Layer::Message, &buf[..len])?;
+            if let Some(ground) = ground {
+                ground_socket.send_to(&buf[..len], ground)?;
+            }
+            idle = false;
+        }
+
+        if idle {
+            sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+fn receive(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+    match socket.recv_from(buf) {
+        Ok(received) => Ok(Some(received)),
+        Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Relay bytes between a serial port and a pseudo-terminal. Traffic from the ground tools is
+/// recorded as transmitted, and traffic from the vehicle as received.
+#[cfg(unix)]
+fn serial(port: &str, path: &str) -> io::Result<()> {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    use flick_bridge::host::pty::Pty;
+
+    let mut port = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+        .open(port)?;
+    let mut capture = CaptureWriter::new(File::create(path)?)?;
+    let mut pty = Pty::open()?;
+    println!("{}", pty.path().display());
+
+    let mut buf = [0; 4096];
+    loop {
+        let mut idle = true;
+        if let Some(len) = read(&mut pty, &mut buf)? {
+            capture.record(Direction::Transmitted, Layer::Stream, &buf[..len])?;
+            port.write_all(&buf[..len])?;
+            idle = false;
+        }
+        match read(&mut port, &mut buf)? {
+            Some(0) => return Ok(()),
+            Some(len) => {
+                capture.record(Direction::Received, Layer::Stream, &buf[..len])?;
+                pty.write_all(&buf[..len])?;
+                idle = false;
+            }
+            None => {}
+        }
+
+        if idle {
+            sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn serial(_port: &str, _path: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "pseudo-terminals are only available on unix",
+    ))
+}
+
+/// Read whatever is available without blocking.
+fn read(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<Option<usize>> {
+    match reader.read(buf) {
+        Ok(len) => Ok(Some(len)),
+        Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Print every message of a capture.
+fn dissect(path: &str) -> io::Result<()> {
+    let reader = CaptureReader::new(BufReader::new(File::open(path)?))?;
+    let mut dissector = Dissector::new();
+    let mut start = None;
+
+    for record in reader {
+        let record = record?;
+        let start = *start.get_or_insert(record.time);
+        let direction = match record.direction {
+            Direction::Received => "<-",
+            Direction::Transmitted => "->",
+        };
+
+        for dissection in dissector.dissect(&record) {
+            println!(
+                "{:>12.6} {} {}",
+                record.time.saturating_sub(start) as f64 / 1e6,
+                direction,
+                dissection
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> T {
+    value.parse().unwrap_or_else(|_| usage())
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: flick-capture udp [--listen ADDR] [--vehicle ADDR] FILE\n       \
+         flick-capture serial PORT FILE\n       \
+         flick-capture dissect FILE"
+    );
+    exit(2)
+}
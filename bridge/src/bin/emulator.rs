@@ -0,0 +1,121 @@
+//! Emulated Flick vehicle, for developing ground tooling without hardware.
+//!
+//! Usage: `flick-emulator [--tcp ADDR | --pty | --udp ADDR [--ground ADDR]] [--launch-after SECS]`
+//! - `--tcp ADDR` serves one ground station at a time over TCP (the default, on
+//!   `127.0.0.1:4710`).
+//! - `--pty` creates a pseudo-terminal and prints its path, for tools expecting a serial port.
+//! - `--udp ADDR` binds to that address and talks to the ground station at `--ground ADDR`
+//!   (`127.0.0.1:4700` by default), such as `flick-mavlink-gateway` with its default addresses
+//!   and `--udp 127.0.0.1:4701`.
+//! - `--launch-after SECS` launches the rocket after that many seconds (10 by default, 0 to wait
+//!   for a command).
+
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::process::exit;
+
+use flick_bridge::device::DeviceError;
+use flick_bridge::host::emulator::{Emulator, EmulatorConfig};
+use flick_bridge::host::stream::StreamDevice;
+use flick_bridge::host::udp::UdpDevice;
+
+fn main() {
+    let mut tcp = SocketAddr::from(([127, 0, 0, 1], 4710));
+    let mut pty = false;
+    let mut udp: Option<SocketAddr> = None;
+    let mut ground = SocketAddr::from(([127, 0, 0, 1], 4700));
+    let mut config = EmulatorConfig::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--tcp" => tcp = parse(&value()),
+            "--pty" => pty = true,
+            "--udp" => udp = Some(parse(&value())),
+            "--ground" => ground = parse(&value()),
+            "--launch-after" => {
+                let secs: f64 = parse(&value());
+                config.launch_after = (secs > 0.0).then_some((secs * 1e6) as u64);
+            }
+            _ => usage(),
+        }
+    }
+
+    let mut emulator = Emulator::new(config);
+    let result = if pty {
+        serve_pty(&mut emulator)
+    } else if let Some(udp) = udp {
+        serve_udp(&mut emulator, udp, ground)
+    } else {
+        serve_tcp(&mut emulator, tcp)
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        exit(1);
+    }
+}
+
+/// Serve ground stations one after the other. The flight goes on between connections.
+fn serve_tcp(emulator: &mut Emulator, addr: SocketAddr) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("listening on {}", listener.local_addr()?);
+
+    loop {
+        let (stream, peer) = listener.accept()?;
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        eprintln!("{} connected", peer);
+
+        emulator.reconnect();
+        match emulator.run(&StreamDevice::new(stream)) {
+            DeviceError::Physical => eprintln!("{} disconnected", peer),
+            err => eprintln!("{} dropped: {}", peer, err),
+        }
+    }
+}
+
+/// Serve a single ground station over UDP, which has no connections to drop.
+fn serve_udp(emulator: &mut Emulator, addr: SocketAddr, ground: SocketAddr) -> io::Result<()> {
+    let device = UdpDevice::bind(addr, ground)?;
+    eprintln!(
+        "listening on {}, talking to {}",
+        device.local_addr()?,
+        ground
+    );
+
+    let err = emulator.run(&device);
+    Err(io::Error::other(err.to_string()))
+}
+
+#[cfg(unix)]
+fn serve_pty(emulator: &mut Emulator) -> io::Result<()> {
+    use flick_bridge::host::pty::Pty;
+
+    let pty = Pty::open()?;
+    println!("{}", pty.path().display());
+
+    let err = emulator.run(&StreamDevice::new(pty));
+    Err(io::Error::other(err.to_string()))
+}
+
+#[cfg(not(unix))]
+fn serve_pty(_emulator: &mut Emulator) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pseudo-terminals are only available on unix",
+    ))
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> T {
+    value.parse().unwrap_or_else(|_| usage())
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: flick-emulator [--tcp ADDR | --pty | --udp ADDR [--ground ADDR]] \
+         [--launch-after SECS]"
+    );
+    exit(2)
+}
@@ -0,0 +1,84 @@
+//! Gateway between a vehicle reachable over UDP and MAVLink ground software.
+//!
+//! Usage: `flick-mavlink-gateway [--bind ADDR] [--vehicle ADDR] [--mavlink ADDR] [--gcs ADDR]
+//! [--origin LAT LON ALT] [--encoding postcard|cbor]`
+
+use std::net::SocketAddr;
+use std::process::exit;
+
+use flick_bridge::host::mavlink::gateway::{Gateway, GatewayConfig, Origin};
+use flick_bridge::host::udp::UdpDevice;
+use flick_bridge::message::Encoding;
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Logger printing warnings of the gateway to the standard error.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        eprintln!("{}: {}", record.level(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+fn main() {
+    let _ = log::set_logger(&StderrLogger).map(|()| log::set_max_level(LevelFilter::Warn));
+
+    let mut bind: SocketAddr = SocketAddr::from(([0, 0, 0, 0], 4700));
+    let mut vehicle: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 4701));
+    let mut config = GatewayConfig::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--bind" => bind = parse(&value()),
+            "--vehicle" => vehicle = parse(&value()),
+            "--mavlink" => config.mavlink_addr = parse(&value()),
+            "--gcs" => config.gcs_addr = parse(&value()),
+            "--origin" => {
+                config.origin = Origin {
+                    latitude: parse(&value()),
+                    longitude: parse(&value()),
+                    altitude: parse(&value()),
+                }
+            }
+            "--encoding" => {
+                config.encoding = match value().as_str() {
+                    "postcard" => Some(Encoding::Postcard),
+                    "cbor" => Some(Encoding::Cbor),
+                    _ => usage(),
+                }
+            }
+            _ => usage(),
+        }
+    }
+
+    let device = UdpDevice::bind(bind, vehicle).unwrap_or_else(|err| fail(err));
+    let mut gateway = Gateway::new(device, config).unwrap_or_else(|err| fail(err));
+    if let Err(err) = gateway.run() {
+        fail(err);
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> T {
+    value.parse().unwrap_or_else(|_| usage())
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: flick-mavlink-gateway [--bind ADDR] [--vehicle ADDR] [--mavlink ADDR] \
+         [--gcs ADDR] [--origin LAT LON ALT] [--encoding postcard|cbor]"
+    );
+    exit(2)
+}
+
+fn fail(err: std::io::Error) -> ! {
+    eprintln!("error: {}", err);
+    exit(1)
+}
@@ -0,0 +1,80 @@
+//! Synchronization with the clock of the other side of the bridge.
+//!
+//! Every [`Message::send_time`](crate::message::Message::send_time) is on the sender's clock,
+//! which starts when the sender does. A [`ClockSync`] estimates the offset between the two clocks
+//! from round trips: a request sent at `sent`, answered by a message stamped `remote`, and
+//! received at `received`. The answer is assumed to have been sent halfway through the round trip,
+//! so the error is at most half of it, and the sample with the shortest round trip is kept.
+
+/// Estimates the clock of the other side of the bridge.
+#[derive(Debug, Clone)]
+pub struct ClockSync {
+    /// Best sample so far: offset of the remote clock, and round trip it was measured over.
+    best: Option<(i64, u64)>,
+    /// Time of the best sample, on the local clock.
+    sampled_at: u64,
+    /// Time after which a sample is replaced by the next one, however long its round trip.
+    max_age: u64,
+}
+
+impl ClockSync {
+    /// Instantiate a new [`ClockSync`]. Samples older than `max_age` microseconds are replaced
+    /// by newer ones even if their round trips were shorter, to follow drift between the clocks.
+    pub fn new(max_age: u64) -> Self {
+        Self {
+            best: None,
+            sampled_at: 0,
+            max_age,
+        }
+    }
+
+    /// Record a round trip. Returns whether the estimate was updated.
+    pub fn sample(&mut self, sent: u64, remote: u64, received: u64) -> bool {
+        if received < sent {
+            return false;
+        }
+
+        let round_trip = received - sent;
+        let midpoint = sent + round_trip / 2;
+        let offset = remote.wrapping_sub(midpoint) as i64;
+        let better = match self.best {
+            Some((_, best)) => {
+                round_trip <= best || received.saturating_sub(self.sampled_at) > self.max_age
+            }
+            None => true,
+        };
+        if better {
+            self.best = Some((offset, round_trip));
+            self.sampled_at = received;
+        }
+
+        better
+    }
+
+    /// Whether the remote clock was estimated yet.
+    pub fn synchronized(&self) -> bool {
+        self.best.is_some()
+    }
+
+    /// Offset of the remote clock from the local one, in microseconds.
+    pub fn offset(&self) -> Option<i64> {
+        self.best.map(|(offset, _)| offset)
+    }
+
+    /// Largest error of the estimate, in microseconds.
+    pub fn uncertainty(&self) -> Option<u64> {
+        self.best.map(|(_, round_trip)| round_trip / 2)
+    }
+
+    /// Convert a local time to the remote clock.
+    pub fn to_remote(&self, local: u64) -> Option<u64> {
+        self.offset()
+            .map(|offset| local.saturating_add_signed(offset))
+    }
+
+    /// Convert a remote time to the local clock.
+    pub fn to_local(&self, remote: u64) -> Option<u64> {
+        self.offset()
+            .map(|offset| remote.saturating_add_signed(offset.wrapping_neg()))
+    }
+}
@@ -1,5 +1,12 @@
+use core::fmt;
+
 use serde::{Deserialize, Serialize};
 
+use crate::telemetry::TelemetrySnapshot;
+use schedule::{ScheduleList, ScheduledAction, Trigger};
+
+pub mod schedule;
+
 /// A command is any request that can be sent through a message.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Command {
@@ -10,16 +17,67 @@ pub enum Command {
 	TogglePyro(u16),
 	/// Change the state of the rocket. This is only meant for rare occations when manual control
 	/// is necessary. Note that the rocket-state is not provided as an enumeration and instead is
-	/// the ID.
+	/// the ID (see [`FlightState::id`](crate::state::FlightState::id)).
 	ChangeState(u32),
 	/// Sent every now and then to ensure the bridge is still active.
 	Heartbeat,
 	/// Request telemetry.
 	Telemetry,
+	/// Queue an action that runs once the trigger fires. See [`schedule`].
+	Schedule(Trigger, ScheduledAction),
+	/// List up to [`schedule::MAX_LISTED`] queued commands, in the order they were queued,
+	/// starting at the given index.
+	ListScheduled(u16),
+	/// Cancel a queued command, given its identifier.
+	CancelScheduled(u32),
+	/// Read a tunable parameter, given its ID (see [`Param::id`](crate::param::Param::id)).
+	GetParameter(u16),
+	/// Set a tunable parameter, given its ID and its new value.
+	SetParameter(u16, f32),
+	/// Measure the ground reference of the barometric altitude again, such as after the vehicle
+	/// was moved to the pad. Only allowed before launch.
+	Rezero,
 }
 
 /// Represents the response to a command.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum CommandResponse {
-	Telemetry()
-}
\ No newline at end of file
+	/// The command was carried out, and has nothing to report.
+	Done,
+	/// The command could not be carried out.
+	Failed(CommandError),
+	/// Most recent telemetry of the vehicle.
+	Telemetry(TelemetrySnapshot),
+	/// The command was queued with the given identifier.
+	Scheduled(u32),
+	/// Commands in the queue.
+	ScheduleList(ScheduleList),
+	/// Current value of the parameter with the given ID.
+	Parameter(u16, f32),
+}
+
+/// Reason that a command could not be carried out.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+	/// The command is not supported by the vehicle.
+	Unsupported,
+	/// The command is not allowed in the current state of the vehicle.
+	InvalidState,
+	/// An argument of the command is out of range, such as a pyro-channel that doesn't exist.
+	InvalidArgument,
+	/// The hardware failed to carry out the command.
+	Hardware,
+}
+
+impl fmt::Display for CommandError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			CommandError::Unsupported => write!(f, "command not supported")?,
+			CommandError::InvalidState => write!(f, "command not allowed in current state")?,
+			CommandError::InvalidArgument => write!(f, "invalid command argument")?,
+			CommandError::Hardware => write!(f, "hardware failure")?,
+		};
+
+		Ok(())
+	}
+}
@@ -0,0 +1,59 @@
+//! Commands that run later, once a trigger fires.
+//!
+//! A [`Command::Schedule`] queues an action on the vehicle, which runs it at a given time on the
+//! vehicle's clock, or a given time after a flight event was detected. The ground converts its own
+//! times to the vehicle's clock with the [`ClockSync`](crate::clock::ClockSync) of its
+//! [`RpcClient`](crate::rpc::RpcClient), which samples the round trip of every request. Queued
+//! commands are listed with [`Command::ListScheduled`], and cancelled with
+//! [`Command::CancelScheduled`].
+
+use serde::{Deserialize, Serialize};
+
+use super::Command;
+use crate::event::Detection;
+
+/// Largest number of scheduled commands listed in a single response.
+pub const MAX_LISTED: usize = 8;
+
+/// Condition that runs a scheduled command.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// At the given time on the vehicle's clock, in microseconds.
+    At(u64),
+    /// The given number of microseconds after the vehicle detected a flight event.
+    After(Detection, u64),
+}
+
+/// Action run by a scheduled command. Only commands that act on the vehicle can be scheduled,
+/// which also keeps [`Command`] from holding itself.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduledAction {
+    /// See [`Command::FirePyro`].
+    FirePyro(u16),
+    /// See [`Command::TogglePyro`].
+    TogglePyro(u16),
+    /// See [`Command::ChangeState`].
+    ChangeState(u32),
+}
+
+impl From<ScheduledAction> for Command {
+    fn from(action: ScheduledAction) -> Self {
+        match action {
+            ScheduledAction::FirePyro(channel) => Command::FirePyro(channel),
+            ScheduledAction::TogglePyro(channel) => Command::TogglePyro(channel),
+            ScheduledAction::ChangeState(state) => Command::ChangeState(state),
+        }
+    }
+}
+
+/// Command waiting in the vehicle's queue.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduledCommand {
+    /// Identifier assigned by the vehicle, used to cancel the command.
+    pub id: u32,
+    pub trigger: Trigger,
+    pub action: ScheduledAction,
+}
+
+/// Scheduled commands listed in response to [`Command::ListScheduled`].
+pub type ScheduleList = heapless::Vec<ScheduledCommand, MAX_LISTED>;
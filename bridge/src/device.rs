@@ -1,6 +1,6 @@
 use core::fmt;
 
-use crate::message::Message;
+use crate::message::{Encoding, Encodings, Message};
 
 /// A bridge device is any device that can transmit and recieve packets.
 pub trait Device {
@@ -16,9 +16,34 @@ pub trait Device {
     fn freq(&self) -> Result<u64, DeviceError>;
     /// Set the frequency of the device.
     fn set_freq(&mut self, freq: u64) -> Result<(), DeviceError>;
+    /// Signal quality of the last received packet, for devices that measure it.
+    fn signal_quality(&self) -> Option<SignalQuality> {
+        None
+    }
+    /// Encodings of messages that the device can carry. Most devices only carry postcard.
+    fn encodings(&self) -> Encodings {
+        Encodings::POSTCARD
+    }
+    /// Encode and decode messages with the given encoding from now on, once both sides agreed on
+    /// it in the handshake.
+    fn set_encoding(&self, encoding: Encoding) -> Result<(), DeviceError> {
+        match encoding {
+            Encoding::Postcard => Ok(()),
+            _ => Err(DeviceError::InvalidMessage),
+        }
+    }
+}
+
+/// Signal quality of a received packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignalQuality {
+    /// Received signal strength, in dBm.
+    pub rssi: i16,
+    /// Signal-to-noise ratio, in quarters of a dB.
+    pub snr: i8,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DeviceError {
     /// Invalid message was provided.
     InvalidMessage,
@@ -29,6 +54,8 @@ pub enum DeviceError {
     NotAcknowledged,
     /// An error with the physical device occured.
     Physical,
+    /// Too many messages are waiting to be acknowledged.
+    QueueFull,
 }
 
 impl fmt::Display for DeviceError {
@@ -38,6 +65,7 @@ impl fmt::Display for DeviceError {
             DeviceError::InvalidMessage => write!(f, "invalid message provided")?,
             DeviceError::NotAcknowledged => write!(f, "reciever did not acknowledge sent packet")?,
             DeviceError::Physical => write!(f, "physical device error")?,
+            DeviceError::QueueFull => write!(f, "too many messages awaiting acknowledgement")?,
         };
 
         Ok(())
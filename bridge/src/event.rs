@@ -0,0 +1,100 @@
+//! Events originated by the vehicle.
+//!
+//! Unlike responses, events are sent without the ground asking for them, as soon as they happen.
+//! They should be sent with [`MessageFlags::REQUIRES_ACK`](crate::message::MessageFlags::REQUIRES_ACK)
+//! through a [`ReliableSender`](crate::reliable::ReliableSender) so that none are missed.
+
+use serde::{Deserialize, Serialize};
+
+/// How important an event is.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Useful for debugging, but not worth the attention of the operator.
+    Debug,
+    /// Normal progress of the flight.
+    Info,
+    /// Something is wrong, but the flight can carry on.
+    Warning,
+    /// Something is wrong and needs immediate attention.
+    Critical,
+}
+
+/// Flight event detected by the vehicle.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detection {
+    /// Liftoff.
+    Launch,
+    /// Motor burnout.
+    Burnout,
+    /// Highest point of the flight.
+    Apogee,
+    /// Touchdown.
+    Landing,
+}
+
+/// Sensor of the vehicle.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sensor {
+    /// Inertial measurement unit.
+    Imu,
+    /// Barometer.
+    Barometer,
+    /// Magnetometer.
+    Magnetometer,
+    /// Satellite navigation receiver.
+    Gnss,
+}
+
+/// What happened on the vehicle.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// The vehicle changed state. States are given by their IDs (see
+    /// [`FlightState::id`](crate::state::FlightState::id)).
+    StateChanged { from: u32, to: u32 },
+    /// A flight event was detected.
+    Detected(Detection),
+    /// A pyro channel was fired.
+    PyroFired(u16),
+    /// A sensor stopped responding or is giving implausible readings.
+    SensorFault(Sensor),
+    /// The battery voltage dropped below its threshold. Contains the voltage in millivolts.
+    LowBattery(u16),
+    /// The link to the ground was lost. Contains the time that the ground was last heard from.
+    LinkLost(u64),
+    /// The link to the ground came back. Contains the time the link was lost at.
+    LinkRestored(u64),
+}
+
+impl EventKind {
+    /// Severity that events of this kind are usually reported with.
+    pub fn severity(&self) -> Severity {
+        match self {
+            EventKind::StateChanged { .. } | EventKind::Detected(_) => Severity::Info,
+            EventKind::PyroFired(_) | EventKind::LinkRestored(_) => Severity::Info,
+            EventKind::LowBattery(_) | EventKind::LinkLost(_) => Severity::Warning,
+            EventKind::SensorFault(_) => Severity::Critical,
+        }
+    }
+}
+
+/// Event that happened on the vehicle.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VehicleEvent {
+    /// Time that the event happened, in microseconds on the vehicle's clock.
+    pub time: u64,
+    /// How important the event is.
+    pub severity: Severity,
+    /// What happened.
+    pub kind: EventKind,
+}
+
+impl VehicleEvent {
+    /// Instantiate a new [`VehicleEvent`] with the usual severity of its kind.
+    pub fn new(time: u64, kind: EventKind) -> Self {
+        Self {
+            time,
+            severity: kind.severity(),
+            kind,
+        }
+    }
+}
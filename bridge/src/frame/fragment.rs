@@ -0,0 +1,150 @@
+//! Fragmentation of frames for links with small packets, such as the 32-byte payloads of
+//! nRF24L01+ radios.
+//!
+//! Every fragment starts with a two-byte header: the sequence number of the frame, followed by
+//! the index of the fragment in the high nibble and the index of the last fragment in the low
+//! nibble. A frame can therefore span at most 16 fragments. Fragments must arrive in order, which
+//! packet radios with hardware acknowledgements guarantee.
+
+use super::{FrameError, MAX_FRAME_LEN};
+
+/// Length of the header of a fragment.
+pub const HEADER_LEN: usize = 2;
+/// Largest number of fragments of a frame.
+const MAX_FRAGMENTS: usize = 16;
+
+/// Header of a fragment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentHeader {
+    /// Sequence number of the frame that the fragment belongs to.
+    pub sequence: u8,
+    /// Index of the fragment in its frame.
+    pub index: u8,
+    /// Index of the last fragment of the frame.
+    pub last: u8,
+}
+
+impl FragmentHeader {
+    /// Encode the header.
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        [self.sequence, (self.index << 4) | (self.last & 0x0f)]
+    }
+
+    /// Decode the header at the start of a fragment.
+    pub fn decode(fragment: &[u8]) -> Result<Self, FrameError> {
+        match fragment {
+            [sequence, indices, ..] => Ok(Self {
+                sequence: *sequence,
+                index: indices >> 4,
+                last: indices & 0x0f,
+            }),
+            _ => Err(FrameError::Truncated),
+        }
+    }
+}
+
+/// Iterator over the fragments of a frame, as headers and the chunk of the frame that follows
+/// them.
+#[derive(Debug, Clone)]
+pub struct Fragments<'a> {
+    chunks: core::slice::Chunks<'a, u8>,
+    sequence: u8,
+    index: u8,
+    last: u8,
+}
+
+impl<'a> Iterator for Fragments<'a> {
+    type Item = (FragmentHeader, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = self.chunks.next()?;
+        let header = FragmentHeader {
+            sequence: self.sequence,
+            index: self.index,
+            last: self.last,
+        };
+        self.index += 1;
+
+        Some((header, chunk))
+    }
+}
+
+/// Split a frame into fragments that fit in packets of `mtu` bytes.
+pub fn fragments(frame: &[u8], mtu: usize, sequence: u8) -> Result<Fragments<'_>, FrameError> {
+    let chunk_len = mtu.saturating_sub(HEADER_LEN);
+    if chunk_len == 0 || frame.is_empty() {
+        return Err(FrameError::TooLarge);
+    }
+
+    let count = frame.len().div_ceil(chunk_len);
+    if count > MAX_FRAGMENTS {
+        return Err(FrameError::TooLarge);
+    }
+
+    Ok(Fragments {
+        chunks: frame.chunks(chunk_len),
+        sequence,
+        index: 0,
+        last: (count - 1) as u8,
+    })
+}
+
+/// Reassembles frames from their fragments.
+#[derive(Debug, Clone)]
+pub struct Reassembler {
+    buf: [u8; MAX_FRAME_LEN],
+    len: usize,
+    /// Header of the next expected fragment, if a frame is being reassembled.
+    next: Option<FragmentHeader>,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reassembler {
+    /// Instantiate a new [`Reassembler`].
+    pub fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME_LEN],
+            len: 0,
+            next: None,
+        }
+    }
+
+    /// Add a received fragment, returning the frame once its last fragment arrived. A frame that
+    /// is missing a fragment is dropped, in which case [`FrameError::MissingFragment`] is
+    /// returned. The first fragment of a frame always starts a new one, even if the previous frame
+    /// was incomplete.
+    pub fn push(&mut self, fragment: &[u8]) -> Result<Option<&[u8]>, FrameError> {
+        let header = FragmentHeader::decode(fragment)?;
+        let chunk = &fragment[HEADER_LEN..];
+
+        if header.index == 0 {
+            self.len = 0;
+        } else if self.next != Some(header) {
+            self.next = None;
+            return Err(FrameError::MissingFragment);
+        }
+
+        if self.len + chunk.len() > MAX_FRAME_LEN {
+            self.next = None;
+            return Err(FrameError::TooLarge);
+        }
+        self.buf[self.len..self.len + chunk.len()].copy_from_slice(chunk);
+        self.len += chunk.len();
+
+        if header.index == header.last {
+            self.next = None;
+            return Ok(Some(&self.buf[..self.len]));
+        }
+
+        self.next = Some(FragmentHeader {
+            index: header.index + 1,
+            ..header
+        });
+        Ok(None)
+    }
+}
@@ -0,0 +1,96 @@
+//! Framing of messages for links that don't check the integrity of what they carry.
+//!
+//! A frame is an encoded [`Message`] followed by its CRC-16, so corrupted messages are rejected
+//! instead of being decoded into garbage. Links with small packets split frames into
+//! [`fragment`]s, and byte streams delimit them with [`stream`] encoding.
+
+use core::fmt;
+
+use crate::device::DeviceError;
+use crate::message::{Message, MAX_MESSAGE_LEN};
+
+pub mod fragment;
+pub mod stream;
+
+/// Length of the checksum at the end of a frame.
+const CRC_LEN: usize = 2;
+/// Largest size of a frame.
+pub const MAX_FRAME_LEN: usize = MAX_MESSAGE_LEN + CRC_LEN;
+
+/// Encode a message into a frame, returning the encoded bytes.
+pub fn encode<'a>(
+    message: &Message,
+    buf: &'a mut [u8; MAX_FRAME_LEN],
+) -> Result<&'a [u8], FrameError> {
+    let len = message
+        .encode(&mut buf[..MAX_MESSAGE_LEN])
+        .map_err(|_| FrameError::Malformed)?
+        .len();
+    let crc = crc16(&buf[..len]);
+    buf[len..len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+    Ok(&buf[..len + CRC_LEN])
+}
+
+/// Check the integrity of a frame and decode its message.
+pub fn decode(frame: &[u8]) -> Result<Message, FrameError> {
+    if frame.len() <= CRC_LEN {
+        return Err(FrameError::Truncated);
+    }
+
+    let (body, crc) = frame.split_at(frame.len() - CRC_LEN);
+    if crc16(body).to_le_bytes() != crc {
+        return Err(FrameError::BadCrc);
+    }
+
+    Message::decode(body).map_err(|_| FrameError::Malformed)
+}
+
+/// CRC-16/CCITT-FALSE of the bytes.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xffff, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The frame is too short to hold a message.
+    Truncated,
+    /// The checksum of the frame didn't match its contents.
+    BadCrc,
+    /// The frame passed its checksum, but doesn't hold a valid message.
+    Malformed,
+    /// A fragment was lost, so the frame it belonged to was dropped.
+    MissingFragment,
+    /// The frame is too large to be split into fragments.
+    TooLarge,
+}
+
+impl From<FrameError> for DeviceError {
+    fn from(_: FrameError) -> Self {
+        DeviceError::InvalidMessage
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Truncated => write!(f, "truncated frame")?,
+            FrameError::BadCrc => write!(f, "bad frame checksum")?,
+            FrameError::Malformed => write!(f, "malformed message")?,
+            FrameError::MissingFragment => write!(f, "fragment lost")?,
+            FrameError::TooLarge => write!(f, "frame too large to fragment")?,
+        };
+
+        Ok(())
+    }
+}
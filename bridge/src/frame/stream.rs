@@ -0,0 +1,123 @@
+//! Framing of frames over byte streams, such as UARTs and USB serial ports.
+//!
+//! Frames are encoded with Consistent Overhead Byte Stuffing (COBS), which removes every zero
+//! byte so that a single zero can delimit frames. A receiver that starts listening in the middle
+//! of a frame, or loses bytes, resynchronizes at the next delimiter.
+//! See https://doi.org/10.1109/90.769765
+
+use super::{FrameError, MAX_FRAME_LEN};
+
+/// Byte that ends every frame.
+pub const DELIMITER: u8 = 0x00;
+/// Largest size of an encoded frame, including its delimiter.
+pub const MAX_ENCODED_LEN: usize = MAX_FRAME_LEN + MAX_FRAME_LEN / 254 + 2;
+
+/// Encode a frame for a byte stream, followed by its delimiter. Returns the number of bytes
+/// written.
+pub fn encode(frame: &[u8], buf: &mut [u8; MAX_ENCODED_LEN]) -> Result<usize, FrameError> {
+    if frame.len() > MAX_FRAME_LEN {
+        return Err(FrameError::TooLarge);
+    }
+
+    // Index of the code byte of the current block, which holds the distance to the next zero.
+    let mut code = 0;
+    let mut len = 1;
+    for &byte in frame {
+        if byte == 0 {
+            buf[code] = (len - code) as u8;
+            code = len;
+            len += 1;
+            continue;
+        }
+
+        buf[len] = byte;
+        len += 1;
+        if len - code == 0xff {
+            buf[code] = 0xff;
+            code = len;
+            len += 1;
+        }
+    }
+    buf[code] = (len - code) as u8;
+    buf[len] = DELIMITER;
+
+    Ok(len + 1)
+}
+
+/// Decode a COBS-encoded frame in place, without its delimiter. Returns the length of the frame.
+fn decode_in_place(buf: &mut [u8]) -> Result<usize, FrameError> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < buf.len() {
+        let code = buf[read] as usize;
+        if code == 0 || read + code > buf.len() {
+            return Err(FrameError::Malformed);
+        }
+        read += 1;
+
+        for _ in 1..code {
+            buf[write] = buf[read];
+            read += 1;
+            write += 1;
+        }
+        // Every block except the longest ones and the last one is followed by a zero.
+        if code != 0xff && read < buf.len() {
+            buf[write] = 0;
+            write += 1;
+        }
+    }
+
+    Ok(write)
+}
+
+/// Splits a byte stream back into frames.
+#[derive(Debug, Clone)]
+pub struct StreamDecoder {
+    buf: [u8; MAX_ENCODED_LEN],
+    len: usize,
+    /// Whether the frame being received didn't fit in the buffer, and is being skipped.
+    overflow: bool,
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamDecoder {
+    /// Instantiate a new [`StreamDecoder`].
+    pub fn new() -> Self {
+        Self {
+            buf: [0; MAX_ENCODED_LEN],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Add a received byte, returning the frame once its delimiter arrived. The frame still has
+    /// to be checked with [`frame::decode`](super::decode).
+    pub fn push(&mut self, byte: u8) -> Result<Option<&[u8]>, FrameError> {
+        if byte != DELIMITER {
+            if self.len == self.buf.len() {
+                self.overflow = true;
+            } else {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            }
+            return Ok(None);
+        }
+
+        let len = core::mem::take(&mut self.len);
+        if core::mem::take(&mut self.overflow) {
+            return Err(FrameError::TooLarge);
+        }
+        // Consecutive delimiters are used to flush the line, and hold no frame.
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let len = decode_in_place(&mut self.buf[..len])?;
+        Ok(Some(&self.buf[..len]))
+    }
+}
@@ -0,0 +1,75 @@
+//! Handshake that starts a session between the ground and the vehicle, and agrees on the
+//! [`Encoding`] of the messages that follow.
+//!
+//! The ground sends a [`MessageKind::Handshake`] with its identifier, the encodings it supports
+//! and the one it prefers. The vehicle answers with its own identifier, the encodings it supports,
+//! and the encoding it picked with [`Encoding::negotiate`]. The answer is still sent in the
+//! encoding of the request, and both sides switch to the picked encoding afterwards.
+//!
+//! Devices recognize handshakes in any encoding they support (see [`Message::decode_handshake`]),
+//! so tools that only speak a self-describing encoding can start a session too.
+
+use crate::device::{Device, DeviceError};
+use crate::message::{Encoding, Encodings, Message, MessageFlags, MessageKind};
+
+/// Encodings that this side can use over the device.
+pub fn supported<D: Device>(device: &D) -> Encodings {
+    Encoding::supported() & device.encodings()
+}
+
+/// Build the handshake that the ground starts a session with, given its identifier and the
+/// encoding it prefers, if any.
+pub fn request<D: Device>(
+    device: &D,
+    ground: u64,
+    preferred: Option<Encoding>,
+    id: u64,
+    now: u64,
+) -> Message {
+    let kind = MessageKind::Handshake(ground, 0, supported(device).bits(), preferred);
+    Message::new(id, kind, now, MessageFlags::empty())
+}
+
+/// Answer the handshake of the ground on the vehicle, then switch the device to the encoding it
+/// picked. Returns that encoding, or `None` if the message isn't a handshake. Fails with
+/// [`DeviceError::InvalidMessage`] if the sides have no encoding in common.
+pub fn answer<D: Device>(
+    device: &D,
+    request: &Message,
+    vehicle: u64,
+    id: u64,
+    now: u64,
+) -> Result<Option<Encoding>, DeviceError> {
+    let (ground, theirs, preferred) = match *request.kind() {
+        MessageKind::Handshake(ground, _, theirs, preferred) => (ground, theirs, preferred),
+        _ => return Ok(None),
+    };
+
+    let ours = supported(device);
+    let encoding = Encoding::negotiate(ours, Encodings::from_bits_truncate(theirs), preferred)
+        .ok_or(DeviceError::InvalidMessage)?;
+    let kind = MessageKind::Handshake(vehicle, ground, ours.bits(), Some(encoding));
+    device.transmit(Message::new(id, kind, now, MessageFlags::empty()))?;
+    device.set_encoding(encoding)?;
+
+    Ok(Some(encoding))
+}
+
+/// Handle the answer of the vehicle on the ground, and switch the device to the encoding that the
+/// vehicle picked. Returns the identifier of the vehicle and that encoding, or `None` if the
+/// message isn't an answer to the handshake of this ground, or picked an unsupported encoding.
+pub fn accept<D: Device>(device: &D, answer: &Message, ground: u64) -> Option<(u64, Encoding)> {
+    let (vehicle, encoding) = match *answer.kind() {
+        MessageKind::Handshake(vehicle, to, _, Some(encoding)) if to == ground => {
+            (vehicle, encoding)
+        }
+        _ => return None,
+    };
+
+    if !supported(device).contains(encoding.flag()) {
+        return None;
+    }
+    device.set_encoding(encoding).ok()?;
+
+    Some((vehicle, encoding))
+}
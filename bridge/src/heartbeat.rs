@@ -0,0 +1,106 @@
+//! Link liveness tracking through [`Command::Heartbeat`](crate::command::Command::Heartbeat).
+//!
+//! Both sides of the bridge periodically send heartbeats, and run a [`HeartbeatMonitor`] that
+//! declares the link lost once nothing has been heard from the other side for too long. All times
+//! are in microseconds, like [`Message::send_time`](crate::message::Message::send_time).
+
+/// State of the link as seen by a [`HeartbeatMonitor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    /// Nothing has been heard from the other side yet.
+    Waiting,
+    /// The other side was heard from recently.
+    Connected,
+    /// Nothing has been heard from the other side for longer than the timeout.
+    Lost,
+}
+
+/// Change in the state of the link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The link was lost. Contains the time that the other side was last heard from, or that the
+    /// monitor started watching if it never was.
+    Lost { last_heard: u64 },
+    /// The link came back after being lost. Contains the time that the link was lost at, and the
+    /// time it came back at.
+    Restored { lost_at: u64, restored_at: u64 },
+}
+
+/// Watches for heartbeats from the other side of the link.
+#[derive(Debug, Clone)]
+pub struct HeartbeatMonitor {
+    /// Time after which the link is declared lost if nothing was heard.
+    timeout: u64,
+    /// Time between two heartbeats sent by this side.
+    interval: u64,
+    /// Time that the other side was last heard from.
+    last_heard: Option<u64>,
+    /// Time that a heartbeat was last sent by this side.
+    last_sent: Option<u64>,
+    /// Time that the link was lost at, if it is currently lost.
+    lost_at: Option<u64>,
+    /// Time of the first update, which a link that never came up times out from.
+    started_at: Option<u64>,
+}
+
+impl HeartbeatMonitor {
+    /// Instantiate a new [`HeartbeatMonitor`] that sends a heartbeat every `interval`, and
+    /// declares the link lost after `timeout` without hearing from the other side. The timeout
+    /// should span several intervals so a single dropped heartbeat doesn't trip it.
+    pub fn new(interval: u64, timeout: u64) -> Self {
+        Self {
+            timeout,
+            interval,
+            last_heard: None,
+            last_sent: None,
+            lost_at: None,
+            started_at: None,
+        }
+    }
+
+    /// Current state of the link.
+    pub fn state(&self) -> LinkState {
+        match (self.last_heard, self.lost_at) {
+            (_, Some(_)) => LinkState::Lost,
+            (Some(_), None) => LinkState::Connected,
+            (None, None) => LinkState::Waiting,
+        }
+    }
+
+    /// Records that the other side was heard from. Every message proves that the link is alive,
+    /// not just heartbeats.
+    pub fn heard(&mut self, now: u64) -> Option<LinkEvent> {
+        self.last_heard = Some(now);
+        self.lost_at.take().map(|lost_at| LinkEvent::Restored {
+            lost_at,
+            restored_at: now,
+        })
+    }
+
+    /// Checks whether the link timed out. This should be called regularly, even when nothing is
+    /// being received. A link that never comes up is lost once the timeout has passed since the
+    /// first update.
+    pub fn update(&mut self, now: u64) -> Option<LinkEvent> {
+        let started_at = *self.started_at.get_or_insert(now);
+        let last_heard = self.last_heard.unwrap_or(started_at);
+        if self.lost_at.is_none() && now.saturating_sub(last_heard) > self.timeout {
+            self.lost_at = Some(now);
+            return Some(LinkEvent::Lost { last_heard });
+        }
+
+        None
+    }
+
+    /// Returns whether a heartbeat is due, and if so, records that one is being sent.
+    pub fn heartbeat_due(&mut self, now: u64) -> bool {
+        let due = match self.last_sent {
+            Some(last_sent) => now.saturating_sub(last_sent) >= self.interval,
+            None => true,
+        };
+        if due {
+            self.last_sent = Some(now);
+        }
+
+        due
+    }
+}
@@ -0,0 +1,222 @@
+//! Decoding of captures into messages.
+//!
+//! A [`Dissector`] reassembles fragments and splits streams back into frames, checks them, and
+//! decodes their messages. Messages seen before in the same direction are flagged as
+//! retransmissions, which keep the ID and send time of the original, and acknowledgements and
+//! responses are matched to the message they answer to measure the delay.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{Direction, Layer, Record};
+use crate::frame::fragment::{FragmentHeader, Reassembler};
+use crate::frame::stream::StreamDecoder;
+use crate::frame::{self, FrameError};
+use crate::message::{Encoding, Message, MessageKind};
+
+/// What was found in a record.
+#[derive(Debug, Clone)]
+pub enum Dissection {
+    /// A message was decoded.
+    Message {
+        message: Box<Message>,
+        /// How the message relates to those seen before.
+        repeat: Repeat,
+        /// For acknowledgements and responses, the delay since the message they answer was last
+        /// sent, in microseconds.
+        delay: Option<u64>,
+    },
+    /// A fragment of a frame.
+    Fragment(FragmentHeader),
+    /// Bytes that couldn't be decoded.
+    Invalid(FrameError),
+}
+
+/// How a message relates to messages seen before in the same direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    /// The message wasn't seen before.
+    First,
+    /// The message was sent again, for the given time.
+    Retransmission(u32),
+}
+
+/// Messages seen with a given ID.
+#[derive(Debug, Clone)]
+struct Seen {
+    /// Number of transmissions.
+    transmissions: u32,
+    /// Capture time of the last transmission.
+    captured: u64,
+}
+
+/// State of one direction of the link.
+#[derive(Debug, Clone, Default)]
+struct Side {
+    reassembler: Reassembler,
+    decoder: StreamDecoder,
+    seen: HashMap<u64, Seen>,
+}
+
+/// Decodes the records of a capture, in order.
+#[derive(Debug, Clone, Default)]
+pub struct Dissector {
+    received: Side,
+    transmitted: Side,
+}
+
+impl Dissector {
+    /// Instantiate a new [`Dissector`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode a record. Records can hold any number of messages, such as chunks of a stream.
+    pub fn dissect(&mut self, record: &Record) -> Vec<Dissection> {
+        let mut dissections = Vec::new();
+        match record.layer {
+            Layer::Message => {
+                dissections.push(match decode(&record.bytes) {
+                    Some(message) => self.message(record, message),
+                    None => Dissection::Invalid(FrameError::Malformed),
+                });
+            }
+            Layer::Frame => dissections.push(self.frame(record, &record.bytes)),
+            Layer::Fragment => {
+                let header = match FragmentHeader::decode(&record.bytes) {
+                    Ok(header) => header,
+                    Err(err) => return vec![Dissection::Invalid(err)],
+                };
+                dissections.push(Dissection::Fragment(header));
+
+                let frame = self
+                    .side(record.direction)
+                    .reassembler
+                    .push(&record.bytes)
+                    .map(|frame| frame.map(<[u8]>::to_vec));
+                match frame {
+                    Ok(Some(frame)) => dissections.push(self.frame(record, &frame)),
+                    Ok(None) => {}
+                    Err(err) => dissections.push(Dissection::Invalid(err)),
+                }
+            }
+            Layer::Stream => {
+                for &byte in &record.bytes {
+                    let frame = self
+                        .side(record.direction)
+                        .decoder
+                        .push(byte)
+                        .map(|frame| frame.map(<[u8]>::to_vec));
+                    match frame {
+                        Ok(Some(frame)) => dissections.push(self.frame(record, &frame)),
+                        Ok(None) => {}
+                        Err(err) => dissections.push(Dissection::Invalid(err)),
+                    }
+                }
+            }
+        }
+
+        dissections
+    }
+
+    fn frame(&mut self, record: &Record, frame: &[u8]) -> Dissection {
+        match frame::decode(frame) {
+            Ok(message) => self.message(record, message),
+            Err(err) => Dissection::Invalid(err),
+        }
+    }
+
+    fn message(&mut self, record: &Record, message: Message) -> Dissection {
+        let repeat = match self.side(record.direction).seen.get_mut(&message.id()) {
+            Some(seen) => {
+                seen.transmissions += 1;
+                seen.captured = record.time;
+                Repeat::Retransmission(seen.transmissions - 1)
+            }
+            None => {
+                self.side(record.direction).seen.insert(
+                    message.id(),
+                    Seen {
+                        transmissions: 1,
+                        captured: record.time,
+                    },
+                );
+                Repeat::First
+            }
+        };
+
+        let answered = match message.kind() {
+            MessageKind::Ack(id) | MessageKind::Response(id, _) => Some(*id),
+            _ => None,
+        };
+        let other = match record.direction {
+            Direction::Received => &self.transmitted,
+            Direction::Transmitted => &self.received,
+        };
+        let delay = answered
+            .and_then(|id| other.seen.get(&id))
+            .map(|seen| record.time.saturating_sub(seen.captured));
+
+        Dissection::Message {
+            message: Box::new(message),
+            repeat,
+            delay,
+        }
+    }
+
+    fn side(&mut self, direction: Direction) -> &mut Side {
+        match direction {
+            Direction::Received => &mut self.received,
+            Direction::Transmitted => &mut self.transmitted,
+        }
+    }
+}
+
+impl fmt::Display for Dissection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dissection::Message {
+                message,
+                repeat,
+                delay,
+            } => {
+                write!(
+                    f,
+                    "#{} t={}us {:?}",
+                    message.id(),
+                    message.send_time(),
+                    message.kind()
+                )?;
+                if !message.flags().is_empty() {
+                    write!(f, " {:?}", message.flags())?;
+                }
+                match repeat {
+                    Repeat::First => {}
+                    Repeat::Retransmission(n) => write!(f, " [retransmission {}]", n)?,
+                }
+                if let Some(delay) = delay {
+                    write!(f, " [after {:.1} ms]", *delay as f64 / 1000.0)?;
+                }
+            }
+            Dissection::Fragment(header) => write!(
+                f,
+                "fragment {}/{} of frame {}",
+                header.index + 1,
+                header.last + 1,
+                header.sequence
+            )?,
+            Dissection::Invalid(err) => write!(f, "invalid: {}", err)?,
+        };
+
+        Ok(())
+    }
+}
+
+/// Decode a message in any encoding supported by this build. Self-describing encodings come first,
+/// since they are less likely to decode by accident.
+fn decode(bytes: &[u8]) -> Option<Message> {
+    [Encoding::Cbor, Encoding::Postcard]
+        .into_iter()
+        .filter(|encoding| Encoding::supported().contains(encoding.flag()))
+        .find_map(|encoding| Message::decode_with(encoding, bytes).ok())
+}
@@ -0,0 +1,262 @@
+//! Capture of raw link traffic to files, for inspection after the fact.
+//!
+//! Captures use the pcap format with the `LINKTYPE_USER0` link type, so they can also be opened in
+//! Wireshark. Every packet starts with a two-byte pseudo-header: the [`Direction`] of the traffic,
+//! and the [`Layer`] that the bytes were captured at. The [`dissect`] module decodes captures back
+//! into messages.
+//!
+//! Traffic is captured below the decoder, so that frames with bad CRCs, fragments and bytes that
+//! don't decode at all show up too. A [`CaptureStream`] records both directions of a byte stream,
+//! such as the serial port under a [`StreamDevice`](crate::host::stream::StreamDevice), and a
+//! [`UdpDevice`](crate::host::udp::UdpDevice) records its datagrams once given a capture. Other
+//! carriers of raw bytes can record them with [`CaptureWriter::record`].
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod dissect;
+
+/// Magic number of pcap files with microsecond timestamps.
+const MAGIC: u32 = 0xa1b2_c3d4;
+/// `LINKTYPE_USER0`, reserved for private use.
+const LINKTYPE: u32 = 147;
+/// Largest packet recorded.
+const SNAPLEN: u32 = 65_535;
+/// Length of the pseudo-header at the start of every packet.
+const PSEUDO_HEADER_LEN: usize = 2;
+
+/// Direction of captured traffic, as seen from the capturing side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Received,
+    Transmitted,
+}
+
+/// Layer of the bridge that bytes were captured at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    /// An encoded message, such as a UDP datagram.
+    Message,
+    /// A frame, with its CRC (see [`crate::frame`]).
+    Frame,
+    /// A fragment of a frame (see [`crate::frame::fragment`]).
+    Fragment,
+    /// Bytes of a stream carrying frames (see [`crate::frame::stream`]).
+    Stream,
+}
+
+/// Packet of a capture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Time that the packet was captured, in microseconds since the Unix epoch.
+    pub time: u64,
+    pub direction: Direction,
+    pub layer: Layer,
+    pub bytes: Vec<u8>,
+}
+
+/// Writes a capture file.
+#[derive(Debug)]
+pub struct CaptureWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Start a capture, writing the file header.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&MAGIC.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        // Time zone offset and timestamp accuracy, which are always zero.
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&LINKTYPE.to_le_bytes());
+        writer.write_all(&header)?;
+
+        Ok(Self { writer })
+    }
+
+    /// Record bytes captured now.
+    pub fn record(&mut self, direction: Direction, layer: Layer, bytes: &[u8]) -> io::Result<()> {
+        self.write(&Record {
+            time: now(),
+            direction,
+            layer,
+            bytes: bytes.to_vec(),
+        })
+    }
+
+    /// Writer that the capture is written to.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Write a record.
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        let len = (PSEUDO_HEADER_LEN + record.bytes.len()) as u32;
+        let mut packet = Vec::with_capacity(16 + len as usize);
+        packet.extend_from_slice(&((record.time / 1_000_000) as u32).to_le_bytes());
+        packet.extend_from_slice(&((record.time % 1_000_000) as u32).to_le_bytes());
+        packet.extend_from_slice(&len.to_le_bytes());
+        packet.extend_from_slice(&len.to_le_bytes());
+        packet.push(match record.direction {
+            Direction::Received => 0,
+            Direction::Transmitted => 1,
+        });
+        packet.push(match record.layer {
+            Layer::Message => 0,
+            Layer::Frame => 1,
+            Layer::Fragment => 2,
+            Layer::Stream => 3,
+        });
+        packet.extend_from_slice(&record.bytes);
+
+        self.writer.write_all(&packet)?;
+        self.writer.flush()
+    }
+}
+
+/// Reads a capture file.
+#[derive(Debug)]
+pub struct CaptureReader<R> {
+    reader: R,
+    /// Whether the file was written with the other byte order.
+    swapped: bool,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Open a capture, checking the file header.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 24];
+        reader.read_exact(&mut header)?;
+
+        let swapped = match u32::from_le_bytes(header[..4].try_into().unwrap()) {
+            MAGIC => false,
+            magic if magic.swap_bytes() == MAGIC => true,
+            _ => return Err(invalid("not a pcap file with microsecond timestamps")),
+        };
+        let reader = Self { reader, swapped };
+        if reader.u32(&header[20..24]) != LINKTYPE {
+            return Err(invalid("not a bridge capture"));
+        }
+
+        Ok(reader)
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let value = u32::from_le_bytes(bytes.try_into().unwrap());
+        match self.swapped {
+            true => value.swap_bytes(),
+            false => value,
+        }
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut header = [0; 16];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err)),
+        }
+
+        let time = self.u32(&header[0..4]) as u64 * 1_000_000 + self.u32(&header[4..8]) as u64;
+        let len = self.u32(&header[8..12]) as usize;
+        if len < PSEUDO_HEADER_LEN || len > SNAPLEN as usize {
+            return Some(Err(invalid("invalid packet length")));
+        }
+        let mut packet = vec![0; len];
+        if let Err(err) = self.reader.read_exact(&mut packet) {
+            return Some(Err(err));
+        }
+
+        let direction = match packet[0] {
+            0 => Direction::Received,
+            1 => Direction::Transmitted,
+            _ => return Some(Err(invalid("unknown direction"))),
+        };
+        let layer = match packet[1] {
+            0 => Layer::Message,
+            1 => Layer::Frame,
+            2 => Layer::Fragment,
+            3 => Layer::Stream,
+            _ => return Some(Err(invalid("unknown layer"))),
+        };
+        packet.drain(..PSEUDO_HEADER_LEN);
+
+        Some(Ok(Record {
+            time,
+            direction,
+            layer,
+            bytes: packet,
+        }))
+    }
+}
+
+/// Byte stream that records everything read from and written to it, at the [`Layer::Stream`]
+/// layer.
+#[derive(Debug)]
+pub struct CaptureStream<S, W> {
+    stream: S,
+    writer: CaptureWriter<W>,
+}
+
+impl<S, W: Write> CaptureStream<S, W> {
+    /// Instantiate a new [`CaptureStream`] recording into the given capture.
+    pub fn new(stream: S, writer: CaptureWriter<W>) -> Self {
+        Self { stream, writer }
+    }
+
+    /// Stream being captured.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+
+    /// Capture being written.
+    pub fn writer(&self) -> &CaptureWriter<W> {
+        &self.writer
+    }
+}
+
+impl<S: Read, W: Write> Read for CaptureStream<S, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.stream.read(buf)?;
+        if len > 0 {
+            self.writer
+                .record(Direction::Received, Layer::Stream, &buf[..len])?;
+        }
+
+        Ok(len)
+    }
+}
+
+impl<S: Write, W: Write> Write for CaptureStream<S, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.stream.write(buf)?;
+        if len > 0 {
+            self.writer
+                .record(Direction::Transmitted, Layer::Stream, &buf[..len])?;
+        }
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Current time, in microseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_micros() as u64)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
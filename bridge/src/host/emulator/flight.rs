@@ -0,0 +1,224 @@
+//! Simple simulated flight of a rocket with dual-deploy recovery.
+//!
+//! The rocket only moves vertically, apart from drifting with the wind under its parachutes. It
+//! points straight up until apogee, and hangs level under its parachutes afterwards.
+
+use crate::event::Detection;
+use crate::state::FlightState;
+use crate::telemetry::TelemetrySnapshot;
+
+/// Standard gravity, in meters per second squared.
+const GRAVITY: f64 = 9.80665;
+/// Step of the integration, in seconds.
+const STEP: f64 = 1e-3;
+/// Quaternion of the body pointing straight up: the forward axis along the up axis.
+const UP: [f64; 4] = [
+    core::f64::consts::FRAC_1_SQRT_2,
+    0.0,
+    -core::f64::consts::FRAC_1_SQRT_2,
+    0.0,
+];
+/// Quaternion of the body hanging level.
+const LEVEL: [f64; 4] = [1.0, 0.0, 0.0, 0.0];
+
+/// Parameters of the simulated flight.
+#[derive(Debug, Clone, Copy)]
+pub struct FlightConfig {
+    /// Acceleration given by the motor, in meters per second squared.
+    pub thrust: f64,
+    /// Burn time of the motor, in seconds.
+    pub burn_time: f64,
+    /// Drag coefficient of the rocket, per meter: the drag deceleration is this times the squared
+    /// speed.
+    pub drag: f64,
+    /// Descent rate under the drogue parachute, in meters per second.
+    pub drogue_rate: f64,
+    /// Descent rate under the main parachute, in meters per second.
+    pub main_rate: f64,
+    /// Altitude that the main parachute opens at, in meters.
+    pub main_altitude: f64,
+    /// Wind velocity, in meters per second along the east and north axes.
+    pub wind: [f64; 2],
+}
+
+impl Default for FlightConfig {
+    fn default() -> Self {
+        Self {
+            thrust: 60.0,
+            burn_time: 2.5,
+            drag: 1.5e-3,
+            drogue_rate: 20.0,
+            main_rate: 6.0,
+            main_altitude: 150.0,
+            wind: [3.0, 1.0],
+        }
+    }
+}
+
+/// Something that happened during a step of the flight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlightEvent {
+    /// The rocket moved to another state.
+    StateChanged {
+        from: FlightState,
+        to: FlightState,
+    },
+    Detected(Detection),
+    /// The parachute on the given pyro channel was deployed.
+    Deployed(u16),
+}
+
+/// Pyro channel of the drogue parachute.
+pub const DROGUE_CHANNEL: u16 = 0;
+/// Pyro channel of the main parachute.
+pub const MAIN_CHANNEL: u16 = 1;
+
+/// State of the simulated rocket.
+#[derive(Debug, Clone)]
+pub struct Flight {
+    config: FlightConfig,
+    state: FlightState,
+    /// Time of the last step, in microseconds.
+    time: u64,
+    /// Time of the launch, in seconds since the simulation started.
+    launched_at: Option<f64>,
+    position: [f64; 3],
+    velocity: [f64; 3],
+    acceleration: [f64; 3],
+}
+
+impl Flight {
+    /// Instantiate a new [`Flight`], with the rocket armed on the pad.
+    pub fn new(config: FlightConfig) -> Self {
+        Self {
+            config,
+            state: FlightState::Armed,
+            time: 0,
+            launched_at: None,
+            position: [0.0; 3],
+            velocity: [0.0; 3],
+            acceleration: [0.0; 3],
+        }
+    }
+
+    /// State of the rocket.
+    pub fn state(&self) -> FlightState {
+        self.state
+    }
+
+    /// Ignite the motor. Returns whether the rocket was armed on the pad.
+    pub fn launch(&mut self) -> bool {
+        if self.state != FlightState::Armed || self.launched_at.is_some() {
+            return false;
+        }
+
+        self.launched_at = Some(seconds(self.time));
+        true
+    }
+
+    /// Advance the flight to the given time, in microseconds, calling `on_event` with everything
+    /// that happened along the way.
+    pub fn advance(&mut self, now: u64, mut on_event: impl FnMut(u64, FlightEvent)) {
+        let step = (STEP * 1e6) as u64;
+        let mut events = Vec::new();
+        while self.time + step <= now {
+            self.time += step;
+            self.step(&mut events);
+            for event in events.drain(..) {
+                on_event(self.time, event);
+            }
+        }
+    }
+
+    /// Telemetry of the rocket.
+    pub fn snapshot(&self) -> TelemetrySnapshot {
+        TelemetrySnapshot {
+            time: self.time,
+            state: self.state.id(),
+            position: self.position,
+            orientation: match self.state {
+                FlightState::DrogueDescent | FlightState::MainDescent | FlightState::Landed => {
+                    LEVEL
+                }
+                _ => UP,
+            },
+            velocity: self.velocity,
+            acceleration: self.acceleration,
+        }
+    }
+
+    /// Advance the flight by a single step.
+    fn step(&mut self, events: &mut Vec<FlightEvent>) {
+        let [.., altitude] = self.position;
+        let speed = self.velocity[2];
+        match self.state {
+            FlightState::Armed if self.launched_at.is_some() => {
+                self.enter(FlightState::Boost, events);
+                events.push(FlightEvent::Detected(Detection::Launch));
+            }
+            FlightState::Boost => {
+                let launched_at = self.launched_at.unwrap_or_default();
+                if seconds(self.time) - launched_at >= self.config.burn_time {
+                    self.enter(FlightState::Coast, events);
+                    events.push(FlightEvent::Detected(Detection::Burnout));
+                }
+            }
+            FlightState::Coast if speed <= 0.0 => {
+                self.enter(FlightState::Apogee, events);
+                events.push(FlightEvent::Detected(Detection::Apogee));
+            }
+            FlightState::Apogee => {
+                events.push(FlightEvent::Deployed(DROGUE_CHANNEL));
+                self.enter(FlightState::DrogueDescent, events);
+            }
+            FlightState::DrogueDescent | FlightState::MainDescent if altitude <= 0.0 => {
+                self.enter(FlightState::Landed, events);
+                self.position[2] = 0.0;
+                self.velocity = [0.0; 3];
+                self.acceleration = [0.0; 3];
+                events.push(FlightEvent::Detected(Detection::Landing));
+            }
+            FlightState::DrogueDescent if altitude <= self.config.main_altitude => {
+                events.push(FlightEvent::Deployed(MAIN_CHANNEL));
+                self.enter(FlightState::MainDescent, events);
+            }
+            _ => {}
+        }
+
+        let drag = -self.config.drag * speed * speed.abs();
+        let acceleration = match self.state {
+            FlightState::Boost => self.config.thrust - GRAVITY + drag,
+            FlightState::Coast | FlightState::Apogee => -GRAVITY + drag,
+            FlightState::DrogueDescent | FlightState::MainDescent => {
+                // Parachutes are modelled as reaching their descent rate right away.
+                let rate = match self.state {
+                    FlightState::MainDescent => self.config.main_rate,
+                    _ => self.config.drogue_rate,
+                };
+                self.velocity = [self.config.wind[0], self.config.wind[1], -rate];
+                self.acceleration = [0.0; 3];
+                for (position, velocity) in self.position.iter_mut().zip(self.velocity) {
+                    *position += velocity * STEP;
+                }
+                return;
+            }
+            _ => return,
+        };
+
+        self.acceleration = [0.0, 0.0, acceleration];
+        self.velocity[2] += acceleration * STEP;
+        self.position[2] += self.velocity[2] * STEP;
+    }
+
+    fn enter(&mut self, state: FlightState, events: &mut Vec<FlightEvent>) {
+        events.push(FlightEvent::StateChanged {
+            from: self.state,
+            to: state,
+        });
+        self.state = state;
+    }
+}
+
+fn seconds(micros: u64) -> f64 {
+    micros as f64 / 1e6
+}
@@ -0,0 +1,385 @@
+//! Emulated vehicle speaking the bridge protocol, for developing ground tooling without hardware.
+//!
+//! The [`Emulator`] answers handshakes, heartbeats, telemetry requests and pyro commands, streams
+//! compressed telemetry of a simulated [`Flight`], and reliably reports the events of the flight.
+//! The rocket waits armed on the pad until it is launched, either after a delay or by changing its
+//! state to [`FlightState::Boost`].
+//!
+//! Like on the vehicle, the pyro channels start disarmed, are armed and disarmed by toggling them,
+//! and only fire by command when armed and after burnout. The parachutes of the simulated flight
+//! deploy by themselves whatever the channels. Requests are handled before they are acknowledged,
+//! and the copies of a request that was already handled get the same acknowledgement and response
+//! again without carrying out the command twice. Every handshake starts a session with a new random
+//! identifier.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use rand_core::{OsRng, RngCore};
+
+use crate::command::{Command, CommandError, CommandResponse};
+use crate::device::{Device, DeviceError};
+use crate::event::{EventKind, VehicleEvent};
+use crate::handshake;
+use crate::message::{Message, MessageFlags, MessageKind};
+use crate::reliable::{self, ReliableSender};
+use crate::rpc;
+use crate::state::FlightState;
+use crate::telemetry::codec::{Quantization, TelemetryEncoder};
+
+pub mod flight;
+
+use flight::{Flight, FlightConfig, FlightEvent};
+
+/// Number of pyro channels of the emulated vehicle.
+const PYRO_CHANNELS: u16 = 4;
+/// Number of handled requests remembered to recognize their copies.
+const HANDLED_REQUESTS: usize = 32;
+
+/// Configuration of an [`Emulator`].
+#[derive(Debug, Clone, Copy)]
+pub struct EmulatorConfig {
+    /// Time after which the rocket launches by itself, in microseconds, if it wasn't launched by
+    /// command before.
+    pub launch_after: Option<u64>,
+    /// Time between two telemetry messages, in microseconds.
+    pub telemetry_interval: u64,
+    /// Number of delta frames between two telemetry keyframes.
+    pub keyframe_interval: u16,
+    /// Time between two heartbeats, in microseconds.
+    pub heartbeat_interval: u64,
+    pub quantization: Quantization,
+    pub flight: FlightConfig,
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        Self {
+            launch_after: Some(10_000_000),
+            telemetry_interval: 100_000,
+            keyframe_interval: 10,
+            heartbeat_interval: 500_000,
+            quantization: Quantization::default(),
+            flight: FlightConfig::default(),
+        }
+    }
+}
+
+/// Emulated vehicle.
+#[derive(Debug)]
+pub struct Emulator {
+    config: EmulatorConfig,
+    start: Instant,
+    flight: Flight,
+    encoder: TelemetryEncoder,
+    events: ReliableSender<16>,
+    /// Whether each pyro channel is armed.
+    armed: [bool; PYRO_CHANNELS as usize],
+    /// Requests handled last, with the IDs of their acknowledgement and response, and the
+    /// response.
+    handled: VecDeque<Handled>,
+    /// ID of the next message.
+    next_id: u64,
+    /// Time that telemetry was last sent at.
+    last_telemetry: Option<u64>,
+    /// Time that a heartbeat was last sent at.
+    last_heartbeat: Option<u64>,
+}
+
+impl Emulator {
+    /// Instantiate a new [`Emulator`], with the rocket on the pad.
+    pub fn new(config: EmulatorConfig) -> Self {
+        Self {
+            start: Instant::now(),
+            flight: Flight::new(config.flight),
+            encoder: TelemetryEncoder::new(config.quantization, config.keyframe_interval),
+            events: ReliableSender::new(500_000, 5),
+            armed: [false; PYRO_CHANNELS as usize],
+            handled: VecDeque::with_capacity(HANDLED_REQUESTS),
+            next_id: 0,
+            last_telemetry: None,
+            last_heartbeat: None,
+            config,
+        }
+    }
+
+    /// Run the emulator over the device until it fails, and return why it did.
+    pub fn run<D: Device>(&mut self, device: &D) -> DeviceError {
+        loop {
+            if let Err(err) = self.step(device) {
+                return err;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Handle everything that was received since the last step, advance the flight, and send
+    /// whatever is due.
+    pub fn step<D: Device>(&mut self, device: &D) -> Result<(), DeviceError> {
+        loop {
+            match device.poll() {
+                Ok(Some(message)) => self.handle(device, message)?,
+                Ok(None) => break,
+                // Corrupted messages are dropped, like on the vehicle.
+                Err(DeviceError::InvalidMessage) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let now = self.now();
+        if let Some(launch_after) = self.config.launch_after {
+            if now >= launch_after {
+                self.flight.launch();
+            }
+        }
+
+        let mut happened = Vec::new();
+        self.flight
+            .advance(now, |time, event| happened.push((time, event)));
+        for (time, event) in happened {
+            self.flight_event(device, time, event)?;
+        }
+
+        match self.events.update(device, now) {
+            // Events the ground never acknowledged are given up on.
+            Ok(()) | Err(DeviceError::NotAcknowledged) => {}
+            Err(err) => return Err(err),
+        }
+
+        if due(
+            &mut self.last_telemetry,
+            now,
+            self.config.telemetry_interval,
+        ) {
+            let payload = self
+                .encoder
+                .encode_payload(&self.flight.snapshot())
+                .map_err(|_| DeviceError::InvalidMessage)?;
+            let id = self.next_id();
+            device.transmit(Message::new(
+                id,
+                MessageKind::Telemetry(payload),
+                now,
+                MessageFlags::COMPRESSED,
+            ))?;
+        }
+        if due(
+            &mut self.last_heartbeat,
+            now,
+            self.config.heartbeat_interval,
+        ) {
+            let id = self.next_id();
+            device.transmit(Message::new(
+                id,
+                MessageKind::Request(Command::Heartbeat),
+                now,
+                MessageFlags::empty(),
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    /// Start over with a new ground station, which needs a telemetry keyframe to decode the
+    /// stream.
+    pub fn reconnect(&mut self) {
+        self.encoder.force_keyframe();
+    }
+
+    /// Time since the emulator started, in microseconds.
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Handle a message from the ground, and acknowledge it once handled.
+    fn handle<D: Device>(&mut self, device: &D, message: Message) -> Result<(), DeviceError> {
+        self.events.receive(&message);
+
+        match message.kind() {
+            MessageKind::Handshake(..) => {
+                let id = self.next_id();
+                let vehicle = OsRng.next_u64();
+                match handshake::answer(device, &message, vehicle, id, self.now()) {
+                    // Grounds that share no encoding with the emulator get no answer.
+                    Ok(_) | Err(DeviceError::InvalidMessage) => {}
+                    Err(err) => return Err(err),
+                }
+                self.acknowledge(device, &message)?;
+            }
+            MessageKind::Request(command) => {
+                if let Some(handled) = self.handled.iter().find(|h| h.request == message.id()) {
+                    return handled.answer(device, &message);
+                }
+
+                let response = self.command(device, command)?;
+                let handled = Handled {
+                    request: message.id(),
+                    ack: self.next_id(),
+                    response: (self.next_id(), response),
+                    time: self.now(),
+                };
+                handled.answer(device, &message)?;
+                if self.handled.len() == HANDLED_REQUESTS {
+                    self.handled.pop_front();
+                }
+                self.handled.push_back(handled);
+            }
+            _ => self.acknowledge(device, &message)?,
+        }
+
+        Ok(())
+    }
+
+    fn acknowledge<D: Device>(&mut self, device: &D, message: &Message) -> Result<(), DeviceError> {
+        let id = self.next_id();
+        if let Some(ack) = reliable::acknowledgement(message, id, self.now()) {
+            device.transmit(ack)?;
+        }
+
+        Ok(())
+    }
+
+    /// Carry out a command.
+    fn command<D: Device>(
+        &mut self,
+        device: &D,
+        command: &Command,
+    ) -> Result<CommandResponse, DeviceError> {
+        let response = match *command {
+            Command::Heartbeat => CommandResponse::Done,
+            Command::Telemetry => CommandResponse::Telemetry(self.flight.snapshot()),
+            Command::FirePyro(channel) | Command::TogglePyro(channel)
+                if channel >= PYRO_CHANNELS =>
+            {
+                CommandResponse::Failed(CommandError::InvalidArgument)
+            }
+            Command::FirePyro(channel)
+                if !self.armed[channel as usize] || !unlocked(self.flight.state()) =>
+            {
+                CommandResponse::Failed(CommandError::InvalidState)
+            }
+            Command::FirePyro(channel) => {
+                self.report(device, EventKind::PyroFired(channel))?;
+                CommandResponse::Done
+            }
+            Command::TogglePyro(channel) => {
+                let armed = &mut self.armed[channel as usize];
+                *armed = !*armed;
+                CommandResponse::Done
+            }
+            Command::ChangeState(id) if id == FlightState::Boost.id() && self.flight.launch() => {
+                CommandResponse::Done
+            }
+            Command::ChangeState(_) => CommandResponse::Failed(CommandError::InvalidState),
+            _ => CommandResponse::Failed(CommandError::Unsupported),
+        };
+
+        Ok(response)
+    }
+
+    /// Report what happened during the flight.
+    fn flight_event<D: Device>(
+        &mut self,
+        device: &D,
+        time: u64,
+        event: FlightEvent,
+    ) -> Result<(), DeviceError> {
+        match event {
+            FlightEvent::StateChanged { from, to } => self.report_at(
+                device,
+                time,
+                EventKind::StateChanged {
+                    from: from.id(),
+                    to: to.id(),
+                },
+            ),
+            FlightEvent::Detected(detection) => {
+                self.report_at(device, time, EventKind::Detected(detection))
+            }
+            FlightEvent::Deployed(channel) => {
+                self.report_at(device, time, EventKind::PyroFired(channel))
+            }
+        }
+    }
+
+    fn report<D: Device>(&mut self, device: &D, kind: EventKind) -> Result<(), DeviceError> {
+        let now = self.now();
+        self.report_at(device, now, kind)
+    }
+
+    /// Reliably send an event to the ground.
+    fn report_at<D: Device>(
+        &mut self,
+        device: &D,
+        time: u64,
+        kind: EventKind,
+    ) -> Result<(), DeviceError> {
+        let id = self.next_id();
+        let message = Message::new(
+            id,
+            MessageKind::Event(VehicleEvent::new(time, kind)),
+            self.now(),
+            MessageFlags::REQUIRES_ACK,
+        );
+        match self.events.send(device, message, self.now()) {
+            // Events are dropped rather than stopping the emulator when nobody acknowledges them.
+            Ok(()) | Err(DeviceError::QueueFull) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Request that was handled, and what it was answered with.
+#[derive(Debug, Clone)]
+struct Handled {
+    /// ID of the request.
+    request: u64,
+    /// ID of the acknowledgement.
+    ack: u64,
+    /// ID of the response, and the response.
+    response: (u64, CommandResponse),
+    /// Time the request was handled at.
+    time: u64,
+}
+
+impl Handled {
+    /// Send the response to the request, then acknowledge it if it needs to.
+    fn answer<D: Device>(&self, device: &D, request: &Message) -> Result<(), DeviceError> {
+        let (id, response) = &self.response;
+        device.transmit(rpc::response(request, *id, response.clone(), self.time))?;
+        if let Some(ack) = reliable::acknowledgement(request, self.ack, self.time) {
+            device.transmit(ack)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns whether the pyros may fire by command in the given state: once the motor burnt out, and
+/// before landing.
+fn unlocked(state: FlightState) -> bool {
+    matches!(
+        state,
+        FlightState::Coast
+            | FlightState::Apogee
+            | FlightState::DrogueDescent
+            | FlightState::MainDescent
+    )
+}
+
+/// Returns whether something sent every `interval` is due, and if so, records that it is sent.
+fn due(last: &mut Option<u64>, now: u64, interval: u64) -> bool {
+    match *last {
+        Some(last) if now.saturating_sub(last) < interval => false,
+        _ => {
+            *last = Some(now);
+            true
+        }
+    }
+}
@@ -0,0 +1,560 @@
+//! Gateway between the bridge and MAVLink ground software, such as QGroundControl.
+//!
+//! Telemetry of the vehicle is forwarded as `ATTITUDE` and `GLOBAL_POSITION_INT`, events as
+//! `STATUSTEXT`, and the state of the link as `HEARTBEAT`. In the other direction, a small set of
+//! `COMMAND_LONG` commands are translated into [`Command`]s:
+//!
+//! * `MAV_CMD_DO_SET_MODE` changes the state to the custom mode (`param2`).
+//! * `MAV_CMD_DO_SET_RELAY` fires (`param2 = 1`) or toggles (`param2 = 2`) the pyro channel given
+//!   by `param1`.
+//! * `MAV_CMD_PREFLIGHT_CALIBRATION` with the ground pressure calibration (`param3 = 1`) rezeroes
+//!   the altitude.
+//! * `MAV_CMD_REQUEST_MESSAGE` requests telemetry.
+//!
+//! Commands are sent reliably, and answered with `COMMAND_ACK` once the vehicle responded: failures
+//! are reported as `DENIED`, or `UNSUPPORTED`, or `FAILED` when the vehicle doesn't respond in time.
+//!
+//! The gateway starts a session with a [`handshake`], asking for [`GatewayConfig::encoding`], and
+//! starts over whenever the link is lost.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use rand_core::{OsRng, RngCore};
+
+use super::*;
+use crate::command::{Command, CommandError, CommandResponse};
+use crate::device::{Device, DeviceError};
+use crate::event::{EventKind, Severity, VehicleEvent};
+use crate::handshake;
+use crate::heartbeat::{HeartbeatMonitor, LinkEvent, LinkState};
+use crate::message::{Encoding, Message, MessageFlags, MessageKind};
+use crate::reliable::{self, ReliableSender};
+use crate::telemetry::codec::{Quantization, TelemetryDecoder};
+use crate::telemetry::TelemetrySnapshot;
+
+/// Mean radius of the earth, in meters.
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+/// Geodetic position of the launch site, which telemetry positions are relative to.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Origin {
+    /// Latitude, in degrees.
+    pub latitude: f64,
+    /// Longitude, in degrees.
+    pub longitude: f64,
+    /// Altitude above mean sea level, in meters.
+    pub altitude: f64,
+}
+
+/// Configuration of the gateway.
+#[derive(Debug, Clone)]
+pub struct GatewayConfig {
+    /// Address to receive MAVLink on.
+    pub mavlink_addr: SocketAddr,
+    /// Address of the ground software to send MAVLink to.
+    pub gcs_addr: SocketAddr,
+    /// MAVLink system ID of the vehicle.
+    pub system_id: u8,
+    /// MAVLink component ID of the vehicle.
+    pub component_id: u8,
+    /// Position of the launch site.
+    pub origin: Origin,
+    /// Quantization of compressed telemetry sent by the vehicle.
+    pub quantization: Quantization,
+    /// Time between two heartbeats, in microseconds.
+    pub heartbeat_interval: u64,
+    /// Time without hearing from the vehicle after which the link is lost, in microseconds.
+    pub timeout: u64,
+    /// Time to wait for the vehicle to respond to a command before it is reported as failed, in
+    /// microseconds.
+    pub command_timeout: u64,
+    /// Time to wait for the vehicle to acknowledge a command before retransmitting it, in
+    /// microseconds.
+    pub retry_interval: u64,
+    /// Number of transmissions of a command before it is given up on.
+    pub max_attempts: u8,
+    /// Encoding to ask the vehicle for in the handshake, or `None` for the most compact one.
+    pub encoding: Option<Encoding>,
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            mavlink_addr: SocketAddr::from(([0, 0, 0, 0], 14551)),
+            gcs_addr: SocketAddr::from(([127, 0, 0, 1], 14550)),
+            system_id: 1,
+            component_id: 1,
+            origin: Origin::default(),
+            quantization: Quantization::default(),
+            heartbeat_interval: 1_000_000,
+            timeout: 3_000_000,
+            command_timeout: 5_000_000,
+            retry_interval: 500_000,
+            max_attempts: 5,
+            encoding: None,
+        }
+    }
+}
+
+/// MAVLink command waiting for the vehicle to respond.
+#[derive(Debug, Clone, Copy)]
+struct PendingCommand {
+    command: u16,
+    /// Time that the command was sent at.
+    sent_at: u64,
+}
+
+/// Translates between a bridge device and MAVLink over UDP.
+pub struct Gateway<D: Device> {
+    device: D,
+    socket: UdpSocket,
+    config: GatewayConfig,
+    start: Instant,
+    /// Sequence number of the next MAVLink frame.
+    sequence: u8,
+    /// ID of the next message sent to the vehicle.
+    next_id: u64,
+    decoder: TelemetryDecoder,
+    monitor: HeartbeatMonitor,
+    /// State of the vehicle, reported as the custom mode.
+    state: u32,
+    /// MAVLink commands waiting for the vehicle to respond, by message ID.
+    pending: HashMap<u64, PendingCommand>,
+    /// Retransmits commands until the vehicle acknowledges them.
+    sender: ReliableSender<16>,
+    /// Identifier of the gateway in the handshake, drawn at random.
+    id: u64,
+    /// Identifier of the vehicle and encoding agreed on in the handshake, once it was answered.
+    session: Option<(u64, Encoding)>,
+}
+
+impl<D: Device> Gateway<D> {
+    /// Instantiate a new [`Gateway`], binding to the MAVLink address.
+    pub fn new(device: D, config: GatewayConfig) -> io::Result<Self> {
+        let socket = UdpSocket::bind(config.mavlink_addr)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            device,
+            socket,
+            start: Instant::now(),
+            sequence: 0,
+            next_id: 0,
+            decoder: TelemetryDecoder::new(config.quantization),
+            monitor: HeartbeatMonitor::new(config.heartbeat_interval, config.timeout),
+            state: 0,
+            pending: HashMap::new(),
+            sender: ReliableSender::new(config.retry_interval, config.max_attempts),
+            id: OsRng.next_u64(),
+            session: None,
+            config,
+        })
+    }
+
+    /// Run the gateway forever.
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            self.step()?;
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Handle everything that was received since the last step, and send heartbeats if due.
+    pub fn step(&mut self) -> io::Result<()> {
+        loop {
+            match self.device.poll() {
+                Ok(Some(message)) => self.handle_vehicle(message)?,
+                Ok(None) => break,
+                Err(DeviceError::InvalidMessage) => log::warn!("dropped invalid message"),
+                Err(err) => return Err(device_error(err)),
+            }
+        }
+
+        let mut buf = [0; MAX_FRAME_LEN];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, _)) => self.handle_datagram(&buf[..len])?,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        let now = self.now();
+        match self.sender.update(&self.device, now) {
+            // Commands that were given up on are reported once they time out.
+            Ok(()) | Err(DeviceError::NotAcknowledged) => {}
+            Err(err) => log::warn!("failed to retransmit command: {}", err),
+        }
+        let timeout = self.config.command_timeout;
+        let expired: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| now.saturating_sub(pending.sent_at) > timeout)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in expired {
+            if let Some(pending) = self.pending.remove(&id) {
+                self.sender.acknowledge(id);
+                self.send_ack(pending.command, MavResult::Failed)?;
+            }
+        }
+
+        if let Some(LinkEvent::Lost { .. }) = self.monitor.update(now) {
+            // The vehicle may have restarted, so the session starts over.
+            self.session = None;
+            self.device
+                .set_encoding(Encoding::Postcard)
+                .map_err(device_error)?;
+            let text = StatusText::new(MavSeverity::Critical, "link to vehicle lost");
+            self.send_mavlink(MavMessage::StatusText(text))?;
+        }
+        if self.monitor.heartbeat_due(now) {
+            if self.session.is_none() {
+                let id = self.next_id();
+                let request =
+                    handshake::request(&self.device, self.id, self.config.encoding, id, now);
+                self.device.transmit(request).map_err(device_error)?;
+            }
+            self.send_vehicle(Command::Heartbeat, MessageFlags::empty())?;
+            self.send_heartbeat()?;
+        }
+
+        Ok(())
+    }
+
+    /// Time since the gateway started, in microseconds.
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    /// Identifier of the vehicle and encoding agreed on in the handshake, if it was answered.
+    pub fn session(&self) -> Option<(u64, Encoding)> {
+        self.session
+    }
+
+    /// Handle a message from the vehicle.
+    fn handle_vehicle(&mut self, message: Message) -> io::Result<()> {
+        let now = self.now();
+        if let Some(LinkEvent::Restored { .. }) = self.monitor.heard(now) {
+            let text = StatusText::new(MavSeverity::Info, "link to vehicle restored");
+            self.send_mavlink(MavMessage::StatusText(text))?;
+        }
+        if let Some(ack) = reliable::acknowledgement(&message, self.next_id(), now) {
+            self.device.transmit(ack).map_err(device_error)?;
+        }
+        self.sender.receive(&message);
+
+        if let Some(session) = handshake::accept(&self.device, &message, self.id) {
+            self.session = Some(session);
+        }
+
+        let compressed = message.flags().contains(MessageFlags::COMPRESSED);
+        match message.into_kind() {
+            MessageKind::Telemetry(payload) => {
+                let snapshot = if compressed {
+                    self.decoder.decode(&payload).ok()
+                } else {
+                    postcard::from_bytes(&payload).ok()
+                };
+                match snapshot {
+                    Some(snapshot) => self.send_telemetry(&snapshot)?,
+                    None => log::warn!("dropped undecodable telemetry"),
+                }
+            }
+            MessageKind::Response(id, response) => {
+                // A response also proves that the request arrived, should its acknowledgement
+                // have been lost.
+                self.sender.acknowledge(id);
+                if let CommandResponse::Telemetry(snapshot) = &response {
+                    self.send_telemetry(snapshot)?;
+                }
+                if let Some(pending) = self.pending.remove(&id) {
+                    self.send_ack(pending.command, result(&response))?;
+                }
+            }
+            MessageKind::Event(event) => self.send_event(&event)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Handle a datagram from the ground software, which may hold several frames.
+    fn handle_datagram(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !bytes.is_empty() {
+            match MavFrame::decode(bytes) {
+                Ok((frame, len)) => {
+                    bytes = &bytes[len..];
+                    if let MavMessage::CommandLong(command) = frame.message {
+                        self.handle_command(&command)?;
+                    }
+                }
+                Err(MavError::Truncated) => break,
+                Err(MavError::Unsupported(len))
+                | Err(MavError::BadChecksum(len))
+                | Err(MavError::UnknownMessage(_, len)) => bytes = &bytes[len..],
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Translate a MAVLink command into a bridge command.
+    fn handle_command(&mut self, command: &CommandLong) -> io::Result<()> {
+        if command.target_system != self.config.system_id {
+            return Ok(());
+        }
+
+        let translated = match command.command {
+            MAV_CMD_DO_SET_MODE => Some(Command::ChangeState(command.params[1] as u32)),
+            MAV_CMD_DO_SET_RELAY => match command.params[1] as u32 {
+                1 => Some(Command::FirePyro(command.params[0] as u16)),
+                2 => Some(Command::TogglePyro(command.params[0] as u16)),
+                _ => None,
+            },
+            MAV_CMD_PREFLIGHT_CALIBRATION if command.params[2] as u32 == 1 => Some(Command::Rezero),
+            MAV_CMD_REQUEST_MESSAGE => Some(Command::Telemetry),
+            _ => None,
+        };
+
+        let translated = match translated {
+            Some(translated) => translated,
+            None => return self.send_ack(command.command, MavResult::Unsupported),
+        };
+
+        let now = self.now();
+        let id = self.next_id();
+        let message = Message::new(
+            id,
+            MessageKind::Request(translated),
+            now,
+            MessageFlags::REQUIRES_ACK,
+        );
+        match self.sender.send(&self.device, message, now) {
+            Ok(()) => {
+                self.pending.insert(
+                    id,
+                    PendingCommand {
+                        command: command.command,
+                        sent_at: now,
+                    },
+                );
+                Ok(())
+            }
+            Err(DeviceError::QueueFull) => {
+                self.send_ack(command.command, MavResult::TemporarilyRejected)
+            }
+            Err(err) => Err(device_error(err)),
+        }
+    }
+
+    /// Send telemetry as `ATTITUDE` and `GLOBAL_POSITION_INT`.
+    fn send_telemetry(&mut self, snapshot: &TelemetrySnapshot) -> io::Result<()> {
+        self.state = snapshot.state;
+        let time_boot_ms = (snapshot.time / 1000) as u32;
+
+        let (roll, pitch, yaw) = ned_euler(snapshot.orientation);
+        self.send_mavlink(MavMessage::Attitude(Attitude {
+            time_boot_ms,
+            roll: roll as f32,
+            pitch: pitch as f32,
+            yaw: yaw as f32,
+            rollspeed: 0.0,
+            pitchspeed: 0.0,
+            yawspeed: 0.0,
+        }))?;
+
+        let origin = self.config.origin;
+        let [east, north, up] = snapshot.position;
+        let latitude = origin.latitude + (north / EARTH_RADIUS).to_degrees();
+        let longitude = origin.longitude
+            + (east / (EARTH_RADIUS * origin.latitude.to_radians().cos())).to_degrees();
+        let [ve, vn, vu] = snapshot.velocity;
+        self.send_mavlink(MavMessage::GlobalPositionInt(GlobalPositionInt {
+            time_boot_ms,
+            lat: (latitude * 1e7) as i32,
+            lon: (longitude * 1e7) as i32,
+            alt: ((origin.altitude + up) * 1e3) as i32,
+            relative_alt: (up * 1e3) as i32,
+            vx: (vn * 1e2) as i16,
+            vy: (ve * 1e2) as i16,
+            vz: (-vu * 1e2) as i16,
+            hdg: u16::MAX,
+        }))
+    }
+
+    /// Send an event as `STATUSTEXT`.
+    fn send_event(&mut self, event: &VehicleEvent) -> io::Result<()> {
+        let severity = match event.severity {
+            Severity::Debug => MavSeverity::Debug,
+            Severity::Info => MavSeverity::Info,
+            Severity::Warning => MavSeverity::Warning,
+            Severity::Critical => MavSeverity::Critical,
+        };
+
+        let mut text = String::new();
+        let _ = match event.kind {
+            EventKind::StateChanged { from, to } => {
+                self.state = to;
+                write!(text, "state {} -> {}", from, to)
+            }
+            EventKind::Detected(detection) => write!(text, "{:?} detected", detection),
+            EventKind::PyroFired(channel) => write!(text, "pyro {} fired", channel),
+            EventKind::SensorFault(sensor) => write!(text, "{:?} fault", sensor),
+            EventKind::LowBattery(millivolts) => write!(text, "low battery: {} mV", millivolts),
+            EventKind::LinkLost(_) => write!(text, "vehicle lost link"),
+            EventKind::LinkRestored(_) => write!(text, "vehicle restored link"),
+        };
+
+        self.send_mavlink(MavMessage::StatusText(StatusText::new(severity, &text)))
+    }
+
+    /// Send a `HEARTBEAT` reporting the state of the vehicle and the link.
+    fn send_heartbeat(&mut self) -> io::Result<()> {
+        let system_status = match self.monitor.state() {
+            LinkState::Waiting => MavState::Standby,
+            LinkState::Connected => MavState::Active,
+            LinkState::Lost => MavState::Critical,
+        };
+
+        self.send_mavlink(MavMessage::Heartbeat(Heartbeat {
+            custom_mode: self.state,
+            mav_type: MAV_TYPE_ROCKET,
+            autopilot: MAV_AUTOPILOT_GENERIC,
+            base_mode: MAV_MODE_FLAG_CUSTOM_MODE_ENABLED,
+            system_status: system_status as u8,
+            mavlink_version: 3,
+        }))
+    }
+
+    /// Send a `COMMAND_ACK`.
+    fn send_ack(&mut self, command: u16, result: MavResult) -> io::Result<()> {
+        self.send_mavlink(MavMessage::CommandAck(CommandAck {
+            command,
+            result: result as u8,
+        }))
+    }
+
+    /// Send a message to the ground software.
+    fn send_mavlink(&mut self, message: MavMessage) -> io::Result<()> {
+        let frame = MavFrame {
+            sequence: self.sequence,
+            system_id: self.config.system_id,
+            component_id: self.config.component_id,
+            message,
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = frame.encode(&mut buf);
+        self.socket.send_to(&buf[..len], self.config.gcs_addr)?;
+        Ok(())
+    }
+
+    /// Send a command to the vehicle, returning the ID of its message.
+    fn send_vehicle(&mut self, command: Command, flags: MessageFlags) -> io::Result<u64> {
+        let id = self.next_id();
+        let message = Message::new(id, MessageKind::Request(command), self.now(), flags);
+        self.device.transmit(message).map_err(device_error)?;
+        Ok(id)
+    }
+
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+}
+
+/// Convert an orientation of the forward-left-up body frame in the east-north-up frame to roll,
+/// pitch and yaw of the forward-right-down body frame in the north-east-down frame, as used by
+/// MAVLink.
+fn ned_euler(orientation: [f64; 4]) -> (f64, f64, f64) {
+    // Rotation from east-north-up to north-east-down is a half turn about the north-east diagonal,
+    // and from forward-left-up to forward-right-down is a half turn about the forward axis.
+    let half = core::f64::consts::FRAC_1_SQRT_2;
+    let q = multiply([0.0, half, half, 0.0], orientation);
+    let [w, x, y, z] = multiply(q, [0.0, 1.0, 0.0, 0.0]);
+
+    let roll = (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y));
+    let pitch = (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin();
+    let yaw = (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z));
+    (roll, pitch, yaw)
+}
+
+/// Hamilton product of two quaternions.
+fn multiply([aw, ax, ay, az]: [f64; 4], [bw, bx, by, bz]: [f64; 4]) -> [f64; 4] {
+    [
+        aw * bw - ax * bx - ay * by - az * bz,
+        aw * bx + ax * bw + ay * bz - az * by,
+        aw * by - ax * bz + ay * bw + az * bx,
+        aw * bz + ax * by - ay * bx + az * bw,
+    ]
+}
+
+/// Result of a command reported to the ground software for the response of the vehicle.
+fn result(response: &CommandResponse) -> MavResult {
+    match response {
+        CommandResponse::Failed(CommandError::Unsupported) => MavResult::Unsupported,
+        CommandResponse::Failed(CommandError::InvalidState | CommandError::InvalidArgument) => {
+            MavResult::Denied
+        }
+        CommandResponse::Failed(CommandError::Hardware) => MavResult::Failed,
+        _ => MavResult::Accepted,
+    }
+}
+
+fn device_error(err: DeviceError) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_2, FRAC_PI_6, PI};
+
+    use super::*;
+
+    /// Rotation by the angle about the axis, as a quaternion.
+    fn rotation(angle: f64, [x, y, z]: [f64; 3]) -> [f64; 4] {
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        [cos, sin * x, sin * y, sin * z]
+    }
+
+    fn assert_angles((roll, pitch, yaw): (f64, f64, f64), expected: (f64, f64, f64)) {
+        assert!((roll - expected.0).abs() < 1e-9, "roll {}", roll);
+        assert!((pitch - expected.1).abs() < 1e-9, "pitch {}", pitch);
+        assert!((yaw - expected.2).abs() < 1e-9, "yaw {}", yaw);
+    }
+
+    #[test]
+    fn converts_level_headings() {
+        // Forward along east, left along north.
+        assert_angles(ned_euler([1.0, 0.0, 0.0, 0.0]), (0.0, 0.0, FRAC_PI_2));
+        let north = rotation(FRAC_PI_2, [0.0, 0.0, 1.0]);
+        assert_angles(ned_euler(north), (0.0, 0.0, 0.0));
+        let west = rotation(PI, [0.0, 0.0, 1.0]);
+        assert_angles(ned_euler(west), (0.0, 0.0, -FRAC_PI_2));
+    }
+
+    #[test]
+    fn converts_roll_and_pitch() {
+        let north = rotation(FRAC_PI_2, [0.0, 0.0, 1.0]);
+        // Rolling about the forward axis lifts the left side, so the right side goes down.
+        let rolled = multiply(north, rotation(FRAC_PI_6, [1.0, 0.0, 0.0]));
+        assert_angles(ned_euler(rolled), (FRAC_PI_6, 0.0, 0.0));
+        // Turning down about the left axis raises the nose.
+        let pitched = multiply(north, rotation(-FRAC_PI_6, [0.0, 1.0, 0.0]));
+        assert_angles(ned_euler(pitched), (0.0, FRAC_PI_6, 0.0));
+    }
+
+    #[test]
+    fn converts_upright() {
+        let up = [FRAC_1_SQRT_2, 0.0, -FRAC_1_SQRT_2, 0.0];
+        let (_, pitch, _) = ned_euler(up);
+        assert!((pitch - FRAC_PI_2).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,530 @@
+//! Minimal MAVLink 2 codec, covering only the messages used by the [`gateway`].
+//!
+//! Frames are parsed in both MAVLink 1 and 2 formats, since some ground software still sends
+//! MAVLink 1, but are always written in MAVLink 2.
+//! See https://mavlink.io/en/guide/serialization.html
+
+use core::fmt;
+
+pub mod gateway;
+
+/// Start-of-frame marker of MAVLink 1.
+const STX_V1: u8 = 0xfe;
+/// Start-of-frame marker of MAVLink 2.
+const STX_V2: u8 = 0xfd;
+/// Length of a MAVLink 2 header, including the start-of-frame marker.
+const HEADER_LEN_V2: usize = 10;
+/// Length of a MAVLink 1 header, including the start-of-frame marker.
+const HEADER_LEN_V1: usize = 6;
+/// Incompatibility flag of MAVLink 2 frames that carry a signature.
+const INCOMPAT_FLAG_SIGNED: u8 = 0x01;
+/// Length of the signature of signed frames.
+const SIGNATURE_LEN: usize = 13;
+/// Largest payload of a message.
+const MAX_PAYLOAD_LEN: usize = 255;
+/// Largest size of an encoded frame.
+pub const MAX_FRAME_LEN: usize = HEADER_LEN_V2 + MAX_PAYLOAD_LEN + 2;
+
+/// Type of the vehicle (`MAV_TYPE_ROCKET`).
+pub const MAV_TYPE_ROCKET: u8 = 9;
+/// Type of the ground station (`MAV_TYPE_GCS`).
+pub const MAV_TYPE_GCS: u8 = 6;
+/// Generic autopilot (`MAV_AUTOPILOT_GENERIC`).
+pub const MAV_AUTOPILOT_GENERIC: u8 = 0;
+/// The custom mode field is used (`MAV_MODE_FLAG_CUSTOM_MODE_ENABLED`).
+pub const MAV_MODE_FLAG_CUSTOM_MODE_ENABLED: u8 = 1;
+
+/// Command to change the mode (`MAV_CMD_DO_SET_MODE`).
+pub const MAV_CMD_DO_SET_MODE: u16 = 176;
+/// Command to set a relay (`MAV_CMD_DO_SET_RELAY`).
+pub const MAV_CMD_DO_SET_RELAY: u16 = 181;
+/// Command to calibrate the sensors before flight (`MAV_CMD_PREFLIGHT_CALIBRATION`).
+pub const MAV_CMD_PREFLIGHT_CALIBRATION: u16 = 241;
+/// Command to request a single message (`MAV_CMD_REQUEST_MESSAGE`).
+pub const MAV_CMD_REQUEST_MESSAGE: u16 = 512;
+
+/// State of the system (`MAV_STATE`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MavState {
+    Uninit = 0,
+    Boot = 1,
+    Calibrating = 2,
+    Standby = 3,
+    Active = 4,
+    Critical = 5,
+    Emergency = 6,
+}
+
+/// Severity of a status text (`MAV_SEVERITY`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MavSeverity {
+    Emergency = 0,
+    Alert = 1,
+    Critical = 2,
+    Error = 3,
+    Warning = 4,
+    Notice = 5,
+    Info = 6,
+    Debug = 7,
+}
+
+/// Result of a command (`MAV_RESULT`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MavResult {
+    Accepted = 0,
+    TemporarilyRejected = 1,
+    Denied = 2,
+    Unsupported = 3,
+    Failed = 4,
+}
+
+/// `HEARTBEAT` (#0).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Heartbeat {
+    pub custom_mode: u32,
+    pub mav_type: u8,
+    pub autopilot: u8,
+    pub base_mode: u8,
+    pub system_status: u8,
+    pub mavlink_version: u8,
+}
+
+/// `ATTITUDE` (#30). Angles are in radians, and rates in radians per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attitude {
+    pub time_boot_ms: u32,
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+    pub rollspeed: f32,
+    pub pitchspeed: f32,
+    pub yawspeed: f32,
+}
+
+/// `GLOBAL_POSITION_INT` (#33). Latitude and longitude are in 1e-7 degrees, altitudes in
+/// millimeters, velocities in centimeters per second (north-east-down) and the heading in
+/// centidegrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlobalPositionInt {
+    pub time_boot_ms: u32,
+    pub lat: i32,
+    pub lon: i32,
+    pub alt: i32,
+    pub relative_alt: i32,
+    pub vx: i16,
+    pub vy: i16,
+    pub vz: i16,
+    pub hdg: u16,
+}
+
+/// `COMMAND_LONG` (#76).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandLong {
+    pub params: [f32; 7],
+    pub command: u16,
+    pub target_system: u8,
+    pub target_component: u8,
+    pub confirmation: u8,
+}
+
+/// `COMMAND_ACK` (#77).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandAck {
+    pub command: u16,
+    pub result: u8,
+}
+
+/// `STATUSTEXT` (#253). The text is NUL-padded and isn't NUL-terminated when it fills the whole
+/// array.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatusText {
+    pub severity: u8,
+    pub text: [u8; 50],
+}
+
+impl StatusText {
+    /// Instantiate a new [`StatusText`], truncating the text if it is too long.
+    pub fn new(severity: MavSeverity, text: &str) -> Self {
+        let mut buf = [0; 50];
+        let len = text.len().min(buf.len());
+        buf[..len].copy_from_slice(&text.as_bytes()[..len]);
+        Self {
+            severity: severity as u8,
+            text: buf,
+        }
+    }
+}
+
+/// Any of the supported MAVLink messages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MavMessage {
+    Heartbeat(Heartbeat),
+    Attitude(Attitude),
+    GlobalPositionInt(GlobalPositionInt),
+    CommandLong(CommandLong),
+    CommandAck(CommandAck),
+    StatusText(StatusText),
+}
+
+impl MavMessage {
+    /// ID of the message, and the extra byte mixed into its checksum.
+    fn id(&self) -> (u32, u8) {
+        match self {
+            MavMessage::Heartbeat(_) => (0, 50),
+            MavMessage::Attitude(_) => (30, 39),
+            MavMessage::GlobalPositionInt(_) => (33, 104),
+            MavMessage::CommandLong(_) => (76, 152),
+            MavMessage::CommandAck(_) => (77, 143),
+            MavMessage::StatusText(_) => (253, 83),
+        }
+    }
+
+    /// Extra checksum byte and wire length of the message with the given ID, if supported.
+    fn info(id: u32) -> Option<(u8, usize)> {
+        match id {
+            0 => Some((50, 9)),
+            30 => Some((39, 28)),
+            33 => Some((104, 28)),
+            76 => Some((152, 33)),
+            77 => Some((143, 3)),
+            253 => Some((83, 51)),
+            _ => None,
+        }
+    }
+
+    /// Write the payload, with fields ordered by decreasing size as required by MAVLink.
+    fn write_payload(&self, w: &mut Writer) {
+        match self {
+            MavMessage::Heartbeat(m) => {
+                w.u32(m.custom_mode);
+                w.u8(m.mav_type);
+                w.u8(m.autopilot);
+                w.u8(m.base_mode);
+                w.u8(m.system_status);
+                w.u8(m.mavlink_version);
+            }
+            MavMessage::Attitude(m) => {
+                w.u32(m.time_boot_ms);
+                for value in [
+                    m.roll,
+                    m.pitch,
+                    m.yaw,
+                    m.rollspeed,
+                    m.pitchspeed,
+                    m.yawspeed,
+                ] {
+                    w.f32(value);
+                }
+            }
+            MavMessage::GlobalPositionInt(m) => {
+                w.u32(m.time_boot_ms);
+                for value in [m.lat, m.lon, m.alt, m.relative_alt] {
+                    w.u32(value as u32);
+                }
+                for value in [m.vx, m.vy, m.vz] {
+                    w.u16(value as u16);
+                }
+                w.u16(m.hdg);
+            }
+            MavMessage::CommandLong(m) => {
+                for value in m.params {
+                    w.f32(value);
+                }
+                w.u16(m.command);
+                w.u8(m.target_system);
+                w.u8(m.target_component);
+                w.u8(m.confirmation);
+            }
+            MavMessage::CommandAck(m) => {
+                w.u16(m.command);
+                w.u8(m.result);
+            }
+            MavMessage::StatusText(m) => {
+                w.u8(m.severity);
+                for byte in m.text {
+                    w.u8(byte);
+                }
+            }
+        }
+    }
+
+    /// Read the payload of the message with the given ID. The payload must be zero-extended to
+    /// the wire length of the message.
+    fn read_payload(id: u32, r: &mut Reader) -> Option<Self> {
+        Some(match id {
+            0 => MavMessage::Heartbeat(Heartbeat {
+                custom_mode: r.u32(),
+                mav_type: r.u8(),
+                autopilot: r.u8(),
+                base_mode: r.u8(),
+                system_status: r.u8(),
+                mavlink_version: r.u8(),
+            }),
+            30 => MavMessage::Attitude(Attitude {
+                time_boot_ms: r.u32(),
+                roll: r.f32(),
+                pitch: r.f32(),
+                yaw: r.f32(),
+                rollspeed: r.f32(),
+                pitchspeed: r.f32(),
+                yawspeed: r.f32(),
+            }),
+            33 => MavMessage::GlobalPositionInt(GlobalPositionInt {
+                time_boot_ms: r.u32(),
+                lat: r.u32() as i32,
+                lon: r.u32() as i32,
+                alt: r.u32() as i32,
+                relative_alt: r.u32() as i32,
+                vx: r.u16() as i16,
+                vy: r.u16() as i16,
+                vz: r.u16() as i16,
+                hdg: r.u16(),
+            }),
+            76 => {
+                let mut params = [0.0; 7];
+                for param in params.iter_mut() {
+                    *param = r.f32();
+                }
+                MavMessage::CommandLong(CommandLong {
+                    params,
+                    command: r.u16(),
+                    target_system: r.u8(),
+                    target_component: r.u8(),
+                    confirmation: r.u8(),
+                })
+            }
+            77 => MavMessage::CommandAck(CommandAck {
+                command: r.u16(),
+                result: r.u8(),
+            }),
+            253 => {
+                let severity = r.u8();
+                let mut text = [0; 50];
+                for byte in text.iter_mut() {
+                    *byte = r.u8();
+                }
+                MavMessage::StatusText(StatusText { severity, text })
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// A MAVLink frame: a message along with the system that sent it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MavFrame {
+    pub sequence: u8,
+    pub system_id: u8,
+    pub component_id: u8,
+    pub message: MavMessage,
+}
+
+impl MavFrame {
+    /// Encode the frame in the MAVLink 2 format, returning the number of bytes written.
+    pub fn encode(&self, buf: &mut [u8; MAX_FRAME_LEN]) -> usize {
+        let (id, crc_extra) = self.message.id();
+
+        let mut payload = [0; MAX_PAYLOAD_LEN];
+        let mut writer = Writer {
+            buf: &mut payload,
+            pos: 0,
+        };
+        self.message.write_payload(&mut writer);
+        // MAVLink 2 truncates trailing zeros of the payload, but always keeps the first byte.
+        let mut len = writer.pos;
+        while len > 1 && payload[len - 1] == 0 {
+            len -= 1;
+        }
+
+        let id = id.to_le_bytes();
+        let header = [
+            STX_V2,
+            len as u8,
+            0,
+            0,
+            self.sequence,
+            self.system_id,
+            self.component_id,
+            id[0],
+            id[1],
+            id[2],
+        ];
+        buf[..HEADER_LEN_V2].copy_from_slice(&header);
+        buf[HEADER_LEN_V2..HEADER_LEN_V2 + len].copy_from_slice(&payload[..len]);
+
+        let end = HEADER_LEN_V2 + len;
+        let crc = checksum(&buf[1..end], crc_extra);
+        buf[end..end + 2].copy_from_slice(&crc.to_le_bytes());
+        end + 2
+    }
+
+    /// Decode the first frame of the buffer, returning it along with the number of bytes it
+    /// took. Bytes before the start of the frame are skipped.
+    pub fn decode(bytes: &[u8]) -> Result<(Self, usize), MavError> {
+        let start = bytes
+            .iter()
+            .position(|&byte| byte == STX_V1 || byte == STX_V2)
+            .ok_or(MavError::Truncated)?;
+        let bytes = &bytes[start..];
+
+        let (header_len, incompat_flags, sequence, system_id, component_id, id) = match bytes[0] {
+            STX_V2 if bytes.len() >= HEADER_LEN_V2 => {
+                let id = u32::from_le_bytes([bytes[7], bytes[8], bytes[9], 0]);
+                (HEADER_LEN_V2, bytes[2], bytes[4], bytes[5], bytes[6], id)
+            }
+            STX_V1 if bytes.len() >= HEADER_LEN_V1 => (
+                HEADER_LEN_V1,
+                0,
+                bytes[2],
+                bytes[3],
+                bytes[4],
+                bytes[5] as u32,
+            ),
+            _ => return Err(MavError::Truncated),
+        };
+
+        // Signed frames are accepted, but their signature isn't checked.
+        let signature_len = if incompat_flags & INCOMPAT_FLAG_SIGNED != 0 {
+            SIGNATURE_LEN
+        } else {
+            0
+        };
+        let len = bytes[1] as usize;
+        let end = header_len + len;
+        if bytes.len() < end + 2 + signature_len {
+            return Err(MavError::Truncated);
+        }
+
+        let consumed = start + end + 2 + signature_len;
+        if incompat_flags & !INCOMPAT_FLAG_SIGNED != 0 {
+            return Err(MavError::Unsupported(consumed));
+        }
+        let (crc_extra, wire_len) =
+            MavMessage::info(id).ok_or(MavError::UnknownMessage(id, consumed))?;
+        let crc = u16::from_le_bytes([bytes[end], bytes[end + 1]]);
+        if checksum(&bytes[1..end], crc_extra) != crc {
+            return Err(MavError::BadChecksum(consumed));
+        }
+
+        let mut payload = [0; MAX_PAYLOAD_LEN];
+        payload[..len].copy_from_slice(&bytes[header_len..end]);
+        let mut reader = Reader {
+            bytes: &payload[..wire_len.max(len)],
+            pos: 0,
+        };
+        let message = MavMessage::read_payload(id, &mut reader)
+            .ok_or(MavError::UnknownMessage(id, consumed))?;
+
+        Ok((
+            Self {
+                sequence,
+                system_id,
+                component_id,
+                message,
+            },
+            consumed,
+        ))
+    }
+}
+
+/// Compute the MAVLink checksum (CRC-16/MCRF4XX) of the bytes, followed by the extra byte of the
+/// message.
+fn checksum(bytes: &[u8], crc_extra: u8) -> u16 {
+    bytes
+        .iter()
+        .chain(core::iter::once(&crc_extra))
+        .fold(0xffff, |crc, &byte| {
+            let mut tmp = byte ^ crc as u8;
+            tmp ^= tmp << 4;
+            let tmp = tmp as u16;
+            (crc >> 8) ^ (tmp << 8) ^ (tmp << 3) ^ (tmp >> 4)
+        })
+}
+
+/// Writes little-endian fields into a payload.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes(&[value]);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes(&value.to_le_bytes());
+    }
+}
+
+/// Reads little-endian fields from a payload.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn array<const N: usize>(&mut self) -> [u8; N] {
+        let mut array = [0; N];
+        array.copy_from_slice(&self.bytes[self.pos..self.pos + N]);
+        self.pos += N;
+        array
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.array::<1>()[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.array())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.array())
+    }
+
+    fn f32(&mut self) -> f32 {
+        f32::from_le_bytes(self.array())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MavError {
+    /// The buffer doesn't hold a whole frame.
+    Truncated,
+    /// The frame uses unsupported features. Contains the length of the frame.
+    Unsupported(usize),
+    /// The checksum of the frame didn't match. Contains the length of the frame.
+    BadChecksum(usize),
+    /// The message isn't supported. Contains its ID, and the length of the frame.
+    UnknownMessage(u32, usize),
+}
+
+impl fmt::Display for MavError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MavError::Truncated => write!(f, "truncated frame")?,
+            MavError::Unsupported(_) => write!(f, "unsupported frame features")?,
+            MavError::BadChecksum(_) => write!(f, "bad frame checksum")?,
+            MavError::UnknownMessage(id, _) => write!(f, "unknown message {}", id)?,
+        };
+
+        Ok(())
+    }
+}
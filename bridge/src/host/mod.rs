@@ -0,0 +1,11 @@
+//! Host-side support for the bridge, for ground tooling running on a computer. Only available
+//! with the `std` feature.
+
+pub mod capture;
+pub mod emulator;
+pub mod mavlink;
+#[cfg(unix)]
+pub mod pty;
+pub mod stream;
+pub mod udp;
+pub mod usb;
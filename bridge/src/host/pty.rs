@@ -0,0 +1,91 @@
+//! Pseudo-terminals, which look like serial ports to the programs opening them.
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::PathBuf;
+
+/// Master side of a pseudo-terminal in raw mode.
+#[derive(Debug)]
+pub struct Pty {
+    master: File,
+    /// Path of the terminal that other programs open.
+    path: PathBuf,
+    /// Terminal kept open so the master doesn't see a hang up while no program has it open.
+    _terminal: OwnedFd,
+}
+
+impl Pty {
+    /// Open a new pseudo-terminal. Reads from the master are non-blocking.
+    pub fn open() -> io::Result<Self> {
+        // Safety: the file descriptors are checked before being owned, and the name of the terminal
+        // is checked before being read.
+        unsafe {
+            let master = check(libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY))?;
+            let master = OwnedFd::from_raw_fd(master);
+            let fd = master.as_raw_fd();
+            check(libc::grantpt(fd))?;
+            check(libc::unlockpt(fd))?;
+
+            // `ptsname` returns a static buffer, which is copied right away.
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let name = CStr::from_ptr(name).to_owned();
+            let path = PathBuf::from(name.to_string_lossy().as_ref());
+
+            let terminal = check(libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY))?;
+            let terminal = OwnedFd::from_raw_fd(terminal);
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            check(libc::tcgetattr(terminal.as_raw_fd(), &mut termios))?;
+            libc::cfmakeraw(&mut termios);
+            check(libc::tcsetattr(
+                terminal.as_raw_fd(),
+                libc::TCSANOW,
+                &termios,
+            ))?;
+
+            let flags = check(libc::fcntl(fd, libc::F_GETFL))?;
+            check(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+
+            Ok(Self {
+                master: File::from(master),
+                path,
+                _terminal: terminal,
+            })
+        }
+    }
+
+    /// Path of the terminal, which other programs open like a serial port.
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+impl Read for Pty {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.master.read(buf)
+    }
+}
+
+impl Write for Pty {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.master.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.master.flush()
+    }
+}
+
+/// Turn the return value of a libc call into an error if it failed.
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
@@ -0,0 +1,94 @@
+//! Bridge device over a byte stream, such as a TCP connection or a pseudo-terminal.
+//!
+//! Frames are delimited with [`stream`] encoding, like on a serial port, so tools talking to a
+//! vehicle over a serial radio can talk to an emulated one over a stream just the same.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+
+use crate::device::{Device, DeviceError};
+use crate::frame::stream::{self, StreamDecoder, MAX_ENCODED_LEN};
+use crate::frame::{self, MAX_FRAME_LEN};
+use crate::message::Message;
+
+/// Size of the buffer used to read from the stream.
+const READ_LEN: usize = 1024;
+
+/// A bridge device over a byte stream.
+#[derive(Debug)]
+pub struct StreamDevice<S> {
+    stream: RefCell<S>,
+    /// Bytes read from the stream that weren't decoded yet.
+    received: RefCell<VecDeque<u8>>,
+    decoder: RefCell<StreamDecoder>,
+}
+
+impl<S: Read + Write> StreamDevice<S> {
+    /// Instantiate a new [`StreamDevice`]. Reads from the stream must not block for long, so it
+    /// should be non-blocking or have a short timeout.
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream: RefCell::new(stream),
+            received: RefCell::new(VecDeque::new()),
+            decoder: RefCell::new(StreamDecoder::new()),
+        }
+    }
+
+    /// Stream that frames are sent over.
+    pub fn stream(&self) -> std::cell::Ref<'_, S> {
+        self.stream.borrow()
+    }
+}
+
+impl<S: Read + Write> Device for StreamDevice<S> {
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let frame = frame::encode(&message, &mut buf)?;
+        let mut encoded = [0; MAX_ENCODED_LEN];
+        let len = stream::encode(frame, &mut encoded)?;
+
+        let mut stream = self.stream.borrow_mut();
+        stream
+            .write_all(&encoded[..len])
+            .and_then(|_| stream.flush())
+            .map_err(|_| DeviceError::Physical)
+    }
+
+    /// Receive a message. Fails with [`DeviceError::Physical`] once the stream was closed.
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        let mut received = self.received.borrow_mut();
+        let mut decoder = self.decoder.borrow_mut();
+        loop {
+            while let Some(byte) = received.pop_front() {
+                if let Some(frame) = decoder.push(byte)? {
+                    return frame::decode(frame).map(Some).map_err(DeviceError::from);
+                }
+            }
+
+            let mut buf = [0; READ_LEN];
+            match self.stream.borrow_mut().read(&mut buf) {
+                Ok(0) => return Err(DeviceError::Physical),
+                Ok(len) => received.extend(&buf[..len]),
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return Err(DeviceError::Physical),
+            }
+        }
+    }
+
+    /// There is a single peer at the other end of the stream, so this does nothing.
+    fn ping(&self, _id: u64, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        Ok(0)
+    }
+
+    fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+        Err(DeviceError::InvalidFrequency)
+    }
+}
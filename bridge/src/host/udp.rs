@@ -0,0 +1,141 @@
+//! Bridge device over UDP, for ground tooling and testing on a single machine.
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::{ErrorKind, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use super::capture::{CaptureWriter, Direction, Layer};
+use crate::device::{Device, DeviceError};
+use crate::message::{Encoding, Encodings, Message};
+
+/// Largest datagram, which leaves room for messages in encodings larger than postcard.
+const MAX_DATAGRAM_LEN: usize = 1024;
+
+/// Capture that datagrams are recorded into.
+type Capture = CaptureWriter<Box<dyn Write>>;
+
+/// A bridge device that sends every message as a single UDP datagram to a peer.
+pub struct UdpDevice {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    encoding: Cell<Encoding>,
+    capture: RefCell<Option<Capture>>,
+}
+
+impl UdpDevice {
+    /// Bind a non-blocking socket to the local address, and send to the given peer.
+    pub fn bind(local: impl ToSocketAddrs, peer: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        let peer = peer
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "no peer address"))?;
+
+        Ok(Self {
+            socket,
+            peer,
+            encoding: Cell::new(Encoding::Postcard),
+            capture: RefCell::new(None),
+        })
+    }
+
+    /// Record every datagram sent and received into the capture, before it is decoded, or stop
+    /// recording if `None`.
+    pub fn set_capture(&mut self, capture: Option<Capture>) {
+        *self.capture.get_mut() = capture;
+    }
+
+    /// Encoding of the messages.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding.get()
+    }
+
+    /// Address that the device is bound to.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn record(&self, direction: Direction, bytes: &[u8]) -> Result<(), DeviceError> {
+        match self.capture.borrow_mut().as_mut() {
+            Some(capture) => capture
+                .record(direction, Layer::Message, bytes)
+                .map_err(|_| DeviceError::Physical),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for UdpDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpDevice")
+            .field("socket", &self.socket)
+            .field("peer", &self.peer)
+            .field("encoding", &self.encoding)
+            .field("capturing", &self.capture.borrow().is_some())
+            .finish()
+    }
+}
+
+impl Device for UdpDevice {
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        let mut buf = [0; MAX_DATAGRAM_LEN];
+        let bytes = message.encode_with(self.encoding.get(), &mut buf)?;
+        self.record(Direction::Transmitted, bytes)?;
+        self.socket
+            .send_to(bytes, self.peer)
+            .map_err(|_| DeviceError::Physical)?;
+
+        Ok(())
+    }
+
+    /// Receive a message in the current encoding, or a handshake in any encoding, which switches
+    /// the device to that encoding so the handshake is answered in it.
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        let mut buf = [0; MAX_DATAGRAM_LEN];
+        let bytes = match self.socket.recv_from(&mut buf) {
+            Ok((len, _)) => &buf[..len],
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(None),
+            Err(_) => return Err(DeviceError::Physical),
+        };
+        self.record(Direction::Received, bytes)?;
+
+        match Message::decode_with(self.encoding.get(), bytes) {
+            Ok(message) => Ok(Some(message)),
+            Err(err) => match Message::decode_handshake(bytes) {
+                Ok((message, encoding)) => {
+                    self.encoding.set(encoding);
+                    Ok(Some(message))
+                }
+                Err(_) => Err(err),
+            },
+        }
+    }
+
+    /// There are no frequencies to pair on over UDP, so this does nothing.
+    fn ping(&self, _id: u64, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        Ok(0)
+    }
+
+    fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+        Err(DeviceError::InvalidFrequency)
+    }
+
+    fn encodings(&self) -> Encodings {
+        Encoding::supported()
+    }
+
+    fn set_encoding(&self, encoding: Encoding) -> Result<(), DeviceError> {
+        if !Encoding::supported().contains(encoding.flag()) {
+            return Err(DeviceError::InvalidMessage);
+        }
+
+        self.encoding.set(encoding);
+        Ok(())
+    }
+}
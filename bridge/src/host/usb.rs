@@ -0,0 +1,138 @@
+//! Bridge device over the vehicle's USB serial port, which also carries its log output.
+//!
+//! The vehicle writes log lines as plain text, and bridge frames [`stream`] encoded with an extra
+//! delimiter in front, so a frame always sits between two zero bytes. Bytes outside of frames
+//! are split into log lines and handed to a callback.
+//!
+//! A receiver that starts listening in the middle of a frame takes its trailing delimiter for the
+//! start of a frame. The "frame" then fails to decode at the next delimiter, which actually
+//! starts a frame, so the receiver is back in sync after losing at most a log line.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+
+use crate::device::{Device, DeviceError};
+use crate::frame::stream::{self, StreamDecoder, DELIMITER, MAX_ENCODED_LEN};
+use crate::frame::{self, MAX_FRAME_LEN};
+use crate::message::Message;
+
+/// Size of the buffer used to read from the port.
+const READ_LEN: usize = 1024;
+
+/// Callback receiving log lines.
+type LogHandler = Box<dyn FnMut(&str)>;
+
+/// A bridge device over a serial port shared with log lines.
+pub struct UsbSerialDevice<P> {
+    port: RefCell<P>,
+    /// Bytes read from the port that weren't processed yet.
+    received: RefCell<VecDeque<u8>>,
+    /// Whether the bytes being received belong to a frame.
+    in_frame: Cell<bool>,
+    decoder: RefCell<StreamDecoder>,
+    /// Log line being received.
+    line: RefCell<Vec<u8>>,
+    on_log: RefCell<LogHandler>,
+}
+
+impl<P: Read + Write> UsbSerialDevice<P> {
+    /// Instantiate a new [`UsbSerialDevice`] over an opened serial port, calling `on_log` with
+    /// every log line received.
+    ///
+    /// Reads from the port must not block for long, so it should be non-blocking or have a short
+    /// timeout.
+    pub fn new(port: P, on_log: impl FnMut(&str) + 'static) -> Self {
+        Self {
+            port: RefCell::new(port),
+            received: RefCell::new(VecDeque::new()),
+            in_frame: Cell::new(false),
+            decoder: RefCell::new(StreamDecoder::new()),
+            line: RefCell::new(Vec::new()),
+            on_log: RefCell::new(Box::new(on_log)),
+        }
+    }
+
+    /// Handle a byte received outside of a frame.
+    fn push_text(&self, byte: u8) {
+        let mut line = self.line.borrow_mut();
+        match byte {
+            DELIMITER => {
+                // Log lines are written whole, so text cut short by a frame isn't a log line.
+                line.clear();
+                self.in_frame.set(true);
+            }
+            b'\r' => {}
+            b'\n' => {
+                (self.on_log.borrow_mut())(&String::from_utf8_lossy(&line));
+                line.clear();
+            }
+            byte => line.push(byte),
+        }
+    }
+
+    /// Handle a byte received inside a frame, returning the frame once it ended.
+    fn push_frame(&self, byte: u8) -> Result<Option<Message>, DeviceError> {
+        let mut decoder = self.decoder.borrow_mut();
+        // An empty frame is a frame's trailing delimiter followed by the next frame's leading one,
+        // and failing frames were text, so in both cases the delimiter started a frame.
+        let frame = match decoder.push(byte)? {
+            Some(frame) => frame::decode(frame)?,
+            None => return Ok(None),
+        };
+
+        self.in_frame.set(false);
+        Ok(Some(frame))
+    }
+}
+
+impl<P: Read + Write> Device for UsbSerialDevice<P> {
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let frame = frame::encode(&message, &mut buf)?;
+        let mut encoded = [0; MAX_ENCODED_LEN];
+        let len = stream::encode(frame, &mut encoded)?;
+
+        let mut port = self.port.borrow_mut();
+        port.write_all(&encoded[..len])
+            .and_then(|_| port.flush())
+            .map_err(|_| DeviceError::Physical)
+    }
+
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        let mut received = self.received.borrow_mut();
+        loop {
+            while let Some(byte) = received.pop_front() {
+                if !self.in_frame.get() {
+                    self.push_text(byte);
+                } else if let Some(message) = self.push_frame(byte)? {
+                    return Ok(Some(message));
+                }
+            }
+
+            let mut buf = [0; READ_LEN];
+            match self.port.borrow_mut().read(&mut buf) {
+                Ok(0) => return Ok(None),
+                Ok(len) => received.extend(&buf[..len]),
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return Err(DeviceError::Physical),
+            }
+        }
+    }
+
+    /// The vehicle is the only peer on the cable, so this does nothing.
+    fn ping(&self, _id: u64, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        Ok(0)
+    }
+
+    fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+        Err(DeviceError::InvalidFrequency)
+    }
+}
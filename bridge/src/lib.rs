@@ -1,5 +1,20 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+pub mod clock;
 pub mod command;
 pub mod device;
-pub mod message;
\ No newline at end of file
+pub mod event;
+pub mod frame;
+pub mod handshake;
+pub mod heartbeat;
+#[cfg(feature = "std")]
+pub mod host;
+pub mod link;
+pub mod message;
+pub mod param;
+pub mod reliable;
+pub mod rpc;
+#[cfg(feature = "encryption")]
+pub mod secure;
+pub mod state;
+pub mod telemetry;
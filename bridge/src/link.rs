@@ -0,0 +1,378 @@
+//! Operation over several links at once, such as USB on the bench and one or two radios in
+//! flight.
+//!
+//! A [`LinkManager`] is itself a [`Device`], so it can be used anywhere a single device can. Links
+//! are given in order of preference, and messages are sent on the preferred link that currently
+//! works, failing over to the next one when a link stops hearing from the other side, keeps
+//! failing to transmit, or has a signal too weak to be relied on. Messages with
+//! [`MessageFlags::CRITICAL`] are sent on every link, and copies received over several links are
+//! only returned once.
+//!
+//! Copies are recognized by their ID and send time within [`LinkConfig::dedup_window`], which
+//! covers both the same message received over several links and retransmissions by a
+//! [`ReliableSender`](crate::reliable::ReliableSender). The send time counts from the start of
+//! the sender, so a sender that restarted and reuses IDs isn't mistaken for sending copies. When a copy of a message that was already
+//! acknowledged arrives, the acknowledgement is sent again, since the first one was probably lost,
+//! and the copy is dropped. Both sides should send heartbeats with [`MessageFlags::CRITICAL`] so
+//! that every link keeps proving that it works.
+
+use core::cell::RefCell;
+
+use crate::device::{Device, DeviceError, SignalQuality};
+use crate::heartbeat::{HeartbeatMonitor, LinkState};
+use crate::message::{Encoding, Encodings, Message, MessageFlags, MessageKind};
+
+/// Default number of recently received messages remembered to recognize copies, enough for the
+/// default [`LinkConfig::dedup_window`] at about 3 messages per second.
+pub const DEFAULT_HISTORY_LEN: usize = 32;
+
+/// Margin above [`LinkConfig::min_snr`] that a marginal link must reach to be trusted again, in
+/// quarters of a dB, so a link hovering around the threshold doesn't keep flapping.
+const SNR_HYSTERESIS: i8 = 8;
+
+/// Configuration of a [`LinkManager`]. Times are in microseconds.
+#[derive(Debug, Clone, Copy)]
+pub struct LinkConfig {
+    /// Time after which a link is avoided if nothing was heard over it.
+    pub timeout: u64,
+    /// Number of consecutive failed transmissions after which a link is avoided.
+    pub max_failures: u8,
+    /// Time during which a message received again is recognized as a copy. This should outlast
+    /// the retransmissions of the other side.
+    pub dedup_window: u64,
+    /// Signal-to-noise ratio below which a link is only used if no better one works, in quarters
+    /// of a dB. Only applies to devices that report their
+    /// [`signal_quality`](Device::signal_quality).
+    pub min_snr: Option<i8>,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 3_000_000,
+            max_failures: 3,
+            dedup_window: 10_000_000,
+            min_snr: Some(0),
+        }
+    }
+}
+
+/// State of a single link.
+#[derive(Debug, Clone)]
+struct Link {
+    monitor: HeartbeatMonitor,
+    /// Number of consecutive failed transmissions.
+    failures: u8,
+    /// Whether the signal of the last received message was too weak.
+    marginal: bool,
+}
+
+/// Recently received message.
+#[derive(Debug, Clone, Copy)]
+struct Seen {
+    id: u64,
+    send_time: u64,
+    /// Time that the message was first received at.
+    at: u64,
+    /// ID and send time of the acknowledgement sent for the message, if any.
+    ack: Option<(u64, u64)>,
+}
+
+#[derive(Debug)]
+struct State<const N: usize, const H: usize> {
+    links: [Link; N],
+    /// Recently received messages, oldest first.
+    history: heapless::Deque<Seen, H>,
+    /// Number of messages forgotten before the end of the dedup window.
+    overflows: u32,
+    /// Time of the last update.
+    now: u64,
+    /// Link to poll first, so every link gets its turn.
+    next_poll: usize,
+}
+
+/// Aggregates `N` devices into a single one, remembering the last `H` received messages to
+/// recognize copies. `H` should cover the [`LinkConfig::dedup_window`] at the highest rate that
+/// messages are received at over every link together.
+pub struct LinkManager<'a, const N: usize, const H: usize = DEFAULT_HISTORY_LEN> {
+    devices: [&'a dyn Device; N],
+    state: RefCell<State<N, H>>,
+    config: LinkConfig,
+}
+
+impl<'a, const N: usize> LinkManager<'a, N> {
+    /// Instantiate a new [`LinkManager`] over devices given in order of preference, remembering
+    /// the last [`DEFAULT_HISTORY_LEN`] received messages.
+    pub fn new(devices: [&'a dyn Device; N], config: LinkConfig) -> Self {
+        Self::with_history(devices, config)
+    }
+}
+
+impl<'a, const N: usize, const H: usize> LinkManager<'a, N, H> {
+    /// Instantiate a new [`LinkManager`] over devices given in order of preference, remembering
+    /// the last `H` received messages.
+    pub fn with_history(devices: [&'a dyn Device; N], config: LinkConfig) -> Self {
+        let link = Link {
+            monitor: HeartbeatMonitor::new(config.timeout, config.timeout),
+            failures: 0,
+            marginal: false,
+        };
+
+        Self {
+            devices,
+            state: RefCell::new(State {
+                links: core::array::from_fn(|_| link.clone()),
+                history: heapless::Deque::new(),
+                overflows: 0,
+                now: 0,
+                next_poll: 0,
+            }),
+            config,
+        }
+    }
+
+    /// Check whether links timed out. This should be called regularly, and gives the time that
+    /// received messages are recorded at.
+    pub fn update(&self, now: u64) {
+        let mut state = self.state.borrow_mut();
+        state.now = now;
+        for link in state.links.iter_mut() {
+            link.monitor.update(now);
+        }
+    }
+
+    /// State of the link at the given index, as seen by its heartbeat monitor.
+    pub fn link_state(&self, index: usize) -> LinkState {
+        self.state.borrow().links[index].monitor.state()
+    }
+
+    /// Index of the link that messages are sent on, or `None` if every link is lost or failing,
+    /// in which case messages are sent on every link.
+    pub fn active(&self) -> Option<usize> {
+        let state = self.state.borrow();
+        (0..N)
+            .filter_map(|index| Some((self.rank(&state.links[index])?, index)))
+            .min()
+            .map(|(_, index)| index)
+    }
+
+    /// Number of messages that were forgotten before the end of the dedup window because the
+    /// history was full, so that copies of them could be returned again. Increases if messages
+    /// arrive faster than `H` per window.
+    pub fn overflows(&self) -> u32 {
+        self.state.borrow().overflows
+    }
+
+    /// Whether the signal received over the link at the given index is too weak to be relied on.
+    pub fn is_marginal(&self, index: usize) -> bool {
+        self.state.borrow().links[index].marginal
+    }
+
+    /// Rank of a link: links that were heard from with a good signal are preferred over marginal
+    /// ones, then over those that weren't heard from yet, and failing links aren't used at all.
+    fn rank(&self, link: &Link) -> Option<u8> {
+        if link.failures >= self.config.max_failures {
+            return None;
+        }
+
+        match link.monitor.state() {
+            LinkState::Connected if !link.marginal => Some(0),
+            LinkState::Connected => Some(1),
+            LinkState::Waiting => Some(2),
+            LinkState::Lost => None,
+        }
+    }
+
+    /// Transmit a message on a single link, keeping track of failures.
+    fn transmit_on(&self, index: usize, message: Message) -> Result<(), DeviceError> {
+        let result = self.devices[index].transmit(message);
+        let link = &mut self.state.borrow_mut().links[index];
+        match result {
+            Ok(()) => link.failures = 0,
+            Err(_) => link.failures = link.failures.saturating_add(1),
+        }
+
+        result
+    }
+
+    /// Transmit a message on every link. Succeeds if any link transmitted it.
+    fn transmit_all(&self, message: Message) -> Result<(), DeviceError> {
+        let mut result = Err(DeviceError::Physical);
+        for index in 0..N {
+            let sent = self.transmit_on(index, message.clone());
+            if result.is_err() {
+                result = sent;
+            }
+        }
+
+        result
+    }
+
+    /// Record a received message, returning whether it is a copy of one received before. Copies
+    /// of messages that were acknowledged are acknowledged again on the link they came from.
+    fn seen(&self, index: usize, message: &Message) -> bool {
+        let quality = self.devices[index].signal_quality();
+
+        let mut state = self.state.borrow_mut();
+        let now = state.now;
+        let link = &mut state.links[index];
+        link.monitor.heard(now);
+        if let (Some(quality), Some(min_snr)) = (quality, self.config.min_snr) {
+            link.marginal = if link.marginal {
+                quality.snr < min_snr.saturating_add(SNR_HYSTERESIS)
+            } else {
+                quality.snr < min_snr
+            };
+        }
+
+        while let Some(seen) = state.history.front() {
+            if now.saturating_sub(seen.at) < self.config.dedup_window {
+                break;
+            }
+            state.history.pop_front();
+        }
+
+        let ack = match state
+            .history
+            .iter()
+            .find(|seen| seen.id == message.id() && seen.send_time == message.send_time())
+        {
+            Some(seen) => seen.ack,
+            None => {
+                // Every message in the history is still within the window.
+                if state.history.is_full() {
+                    state.history.pop_front();
+                    state.overflows = state.overflows.saturating_add(1);
+                }
+                // Can't fail since there is room for the message.
+                let _ = state.history.push_back(Seen {
+                    id: message.id(),
+                    send_time: message.send_time(),
+                    at: now,
+                    ack: None,
+                });
+                return false;
+            }
+        };
+        drop(state);
+
+        if let Some((id, send_time)) = ack {
+            if message.flags().contains(MessageFlags::REQUIRES_ACK) {
+                let ack = Message::new(
+                    id,
+                    MessageKind::Ack(message.id()),
+                    send_time,
+                    MessageFlags::empty(),
+                );
+                // The other side retransmits the message if this acknowledgement is lost too.
+                let _ = self.transmit_on(index, ack);
+            }
+        }
+        true
+    }
+
+    /// Remember the acknowledgement sent for a received message, to repeat it if a copy of that
+    /// message arrives.
+    fn acknowledged(&self, message: &Message) {
+        let acked = match message.kind() {
+            MessageKind::Ack(acked) => *acked,
+            _ => return,
+        };
+
+        let mut state = self.state.borrow_mut();
+        // The latest message with the ID, in case an earlier session of the sender used it too.
+        if let Some(seen) = state.history.iter_mut().rev().find(|seen| seen.id == acked) {
+            seen.ack = Some((message.id(), message.send_time()));
+        }
+    }
+}
+
+impl<const N: usize, const H: usize> Device for LinkManager<'_, N, H> {
+    /// Transmit the message on the best link, failing over to the next ones if it fails.
+    /// Critical messages, and messages sent while no link is known to work, go on every link.
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        self.acknowledged(&message);
+
+        let active = match self.active() {
+            Some(active) if !message.flags().contains(MessageFlags::CRITICAL) => active,
+            _ => return self.transmit_all(message),
+        };
+
+        let mut result = self.transmit_on(active, message.clone());
+        // Fall back on the other links in order of preference.
+        for index in (0..N).filter(|index| *index != active) {
+            if result.is_ok() {
+                break;
+            }
+            result = self.transmit_on(index, message.clone());
+        }
+
+        result
+    }
+
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        let first = self.state.borrow().next_poll;
+        for offset in 0..N {
+            let index = (first + offset) % N;
+            loop {
+                let received = self.devices[index].poll();
+                if !matches!(received, Ok(None)) {
+                    self.state.borrow_mut().next_poll = (index + 1) % N;
+                }
+                match received? {
+                    Some(message) if self.seen(index, &message) => continue,
+                    Some(message) => return Ok(Some(message)),
+                    None => break,
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn ping(&self, id: u64, freq: u64) -> Result<(), DeviceError> {
+        let mut result = Err(DeviceError::Physical);
+        for device in self.devices {
+            let pinged = device.ping(id, freq);
+            if result.is_err() {
+                result = pinged;
+            }
+        }
+
+        result
+    }
+
+    /// Frequency of the link that messages are sent on.
+    fn freq(&self) -> Result<u64, DeviceError> {
+        match self.devices.get(self.active().unwrap_or(0)) {
+            Some(device) => device.freq(),
+            None => Err(DeviceError::Physical),
+        }
+    }
+
+    /// Links keep their own frequencies, so this always fails.
+    fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+        Err(DeviceError::InvalidFrequency)
+    }
+
+    /// Signal quality of the link that messages are sent on.
+    fn signal_quality(&self) -> Option<SignalQuality> {
+        self.devices.get(self.active()?)?.signal_quality()
+    }
+
+    /// Encodings that every link can carry.
+    fn encodings(&self) -> Encodings {
+        self.devices
+            .iter()
+            .fold(Encodings::all(), |encodings, device| {
+                encodings & device.encodings()
+            })
+    }
+
+    /// Switch every link to the encoding.
+    fn set_encoding(&self, encoding: Encoding) -> Result<(), DeviceError> {
+        self.devices
+            .iter()
+            .try_for_each(|device| device.set_encoding(encoding))
+    }
+}
@@ -1,5 +1,17 @@
 use serde::{Deserialize, Serialize};
 use crate::command::{Command, CommandResponse};
+use crate::device::DeviceError;
+use crate::event::VehicleEvent;
+use crate::telemetry::TelemetryPayload;
+
+/// Largest size of an encoded [`Message`].
+pub const MAX_MESSAGE_LEN: usize = 256;
+/// Largest size of the payload of a [`MessageKind::Sealed`] message, leaving room for the rest of
+/// the message.
+pub const MAX_SEALED_LEN: usize = 224;
+
+/// Encrypted payload of a [`MessageKind::Sealed`] message.
+pub type SealedPayload = heapless::Vec<u8, MAX_SEALED_LEN>;
 
 /// A message is the basis for all communication operations that can be done on the bridge.
 /// Messages contain the data to be sent, along with other metadata.
@@ -10,26 +22,204 @@ pub struct Message {
 	id: u64,
 	/// Data specific to the type of packet.
 	kind: MessageKind,
-	/// Time that the packet was sent.
+	/// Time that the packet was sent, in microseconds since the sender started.
 	send_time: u64,
 	/// Any additional flags that were passed.
 	flags: u8,
 }
 
+impl Message {
+	/// Instantiate a new [`Message`].
+	pub fn new(id: u64, kind: MessageKind, send_time: u64, flags: MessageFlags) -> Self {
+		Self {
+			id,
+			kind,
+			send_time,
+			flags: flags.bits(),
+		}
+	}
+
+	/// Unique identifier of the message.
+	pub fn id(&self) -> u64 {
+		self.id
+	}
+
+	/// Data specific to the type of message.
+	pub fn kind(&self) -> &MessageKind {
+		&self.kind
+	}
+
+	/// Consumes the message, returning its data.
+	pub fn into_kind(self) -> MessageKind {
+		self.kind
+	}
+
+	/// Time that the message was sent.
+	pub fn send_time(&self) -> u64 {
+		self.send_time
+	}
+
+	/// Flags of the message. Unknown bits are ignored.
+	pub fn flags(&self) -> MessageFlags {
+		MessageFlags::from_bits_truncate(self.flags)
+	}
+
+	/// Encode the message into the buffer, returning the encoded bytes.
+	pub fn encode<'a>(&self, buf: &'a mut [u8]) -> Result<&'a mut [u8], DeviceError> {
+		postcard::to_slice(self, buf).map_err(|_| DeviceError::InvalidMessage)
+	}
+
+	/// Decode a message previously encoded with [`Message::encode`].
+	pub fn decode(bytes: &[u8]) -> Result<Self, DeviceError> {
+		postcard::from_bytes(bytes).map_err(|_| DeviceError::InvalidMessage)
+	}
+
+	/// Encode the message into the buffer with the given encoding, returning the encoded bytes.
+	pub fn encode_with<'a>(
+		&self,
+		encoding: Encoding,
+		buf: &'a mut [u8],
+	) -> Result<&'a mut [u8], DeviceError> {
+		match encoding {
+			Encoding::Postcard => self.encode(buf),
+			#[cfg(feature = "cbor")]
+			Encoding::Cbor => {
+				let writer = serde_cbor::ser::SliceWrite::new(buf);
+				let mut serializer = serde_cbor::Serializer::new(writer);
+				self.serialize(&mut serializer).map_err(|_| DeviceError::InvalidMessage)?;
+				let len = serializer.into_inner().bytes_written();
+				Ok(&mut buf[..len])
+			}
+			#[cfg(not(feature = "cbor"))]
+			Encoding::Cbor => Err(DeviceError::InvalidMessage),
+		}
+	}
+
+	/// Decode a message previously encoded with [`Message::encode_with`].
+	pub fn decode_with(encoding: Encoding, bytes: &[u8]) -> Result<Self, DeviceError> {
+		match encoding {
+			Encoding::Postcard => Self::decode(bytes),
+			// Without scratch space, indefinite-length strings are rejected, which messages never
+			// need.
+			#[cfg(feature = "cbor")]
+			Encoding::Cbor => serde_cbor::de::from_slice_with_scratch(bytes, &mut [])
+				.map_err(|_| DeviceError::InvalidMessage),
+			#[cfg(not(feature = "cbor"))]
+			Encoding::Cbor => Err(DeviceError::InvalidMessage),
+		}
+	}
+
+	/// Decode a [`MessageKind::Handshake`] in any encoding supported by this build, returning it
+	/// along with its encoding. Other kinds of messages are rejected, since bytes in one encoding
+	/// may happen to decode in another.
+	pub fn decode_handshake(bytes: &[u8]) -> Result<(Self, Encoding), DeviceError> {
+		// Self-describing encodings come first, since they are less likely to decode by accident.
+		for encoding in [Encoding::Cbor, Encoding::Postcard] {
+			if !Encoding::supported().contains(encoding.flag()) {
+				continue;
+			}
+			if let Ok(message) = Self::decode_with(encoding, bytes) {
+				if matches!(message.kind, MessageKind::Handshake(..)) {
+					return Ok((message, encoding));
+				}
+			}
+		}
+
+		Err(DeviceError::InvalidMessage)
+	}
+}
+
+/// Encoding of messages on the wire.
+///
+/// Both sides start out with postcard, and switch to another encoding once they agreed on it in
+/// the [`MessageKind::Handshake`] (see [`handshake`](crate::handshake)). CBOR (RFC 8949) is
+/// self-describing, with fields named and enum variants tagged by name, so tools outside of Rust
+/// can decode messages without this crate, and can start with a handshake in CBOR. It is only
+/// available with the `cbor` feature, and much larger than postcard, so it is meant for ground
+/// links rather than radios.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+	Postcard,
+	Cbor,
+}
+
+impl Encoding {
+	/// Encodings supported by this build, to announce in the handshake.
+	pub fn supported() -> Encodings {
+		#[cfg(feature = "cbor")]
+		return Encodings::POSTCARD | Encodings::CBOR;
+		#[cfg(not(feature = "cbor"))]
+		return Encodings::POSTCARD;
+	}
+
+	/// Flag of the encoding in a set of [`Encodings`].
+	pub fn flag(self) -> Encodings {
+		match self {
+			Encoding::Postcard => Encodings::POSTCARD,
+			Encoding::Cbor => Encodings::CBOR,
+		}
+	}
+
+	/// Pick the encoding to use given the encodings announced by both sides, and the encoding
+	/// preferred by the ground. Without a preference, or if either side doesn't support it, the
+	/// most compact encoding is picked. Returns `None` if the sides have no encoding in common.
+	pub fn negotiate(ours: Encodings, theirs: Encodings, preferred: Option<Self>) -> Option<Self> {
+		let common = ours & theirs;
+		if let Some(preferred) = preferred.filter(|preferred| common.contains(preferred.flag())) {
+			Some(preferred)
+		} else if common.contains(Encodings::POSTCARD) {
+			Some(Encoding::Postcard)
+		} else if common.contains(Encodings::CBOR) {
+			Some(Encoding::Cbor)
+		} else {
+			None
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum MessageKind {
 	/// Command being sent.
 	Request(Command),
 	/// Response to a previously-sent command.
 	Response(u64, CommandResponse),
-	/// Handshake to establish connection between two parties, each with unique identifiers.
-	Handshake(u64, u64),
+	/// Handshake to establish connection between two parties, each with unique identifiers. Also
+	/// announces the encodings supported by the sender, as [`Encodings`] bits, and the encoding
+	/// that the ground prefers, or that the vehicle picked in its answer.
+	Handshake(u64, u64, u8, Option<Encoding>),
 	/// Acknowledges a previously-sent packet.
-	Ack(u64)
+	Ack(u64),
+	/// Streamed telemetry. If the message has the [`MessageFlags::COMPRESSED`] flag, the payload
+	/// was produced by a [`TelemetryEncoder`](crate::telemetry::codec::TelemetryEncoder), and
+	/// otherwise it is a postcard-encoded [`TelemetrySnapshot`](crate::telemetry::TelemetrySnapshot).
+	Telemetry(TelemetryPayload),
+	/// Event originated by the vehicle, sent without being requested.
+	Event(VehicleEvent),
+	/// Announces a device with the given identifier, that wants to pair on the given frequency
+	/// (see [`Device::ping`](crate::device::Device::ping)).
+	Ping(u64, u64),
+	/// Encrypted [`MessageKind`], followed by its authentication tag. See the `secure` module,
+	/// available with the `encryption` feature.
+	Sealed(SealedPayload),
 }
 
 bitflags::bitflags! {
 	/// Flags for packets. See [`Message::flags`].
 	pub struct MessageFlags: u8 {
+		/// The payload of the message is delta-compressed.
+		const COMPRESSED = 1 << 0;
+		/// The receiver must acknowledge the message with a [`MessageKind::Ack`].
+		const REQUIRES_ACK = 1 << 1;
+		/// The message is sent on every link rather than only the best one. See
+		/// [`LinkManager`](crate::link::LinkManager).
+		const CRITICAL = 1 << 2;
 	}
-}
\ No newline at end of file
+}
+
+bitflags::bitflags! {
+	/// Set of [`Encoding`]s, announced in the [`MessageKind::Handshake`].
+	pub struct Encodings: u8 {
+		const POSTCARD = 1 << 0;
+		const CBOR = 1 << 1;
+	}
+}
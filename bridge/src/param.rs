@@ -0,0 +1,161 @@
+//! Tunable parameters of the vehicle.
+//!
+//! Parameters are read and written through [`Command::GetParameter`] and
+//! [`Command::SetParameter`], which refer to them by ID. Like the IDs of
+//! [`FlightState`](crate::state::FlightState)s, these never change once assigned. Values are in SI
+//! units, with times in seconds.
+//!
+//! [`Command::GetParameter`]: crate::command::Command::GetParameter
+//! [`Command::SetParameter`]: crate::command::Command::SetParameter
+
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Tunable parameter.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Param {
+    /// Acceleration above which the vehicle may have launched, in meters per second squared.
+    LaunchAcceleration,
+    /// Time that the acceleration must stay above [`Param::LaunchAcceleration`] for launch to be
+    /// detected, in seconds.
+    LaunchDebounce,
+    /// Acceleration below which the motor may have burnt out, in meters per second squared.
+    BurnoutAcceleration,
+    /// Time that the acceleration must stay below [`Param::BurnoutAcceleration`] for burnout to
+    /// be detected, in seconds.
+    BurnoutDebounce,
+    /// Time after launch before apogee is looked for, in seconds.
+    ApogeeMinTime,
+    /// Speed above which the barometer is locked out, in meters per second.
+    ApogeeLockoutSpeed,
+    /// Time that the vertical velocity must stay at or below zero for apogee to be confirmed, in
+    /// seconds.
+    ApogeeConfirmTime,
+    /// Largest change of altitude while landed, in meters.
+    LandingAltitudeWindow,
+    /// Largest variance of the acceleration while landed, in squared meters per second squared.
+    LandingVariance,
+    /// Time that the altitude and acceleration must stay stable for landing to be detected, in
+    /// seconds.
+    LandingTime,
+    /// Pyro channel of the drogue parachute, or -1 for none.
+    DrogueChannel,
+    /// Redundant pyro channel of the drogue parachute, or -1 for none.
+    DrogueBackupChannel,
+    /// Time between apogee and the drogue deployment, in seconds.
+    DrogueDelay,
+    /// Time after launch at which the drogue deploys if apogee wasn't detected, in seconds, or 0
+    /// to disable.
+    DrogueBackupTime,
+    /// Pyro channel of the main parachute, or -1 for none.
+    MainChannel,
+    /// Redundant pyro channel of the main parachute, or -1 for none.
+    MainBackupChannel,
+    /// Altitude above the pad at which the main parachute deploys, in meters.
+    MainAltitude,
+    /// Time after apogee at which the main deploys if it didn't yet, in seconds, or 0 to disable.
+    MainBackupTime,
+    /// Time after firing a primary channel before firing its redundant channel, if the primary
+    /// showed no continuity change, in seconds.
+    RedundancyDelay,
+    /// Time that the barometer is averaged over on the pad for the ground reference of the
+    /// altitude, in seconds.
+    GroundAveragingTime,
+}
+
+impl Param {
+    /// Every parameter, ordered by ID.
+    pub const ALL: [Param; 20] = [
+        Param::LaunchAcceleration,
+        Param::LaunchDebounce,
+        Param::BurnoutAcceleration,
+        Param::BurnoutDebounce,
+        Param::ApogeeMinTime,
+        Param::ApogeeLockoutSpeed,
+        Param::ApogeeConfirmTime,
+        Param::LandingAltitudeWindow,
+        Param::LandingVariance,
+        Param::LandingTime,
+        Param::DrogueChannel,
+        Param::DrogueBackupChannel,
+        Param::DrogueDelay,
+        Param::DrogueBackupTime,
+        Param::MainChannel,
+        Param::MainBackupChannel,
+        Param::MainAltitude,
+        Param::MainBackupTime,
+        Param::RedundancyDelay,
+        Param::GroundAveragingTime,
+    ];
+
+    /// ID of the parameter.
+    pub const fn id(self) -> u16 {
+        match self {
+            Param::LaunchAcceleration => 0,
+            Param::LaunchDebounce => 1,
+            Param::BurnoutAcceleration => 2,
+            Param::BurnoutDebounce => 3,
+            Param::ApogeeMinTime => 4,
+            Param::ApogeeLockoutSpeed => 5,
+            Param::ApogeeConfirmTime => 6,
+            Param::LandingAltitudeWindow => 7,
+            Param::LandingVariance => 8,
+            Param::LandingTime => 9,
+            Param::DrogueChannel => 10,
+            Param::DrogueBackupChannel => 11,
+            Param::DrogueDelay => 12,
+            Param::DrogueBackupTime => 13,
+            Param::MainChannel => 14,
+            Param::MainBackupChannel => 15,
+            Param::MainAltitude => 16,
+            Param::MainBackupTime => 17,
+            Param::RedundancyDelay => 18,
+            Param::GroundAveragingTime => 19,
+        }
+    }
+
+    /// Parameter with the given ID, if there is one.
+    pub fn from_id(id: u16) -> Option<Self> {
+        Self::ALL.into_iter().find(|param| param.id() == id)
+    }
+
+    /// Name of the parameter, for ground tools.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Param::LaunchAcceleration => "launch.acceleration",
+            Param::LaunchDebounce => "launch.debounce",
+            Param::BurnoutAcceleration => "burnout.acceleration",
+            Param::BurnoutDebounce => "burnout.debounce",
+            Param::ApogeeMinTime => "apogee.min_time",
+            Param::ApogeeLockoutSpeed => "apogee.lockout_speed",
+            Param::ApogeeConfirmTime => "apogee.confirm_time",
+            Param::LandingAltitudeWindow => "landing.altitude_window",
+            Param::LandingVariance => "landing.variance",
+            Param::LandingTime => "landing.time",
+            Param::DrogueChannel => "drogue.channel",
+            Param::DrogueBackupChannel => "drogue.backup_channel",
+            Param::DrogueDelay => "drogue.delay",
+            Param::DrogueBackupTime => "drogue.backup_time",
+            Param::MainChannel => "main.channel",
+            Param::MainBackupChannel => "main.backup_channel",
+            Param::MainAltitude => "main.altitude",
+            Param::MainBackupTime => "main.backup_time",
+            Param::RedundancyDelay => "recovery.redundancy_delay",
+            Param::GroundAveragingTime => "ground.averaging_time",
+        }
+    }
+
+    /// Parameter with the given name, if there is one.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|param| param.name() == name)
+    }
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+//! Reliable delivery of messages through acknowledgements.
+//!
+//! Messages sent with [`MessageFlags::REQUIRES_ACK`] must be answered by the receiver with a
+//! [`MessageKind::Ack`]. The sender keeps them around and retransmits them until that
+//! acknowledgement arrives, or until it gives up.
+
+use crate::device::{Device, DeviceError};
+use crate::message::{Message, MessageFlags, MessageKind};
+
+/// Message waiting for an acknowledgement.
+#[derive(Debug, Clone)]
+struct Pending {
+    message: Message,
+    /// Time that the message was last transmitted, or `None` if it never was.
+    last_sent: Option<u64>,
+    /// Number of times the message was transmitted.
+    attempts: u8,
+}
+
+/// Retransmits messages until they are acknowledged. Holds up to `N` unacknowledged messages.
+#[derive(Debug, Clone)]
+pub struct ReliableSender<const N: usize> {
+    pending: heapless::Vec<Pending, N>,
+    /// Time to wait for an acknowledgement before retransmitting, in microseconds.
+    retry_interval: u64,
+    /// Number of transmissions after which a message is given up on.
+    max_attempts: u8,
+}
+
+impl<const N: usize> ReliableSender<N> {
+    /// Instantiate a new [`ReliableSender`].
+    pub fn new(retry_interval: u64, max_attempts: u8) -> Self {
+        Self {
+            pending: heapless::Vec::new(),
+            retry_interval,
+            max_attempts,
+        }
+    }
+
+    /// Number of messages waiting for an acknowledgement.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Transmit a message and keep it until it is acknowledged. The message must have the
+    /// [`MessageFlags::REQUIRES_ACK`] flag. It is kept even if it couldn't be transmitted, in
+    /// which case [`update`](Self::update) retries it.
+    pub fn send<D: Device>(
+        &mut self,
+        device: &D,
+        message: Message,
+        now: u64,
+    ) -> Result<(), DeviceError> {
+        if !message.flags().contains(MessageFlags::REQUIRES_ACK) {
+            return Err(DeviceError::InvalidMessage);
+        }
+        if self.pending.is_full() {
+            return Err(DeviceError::QueueFull);
+        }
+
+        let sent = device.transmit(message.clone()).is_ok();
+        // Can't fail since there was room for the message.
+        let _ = self.pending.push(Pending {
+            message,
+            last_sent: sent.then_some(now),
+            attempts: u8::from(sent),
+        });
+
+        Ok(())
+    }
+
+    /// Handle a received message. Returns whether it acknowledged a pending message.
+    pub fn receive(&mut self, message: &Message) -> bool {
+        match message.kind() {
+            MessageKind::Ack(id) => self.acknowledge(*id),
+            _ => false,
+        }
+    }
+
+    /// Stop retransmitting the message with the given ID. Returns whether it was pending.
+    pub fn acknowledge(&mut self, id: u64) -> bool {
+        match self
+            .pending
+            .iter()
+            .position(|pending| pending.message.id() == id)
+        {
+            Some(index) => {
+                self.pending.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Retransmit every message that wasn't acknowledged in time, or couldn't be transmitted.
+    /// This should be called regularly. Messages that were transmitted too many times are
+    /// dropped, in which case [`DeviceError::NotAcknowledged`] is returned. Every message is
+    /// handled even if some fail to transmit, and the first error is returned.
+    pub fn update<D: Device>(&mut self, device: &D, now: u64) -> Result<(), DeviceError> {
+        let mut result = Ok(());
+
+        let mut index = 0;
+        while index < self.pending.len() {
+            let pending = &mut self.pending[index];
+            let due = match pending.last_sent {
+                Some(last_sent) => now.saturating_sub(last_sent) >= self.retry_interval,
+                None => true,
+            };
+            if !due {
+                index += 1;
+                continue;
+            }
+
+            if pending.attempts >= self.max_attempts {
+                self.pending.swap_remove(index);
+                result = result.and(Err(DeviceError::NotAcknowledged));
+                continue;
+            }
+
+            // Retransmissions keep the ID and send time of the original, so that the receiver
+            // recognizes them as copies.
+            if let Err(err) = device.transmit(pending.message.clone()) {
+                result = result.and(Err(err));
+            }
+            // A failed transmission counts as an attempt, so a message that can never be
+            // transmitted is eventually dropped.
+            pending.last_sent = Some(now);
+            pending.attempts += 1;
+            index += 1;
+        }
+
+        result
+    }
+}
+
+/// Build the acknowledgement of a received message, if it needs one.
+pub fn acknowledgement(message: &Message, id: u64, now: u64) -> Option<Message> {
+    if !message.flags().contains(MessageFlags::REQUIRES_ACK) {
+        return None;
+    }
+
+    Some(Message::new(
+        id,
+        MessageKind::Ack(message.id()),
+        now,
+        MessageFlags::empty(),
+    ))
+}
@@ -0,0 +1,103 @@
+//! Futures completing with the responses of a [`RpcClient`].
+//!
+//! The client doesn't own the device, so something else must keep feeding it: a receive task (or
+//! interrupt handler) should hand every received message to [`AsyncClient::receive`], and call
+//! [`AsyncClient::update`] regularly so that requests time out.
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use super::{RequestHandle, RpcClient, RpcError};
+use crate::clock::ClockSync;
+use crate::command::{Command, CommandResponse};
+use crate::device::Device;
+use crate::message::Message;
+
+/// Asynchronous wrapper around a [`RpcClient`].
+#[derive(Debug)]
+pub struct AsyncClient<const N: usize> {
+    client: RefCell<RpcClient<N>>,
+    /// Current time, in microseconds.
+    clock: fn() -> u64,
+}
+
+impl<const N: usize> AsyncClient<N> {
+    /// Instantiate a new [`AsyncClient`].
+    pub fn new(client: RpcClient<N>, clock: fn() -> u64) -> Self {
+        Self {
+            client: RefCell::new(client),
+            clock,
+        }
+    }
+
+    /// Send a command, returning a future that completes with its response.
+    pub fn call<D: Device>(&self, device: &D, command: Command) -> ResponseFuture<'_, N> {
+        let handle = self
+            .client
+            .borrow_mut()
+            .send(device, command, (self.clock)())
+            .map_err(Some);
+
+        ResponseFuture {
+            client: self,
+            handle,
+        }
+    }
+
+    /// Handle a received message. See [`RpcClient::receive`].
+    pub fn receive(&self, message: Message) -> Option<Message> {
+        self.client.borrow_mut().receive(message, (self.clock)())
+    }
+
+    /// Estimate of the vehicle's clock. See [`RpcClient::clock`].
+    pub fn clock_sync(&self) -> ClockSync {
+        self.client.borrow().clock().clone()
+    }
+
+    /// Time out every request whose deadline passed.
+    pub fn update(&self) {
+        self.client.borrow_mut().update((self.clock)());
+    }
+}
+
+/// Future returned by [`AsyncClient::call`]. Dropping it before it completes cancels the request.
+#[derive(Debug)]
+pub struct ResponseFuture<'a, const N: usize> {
+    client: &'a AsyncClient<N>,
+    /// Handle to the request, or the error that prevented it from being sent.
+    handle: Result<RequestHandle, Option<RpcError>>,
+}
+
+impl<const N: usize> Future for ResponseFuture<'_, N> {
+    type Output = Result<CommandResponse, RpcError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let handle = match &mut self.handle {
+            Ok(handle) => *handle,
+            Err(err) => return Poll::Ready(Err(err.take().expect("polled after completion"))),
+        };
+
+        let mut client = self.client.client.borrow_mut();
+        match client.take(handle) {
+            Some(outcome) => {
+                drop(client);
+                self.handle = Err(None);
+                Poll::Ready(outcome)
+            }
+            None => {
+                client.register(handle, cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<const N: usize> Drop for ResponseFuture<'_, N> {
+    fn drop(&mut self) {
+        if let Ok(handle) = self.handle {
+            self.client.client.borrow_mut().cancel(handle);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! Request/response correlation for commands.
+//!
+//! A [`RpcClient`] sends [`Command`]s and matches every
+//! [`MessageKind::Response`] to the request it answers, so callers get a [`RequestHandle`] that
+//! completes with the [`CommandResponse`] instead of matching message IDs by hand.
+//!
+//! The client is poll-driven: received messages are handed to [`RpcClient::receive`], and
+//! [`RpcClient::update`] expires requests that timed out. Every response also completes a round
+//! trip, which the client uses to estimate the vehicle's clock with a [`ClockSync`]. [`RpcClient::call`] wraps this into a
+//! blocking call, and with the `async` feature, [`future::AsyncClient`] provides futures.
+
+use core::fmt;
+
+use crate::clock::ClockSync;
+use crate::command::{Command, CommandError, CommandResponse};
+use crate::device::{Device, DeviceError};
+use crate::message::{Message, MessageFlags, MessageKind};
+
+#[cfg(feature = "async")]
+pub mod future;
+
+/// Age after which the estimate of the vehicle's clock is replaced by the next round trip, in
+/// microseconds.
+const CLOCK_MAX_AGE: u64 = 10_000_000;
+
+/// Handle to a request sent by a [`RpcClient`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestHandle {
+    id: u64,
+}
+
+impl RequestHandle {
+    /// ID of the message that carried the request.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+/// Request waiting for its response.
+#[derive(Debug)]
+struct Pending {
+    id: u64,
+    /// Time that the request was sent.
+    sent: u64,
+    /// Time after which the request times out.
+    deadline: u64,
+    /// Outcome of the request, once it completed.
+    outcome: Option<Result<CommandResponse, RpcError>>,
+    /// Task to wake once the request completes.
+    #[cfg(feature = "async")]
+    waker: Option<core::task::Waker>,
+}
+
+/// Sends commands and correlates their responses. Up to `N` requests can be in flight at once.
+#[derive(Debug)]
+pub struct RpcClient<const N: usize> {
+    pending: heapless::Vec<Pending, N>,
+    /// ID of the next request.
+    next_id: u64,
+    /// Time to wait for a response, in microseconds.
+    timeout: u64,
+    clock: ClockSync,
+}
+
+impl<const N: usize> RpcClient<N> {
+    /// Instantiate a new [`RpcClient`]. Message IDs are allocated by the client, starting at
+    /// `first_id`, so they must not overlap with IDs used by other senders on the same side.
+    pub fn new(first_id: u64, timeout: u64) -> Self {
+        Self {
+            pending: heapless::Vec::new(),
+            next_id: first_id,
+            timeout,
+            clock: ClockSync::new(CLOCK_MAX_AGE),
+        }
+    }
+
+    /// Send a command, returning a handle to its response.
+    pub fn send<D: Device>(
+        &mut self,
+        device: &D,
+        command: Command,
+        now: u64,
+    ) -> Result<RequestHandle, RpcError> {
+        if self.pending.is_full() {
+            return Err(RpcError::TooManyRequests);
+        }
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let message = Message::new(
+            id,
+            MessageKind::Request(command),
+            now,
+            MessageFlags::empty(),
+        );
+        device.transmit(message)?;
+
+        // Can't fail since there was room for the request.
+        let _ = self.pending.push(Pending {
+            id,
+            sent: now,
+            deadline: now.saturating_add(self.timeout),
+            outcome: None,
+            #[cfg(feature = "async")]
+            waker: None,
+        });
+
+        Ok(RequestHandle { id })
+    }
+
+    /// Handle a message received at `now`. Responses to pending requests are consumed, and every
+    /// other message is given back.
+    pub fn receive(&mut self, message: Message, now: u64) -> Option<Message> {
+        let request = match message.kind() {
+            MessageKind::Response(request, _) => *request,
+            _ => return Some(message),
+        };
+        let pending = match self
+            .pending
+            .iter_mut()
+            .find(|pending| pending.id == request)
+        {
+            Some(pending) if pending.outcome.is_none() => pending,
+            _ => return Some(message),
+        };
+
+        self.clock.sample(pending.sent, message.send_time(), now);
+        let outcome = match message.into_kind() {
+            MessageKind::Response(_, CommandResponse::Failed(err)) => Err(RpcError::Failed(err)),
+            MessageKind::Response(_, response) => Ok(response),
+            _ => unreachable!(),
+        };
+        pending.complete(outcome);
+        None
+    }
+
+    /// Estimate of the vehicle's clock, from the round trips of the requests.
+    pub fn clock(&self) -> &ClockSync {
+        &self.clock
+    }
+
+    /// Time out every request whose deadline passed.
+    pub fn update(&mut self, now: u64) {
+        for pending in self.pending.iter_mut() {
+            if pending.outcome.is_none() && now >= pending.deadline {
+                pending.complete(Err(RpcError::Timeout));
+            }
+        }
+    }
+
+    /// Take the outcome of a request. Returns `None` while the request is still pending, or if it
+    /// is unknown to the client. Once taken, the handle is no longer valid.
+    pub fn take(&mut self, handle: RequestHandle) -> Option<Result<CommandResponse, RpcError>> {
+        let index = self
+            .pending
+            .iter()
+            .position(|pending| pending.id == handle.id && pending.outcome.is_some())?;
+        self.pending.swap_remove(index).outcome
+    }
+
+    /// Stop waiting for a request. Its response will be given back by [`RpcClient::receive`].
+    pub fn cancel(&mut self, handle: RequestHandle) {
+        if let Some(index) = self
+            .pending
+            .iter()
+            .position(|pending| pending.id == handle.id)
+        {
+            self.pending.swap_remove(index);
+        }
+    }
+
+    /// Send a command and block until its response arrives or it times out. Messages received
+    /// in the meantime that aren't responses are handed to `unhandled`.
+    pub fn call<D: Device>(
+        &mut self,
+        device: &D,
+        command: Command,
+        mut now: impl FnMut() -> u64,
+        mut unhandled: impl FnMut(Message),
+    ) -> Result<CommandResponse, RpcError> {
+        let handle = self.send(device, command, now())?;
+        loop {
+            let received = match device.poll() {
+                Ok(received) => received,
+                Err(err) => {
+                    self.cancel(handle);
+                    return Err(err.into());
+                }
+            };
+            let now = now();
+            if let Some(message) = received.and_then(|message| self.receive(message, now)) {
+                unhandled(message);
+            }
+
+            self.update(now);
+            if let Some(outcome) = self.take(handle) {
+                return outcome;
+            }
+        }
+    }
+
+    /// Wake the task once the request completes.
+    #[cfg(feature = "async")]
+    fn register(&mut self, handle: RequestHandle, waker: &core::task::Waker) {
+        if let Some(pending) = self
+            .pending
+            .iter_mut()
+            .find(|pending| pending.id == handle.id)
+        {
+            pending.waker = Some(waker.clone());
+        }
+    }
+}
+
+impl Pending {
+    fn complete(&mut self, outcome: Result<CommandResponse, RpcError>) {
+        self.outcome = Some(outcome);
+        #[cfg(feature = "async")]
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Build the response to a received request.
+pub fn response(request: &Message, id: u64, response: CommandResponse, now: u64) -> Message {
+    Message::new(
+        id,
+        MessageKind::Response(request.id(), response),
+        now,
+        MessageFlags::empty(),
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcError {
+    /// The device failed to transmit or receive.
+    Device(DeviceError),
+    /// No response arrived in time.
+    Timeout,
+    /// Too many requests are already in flight.
+    TooManyRequests,
+    /// The vehicle could not carry out the command.
+    Failed(CommandError),
+}
+
+impl From<DeviceError> for RpcError {
+    fn from(err: DeviceError) -> Self {
+        RpcError::Device(err)
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Device(err) => write!(f, "device error: {}", err)?,
+            RpcError::Timeout => write!(f, "request timed out")?,
+            RpcError::TooManyRequests => write!(f, "too many requests in flight")?,
+            RpcError::Failed(err) => write!(f, "command failed: {}", err)?,
+        };
+
+        Ok(())
+    }
+}
@@ -0,0 +1,499 @@
+//! Authenticated encryption of bridge traffic with ChaCha20-Poly1305 (RFC 8439).
+//!
+//! Both sides share a long-term key, and derive per-session keys from it and the identifiers
+//! exchanged in the [`MessageKind::Handshake`]: HChaCha20 mixes the identifiers into the shared
+//! key, and again derives one key for each direction of the link. Each side draws its identifier
+//! at random for every session with [`SecureDevice::start`], and can't establish a session without
+//! a fresh one, so the keys it seals with are never reused even if the other side reuses its
+//! identifier.
+//!
+//! A [`SecureDevice`] wraps any device, and seals every message into a [`MessageKind::Sealed`]
+//! holding its encrypted kind. The ID, send time and flags stay in the clear so the message can be
+//! routed and de-duplicated, but are authenticated along with the payload. The nonce is made of
+//! the message ID and the lower 32 bits of the send time. Both restart after a reboot, which is
+//! why the keys must be new for every session. Retransmissions keep both, along with the payload,
+//! so they are sealed into the same bytes without revealing anything. Handshakes and pings are
+//! never sealed, since they set up the session.
+//!
+//! Sealing doesn't protect against replayed messages.
+
+use chacha20::hchacha;
+use chacha20poly1305::consts::U10;
+use chacha20poly1305::{AeadInPlace, ChaCha20Poly1305, KeyInit, Nonce, Tag};
+use rand_core::{CryptoRng, RngCore};
+
+use crate::device::{Device, DeviceError, SignalQuality};
+use crate::message::{Encoding, Encodings, Message, MessageKind, SealedPayload, MAX_SEALED_LEN};
+
+/// Size of a key.
+pub const KEY_LEN: usize = 32;
+/// Size of the authentication tag appended to sealed payloads.
+pub const TAG_LEN: usize = 16;
+
+/// Labels of the keys of each direction of the link.
+const VEHICLE_TO_GROUND: &[u8; 16] = b"flick-bridge v2g";
+const GROUND_TO_VEHICLE: &[u8; 16] = b"flick-bridge g2v";
+
+/// Side of the link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Vehicle,
+    Ground,
+}
+
+/// Keys of a session.
+#[derive(Clone)]
+pub struct Session {
+    /// Key of the messages sent by this side.
+    transmit: ChaCha20Poly1305,
+    /// Key of the messages received from the other side.
+    receive: ChaCha20Poly1305,
+}
+
+impl Session {
+    /// Derive the keys of a session from the shared key and the identifiers of the vehicle and
+    /// the ground station.
+    pub fn derive(key: &[u8; KEY_LEN], vehicle: u64, ground: u64, role: Role) -> Self {
+        let mut ids = [0; 16];
+        ids[..8].copy_from_slice(&vehicle.to_le_bytes());
+        ids[8..].copy_from_slice(&ground.to_le_bytes());
+        let root = hchacha::<U10>(key.into(), &ids.into());
+
+        let to_ground = ChaCha20Poly1305::new(&hchacha::<U10>(&root, VEHICLE_TO_GROUND.into()));
+        let to_vehicle = ChaCha20Poly1305::new(&hchacha::<U10>(&root, GROUND_TO_VEHICLE.into()));
+        match role {
+            Role::Vehicle => Self {
+                transmit: to_ground,
+                receive: to_vehicle,
+            },
+            Role::Ground => Self {
+                transmit: to_vehicle,
+                receive: to_ground,
+            },
+        }
+    }
+
+    /// Encrypt a message into a [`MessageKind::Sealed`] message with the same ID, send time and
+    /// flags.
+    pub fn seal(&self, message: &Message) -> Result<Message, DeviceError> {
+        let mut buf = [0; MAX_SEALED_LEN];
+        let len = postcard::to_slice(message.kind(), &mut buf[..MAX_SEALED_LEN - TAG_LEN])
+            .map_err(|_| DeviceError::InvalidMessage)?
+            .len();
+
+        let (nonce, aad) = header(message);
+        let tag = self
+            .transmit
+            .encrypt_in_place_detached(&nonce, &aad, &mut buf[..len])
+            .map_err(|_| DeviceError::InvalidMessage)?;
+        buf[len..len + TAG_LEN].copy_from_slice(&tag);
+
+        let payload = SealedPayload::from_slice(&buf[..len + TAG_LEN])
+            .map_err(|_| DeviceError::InvalidMessage)?;
+        Ok(Message::new(
+            message.id(),
+            MessageKind::Sealed(payload),
+            message.send_time(),
+            message.flags(),
+        ))
+    }
+
+    /// Decrypt a [`MessageKind::Sealed`] message, rejecting it if it was tampered with.
+    pub fn open(&self, message: &Message) -> Result<Message, DeviceError> {
+        let payload = match message.kind() {
+            MessageKind::Sealed(payload) if payload.len() >= TAG_LEN => payload,
+            _ => return Err(DeviceError::InvalidMessage),
+        };
+
+        let len = payload.len() - TAG_LEN;
+        let mut buf = [0; MAX_SEALED_LEN];
+        buf[..len].copy_from_slice(&payload[..len]);
+        let tag = Tag::from_slice(&payload[len..]);
+
+        let (nonce, aad) = header(message);
+        self.receive
+            .decrypt_in_place_detached(&nonce, &aad, &mut buf[..len], tag)
+            .map_err(|_| DeviceError::InvalidMessage)?;
+        let kind = postcard::from_bytes(&buf[..len]).map_err(|_| DeviceError::InvalidMessage)?;
+
+        Ok(Message::new(
+            message.id(),
+            kind,
+            message.send_time(),
+            message.flags(),
+        ))
+    }
+}
+
+/// Nonce and associated data of a message.
+fn header(message: &Message) -> (Nonce, [u8; 17]) {
+    let mut nonce = Nonce::default();
+    nonce[..8].copy_from_slice(&message.id().to_le_bytes());
+    nonce[8..].copy_from_slice(&(message.send_time() as u32).to_le_bytes());
+
+    let mut aad = [0; 17];
+    aad[..8].copy_from_slice(&message.id().to_le_bytes());
+    aad[8..16].copy_from_slice(&message.send_time().to_le_bytes());
+    aad[16] = message.flags().bits();
+
+    (nonce, aad)
+}
+
+/// Whether a message is sent in the clear.
+fn is_clear(kind: &MessageKind) -> bool {
+    matches!(kind, MessageKind::Handshake(..) | MessageKind::Ping(..))
+}
+
+/// Device that encrypts the traffic of another device.
+pub struct SecureDevice<D> {
+    device: D,
+    key: [u8; KEY_LEN],
+    role: Role,
+    /// Identifier of this side for the next session, which no session used yet.
+    id: Option<u64>,
+    session: Option<Session>,
+}
+
+impl<D: Device> SecureDevice<D> {
+    /// Instantiate a new [`SecureDevice`] with the key shared by both sides. Until a session is
+    /// established, only handshakes and pings go through.
+    pub fn new(device: D, key: [u8; KEY_LEN], role: Role) -> Self {
+        Self {
+            device,
+            key,
+            role,
+            id: None,
+            session: None,
+        }
+    }
+
+    /// Draw a random identifier for this side, to send in the handshake of a new session. This
+    /// ends the current session.
+    pub fn start<R: RngCore + CryptoRng>(&mut self, rng: &mut R) -> u64 {
+        let id = rng.next_u64();
+        self.id = Some(id);
+        self.session = None;
+        id
+    }
+
+    /// Establish the session with the identifier that the other side sent in the handshake. Fails
+    /// with [`DeviceError::InvalidMessage`] unless [`start`](Self::start) drew an identifier for
+    /// this side since the last session was established.
+    pub fn establish(&mut self, remote: u64) -> Result<(), DeviceError> {
+        let id = self.id.take().ok_or(DeviceError::InvalidMessage)?;
+        let (vehicle, ground) = match self.role {
+            Role::Vehicle => (id, remote),
+            Role::Ground => (remote, id),
+        };
+        self.session = Some(Session::derive(&self.key, vehicle, ground, self.role));
+        Ok(())
+    }
+
+    /// End the session, after which only handshakes and pings go through.
+    pub fn end(&mut self) {
+        self.session = None;
+    }
+
+    /// Whether a session is established.
+    pub fn established(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Device that carries the encrypted traffic.
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+}
+
+impl<D: Device> Device for SecureDevice<D> {
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        if is_clear(message.kind()) {
+            return self.device.transmit(message);
+        }
+
+        let session = self.session.as_ref().ok_or(DeviceError::InvalidMessage)?;
+        self.device.transmit(session.seal(&message)?)
+    }
+
+    /// Receive a message, rejecting messages that weren't sealed with the session's keys.
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        let message = match self.device.poll()? {
+            Some(message) => message,
+            None => return Ok(None),
+        };
+        if is_clear(message.kind()) {
+            return Ok(Some(message));
+        }
+
+        let session = self.session.as_ref().ok_or(DeviceError::InvalidMessage)?;
+        session.open(&message).map(Some)
+    }
+
+    fn ping(&self, id: u64, freq: u64) -> Result<(), DeviceError> {
+        self.device.ping(id, freq)
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        self.device.freq()
+    }
+
+    fn set_freq(&mut self, freq: u64) -> Result<(), DeviceError> {
+        self.device.set_freq(freq)
+    }
+
+    fn signal_quality(&self) -> Option<SignalQuality> {
+        self.device.signal_quality()
+    }
+
+    fn encodings(&self) -> Encodings {
+        self.device.encodings()
+    }
+
+    fn set_encoding(&self, encoding: Encoding) -> Result<(), DeviceError> {
+        self.device.set_encoding(encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::MessageFlags;
+
+    use super::*;
+
+    /// Generator that counts up, which is as good as random for the tests.
+    struct Counter(u64);
+
+    impl RngCore for Counter {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 += 1;
+            self.0
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand_core::impls::fill_bytes_via_next(self, dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for Counter {}
+
+    /// Device that records the last message transmitted, and receives it back.
+    #[derive(Default)]
+    struct Wire(core::cell::RefCell<Option<Message>>);
+
+    impl Device for &Wire {
+        fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+            *self.0.borrow_mut() = Some(message);
+            Ok(())
+        }
+
+        fn poll(&self) -> Result<Option<Message>, DeviceError> {
+            Ok(self.0.borrow_mut().take())
+        }
+
+        fn ping(&self, _id: u64, _freq: u64) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn freq(&self) -> Result<u64, DeviceError> {
+            Ok(0)
+        }
+
+        fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+            Ok(())
+        }
+    }
+
+    const KEY: [u8; KEY_LEN] = [0x42; KEY_LEN];
+
+    fn message() -> Message {
+        Message::new(7, MessageKind::Ack(3), 123_456, MessageFlags::REQUIRES_ACK)
+    }
+
+    fn sessions() -> (Session, Session) {
+        (
+            Session::derive(&KEY, 1, 2, Role::Vehicle),
+            Session::derive(&KEY, 1, 2, Role::Ground),
+        )
+    }
+
+    /// Seal a message and replace its payload.
+    fn tampered(sealed: &Message, tamper: impl FnOnce(&mut SealedPayload)) -> Message {
+        let mut payload = match sealed.kind() {
+            MessageKind::Sealed(payload) => payload.clone(),
+            _ => unreachable!(),
+        };
+        tamper(&mut payload);
+        Message::new(
+            sealed.id(),
+            MessageKind::Sealed(payload),
+            sealed.send_time(),
+            sealed.flags(),
+        )
+    }
+
+    #[test]
+    fn encrypts_rfc8439_vector() {
+        // RFC 8439, section 2.8.2.
+        let key: [u8; 32] = core::array::from_fn(|i| 0x80 + i as u8);
+        let nonce = [
+            0x07, 0, 0, 0, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+        ];
+        let aad = [
+            0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+        ];
+        let mut buf =
+            *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip \
+            for the future, sunscreen would be it.";
+        let tag = ChaCha20Poly1305::new(&key.into())
+            .encrypt_in_place_detached(&nonce.into(), &aad, &mut buf)
+            .unwrap();
+        assert_eq!(buf, AEAD_CIPHERTEXT);
+        assert_eq!(tag[..], AEAD_TAG);
+    }
+
+    #[test]
+    fn derives_xchacha_draft_vector() {
+        // draft-irtf-cfrg-xchacha-03, section 2.2.1.
+        let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let input = [
+            0, 0, 0, 0x09, 0, 0, 0, 0x4a, 0, 0, 0, 0, 0x31, 0x41, 0x59, 0x27,
+        ];
+        assert_eq!(
+            hchacha::<U10>(&key.into(), &input.into())[..],
+            HCHACHA_OUTPUT
+        );
+    }
+
+    #[test]
+    fn opens_sealed_messages() {
+        let (vehicle, ground) = sessions();
+        let sealed = vehicle.seal(&message()).unwrap();
+        assert_eq!(sealed.id(), 7);
+        assert_eq!(sealed.send_time(), 123_456);
+        assert_eq!(sealed.flags(), MessageFlags::REQUIRES_ACK);
+
+        let opened = ground.open(&sealed).unwrap();
+        assert!(matches!(opened.kind(), MessageKind::Ack(3)));
+        assert_eq!(opened.id(), 7);
+        assert_eq!(opened.send_time(), 123_456);
+        assert_eq!(opened.flags(), MessageFlags::REQUIRES_ACK);
+
+        let sealed = ground.seal(&message()).unwrap();
+        assert!(matches!(
+            vehicle.open(&sealed).unwrap().kind(),
+            MessageKind::Ack(3)
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_messages() {
+        let (vehicle, ground) = sessions();
+        let sealed = vehicle.seal(&message()).unwrap();
+
+        let flipped = tampered(&sealed, |payload| payload[0] ^= 1);
+        assert!(ground.open(&flipped).is_err());
+        let tag = tampered(&sealed, |payload| {
+            let last = payload.len() - 1;
+            payload[last] ^= 1;
+        });
+        assert!(ground.open(&tag).is_err());
+        let truncated = tampered(&sealed, |payload| payload.truncate(TAG_LEN - 1));
+        assert!(ground.open(&truncated).is_err());
+
+        // The header is authenticated too.
+        let kind = sealed.kind().clone();
+        let id = Message::new(8, kind.clone(), 123_456, MessageFlags::REQUIRES_ACK);
+        assert!(ground.open(&id).is_err());
+        let send_time = Message::new(7, kind.clone(), 123_457, MessageFlags::REQUIRES_ACK);
+        assert!(ground.open(&send_time).is_err());
+        let flags = Message::new(7, kind, 123_456, MessageFlags::CRITICAL);
+        assert!(ground.open(&flags).is_err());
+    }
+
+    #[test]
+    fn rejects_other_keys() {
+        let (vehicle, _) = sessions();
+        let sealed = vehicle.seal(&message()).unwrap();
+
+        // Messages can't be reflected back to their sender.
+        assert!(vehicle.open(&sealed).is_err());
+        let other = Session::derive(&KEY, 1, 3, Role::Ground);
+        assert!(other.open(&sealed).is_err());
+        let other = Session::derive(&[0x43; KEY_LEN], 1, 2, Role::Ground);
+        assert!(other.open(&sealed).is_err());
+    }
+
+    #[test]
+    fn requires_a_fresh_identifier() {
+        let wire = Wire::default();
+        let mut rng = Counter(0);
+        let mut vehicle = SecureDevice::new(&wire, KEY, Role::Vehicle);
+        let mut ground = SecureDevice::new(&wire, KEY, Role::Ground);
+
+        assert!(vehicle.establish(1).is_err());
+        let vehicle_id = vehicle.start(&mut rng);
+        let ground_id = ground.start(&mut rng);
+        assert_ne!(vehicle_id, ground_id);
+        vehicle.establish(ground_id).unwrap();
+        ground.establish(vehicle_id).unwrap();
+
+        vehicle.transmit(message()).unwrap();
+        let received = ground.poll().unwrap().unwrap();
+        assert!(matches!(received.kind(), MessageKind::Ack(3)));
+
+        // Each identifier serves a single session.
+        assert!(vehicle.establish(ground_id).is_err());
+        assert!(vehicle.established());
+        assert_ne!(vehicle.start(&mut rng), vehicle_id);
+        assert!(!vehicle.established());
+    }
+
+    #[test]
+    fn sends_handshakes_in_the_clear() {
+        let wire = Wire::default();
+        let vehicle = SecureDevice::new(&wire, KEY, Role::Vehicle);
+
+        assert!(vehicle.transmit(message()).is_err());
+        let handshake = Message::new(
+            1,
+            MessageKind::Handshake(2, 3, 1, None),
+            0,
+            MessageFlags::empty(),
+        );
+        vehicle.transmit(handshake).unwrap();
+        let received = vehicle.poll().unwrap().unwrap();
+        assert!(matches!(
+            received.kind(),
+            MessageKind::Handshake(2, 3, 1, None)
+        ));
+    }
+
+    const AEAD_CIPHERTEXT: [u8; 114] = [
+        0xd3, 0x1a, 0x8d, 0x34, 0x64, 0x8e, 0x60, 0xdb, 0x7b, 0x86, 0xaf, 0xbc, 0x53, 0xef, 0x7e,
+        0xc2, 0xa4, 0xad, 0xed, 0x51, 0x29, 0x6e, 0x08, 0xfe, 0xa9, 0xe2, 0xb5, 0xa7, 0x36, 0xee,
+        0x62, 0xd6, 0x3d, 0xbe, 0xa4, 0x5e, 0x8c, 0xa9, 0x67, 0x12, 0x82, 0xfa, 0xfb, 0x69, 0xda,
+        0x92, 0x72, 0x8b, 0x1a, 0x71, 0xde, 0x0a, 0x9e, 0x06, 0x0b, 0x29, 0x05, 0xd6, 0xa5, 0xb6,
+        0x7e, 0xcd, 0x3b, 0x36, 0x92, 0xdd, 0xbd, 0x7f, 0x2d, 0x77, 0x8b, 0x8c, 0x98, 0x03, 0xae,
+        0xe3, 0x28, 0x09, 0x1b, 0x58, 0xfa, 0xb3, 0x24, 0xe4, 0xfa, 0xd6, 0x75, 0x94, 0x55, 0x85,
+        0x80, 0x8b, 0x48, 0x31, 0xd7, 0xbc, 0x3f, 0xf4, 0xde, 0xf0, 0x8e, 0x4b, 0x7a, 0x9d, 0xe5,
+        0x76, 0xd2, 0x65, 0x86, 0xce, 0xc6, 0x4b, 0x61, 0x16,
+    ];
+    const AEAD_TAG: [u8; 16] = [
+        0x1a, 0xe1, 0x0b, 0x59, 0x4f, 0x09, 0xe2, 0x6a, 0x7e, 0x90, 0x2e, 0xcb, 0xd0, 0x60, 0x06,
+        0x91,
+    ];
+    const HCHACHA_OUTPUT: [u8; 32] = [
+        0x82, 0x41, 0x3b, 0x42, 0x27, 0xb2, 0x7b, 0xfe, 0xd3, 0x0e, 0x42, 0x50, 0x8a, 0x87, 0x7d,
+        0x73, 0xa0, 0xf9, 0xe4, 0xd5, 0x8a, 0x74, 0xa8, 0x53, 0xc1, 0x2e, 0xc4, 0x13, 0x26, 0xd3,
+        0xec, 0xdc,
+    ];
+}
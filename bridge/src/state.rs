@@ -0,0 +1,109 @@
+//! States of the flight, shared by the vehicle and the ground.
+//!
+//! States travel as their identifier in [`Command::ChangeState`](crate::command::Command::ChangeState)
+//! and [`TelemetrySnapshot::state`](crate::telemetry::TelemetrySnapshot::state). Identifiers are
+//! part of the protocol, so they never change once assigned.
+
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// State of the flight.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FlightState {
+    /// On the pad, with the pyros safe.
+    Idle,
+    /// On the pad, ready for launch.
+    Armed,
+    /// Motor burning.
+    Boost,
+    /// Coasting up to apogee after burnout.
+    Coast,
+    /// Apogee was reached, and the drogue parachute is being deployed.
+    Apogee,
+    /// Descending under the drogue parachute.
+    DrogueDescent,
+    /// Descending under the main parachute.
+    MainDescent,
+    /// On the ground after the flight.
+    Landed,
+    /// Something went wrong before launch, and the vehicle must not fly.
+    Fault,
+}
+
+impl FlightState {
+    /// Every state, ordered by identifier.
+    pub const ALL: [FlightState; 9] = [
+        FlightState::Idle,
+        FlightState::Armed,
+        FlightState::Boost,
+        FlightState::Coast,
+        FlightState::Apogee,
+        FlightState::DrogueDescent,
+        FlightState::MainDescent,
+        FlightState::Landed,
+        FlightState::Fault,
+    ];
+
+    /// Identifier of the state.
+    pub const fn id(self) -> u32 {
+        match self {
+            FlightState::Idle => 0,
+            FlightState::Armed => 1,
+            FlightState::Boost => 2,
+            FlightState::Coast => 3,
+            FlightState::Apogee => 4,
+            FlightState::DrogueDescent => 5,
+            FlightState::MainDescent => 6,
+            FlightState::Landed => 7,
+            FlightState::Fault => 8,
+        }
+    }
+
+    /// State with the given identifier, if there is one.
+    pub fn from_id(id: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|state| state.id() == id)
+    }
+
+    /// Whether the vehicle is flying, between launch and landing.
+    pub fn in_flight(self) -> bool {
+        matches!(
+            self,
+            FlightState::Boost
+                | FlightState::Coast
+                | FlightState::Apogee
+                | FlightState::DrogueDescent
+                | FlightState::MainDescent
+        )
+    }
+
+    /// State that normally follows this one in flight, if the vehicle is flying.
+    pub fn next_in_flight(self) -> Option<Self> {
+        match self {
+            FlightState::Boost => Some(FlightState::Coast),
+            FlightState::Coast => Some(FlightState::Apogee),
+            FlightState::Apogee => Some(FlightState::DrogueDescent),
+            FlightState::DrogueDescent => Some(FlightState::MainDescent),
+            FlightState::MainDescent => Some(FlightState::Landed),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FlightState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlightState::Idle => write!(f, "idle")?,
+            FlightState::Armed => write!(f, "armed")?,
+            FlightState::Boost => write!(f, "boost")?,
+            FlightState::Coast => write!(f, "coast")?,
+            FlightState::Apogee => write!(f, "apogee")?,
+            FlightState::DrogueDescent => write!(f, "drogue descent")?,
+            FlightState::MainDescent => write!(f, "main descent")?,
+            FlightState::Landed => write!(f, "landed")?,
+            FlightState::Fault => write!(f, "fault")?,
+        };
+
+        Ok(())
+    }
+}
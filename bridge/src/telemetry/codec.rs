@@ -0,0 +1,340 @@
+//! Delta-compression of telemetry snapshots.
+//!
+//! Consecutive snapshots barely change, so instead of sending every field of every snapshot, the
+//! encoder periodically sends a keyframe holding every field, and in between only sends the
+//! differences to the previous snapshot.
+//!
+//! Every frame starts with a header byte: the top bit is set for keyframes, and the remaining 7
+//! bits are a sequence number used to detect lost frames. Keyframes then hold every quantized
+//! field, while delta frames hold a bit-mask of the fields that changed followed by their
+//! differences. Fields are fixed-point quantized (see [`Quantization`]) and written as
+//! zigzag-encoded LEB128 varints, so small differences only take a single byte.
+
+use core::fmt;
+
+use super::{TelemetryPayload, TelemetrySnapshot, MAX_PAYLOAD_LEN};
+
+/// Number of quantized fields in a snapshot: time, state, position, orientation, velocity and
+/// acceleration.
+const FIELD_COUNT: usize = 15;
+/// Header bit that marks a keyframe.
+const KEYFRAME_BIT: u8 = 0x80;
+/// Mask of the sequence number in the header.
+const SEQUENCE_MASK: u8 = 0x7f;
+
+/// Quantized fields of a snapshot.
+type Fields = [i64; FIELD_COUNT];
+
+/// Fixed-point resolution that each kind of field is quantized to before it is encoded. Both
+/// sides of the link must agree on the quantization.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantization {
+	/// Resolution of positions, in meters.
+	pub position: f64,
+	/// Resolution of velocities, in meters per second.
+	pub velocity: f64,
+	/// Resolution of accelerations, in meters per second squared.
+	pub acceleration: f64,
+	/// Resolution of each quaternion component.
+	pub orientation: f64,
+}
+
+impl Default for Quantization {
+	fn default() -> Self {
+		Self {
+			position: 1e-3,
+			velocity: 1e-3,
+			acceleration: 1e-2,
+			orientation: 1.0 / 32767.0,
+		}
+	}
+}
+
+impl Quantization {
+	/// Quantize every field of a snapshot.
+	fn quantize(&self, snapshot: &TelemetrySnapshot) -> Fields {
+		let mut fields = [0; FIELD_COUNT];
+		fields[0] = snapshot.time as i64;
+		fields[1] = snapshot.state as i64;
+		for i in 0..3 {
+			fields[2 + i] = quantize(snapshot.position[i], self.position);
+			fields[9 + i] = quantize(snapshot.velocity[i], self.velocity);
+			fields[12 + i] = quantize(snapshot.acceleration[i], self.acceleration);
+		}
+		for i in 0..4 {
+			fields[5 + i] = quantize(snapshot.orientation[i], self.orientation);
+		}
+
+		fields
+	}
+
+	/// Reconstruct a snapshot from its quantized fields.
+	fn dequantize(&self, fields: &Fields) -> TelemetrySnapshot {
+		let mut snapshot = TelemetrySnapshot {
+			time: fields[0] as u64,
+			state: fields[1] as u32,
+			..Default::default()
+		};
+		for i in 0..3 {
+			snapshot.position[i] = fields[2 + i] as f64 * self.position;
+			snapshot.velocity[i] = fields[9 + i] as f64 * self.velocity;
+			snapshot.acceleration[i] = fields[12 + i] as f64 * self.acceleration;
+		}
+		for i in 0..4 {
+			snapshot.orientation[i] = fields[5 + i] as f64 * self.orientation;
+		}
+
+		snapshot
+	}
+}
+
+/// Round a value to the nearest multiple of the resolution. Values out of range saturate.
+fn quantize(value: f64, resolution: f64) -> i64 {
+	let scaled = value / resolution;
+	if scaled >= 0.0 {
+		(scaled + 0.5) as i64
+	} else {
+		(scaled - 0.5) as i64
+	}
+}
+
+/// Encodes a stream of telemetry snapshots into keyframes and delta frames.
+#[derive(Debug, Clone)]
+pub struct TelemetryEncoder {
+	/// Quantization of the fields.
+	quantization: Quantization,
+	/// Number of delta frames sent between two keyframes.
+	keyframe_interval: u16,
+	/// Number of delta frames sent since the last keyframe.
+	since_keyframe: u16,
+	/// Sequence number of the next frame.
+	sequence: u8,
+	/// Quantized fields of the previously encoded snapshot.
+	previous: Option<Fields>,
+}
+
+impl TelemetryEncoder {
+	/// Instantiate a new [`TelemetryEncoder`] that sends a keyframe after every
+	/// `keyframe_interval` delta frames. The first frame is always a keyframe.
+	pub fn new(quantization: Quantization, keyframe_interval: u16) -> Self {
+		Self {
+			quantization,
+			keyframe_interval,
+			since_keyframe: 0,
+			sequence: 0,
+			previous: None,
+		}
+	}
+
+	/// Make the next frame a keyframe. This should be called whenever the receiver may have lost
+	/// track of the stream, such as after the link was re-established.
+	pub fn force_keyframe(&mut self) {
+		self.previous = None;
+	}
+
+	/// Encode a snapshot into the buffer, returning the number of bytes written.
+	pub fn encode(
+		&mut self,
+		snapshot: &TelemetrySnapshot,
+		buf: &mut [u8],
+	) -> Result<usize, CodecError> {
+		let fields = self.quantization.quantize(snapshot);
+		let mut writer = Writer { buf, pos: 0 };
+
+		match self.previous {
+			Some(previous) if self.since_keyframe < self.keyframe_interval => {
+				writer.write_byte(self.sequence)?;
+				let mut mask = 0u64;
+				for i in 0..FIELD_COUNT {
+					if fields[i] != previous[i] {
+						mask |= 1 << i;
+					}
+				}
+				writer.write_varint(mask)?;
+				for i in 0..FIELD_COUNT {
+					if mask & (1 << i) != 0 {
+						writer.write_signed(fields[i].wrapping_sub(previous[i]))?;
+					}
+				}
+				self.since_keyframe += 1;
+			}
+			_ => {
+				writer.write_byte(KEYFRAME_BIT | self.sequence)?;
+				for field in fields {
+					writer.write_signed(field)?;
+				}
+				self.since_keyframe = 0;
+			}
+		}
+
+		self.sequence = (self.sequence + 1) & SEQUENCE_MASK;
+		self.previous = Some(fields);
+		Ok(writer.pos)
+	}
+
+	/// Encode a snapshot into the payload of a
+	/// [`MessageKind::Telemetry`](crate::message::MessageKind::Telemetry) message. The message
+	/// must be sent with the [`MessageFlags::COMPRESSED`](crate::message::MessageFlags::COMPRESSED)
+	/// flag.
+	pub fn encode_payload(
+		&mut self,
+		snapshot: &TelemetrySnapshot,
+	) -> Result<TelemetryPayload, CodecError> {
+		let mut buf = [0; MAX_PAYLOAD_LEN];
+		let len = self.encode(snapshot, &mut buf)?;
+		TelemetryPayload::from_slice(&buf[..len]).map_err(|_| CodecError::BufferTooSmall)
+	}
+}
+
+/// Decodes a stream of frames produced by a [`TelemetryEncoder`].
+#[derive(Debug, Clone)]
+pub struct TelemetryDecoder {
+	/// Quantization of the fields.
+	quantization: Quantization,
+	/// Sequence number of the next expected frame.
+	sequence: u8,
+	/// Quantized fields of the previously decoded snapshot.
+	previous: Option<Fields>,
+}
+
+impl TelemetryDecoder {
+	/// Instantiate a new [`TelemetryDecoder`]. Frames are rejected until the first keyframe.
+	pub fn new(quantization: Quantization) -> Self {
+		Self {
+			quantization,
+			sequence: 0,
+			previous: None,
+		}
+	}
+
+	/// Decode a single frame. If a frame was lost, every delta frame is rejected until the next
+	/// keyframe.
+	pub fn decode(&mut self, bytes: &[u8]) -> Result<TelemetrySnapshot, CodecError> {
+		let mut reader = Reader { bytes, pos: 0 };
+		let header = reader.read_byte()?;
+		let sequence = header & SEQUENCE_MASK;
+
+		let fields = if header & KEYFRAME_BIT != 0 {
+			let mut fields = [0; FIELD_COUNT];
+			for field in fields.iter_mut() {
+				*field = reader.read_signed()?;
+			}
+			fields
+		} else {
+			let mut fields = self.previous.ok_or(CodecError::MissingKeyframe)?;
+			if sequence != self.sequence {
+				self.previous = None;
+				return Err(CodecError::SequenceGap);
+			}
+
+			let mask = reader.read_varint()?;
+			if mask >> FIELD_COUNT != 0 {
+				return Err(CodecError::InvalidFrame);
+			}
+			for (i, field) in fields.iter_mut().enumerate() {
+				if mask & (1 << i) != 0 {
+					*field = field.wrapping_add(reader.read_signed()?);
+				}
+			}
+			fields
+		};
+
+		if reader.pos != bytes.len() {
+			return Err(CodecError::InvalidFrame);
+		}
+
+		self.sequence = (sequence + 1) & SEQUENCE_MASK;
+		self.previous = Some(fields);
+		Ok(self.quantization.dequantize(&fields))
+	}
+}
+
+/// Writes varints into a buffer.
+struct Writer<'a> {
+	buf: &'a mut [u8],
+	pos: usize,
+}
+
+impl Writer<'_> {
+	fn write_byte(&mut self, byte: u8) -> Result<(), CodecError> {
+		let slot = self
+			.buf
+			.get_mut(self.pos)
+			.ok_or(CodecError::BufferTooSmall)?;
+		*slot = byte;
+		self.pos += 1;
+		Ok(())
+	}
+
+	fn write_varint(&mut self, mut value: u64) -> Result<(), CodecError> {
+		while value >= 0x80 {
+			self.write_byte(value as u8 | 0x80)?;
+			value >>= 7;
+		}
+		self.write_byte(value as u8)
+	}
+
+	fn write_signed(&mut self, value: i64) -> Result<(), CodecError> {
+		self.write_varint(((value << 1) ^ (value >> 63)) as u64)
+	}
+}
+
+/// Reads varints from a buffer.
+struct Reader<'a> {
+	bytes: &'a [u8],
+	pos: usize,
+}
+
+impl Reader<'_> {
+	fn read_byte(&mut self) -> Result<u8, CodecError> {
+		let byte = *self.bytes.get(self.pos).ok_or(CodecError::Truncated)?;
+		self.pos += 1;
+		Ok(byte)
+	}
+
+	fn read_varint(&mut self) -> Result<u64, CodecError> {
+		let mut value = 0u64;
+		for shift in (0..64).step_by(7) {
+			let byte = self.read_byte()?;
+			value |= ((byte & 0x7f) as u64) << shift;
+			if byte & 0x80 == 0 {
+				return Ok(value);
+			}
+		}
+
+		Err(CodecError::InvalidFrame)
+	}
+
+	fn read_signed(&mut self) -> Result<i64, CodecError> {
+		let value = self.read_varint()?;
+		Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodecError {
+	/// The output buffer is too small to hold the frame.
+	BufferTooSmall,
+	/// The frame ended unexpectedly.
+	Truncated,
+	/// The frame is malformed.
+	InvalidFrame,
+	/// A delta frame was received before any keyframe.
+	MissingKeyframe,
+	/// A frame was lost, so delta frames can't be applied until the next keyframe.
+	SequenceGap,
+}
+
+impl fmt::Display for CodecError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			CodecError::BufferTooSmall => write!(f, "buffer too small to hold frame")?,
+			CodecError::Truncated => write!(f, "frame ended unexpectedly")?,
+			CodecError::InvalidFrame => write!(f, "malformed frame")?,
+			CodecError::MissingKeyframe => write!(f, "delta frame received before keyframe")?,
+			CodecError::SequenceGap => write!(f, "frame lost since last keyframe")?,
+		};
+
+		Ok(())
+	}
+}
@@ -0,0 +1,31 @@
+//! Telemetry of the vehicle, and the codec used to stream it.
+
+use serde::{Deserialize, Serialize};
+
+pub mod codec;
+
+/// Largest payload that can be carried by a [`MessageKind::Telemetry`](crate::message::MessageKind::Telemetry)
+/// message.
+pub const MAX_PAYLOAD_LEN: usize = 160;
+
+/// Raw payload of a streamed telemetry message.
+pub type TelemetryPayload = heapless::Vec<u8, MAX_PAYLOAD_LEN>;
+
+/// Sensor-data of the vehicle at a given instant. All vectors are in the east-north-up earth frame
+/// centered on the launch site, in meters and seconds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct TelemetrySnapshot {
+	/// Time that the snapshot was taken.
+	pub time: u64,
+	/// Identifier of the state of the vehicle (see [`FlightState::id`](crate::state::FlightState::id)).
+	pub state: u32,
+	/// Position relative to the launch site.
+	pub position: [f64; 3],
+	/// Rotation from the forward-left-up body frame to the earth frame as a unit quaternion, in
+	/// `[w, i, j, k]` order.
+	pub orientation: [f64; 4],
+	/// Velocity.
+	pub velocity: [f64; 3],
+	/// Acceleration.
+	pub acceleration: [f64; 3],
+}
@@ -0,0 +1,193 @@
+//! Tests of captures taken below the decoder, and of their dissection.
+
+#![cfg(feature = "std")]
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::UdpSocket;
+use std::rc::Rc;
+
+use flick_bridge::command::Command;
+use flick_bridge::device::{Device, DeviceError};
+use flick_bridge::frame::stream::{self, MAX_ENCODED_LEN};
+use flick_bridge::frame::{self, FrameError, MAX_FRAME_LEN};
+use flick_bridge::host::capture::dissect::{Dissection, Dissector};
+use flick_bridge::host::capture::{
+    CaptureReader, CaptureStream, CaptureWriter, Direction, Layer, Record,
+};
+use flick_bridge::host::stream::StreamDevice;
+use flick_bridge::host::udp::UdpDevice;
+use flick_bridge::message::{Message, MessageFlags, MessageKind};
+
+/// Stream that reads queued bytes and keeps what is written to it.
+#[derive(Default)]
+struct MockStream {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.input.is_empty() {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        let len = self.input.read(buf)?;
+        Ok(len)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Capture file kept in memory, shared with the test.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn heartbeat(id: u64) -> Message {
+    Message::new(
+        id,
+        MessageKind::Request(Command::Heartbeat),
+        0,
+        MessageFlags::REQUIRES_ACK,
+    )
+}
+
+/// Stream-encoded frame of a message, with a byte of the frame flipped if `corrupt`.
+fn encoded(message: &Message, corrupt: bool) -> Vec<u8> {
+    let mut buf = [0; MAX_FRAME_LEN];
+    let mut frame = frame::encode(message, &mut buf).unwrap().to_vec();
+    if corrupt {
+        frame[1] ^= 0x40;
+    }
+    let mut encoded = [0; MAX_ENCODED_LEN];
+    let len = stream::encode(&frame, &mut encoded).unwrap();
+    encoded[..len].to_vec()
+}
+
+fn records(capture: &[u8]) -> Vec<Record> {
+    CaptureReader::new(capture)
+        .unwrap()
+        .collect::<io::Result<_>>()
+        .unwrap()
+}
+
+fn dissect(records: &[Record]) -> Vec<(Direction, Dissection)> {
+    let mut dissector = Dissector::new();
+    records
+        .iter()
+        .flat_map(|record| {
+            dissector
+                .dissect(record)
+                .into_iter()
+                .map(|dissection| (record.direction, dissection))
+        })
+        .collect()
+}
+
+#[test]
+fn captures_streams_in_both_directions() {
+    let mut stream = MockStream::default();
+    stream.input.extend(encoded(&heartbeat(1), true));
+    stream.input.extend([0x13, 0x37]);
+    stream.input.push_back(stream::DELIMITER);
+    stream.input.extend(encoded(&heartbeat(2), false));
+    let capture = CaptureWriter::new(Vec::new()).unwrap();
+    let device = StreamDevice::new(CaptureStream::new(stream, capture));
+
+    device.transmit(heartbeat(7)).unwrap();
+    // The corrupted frame and the noise are dropped by the device, but captured.
+    assert!(matches!(device.poll(), Err(DeviceError::InvalidMessage)));
+    assert!(matches!(device.poll(), Err(DeviceError::InvalidMessage)));
+    assert_eq!(device.poll().unwrap().unwrap().id(), 2);
+
+    let stream = device.stream();
+    let records = records(stream.writer().get_ref());
+    assert!(records.iter().all(|record| record.layer == Layer::Stream));
+    assert_eq!(records[0].direction, Direction::Transmitted);
+    assert_eq!(records[0].bytes, stream.stream().output);
+
+    let dissections = dissect(&records);
+    assert_eq!(dissections.len(), 4);
+    assert!(matches!(
+        &dissections[0],
+        (Direction::Transmitted, Dissection::Message { message, .. }) if message.id() == 7
+    ));
+    assert!(matches!(
+        dissections[1],
+        (Direction::Received, Dissection::Invalid(FrameError::BadCrc))
+    ));
+    assert!(matches!(
+        dissections[2],
+        (Direction::Received, Dissection::Invalid(_))
+    ));
+    assert!(matches!(
+        &dissections[3],
+        (Direction::Received, Dissection::Message { message, .. }) if message.id() == 2
+    ));
+}
+
+#[test]
+fn captures_datagrams_before_decoding() {
+    let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut device = UdpDevice::bind("127.0.0.1:0", peer.local_addr().unwrap()).unwrap();
+    let capture = SharedBuffer::default();
+    let writer: Box<dyn Write> = Box::new(capture.clone());
+    device.set_capture(Some(CaptureWriter::new(writer).unwrap()));
+
+    device.transmit(heartbeat(1)).unwrap();
+    let mut buf = [0; 1024];
+    let (len, _) = peer.recv_from(&mut buf).unwrap();
+    let addr = device.local_addr().unwrap();
+    peer.send_to(&[0xff; 3], addr).unwrap();
+    peer.send_to(&buf[..len], addr).unwrap();
+
+    let mut polled = Vec::new();
+    while polled.len() < 2 {
+        match device.poll() {
+            Ok(None) => std::thread::sleep(std::time::Duration::from_millis(1)),
+            result => polled.push(result),
+        }
+    }
+    assert!(matches!(polled[0], Err(DeviceError::InvalidMessage)));
+
+    let records = records(&capture.0.borrow());
+    assert_eq!(records.len(), 3);
+    assert!(records.iter().all(|record| record.layer == Layer::Message));
+    assert_eq!(records[1].bytes, [0xff; 3]);
+
+    let dissections = dissect(&records);
+    assert!(matches!(
+        dissections[0],
+        (Direction::Transmitted, Dissection::Message { .. })
+    ));
+    assert!(matches!(
+        dissections[1],
+        (
+            Direction::Received,
+            Dissection::Invalid(FrameError::Malformed)
+        )
+    ));
+    assert!(matches!(
+        dissections[2],
+        (Direction::Received, Dissection::Message { .. })
+    ));
+}
@@ -0,0 +1,63 @@
+//! Tests of the CBOR encoding of messages, which needs neither `std` nor an allocator.
+
+#![cfg(feature = "cbor")]
+
+use flick_bridge::command::{Command, CommandResponse};
+use flick_bridge::message::{Encoding, Message, MessageFlags, MessageKind, MAX_MESSAGE_LEN};
+
+fn round_trip(message: &Message) -> (Vec<u8>, Message) {
+    let mut buf = [0; MAX_MESSAGE_LEN];
+    let bytes = message.encode_with(Encoding::Cbor, &mut buf).unwrap();
+    let decoded = Message::decode_with(Encoding::Cbor, bytes).unwrap();
+    (bytes.to_vec(), decoded)
+}
+
+#[test]
+fn round_trips_messages() {
+    let message = Message::new(
+        u64::MAX,
+        MessageKind::Response(7, CommandResponse::Scheduled(3)),
+        123_456,
+        MessageFlags::REQUIRES_ACK,
+    );
+    let (_, decoded) = round_trip(&message);
+    assert_eq!(decoded.id(), u64::MAX);
+    assert_eq!(decoded.send_time(), 123_456);
+    assert_eq!(decoded.flags(), MessageFlags::REQUIRES_ACK);
+    assert!(matches!(
+        decoded.kind(),
+        MessageKind::Response(7, CommandResponse::Scheduled(3))
+    ));
+}
+
+#[test]
+fn names_fields_and_variants() {
+    let message = Message::new(
+        1,
+        MessageKind::Request(Command::Heartbeat),
+        0,
+        MessageFlags::empty(),
+    );
+    let (bytes, _) = round_trip(&message);
+    let contains = |name: &[u8]| bytes.windows(name.len()).any(|window| window == name);
+    assert!(contains(b"send_time"));
+    assert!(contains(b"Request"));
+    assert!(contains(b"Heartbeat"));
+}
+
+#[test]
+fn rejects_what_does_not_fit_or_decode() {
+    let message = Message::new(
+        1,
+        MessageKind::Request(Command::Heartbeat),
+        0,
+        MessageFlags::empty(),
+    );
+    assert!(message.encode_with(Encoding::Cbor, &mut [0; 8]).is_err());
+
+    let (bytes, _) = round_trip(&message);
+    assert!(Message::decode_with(Encoding::Cbor, &bytes[..bytes.len() - 1]).is_err());
+    let mut padded = bytes.clone();
+    padded.push(0);
+    assert!(Message::decode_with(Encoding::Cbor, &padded).is_err());
+}
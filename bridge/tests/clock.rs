@@ -0,0 +1,59 @@
+//! Tests of the estimate of the other side's clock.
+
+use flick_bridge::clock::ClockSync;
+
+const SECOND: u64 = 1_000_000;
+
+#[test]
+fn estimates_the_offset_from_the_midpoint_of_the_round_trip() {
+    let mut clock = ClockSync::new(10 * SECOND);
+    assert!(!clock.synchronized());
+    assert_eq!(clock.to_remote(0), None);
+
+    // The remote clock is 100 s ahead, and the round trip took 20 ms.
+    assert!(clock.sample(SECOND, 101 * SECOND + 10_000, SECOND + 20_000));
+    assert_eq!(clock.offset(), Some(100 * SECOND as i64));
+    assert_eq!(clock.uncertainty(), Some(10_000));
+    assert_eq!(clock.to_remote(2 * SECOND), Some(102 * SECOND));
+    assert_eq!(clock.to_local(102 * SECOND), Some(2 * SECOND));
+}
+
+#[test]
+fn handles_a_remote_clock_behind_the_local_one() {
+    let mut clock = ClockSync::new(10 * SECOND);
+    assert!(clock.sample(100 * SECOND, 2 * SECOND, 100 * SECOND));
+    assert_eq!(clock.offset(), Some(-98 * SECOND as i64));
+    assert_eq!(clock.to_remote(100 * SECOND), Some(2 * SECOND));
+    assert_eq!(clock.to_local(2 * SECOND), Some(100 * SECOND));
+    // Times before the start of the remote clock saturate.
+    assert_eq!(clock.to_remote(SECOND), Some(0));
+}
+
+#[test]
+fn keeps_the_sample_with_the_shortest_round_trip() {
+    let mut clock = ClockSync::new(10 * SECOND);
+    assert!(clock.sample(0, 5_000, 10_000));
+    assert!(!clock.sample(SECOND, SECOND + 50_000, SECOND + 40_000));
+    assert_eq!(clock.uncertainty(), Some(5_000));
+    assert!(clock.sample(2 * SECOND, 2 * SECOND + 3_000, 2 * SECOND + 2_000));
+    assert_eq!(clock.offset(), Some(2_000));
+    assert_eq!(clock.uncertainty(), Some(1_000));
+}
+
+#[test]
+fn replaces_old_samples_to_follow_drift() {
+    let mut clock = ClockSync::new(10 * SECOND);
+    assert!(clock.sample(0, 1_000, 2_000));
+    assert!(!clock.sample(9 * SECOND, 9 * SECOND + 10_000, 9 * SECOND + 20_000));
+    // More than 10 s after the best sample, even a longer round trip replaces it.
+    assert!(clock.sample(11 * SECOND, 11 * SECOND + 20_000, 11 * SECOND + 20_000));
+    assert_eq!(clock.offset(), Some(10_000));
+    assert_eq!(clock.uncertainty(), Some(10_000));
+}
+
+#[test]
+fn ignores_round_trips_ending_before_they_started() {
+    let mut clock = ClockSync::new(10 * SECOND);
+    assert!(!clock.sample(SECOND, SECOND, 0));
+    assert!(!clock.synchronized());
+}
@@ -0,0 +1,207 @@
+//! Tests of the emulator over a mock device.
+
+#![cfg(feature = "std")]
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use flick_bridge::command::{Command, CommandError, CommandResponse};
+use flick_bridge::device::{Device, DeviceError};
+use flick_bridge::event::EventKind;
+use flick_bridge::handshake;
+use flick_bridge::host::emulator::flight::FlightConfig;
+use flick_bridge::host::emulator::{Emulator, EmulatorConfig};
+use flick_bridge::message::{Message, MessageFlags, MessageKind};
+use flick_bridge::state::FlightState;
+
+/// Device whose received messages are queued by the test, and which records what it sends.
+#[derive(Default)]
+struct MockDevice {
+    received: RefCell<VecDeque<Message>>,
+    sent: RefCell<Vec<Message>>,
+}
+
+impl MockDevice {
+    fn receive(&self, message: Message) {
+        self.received.borrow_mut().push_back(message);
+    }
+
+    /// Messages sent since last called, apart from telemetry and heartbeats.
+    fn sent(&self) -> Vec<Message> {
+        self.sent
+            .borrow_mut()
+            .drain(..)
+            .filter(|message| {
+                !matches!(
+                    message.kind(),
+                    MessageKind::Telemetry(_) | MessageKind::Request(Command::Heartbeat)
+                )
+            })
+            .collect()
+    }
+}
+
+impl Device for MockDevice {
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        self.sent.borrow_mut().push(message);
+        Ok(())
+    }
+
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        Ok(self.received.borrow_mut().pop_front())
+    }
+
+    fn ping(&self, _id: u64, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        Ok(0)
+    }
+
+    fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+/// Emulator waiting on the pad, with a motor that burns for 50 ms.
+fn emulator() -> Emulator {
+    Emulator::new(EmulatorConfig {
+        launch_after: None,
+        flight: FlightConfig {
+            burn_time: 0.05,
+            ..FlightConfig::default()
+        },
+        ..EmulatorConfig::default()
+    })
+}
+
+/// Launch the rocket, and wait for the motor to burn out.
+fn coast(emulator: &mut Emulator, device: &MockDevice) {
+    let boost = FlightState::Boost.id();
+    command(emulator, device, 100, Command::ChangeState(boost));
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    emulator.step(device).unwrap();
+    device.sent();
+}
+
+/// Whether the command was refused in the current state.
+fn failed(response: &Message) -> bool {
+    matches!(
+        response.kind(),
+        MessageKind::Response(_, CommandResponse::Failed(CommandError::InvalidState))
+    )
+}
+
+fn request(id: u64, command: Command) -> Message {
+    Message::new(
+        id,
+        MessageKind::Request(command),
+        0,
+        MessageFlags::REQUIRES_ACK,
+    )
+}
+
+/// Send a command to the emulator, and return its response.
+fn command(emulator: &mut Emulator, device: &MockDevice, id: u64, command: Command) -> Message {
+    device.receive(request(id, command));
+    emulator.step(device).unwrap();
+    device
+        .sent()
+        .into_iter()
+        .find(
+            |message| matches!(message.kind(), MessageKind::Response(request, _) if *request == id),
+        )
+        .unwrap()
+}
+
+#[test]
+fn acknowledges_requests_once_handled() {
+    let mut emulator = emulator();
+    let device = MockDevice::default();
+
+    device.receive(request(1, Command::TogglePyro(0)));
+    emulator.step(&device).unwrap();
+    let sent = device.sent();
+    assert_eq!(sent.len(), 2);
+    assert!(matches!(
+        sent[0].kind(),
+        MessageKind::Response(1, CommandResponse::Done)
+    ));
+    assert!(matches!(sent[1].kind(), MessageKind::Ack(1)));
+}
+
+#[test]
+fn fires_armed_pyros_only_after_burnout() {
+    let mut emulator = emulator();
+    let device = MockDevice::default();
+
+    let response = command(&mut emulator, &device, 1, Command::FirePyro(2));
+    assert!(failed(&response));
+    // Still locked out on the pad once armed.
+    command(&mut emulator, &device, 2, Command::TogglePyro(2));
+    let response = command(&mut emulator, &device, 3, Command::FirePyro(2));
+    assert!(failed(&response));
+
+    coast(&mut emulator, &device);
+    device.receive(request(4, Command::FirePyro(2)));
+    emulator.step(&device).unwrap();
+    let sent = device.sent();
+    assert!(sent.iter().any(|message| matches!(
+        message.kind(),
+        MessageKind::Response(4, CommandResponse::Done)
+    )));
+    assert!(sent.iter().any(|message| matches!(
+        message.kind(),
+        MessageKind::Event(event) if event.kind == EventKind::PyroFired(2)
+    )));
+
+    // Disarmed again.
+    command(&mut emulator, &device, 5, Command::TogglePyro(2));
+    let response = command(&mut emulator, &device, 6, Command::FirePyro(2));
+    assert!(failed(&response));
+}
+
+#[test]
+fn answers_copies_without_handling_them_again() {
+    let mut emulator = emulator();
+    let device = MockDevice::default();
+    coast(&mut emulator, &device);
+
+    device.receive(request(1, Command::TogglePyro(3)));
+    emulator.step(&device).unwrap();
+    let first = device.sent();
+
+    // The acknowledgement was lost, so the ground retransmits the request.
+    device.receive(request(1, Command::TogglePyro(3)));
+    emulator.step(&device).unwrap();
+    let again = device.sent();
+    assert_eq!(again.len(), 2);
+    for (first, again) in first.iter().zip(&again) {
+        assert_eq!(first.id(), again.id());
+        assert_eq!(first.send_time(), again.send_time());
+    }
+
+    // The channel was only toggled once, so it is armed.
+    let response = command(&mut emulator, &device, 2, Command::FirePyro(3));
+    assert!(matches!(
+        response.kind(),
+        MessageKind::Response(2, CommandResponse::Done)
+    ));
+}
+
+#[test]
+fn draws_a_new_identifier_every_session() {
+    let mut emulator = emulator();
+    let device = MockDevice::default();
+
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        device.receive(handshake::request(&device, 0x1234, None, 0, 0));
+        emulator.step(&device).unwrap();
+        let answer = device.sent().pop().unwrap();
+        let (id, _) = handshake::accept(&device, &answer, 0x1234).unwrap();
+        ids.push(id);
+    }
+    assert_ne!(ids[0], ids[1]);
+}
@@ -0,0 +1,181 @@
+//! Tests of the handshake and the negotiation of the encoding, over UDP on localhost.
+
+#![cfg(feature = "std")]
+
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use flick_bridge::device::Device;
+use flick_bridge::handshake;
+use flick_bridge::host::udp::UdpDevice;
+use flick_bridge::message::{Encoding, Encodings, Message};
+
+const GROUND: u64 = 0x1234;
+const VEHICLE: u64 = 0x5678;
+
+/// Pair of devices talking to each other.
+fn pair() -> (UdpDevice, UdpDevice) {
+    let ground_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let vehicle_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let ground_addr = ground_socket.local_addr().unwrap();
+    let vehicle_addr = vehicle_socket.local_addr().unwrap();
+    drop((ground_socket, vehicle_socket));
+
+    (
+        UdpDevice::bind(ground_addr, vehicle_addr).unwrap(),
+        UdpDevice::bind(vehicle_addr, ground_addr).unwrap(),
+    )
+}
+
+/// Wait for the next message on the device.
+fn receive(device: &UdpDevice) -> Message {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if let Some(message) = device.poll().unwrap() {
+            return message;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    panic!("no message received");
+}
+
+/// Run the handshake between the devices, returning the encoding the ground agreed on.
+fn connect(ground: &UdpDevice, vehicle: &UdpDevice, preferred: Option<Encoding>) -> Encoding {
+    ground
+        .transmit(handshake::request(ground, GROUND, preferred, 0, 0))
+        .unwrap();
+    let request = receive(vehicle);
+    let picked = handshake::answer(vehicle, &request, VEHICLE, 0, 0)
+        .unwrap()
+        .unwrap();
+
+    let answer = receive(ground);
+    let (id, encoding) = handshake::accept(ground, &answer, GROUND).unwrap();
+    assert_eq!(id, VEHICLE);
+    assert_eq!(encoding, picked);
+    encoding
+}
+
+#[test]
+fn negotiates_the_preferred_encoding() {
+    let both = Encodings::POSTCARD | Encodings::CBOR;
+    let postcard = Encodings::POSTCARD;
+    let cbor = Encodings::CBOR;
+
+    assert_eq!(
+        Encoding::negotiate(both, both, None),
+        Some(Encoding::Postcard)
+    );
+    assert_eq!(
+        Encoding::negotiate(both, both, Some(Encoding::Cbor)),
+        Some(Encoding::Cbor)
+    );
+    assert_eq!(
+        Encoding::negotiate(postcard, both, Some(Encoding::Cbor)),
+        Some(Encoding::Postcard)
+    );
+    assert_eq!(
+        Encoding::negotiate(both, cbor, Some(Encoding::Postcard)),
+        Some(Encoding::Cbor)
+    );
+    assert_eq!(Encoding::negotiate(postcard, cbor, None), None);
+}
+
+#[test]
+fn keeps_postcard_without_a_preference() {
+    let (ground, vehicle) = pair();
+    assert_eq!(connect(&ground, &vehicle, None), Encoding::Postcard);
+    assert_eq!(vehicle.encoding(), Encoding::Postcard);
+}
+
+#[test]
+fn ignores_answers_to_other_grounds() {
+    let (ground, vehicle) = pair();
+    ground
+        .transmit(handshake::request(&ground, GROUND + 1, None, 0, 0))
+        .unwrap();
+    let request = receive(&vehicle);
+    handshake::answer(&vehicle, &request, VEHICLE, 0, 0).unwrap();
+
+    let answer = receive(&ground);
+    assert!(handshake::accept(&ground, &answer, GROUND).is_none());
+}
+
+#[cfg(feature = "cbor")]
+mod cbor {
+    use flick_bridge::command::Command;
+    use flick_bridge::message::{MessageFlags, MessageKind};
+
+    use super::*;
+
+    fn heartbeat(id: u64) -> Message {
+        Message::new(
+            id,
+            MessageKind::Request(Command::Heartbeat),
+            0,
+            MessageFlags::REQUIRES_ACK,
+        )
+    }
+
+    #[test]
+    fn switches_to_the_encoding_preferred_by_the_ground() {
+        let (ground, vehicle) = pair();
+        assert_eq!(
+            connect(&ground, &vehicle, Some(Encoding::Cbor)),
+            Encoding::Cbor
+        );
+        assert_eq!(ground.encoding(), Encoding::Cbor);
+        assert_eq!(vehicle.encoding(), Encoding::Cbor);
+
+        ground.transmit(heartbeat(1)).unwrap();
+        let message = receive(&vehicle);
+        assert_eq!(message.id(), 1);
+        assert!(matches!(
+            message.kind(),
+            MessageKind::Request(Command::Heartbeat)
+        ));
+    }
+
+    #[test]
+    fn answers_handshakes_in_cbor() {
+        let tool = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tool.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let vehicle = UdpDevice::bind("127.0.0.1:0", tool.local_addr().unwrap()).unwrap();
+
+        // A tool that only speaks CBOR starts the session in CBOR.
+        let request = Message::new(
+            0,
+            MessageKind::Handshake(GROUND, 0, Encodings::CBOR.bits(), Some(Encoding::Cbor)),
+            0,
+            MessageFlags::empty(),
+        );
+        let mut buf = [0; 1024];
+        let bytes = request.encode_with(Encoding::Cbor, &mut buf).unwrap();
+        tool.send_to(bytes, vehicle.local_addr().unwrap()).unwrap();
+
+        let request = receive(&vehicle);
+        handshake::answer(&vehicle, &request, VEHICLE, 0, 0).unwrap();
+        let (len, _) = tool.recv_from(&mut buf).unwrap();
+        let answer = Message::decode_with(Encoding::Cbor, &buf[..len]).unwrap();
+        assert!(matches!(
+            answer.kind(),
+            MessageKind::Handshake(VEHICLE, GROUND, _, Some(Encoding::Cbor))
+        ));
+
+        // Postcard no longer decodes once the vehicle switched.
+        let mut buf = [0; 1024];
+        let bytes = heartbeat(1).encode(&mut buf).unwrap();
+        tool.send_to(bytes, vehicle.local_addr().unwrap()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let result = loop {
+            match vehicle.poll() {
+                Ok(None) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(1))
+                }
+                result => break result,
+            }
+        };
+        assert!(result.is_err());
+    }
+}
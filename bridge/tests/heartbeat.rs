@@ -0,0 +1,83 @@
+//! Tests of the heartbeat monitor.
+
+use flick_bridge::heartbeat::{HeartbeatMonitor, LinkEvent, LinkState};
+
+const SECOND: u64 = 1_000_000;
+
+/// Monitor sending a heartbeat every half second, and timing out after 3 seconds.
+fn monitor() -> HeartbeatMonitor {
+    HeartbeatMonitor::new(SECOND / 2, 3 * SECOND)
+}
+
+#[test]
+fn loses_and_restores_the_link() {
+    let mut monitor = monitor();
+    assert_eq!(monitor.state(), LinkState::Waiting);
+    assert_eq!(monitor.heard(SECOND), None);
+    assert_eq!(monitor.state(), LinkState::Connected);
+
+    assert_eq!(monitor.update(4 * SECOND), None);
+    assert_eq!(
+        monitor.update(4 * SECOND + 1),
+        Some(LinkEvent::Lost { last_heard: SECOND })
+    );
+    assert_eq!(monitor.state(), LinkState::Lost);
+    // The loss is only reported once.
+    assert_eq!(monitor.update(10 * SECOND), None);
+
+    assert_eq!(
+        monitor.heard(11 * SECOND),
+        Some(LinkEvent::Restored {
+            lost_at: 4 * SECOND + 1,
+            restored_at: 11 * SECOND,
+        })
+    );
+    assert_eq!(monitor.state(), LinkState::Connected);
+    assert_eq!(monitor.heard(12 * SECOND), None);
+}
+
+#[test]
+fn keeps_the_link_while_hearing_from_the_other_side() {
+    let mut monitor = monitor();
+    for time in (0..20 * SECOND).step_by(SECOND as usize) {
+        monitor.heard(time);
+        assert_eq!(monitor.update(time + SECOND / 2), None);
+    }
+    assert_eq!(monitor.state(), LinkState::Connected);
+}
+
+#[test]
+fn times_out_a_link_that_never_came_up() {
+    let mut monitor = monitor();
+    // The timeout runs from the first update, not from time 0.
+    assert_eq!(monitor.update(10 * SECOND), None);
+    assert_eq!(monitor.update(13 * SECOND), None);
+    assert_eq!(monitor.state(), LinkState::Waiting);
+
+    assert_eq!(
+        monitor.update(13 * SECOND + 1),
+        Some(LinkEvent::Lost {
+            last_heard: 10 * SECOND
+        })
+    );
+    assert_eq!(monitor.state(), LinkState::Lost);
+    assert_eq!(
+        monitor.heard(14 * SECOND),
+        Some(LinkEvent::Restored {
+            lost_at: 13 * SECOND + 1,
+            restored_at: 14 * SECOND,
+        })
+    );
+}
+
+#[test]
+fn sends_heartbeats_at_the_interval() {
+    let mut monitor = monitor();
+    assert!(monitor.heartbeat_due(SECOND));
+    assert!(!monitor.heartbeat_due(SECOND + SECOND / 2 - 1));
+    assert!(monitor.heartbeat_due(SECOND + SECOND / 2));
+    assert!(!monitor.heartbeat_due(SECOND + SECOND / 2));
+    // Late heartbeats don't pile up.
+    assert!(monitor.heartbeat_due(5 * SECOND));
+    assert!(!monitor.heartbeat_due(5 * SECOND + 1));
+}
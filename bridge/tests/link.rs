@@ -0,0 +1,322 @@
+//! Tests of the link manager over mock devices, together with the reliable sender on the other
+//! side.
+
+#![cfg(feature = "std")]
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use flick_bridge::command::Command;
+use flick_bridge::device::{Device, DeviceError, SignalQuality};
+use flick_bridge::heartbeat::LinkState;
+use flick_bridge::link::{LinkConfig, LinkManager};
+use flick_bridge::message::{Message, MessageFlags, MessageKind};
+use flick_bridge::reliable::{acknowledgement, ReliableSender};
+
+/// Device whose received messages are queued by the test, and which records what it sends.
+#[derive(Default)]
+struct MockDevice {
+    received: RefCell<VecDeque<Message>>,
+    sent: RefCell<Vec<Message>>,
+    quality: Cell<Option<SignalQuality>>,
+}
+
+impl MockDevice {
+    fn receive(&self, message: Message) {
+        self.received.borrow_mut().push_back(message);
+    }
+
+    fn sent(&self) -> Vec<Message> {
+        self.sent.borrow_mut().drain(..).collect()
+    }
+
+    fn set_snr(&self, snr: i8) {
+        self.quality.set(Some(SignalQuality { rssi: -100, snr }));
+    }
+}
+
+impl Device for MockDevice {
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        self.sent.borrow_mut().push(message);
+        Ok(())
+    }
+
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        Ok(self.received.borrow_mut().pop_front())
+    }
+
+    fn ping(&self, _id: u64, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        Ok(0)
+    }
+
+    fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn signal_quality(&self) -> Option<SignalQuality> {
+        self.quality.get()
+    }
+}
+
+/// Device that forwards what it transmits to a mock device as if received over the air.
+struct Air<'a>(&'a MockDevice);
+
+impl Device for Air<'_> {
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        self.0.receive(message);
+        Ok(())
+    }
+
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        Ok(None)
+    }
+
+    fn ping(&self, _id: u64, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        Ok(0)
+    }
+
+    fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+fn command(id: u64, send_time: u64) -> Message {
+    Message::new(
+        id,
+        MessageKind::Request(Command::Heartbeat),
+        send_time,
+        MessageFlags::REQUIRES_ACK,
+    )
+}
+
+fn heartbeat(id: u64) -> Message {
+    Message::new(
+        id,
+        MessageKind::Request(Command::Heartbeat),
+        0,
+        MessageFlags::CRITICAL,
+    )
+}
+
+/// Poll every message received so far.
+fn poll_all<const H: usize>(links: &LinkManager<'_, 2, H>) -> Vec<Message> {
+    std::iter::from_fn(|| links.poll().unwrap()).collect()
+}
+
+#[test]
+fn delivers_retransmissions_once() {
+    let radio = MockDevice::default();
+    let usb = MockDevice::default();
+    let links = LinkManager::new([&radio, &usb], LinkConfig::default());
+
+    // The other side keeps retransmitting since its acknowledgements are lost.
+    let mut sender = ReliableSender::<4>::new(100_000, 5);
+    sender.send(&Air(&radio), command(1, 10), 10).unwrap();
+    for now in [100_000, 200_000, 300_000] {
+        sender.update(&Air(&radio), now).unwrap();
+    }
+    // Copies also arrive over the other link.
+    usb.receive(command(1, 10));
+
+    links.update(300_000);
+    let received = poll_all(&links);
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].id(), 1);
+    assert_eq!(received[0].send_time(), 10);
+}
+
+#[test]
+fn acknowledges_copies_again() {
+    let radio = MockDevice::default();
+    let usb = MockDevice::default();
+    let links = LinkManager::new([&radio, &usb], LinkConfig::default());
+
+    radio.receive(command(1, 10));
+    links.update(1_000);
+    let message = links.poll().unwrap().unwrap();
+    links
+        .transmit(acknowledgement(&message, 50, 1_000).unwrap())
+        .unwrap();
+    let ack = radio.sent().pop().unwrap();
+    assert!(matches!(ack.kind(), MessageKind::Ack(1)));
+
+    // The acknowledgement was lost, so the message is retransmitted, and acknowledged again
+    // without reaching the application.
+    usb.receive(command(1, 10));
+    links.update(101_000);
+    assert!(links.poll().unwrap().is_none());
+    let sent = usb.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].id(), ack.id());
+    assert_eq!(sent[0].send_time(), ack.send_time());
+    assert!(matches!(sent[0].kind(), MessageKind::Ack(1)));
+}
+
+#[test]
+fn drops_unacknowledged_copies_silently() {
+    let radio = MockDevice::default();
+    let usb = MockDevice::default();
+    let links = LinkManager::new([&radio, &usb], LinkConfig::default());
+
+    radio.receive(command(1, 10));
+    usb.receive(command(1, 10));
+    links.update(1_000);
+    assert_eq!(poll_all(&links).len(), 1);
+    // Nothing is acknowledged until the application does.
+    assert!(radio.sent().is_empty());
+    assert!(usb.sent().is_empty());
+}
+
+#[test]
+fn forgets_messages_after_the_window() {
+    let radio = MockDevice::default();
+    let usb = MockDevice::default();
+    let config = LinkConfig {
+        timeout: 100_000_000,
+        dedup_window: 1_000_000,
+        ..LinkConfig::default()
+    };
+    let links = LinkManager::new([&radio, &usb], config);
+
+    radio.receive(command(1, 10));
+    links.update(0);
+    assert_eq!(poll_all(&links).len(), 1);
+
+    radio.receive(command(1, 10));
+    links.update(999_999);
+    assert!(poll_all(&links).is_empty());
+
+    // Once the window is over, the same message is a new one.
+    radio.receive(command(1, 10));
+    links.update(1_000_000);
+    assert_eq!(poll_all(&links).len(), 1);
+}
+
+#[test]
+fn remembers_messages_beyond_the_default_history_within_the_window() {
+    let radio = MockDevice::default();
+    let usb = MockDevice::default();
+    // Room for 10 s of messages at 10 per second.
+    let links: LinkManager<'_, 2, 100> =
+        LinkManager::with_history([&radio, &usb], LinkConfig::default());
+
+    for id in 0..50 {
+        radio.receive(command(id, id * 100_000));
+        links.update(id * 100_000);
+        assert_eq!(poll_all(&links).len(), 1);
+    }
+    // A late copy of an early message.
+    usb.receive(command(1, 100_000));
+    links.update(5_000_000);
+    assert!(poll_all(&links).is_empty());
+    assert_eq!(links.overflows(), 0);
+}
+
+#[test]
+fn reports_messages_forgotten_within_the_window() {
+    let radio = MockDevice::default();
+    let usb = MockDevice::default();
+    let links = LinkManager::new([&radio, &usb], LinkConfig::default());
+
+    for id in 0..40 {
+        radio.receive(command(id, id * 100_000));
+        links.update(id * 100_000);
+        assert_eq!(poll_all(&links).len(), 1);
+    }
+    assert_eq!(links.overflows(), 8);
+    // The early messages were forgotten, so their copies are returned again.
+    usb.receive(command(1, 100_000));
+    usb.receive(command(39, 3_900_000));
+    links.update(4_000_000);
+    let received: Vec<_> = poll_all(&links).iter().map(Message::id).collect();
+    assert_eq!(received, [1]);
+}
+
+#[test]
+fn tells_apart_messages_from_a_restarted_sender() {
+    let radio = MockDevice::default();
+    let usb = MockDevice::default();
+    let links = LinkManager::new([&radio, &usb], LinkConfig::default());
+
+    radio.receive(command(1, 5_000_000));
+    links.update(0);
+    assert_eq!(poll_all(&links).len(), 1);
+
+    // The other side restarted, and its IDs and clock start over.
+    radio.receive(command(1, 1_000));
+    links.update(1_000_000);
+    assert_eq!(poll_all(&links).len(), 1);
+    radio.receive(command(1, 1_000));
+    assert!(poll_all(&links).is_empty());
+}
+
+#[test]
+fn fails_over_on_a_weak_signal() {
+    let radio = MockDevice::default();
+    let usb = MockDevice::default();
+    let config = LinkConfig {
+        min_snr: Some(0),
+        ..LinkConfig::default()
+    };
+    let links = LinkManager::new([&radio, &usb], config);
+
+    radio.set_snr(20);
+    radio.receive(heartbeat(1));
+    usb.receive(heartbeat(2));
+    links.update(0);
+    poll_all(&links);
+    assert_eq!(links.active(), Some(0));
+
+    radio.set_snr(-4);
+    radio.receive(heartbeat(3));
+    poll_all(&links);
+    assert!(links.is_marginal(0));
+    assert_eq!(links.active(), Some(1));
+
+    links.transmit(command(10, 0)).unwrap();
+    assert!(radio.sent().is_empty());
+    assert_eq!(usb.sent().len(), 1);
+
+    // Just above the threshold isn't enough to switch back.
+    radio.set_snr(4);
+    radio.receive(heartbeat(4));
+    poll_all(&links);
+    assert_eq!(links.active(), Some(1));
+
+    radio.set_snr(8);
+    radio.receive(heartbeat(5));
+    poll_all(&links);
+    assert!(!links.is_marginal(0));
+    assert_eq!(links.active(), Some(0));
+}
+
+#[test]
+fn prefers_a_marginal_link_over_a_lost_one() {
+    let radio = MockDevice::default();
+    let usb = MockDevice::default();
+    let config = LinkConfig {
+        timeout: 1_000_000,
+        ..LinkConfig::default()
+    };
+    let links = LinkManager::new([&usb, &radio], config);
+
+    links.update(0);
+    radio.set_snr(-40);
+    radio.receive(heartbeat(1));
+    links.update(500_000);
+    poll_all(&links);
+
+    // USB never comes up, so the weak radio is still the best link.
+    links.update(1_200_000);
+    assert_eq!(links.link_state(0), LinkState::Lost);
+    assert!(links.is_marginal(1));
+    assert_eq!(links.active(), Some(1));
+}
@@ -0,0 +1,120 @@
+//! Known-byte vectors of the MAVLink 2 codec. The frames were built from the message definitions
+//! of the MAVLink common dialect, independently of the codec, so they catch a wrong field order,
+//! length or checksum extra byte.
+
+#![cfg(feature = "std")]
+
+use flick_bridge::host::mavlink::*;
+
+/// Encode the frame, check it against the vector, and decode the vector back.
+fn check(sequence: u8, message: MavMessage, expected: &[u8]) {
+    let frame = MavFrame {
+        sequence,
+        system_id: 1,
+        component_id: 1,
+        message,
+    };
+    let mut buf = [0; MAX_FRAME_LEN];
+    let len = frame.encode(&mut buf);
+    assert_eq!(&buf[..len], expected);
+    assert_eq!(MavFrame::decode(expected), Ok((frame, expected.len())));
+}
+
+#[test]
+fn encodes_heartbeat() {
+    let heartbeat = Heartbeat {
+        custom_mode: 4,
+        mav_type: MAV_TYPE_ROCKET,
+        autopilot: MAV_AUTOPILOT_GENERIC,
+        base_mode: MAV_MODE_FLAG_CUSTOM_MODE_ENABLED,
+        system_status: MavState::Active as u8,
+        mavlink_version: 3,
+    };
+    check(7, MavMessage::Heartbeat(heartbeat), &HEARTBEAT);
+}
+
+#[test]
+fn encodes_attitude() {
+    let attitude = Attitude {
+        time_boot_ms: 123_456,
+        roll: 0.5,
+        pitch: -0.25,
+        yaw: 1.5,
+        rollspeed: 0.0,
+        pitchspeed: 0.0,
+        yawspeed: 0.0,
+    };
+    // The trailing zero rates are truncated.
+    check(8, MavMessage::Attitude(attitude), &ATTITUDE);
+}
+
+#[test]
+fn encodes_global_position_int() {
+    let position = GlobalPositionInt {
+        time_boot_ms: 123_456,
+        lat: 473_900_000,
+        lon: 85_400_000,
+        alt: 588_000,
+        relative_alt: 100_000,
+        vx: -150,
+        vy: 20,
+        vz: -3000,
+        hdg: u16::MAX,
+    };
+    check(
+        9,
+        MavMessage::GlobalPositionInt(position),
+        &GLOBAL_POSITION_INT,
+    );
+}
+
+#[test]
+fn encodes_statustext() {
+    let text = StatusText::new(MavSeverity::Critical, "link to vehicle lost");
+    check(10, MavMessage::StatusText(text), &STATUSTEXT);
+}
+
+#[test]
+fn encodes_command_ack() {
+    let ack = CommandAck {
+        command: MAV_CMD_REQUEST_MESSAGE,
+        result: MavResult::Accepted as u8,
+    };
+    check(11, MavMessage::CommandAck(ack), &COMMAND_ACK);
+}
+
+#[test]
+fn rejects_a_bad_checksum() {
+    let mut frame = HEARTBEAT;
+    frame[HEARTBEAT.len() - 1] ^= 1;
+    assert_eq!(
+        MavFrame::decode(&frame),
+        Err(MavError::BadChecksum(frame.len()))
+    );
+}
+
+const HEARTBEAT: [u8; 21] = [
+    0xfd, 0x09, 0x00, 0x00, 0x07, 0x01, 0x01, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x09, 0x00,
+    0x01, 0x04, 0x03, 0xc7, 0xf9,
+];
+
+const ATTITUDE: [u8; 28] = [
+    0xfd, 0x10, 0x00, 0x00, 0x08, 0x01, 0x01, 0x1e, 0x00, 0x00, 0x40, 0xe2, 0x01, 0x00, 0x00, 0x00,
+    0x00, 0x3f, 0x00, 0x00, 0x80, 0xbe, 0x00, 0x00, 0xc0, 0x3f, 0x72, 0xf1,
+];
+
+const GLOBAL_POSITION_INT: [u8; 40] = [
+    0xfd, 0x1c, 0x00, 0x00, 0x09, 0x01, 0x01, 0x21, 0x00, 0x00, 0x40, 0xe2, 0x01, 0x00, 0xe0, 0x23,
+    0x3f, 0x1c, 0xc0, 0x19, 0x17, 0x05, 0xe0, 0xf8, 0x08, 0x00, 0xa0, 0x86, 0x01, 0x00, 0x6a, 0xff,
+    0x14, 0x00, 0x48, 0xf4, 0xff, 0xff, 0xaf, 0x3a,
+];
+
+const STATUSTEXT: [u8; 33] = [
+    0xfd, 0x15, 0x00, 0x00, 0x0a, 0x01, 0x01, 0xfd, 0x00, 0x00, 0x02, 0x6c, 0x69, 0x6e, 0x6b, 0x20,
+    0x74, 0x6f, 0x20, 0x76, 0x65, 0x68, 0x69, 0x63, 0x6c, 0x65, 0x20, 0x6c, 0x6f, 0x73, 0x74, 0xd1,
+    0x20,
+];
+
+const COMMAND_ACK: [u8; 14] = [
+    0xfd, 0x02, 0x00, 0x00, 0x0b, 0x01, 0x01, 0x4d, 0x00, 0x00, 0x00, 0x02, 0x7c, 0x52,
+];
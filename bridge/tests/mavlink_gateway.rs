@@ -0,0 +1,256 @@
+//! End-to-end test of the MAVLink gateway, talking to the emulator over UDP on localhost.
+
+#![cfg(feature = "std")]
+
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use flick_bridge::host::emulator::{Emulator, EmulatorConfig};
+use flick_bridge::host::mavlink::gateway::{Gateway, GatewayConfig, Origin};
+use flick_bridge::host::mavlink::*;
+use flick_bridge::host::udp::UdpDevice;
+use flick_bridge::message::Encoding;
+use flick_bridge::state::FlightState;
+
+/// Position of the launch site.
+const ORIGIN: Origin = Origin {
+    latitude: 47.39,
+    longitude: 8.54,
+    altitude: 488.0,
+};
+
+/// Address of a free port on localhost.
+fn free_addr() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.local_addr().unwrap()
+}
+
+struct Setup {
+    emulator: Emulator,
+    /// Device of the vehicle, once it came up.
+    vehicle: Option<UdpDevice>,
+    vehicle_addr: SocketAddr,
+    ground_addr: SocketAddr,
+    gateway: Gateway<UdpDevice>,
+    gcs: UdpSocket,
+    gateway_addr: SocketAddr,
+    sequence: u8,
+}
+
+impl Setup {
+    /// Set up the gateway, and the vehicle if it is `up`.
+    fn new(up: bool) -> Self {
+        Self::with_encoding(up, None)
+    }
+
+    /// Set up the gateway asking for the given encoding, and the vehicle if it is `up`.
+    fn with_encoding(up: bool, encoding: Option<Encoding>) -> Self {
+        let vehicle_addr = free_addr();
+        let ground_addr = free_addr();
+        let gcs = UdpSocket::bind("127.0.0.1:0").unwrap();
+        gcs.set_nonblocking(true).unwrap();
+
+        let config = GatewayConfig {
+            mavlink_addr: free_addr(),
+            gcs_addr: gcs.local_addr().unwrap(),
+            command_timeout: 500_000,
+            retry_interval: 100_000,
+            origin: ORIGIN,
+            encoding,
+            ..GatewayConfig::default()
+        };
+        let gateway_addr = config.mavlink_addr;
+        let gateway = Gateway::new(UdpDevice::bind(ground_addr, vehicle_addr).unwrap(), config);
+
+        let mut setup = Self {
+            emulator: Emulator::new(EmulatorConfig {
+                launch_after: None,
+                ..EmulatorConfig::default()
+            }),
+            vehicle: None,
+            vehicle_addr,
+            ground_addr,
+            gateway: gateway.unwrap(),
+            gcs,
+            gateway_addr,
+            sequence: 0,
+        };
+        if up {
+            setup.bring_up();
+        }
+
+        setup
+    }
+
+    /// Bring the vehicle up. Whatever was sent to it before is lost.
+    fn bring_up(&mut self) {
+        self.vehicle = Some(UdpDevice::bind(self.vehicle_addr, self.ground_addr).unwrap());
+    }
+
+    /// Send a `COMMAND_LONG` from the ground software.
+    fn command(&mut self, command: u16, params: [f32; 7]) {
+        let frame = MavFrame {
+            sequence: self.sequence,
+            system_id: 255,
+            component_id: 190,
+            message: MavMessage::CommandLong(CommandLong {
+                params,
+                command,
+                target_system: 1,
+                target_component: 1,
+                confirmation: 0,
+            }),
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = frame.encode(&mut buf);
+        self.gcs.send_to(&buf[..len], self.gateway_addr).unwrap();
+    }
+
+    /// Run the gateway, and the emulator if it is up, until the ground software receives a
+    /// message that `find` picks.
+    fn receive<T>(&mut self, mut find: impl FnMut(MavMessage) -> Option<T>) -> T {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut buf = [0; MAX_FRAME_LEN];
+        while Instant::now() < deadline {
+            if let Some(vehicle) = &self.vehicle {
+                self.emulator.step(vehicle).unwrap();
+            }
+            self.gateway.step().unwrap();
+
+            while let Ok((len, _)) = self.gcs.recv_from(&mut buf) {
+                let mut bytes = &buf[..len];
+                while let Ok((frame, len)) = MavFrame::decode(bytes) {
+                    bytes = &bytes[len..];
+                    if let Some(found) = find(frame.message) {
+                        return found;
+                    }
+                }
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        panic!("expected message not received");
+    }
+
+    /// Run the gateway, and the emulator if it is up, until the ground software receives a
+    /// `COMMAND_ACK`.
+    fn ack(&mut self) -> CommandAck {
+        self.receive(|message| match message {
+            MavMessage::CommandAck(ack) => Some(ack),
+            _ => None,
+        })
+    }
+}
+
+#[test]
+fn acknowledges_with_the_response_of_the_vehicle() {
+    let mut setup = Setup::new(true);
+
+    setup.command(MAV_CMD_REQUEST_MESSAGE, [0.0; 7]);
+    let ack = setup.ack();
+    assert_eq!(ack.command, MAV_CMD_REQUEST_MESSAGE);
+    assert_eq!(ack.result, MavResult::Accepted as u8);
+
+    // The emulator only changes to boost, and only from the pad.
+    let landed = FlightState::Landed.id() as f32;
+    setup.command(MAV_CMD_DO_SET_MODE, [0.0, landed, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    let ack = setup.ack();
+    assert_eq!(ack.command, MAV_CMD_DO_SET_MODE);
+    assert_eq!(ack.result, MavResult::Denied as u8);
+
+    setup.command(MAV_CMD_DO_SET_RELAY, [99.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    let ack = setup.ack();
+    assert_eq!(ack.command, MAV_CMD_DO_SET_RELAY);
+    assert_eq!(ack.result, MavResult::Denied as u8);
+}
+
+#[test]
+fn forwards_heartbeats_and_telemetry() {
+    let mut setup = Setup::new(true);
+
+    let heartbeat = setup.receive(|message| match message {
+        MavMessage::Heartbeat(heartbeat) if heartbeat.system_status == MavState::Active as u8 => {
+            Some(heartbeat)
+        }
+        _ => None,
+    });
+    assert_eq!(heartbeat.mav_type, MAV_TYPE_ROCKET);
+    assert_eq!(heartbeat.autopilot, MAV_AUTOPILOT_GENERIC);
+    assert_eq!(heartbeat.base_mode, MAV_MODE_FLAG_CUSTOM_MODE_ENABLED);
+    assert_eq!(heartbeat.mavlink_version, 3);
+
+    // The rocket stands upright on the pad, at the launch site.
+    let attitude = setup.receive(|message| match message {
+        MavMessage::Attitude(attitude) => Some(attitude),
+        _ => None,
+    });
+    assert!((attitude.pitch - core::f32::consts::FRAC_PI_2).abs() < 1e-3);
+    let position = setup.receive(|message| match message {
+        MavMessage::GlobalPositionInt(position) => Some(position),
+        _ => None,
+    });
+    assert!((position.lat - 473_900_000).abs() <= 1);
+    assert!((position.lon - 85_400_000).abs() <= 1);
+    assert_eq!(position.alt, 488_000);
+    assert_eq!(position.relative_alt, 0);
+    assert_eq!((position.vx, position.vy, position.vz), (0, 0, 0));
+}
+
+#[test]
+fn reports_the_link_lost() {
+    let mut setup = Setup::new(false);
+
+    let text = setup.receive(|message| match message {
+        MavMessage::StatusText(text) => Some(text),
+        _ => None,
+    });
+    assert_eq!(text.severity, MavSeverity::Critical as u8);
+    assert!(text.text.starts_with(b"link to vehicle lost\0"));
+}
+
+#[test]
+fn rejects_unsupported_commands() {
+    let mut setup = Setup::new(false);
+
+    setup.command(400, [0.0; 7]);
+    let ack = setup.ack();
+    assert_eq!(ack.command, 400);
+    assert_eq!(ack.result, MavResult::Unsupported as u8);
+}
+
+#[test]
+fn fails_commands_the_vehicle_never_responds_to() {
+    let mut setup = Setup::new(false);
+
+    setup.command(MAV_CMD_REQUEST_MESSAGE, [0.0; 7]);
+    let ack = setup.ack();
+    assert_eq!(ack.command, MAV_CMD_REQUEST_MESSAGE);
+    assert_eq!(ack.result, MavResult::Failed as u8);
+}
+
+#[test]
+fn retransmits_commands_until_the_vehicle_comes_up() {
+    let mut setup = Setup::new(false);
+
+    // The first transmission of the command is lost, since the vehicle isn't up yet.
+    setup.command(MAV_CMD_REQUEST_MESSAGE, [0.0; 7]);
+    setup.gateway.step().unwrap();
+    setup.bring_up();
+    let ack = setup.ack();
+    assert_eq!(ack.result, MavResult::Accepted as u8);
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn negotiates_the_encoding_asked_for() {
+    let mut setup = Setup::with_encoding(true, Some(Encoding::Cbor));
+
+    setup.command(MAV_CMD_REQUEST_MESSAGE, [0.0; 7]);
+    let ack = setup.ack();
+    assert_eq!(ack.result, MavResult::Accepted as u8);
+    let (_, encoding) = setup.gateway.session().unwrap();
+    assert_eq!(encoding, Encoding::Cbor);
+    assert_eq!(setup.vehicle.as_ref().unwrap().encoding(), Encoding::Cbor);
+}
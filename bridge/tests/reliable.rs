@@ -0,0 +1,203 @@
+//! Tests of the reliable sender over a mock device.
+
+use std::cell::{Cell, RefCell};
+
+use flick_bridge::command::Command;
+use flick_bridge::device::{Device, DeviceError};
+use flick_bridge::message::{Message, MessageFlags, MessageKind};
+use flick_bridge::reliable::{acknowledgement, ReliableSender};
+
+const RETRY_INTERVAL: u64 = 100_000;
+const MAX_ATTEMPTS: u8 = 3;
+
+/// Device that records the IDs of what it sends, and fails to transmit while it is down.
+#[derive(Default)]
+struct MockDevice {
+    sent: RefCell<Vec<u64>>,
+    down: Cell<bool>,
+}
+
+impl MockDevice {
+    fn sent(&self) -> Vec<u64> {
+        self.sent.borrow_mut().drain(..).collect()
+    }
+}
+
+impl Device for MockDevice {
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        if self.down.get() {
+            return Err(DeviceError::Physical);
+        }
+        self.sent.borrow_mut().push(message.id());
+        Ok(())
+    }
+
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        Ok(None)
+    }
+
+    fn ping(&self, _id: u64, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        Ok(0)
+    }
+
+    fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+fn message(id: u64, flags: MessageFlags) -> Message {
+    Message::new(id, MessageKind::Request(Command::Heartbeat), 0, flags)
+}
+
+fn ack(id: u64) -> Message {
+    Message::new(100 + id, MessageKind::Ack(id), 0, MessageFlags::empty())
+}
+
+#[test]
+fn retransmits_until_acknowledged() {
+    let device = MockDevice::default();
+    let mut sender = ReliableSender::<4>::new(RETRY_INTERVAL, MAX_ATTEMPTS);
+    sender
+        .send(&device, message(1, MessageFlags::REQUIRES_ACK), 0)
+        .unwrap();
+    assert_eq!(device.sent(), [1]);
+
+    sender.update(&device, RETRY_INTERVAL - 1).unwrap();
+    assert_eq!(device.sent(), []);
+    sender.update(&device, RETRY_INTERVAL).unwrap();
+    assert_eq!(device.sent(), [1]);
+    // The interval runs from the last retransmission.
+    sender.update(&device, 2 * RETRY_INTERVAL - 1).unwrap();
+    assert_eq!(device.sent(), []);
+
+    assert!(!sender.receive(&ack(2)));
+    assert!(sender.receive(&ack(1)));
+    assert_eq!(sender.pending(), 0);
+    sender.update(&device, 10 * RETRY_INTERVAL).unwrap();
+    assert_eq!(device.sent(), []);
+}
+
+#[test]
+fn gives_up_after_the_maximum_number_of_attempts() {
+    let device = MockDevice::default();
+    let mut sender = ReliableSender::<4>::new(RETRY_INTERVAL, MAX_ATTEMPTS);
+    sender
+        .send(&device, message(1, MessageFlags::REQUIRES_ACK), 0)
+        .unwrap();
+    sender
+        .send(
+            &device,
+            message(2, MessageFlags::REQUIRES_ACK),
+            RETRY_INTERVAL / 2,
+        )
+        .unwrap();
+    assert_eq!(device.sent(), [1, 2]);
+
+    for attempt in 1..MAX_ATTEMPTS as u64 {
+        sender.update(&device, attempt * RETRY_INTERVAL).unwrap();
+        assert_eq!(device.sent(), [1]);
+        sender
+            .update(&device, attempt * RETRY_INTERVAL + RETRY_INTERVAL / 2)
+            .unwrap();
+        assert_eq!(device.sent(), [2]);
+    }
+
+    let time = MAX_ATTEMPTS as u64 * RETRY_INTERVAL;
+    assert_eq!(
+        sender.update(&device, time),
+        Err(DeviceError::NotAcknowledged)
+    );
+    assert_eq!(sender.pending(), 1);
+    assert_eq!(
+        sender.update(&device, time + RETRY_INTERVAL / 2),
+        Err(DeviceError::NotAcknowledged)
+    );
+    assert_eq!(sender.pending(), 0);
+    assert_eq!(device.sent(), []);
+}
+
+#[test]
+fn queues_messages_that_failed_to_transmit() {
+    let device = MockDevice::default();
+    let mut sender = ReliableSender::<4>::new(RETRY_INTERVAL, MAX_ATTEMPTS);
+
+    device.down.set(true);
+    sender
+        .send(&device, message(1, MessageFlags::REQUIRES_ACK), 0)
+        .unwrap();
+    assert_eq!(sender.pending(), 1);
+
+    // Retried on the next update.
+    device.down.set(false);
+    sender.update(&device, 1).unwrap();
+    assert_eq!(device.sent(), [1]);
+
+    // Failed retransmissions count as attempts.
+    device.down.set(true);
+    for attempt in 1..MAX_ATTEMPTS as u64 {
+        assert_eq!(
+            sender.update(&device, 1 + attempt * RETRY_INTERVAL),
+            Err(DeviceError::Physical)
+        );
+    }
+    assert_eq!(
+        sender.update(&device, 1 + MAX_ATTEMPTS as u64 * RETRY_INTERVAL),
+        Err(DeviceError::NotAcknowledged)
+    );
+    assert_eq!(sender.pending(), 0);
+}
+
+#[test]
+fn keeps_retrying_the_others_after_an_error() {
+    let device = MockDevice::default();
+    let mut sender = ReliableSender::<4>::new(RETRY_INTERVAL, 1);
+    sender
+        .send(&device, message(1, MessageFlags::REQUIRES_ACK), 0)
+        .unwrap();
+    device.down.set(true);
+    sender
+        .send(&device, message(2, MessageFlags::REQUIRES_ACK), 0)
+        .unwrap();
+    device.down.set(false);
+
+    // The first is given up on, and the second is still transmitted.
+    assert_eq!(
+        sender.update(&device, RETRY_INTERVAL),
+        Err(DeviceError::NotAcknowledged)
+    );
+    assert_eq!(device.sent(), [1, 2]);
+    assert_eq!(sender.pending(), 1);
+}
+
+#[test]
+fn rejects_messages_it_cannot_hold() {
+    let device = MockDevice::default();
+    let mut sender = ReliableSender::<2>::new(RETRY_INTERVAL, MAX_ATTEMPTS);
+    assert_eq!(
+        sender.send(&device, message(1, MessageFlags::empty()), 0),
+        Err(DeviceError::InvalidMessage)
+    );
+    for id in 1..=2 {
+        sender
+            .send(&device, message(id, MessageFlags::REQUIRES_ACK), 0)
+            .unwrap();
+    }
+    assert_eq!(
+        sender.send(&device, message(3, MessageFlags::REQUIRES_ACK), 0),
+        Err(DeviceError::QueueFull)
+    );
+    assert_eq!(device.sent(), [1, 2]);
+}
+
+#[test]
+fn acknowledges_only_messages_that_require_it() {
+    assert!(acknowledgement(&message(1, MessageFlags::empty()), 2, 3).is_none());
+    let ack = acknowledgement(&message(1, MessageFlags::REQUIRES_ACK), 2, 3).unwrap();
+    assert_eq!(ack.id(), 2);
+    assert_eq!(ack.send_time(), 3);
+    assert!(matches!(ack.kind(), MessageKind::Ack(1)));
+}
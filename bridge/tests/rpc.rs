@@ -0,0 +1,338 @@
+//! Tests of the RPC client and its futures over a mock device.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use flick_bridge::command::{Command, CommandError, CommandResponse};
+use flick_bridge::device::{Device, DeviceError};
+use flick_bridge::message::{Message, MessageFlags, MessageKind};
+use flick_bridge::rpc::{self, RpcClient, RpcError};
+
+const TIMEOUT: u64 = 1_000_000;
+
+/// Device whose received messages are queued by the test, and which records what it sends.
+#[derive(Default)]
+struct MockDevice {
+    received: RefCell<VecDeque<Message>>,
+    sent: RefCell<Vec<Message>>,
+    down: Cell<bool>,
+}
+
+impl Device for MockDevice {
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        if self.down.get() {
+            return Err(DeviceError::Physical);
+        }
+        self.sent.borrow_mut().push(message);
+        Ok(())
+    }
+
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        Ok(self.received.borrow_mut().pop_front())
+    }
+
+    fn ping(&self, _id: u64, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        Ok(0)
+    }
+
+    fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+/// Response of the vehicle to the request with the given ID.
+fn response(request: u64, response: CommandResponse) -> Message {
+    let request = Message::new(
+        request,
+        MessageKind::Request(Command::Heartbeat),
+        0,
+        MessageFlags::empty(),
+    );
+    rpc::response(&request, 1000 + request.id(), response, 0)
+}
+
+#[test]
+fn correlates_responses_by_id() {
+    let device = MockDevice::default();
+    let mut client = RpcClient::<4>::new(10, TIMEOUT);
+    let first = client.send(&device, Command::Heartbeat, 0).unwrap();
+    let second = client.send(&device, Command::Heartbeat, 0).unwrap();
+    assert_eq!((first.id(), second.id()), (10, 11));
+    let sent: Vec<_> = device.sent.borrow().iter().map(Message::id).collect();
+    assert_eq!(sent, [10, 11]);
+
+    // Responses arrive out of order.
+    assert!(client
+        .receive(response(11, CommandResponse::Scheduled(2)), 0)
+        .is_none());
+    assert_eq!(client.take(first), None);
+    assert!(client
+        .receive(response(10, CommandResponse::Scheduled(1)), 0)
+        .is_none());
+    assert_eq!(client.take(second), Some(Ok(CommandResponse::Scheduled(2))));
+    assert_eq!(client.take(first), Some(Ok(CommandResponse::Scheduled(1))));
+    // Once taken, the handle is no longer valid.
+    assert_eq!(client.take(first), None);
+}
+
+#[test]
+fn gives_back_other_messages() {
+    let mut client = RpcClient::<4>::new(10, TIMEOUT);
+    let handle = client
+        .send(&MockDevice::default(), Command::Heartbeat, 0)
+        .unwrap();
+
+    let ack = Message::new(1, MessageKind::Ack(10), 0, MessageFlags::empty());
+    assert!(client.receive(ack, 0).is_some());
+    // Responses to unknown requests.
+    assert!(client
+        .receive(response(9, CommandResponse::Done), 0)
+        .is_some());
+    // A second response to a request that already completed.
+    assert!(client
+        .receive(response(10, CommandResponse::Done), 0)
+        .is_none());
+    assert!(client
+        .receive(response(10, CommandResponse::Done), 0)
+        .is_some());
+    assert_eq!(client.take(handle), Some(Ok(CommandResponse::Done)));
+}
+
+#[test]
+fn maps_failed_responses_to_errors() {
+    let mut client = RpcClient::<4>::new(10, TIMEOUT);
+    let handle = client
+        .send(&MockDevice::default(), Command::Heartbeat, 0)
+        .unwrap();
+
+    let failed = CommandResponse::Failed(CommandError::InvalidState);
+    assert!(client.receive(response(10, failed), 0).is_none());
+    assert_eq!(
+        client.take(handle),
+        Some(Err(RpcError::Failed(CommandError::InvalidState)))
+    );
+}
+
+#[test]
+fn times_out_requests() {
+    let mut client = RpcClient::<4>::new(10, TIMEOUT);
+    let handle = client
+        .send(&MockDevice::default(), Command::Heartbeat, 100)
+        .unwrap();
+
+    client.update(100 + TIMEOUT - 1);
+    assert_eq!(client.take(handle), None);
+    client.update(100 + TIMEOUT);
+    // A late response is given back.
+    assert!(client
+        .receive(response(10, CommandResponse::Done), 0)
+        .is_some());
+    assert_eq!(client.take(handle), Some(Err(RpcError::Timeout)));
+}
+
+#[test]
+fn cancels_requests() {
+    let mut client = RpcClient::<1>::new(10, TIMEOUT);
+    let device = MockDevice::default();
+    let handle = client.send(&device, Command::Heartbeat, 0).unwrap();
+    assert_eq!(
+        client.send(&device, Command::Heartbeat, 0),
+        Err(RpcError::TooManyRequests)
+    );
+
+    client.cancel(handle);
+    assert!(client
+        .receive(response(10, CommandResponse::Done), 0)
+        .is_some());
+    assert_eq!(client.take(handle), None);
+    // The request no longer takes room.
+    assert!(client.send(&device, Command::Heartbeat, 0).is_ok());
+}
+
+#[test]
+fn fails_requests_that_could_not_be_sent() {
+    let mut client = RpcClient::<1>::new(10, TIMEOUT);
+    let device = MockDevice::default();
+    device.down.set(true);
+    assert_eq!(
+        client.send(&device, Command::Heartbeat, 0),
+        Err(RpcError::Device(DeviceError::Physical))
+    );
+
+    // Nothing was left pending.
+    device.down.set(false);
+    assert!(client.send(&device, Command::Heartbeat, 0).is_ok());
+}
+
+#[test]
+fn estimates_the_vehicle_clock_from_round_trips() {
+    let mut client = RpcClient::<4>::new(10, TIMEOUT);
+    let device = MockDevice::default();
+    assert!(!client.clock().synchronized());
+
+    let handle = client.send(&device, Command::Heartbeat, 1_000).unwrap();
+    let response = rpc::response(&device.sent.borrow()[0], 1, CommandResponse::Done, 50_500);
+    assert!(client.receive(response, 2_000).is_none());
+    assert_eq!(client.take(handle), Some(Ok(CommandResponse::Done)));
+    // The response was sent halfway through the round trip.
+    assert_eq!(client.clock().offset(), Some(49_000));
+    assert_eq!(client.clock().uncertainty(), Some(500));
+}
+
+#[test]
+fn blocks_until_the_response_arrives() {
+    let device = MockDevice::default();
+    let ack = Message::new(1, MessageKind::Ack(10), 0, MessageFlags::empty());
+    device.received.borrow_mut().push_back(ack);
+    device
+        .received
+        .borrow_mut()
+        .push_back(response(10, CommandResponse::Done));
+
+    let mut client = RpcClient::<4>::new(10, TIMEOUT);
+    let mut unhandled = Vec::new();
+    let outcome = client.call(
+        &device,
+        Command::Heartbeat,
+        || 0,
+        |message| unhandled.push(message.id()),
+    );
+    assert_eq!(outcome, Ok(CommandResponse::Done));
+    assert_eq!(unhandled, [1]);
+}
+
+#[test]
+fn blocks_until_the_request_times_out() {
+    let device = MockDevice::default();
+    let mut client = RpcClient::<4>::new(10, TIMEOUT);
+    let mut now = 0;
+    let outcome = client.call(
+        &device,
+        Command::Heartbeat,
+        || {
+            now += TIMEOUT / 4;
+            now
+        },
+        |_| {},
+    );
+    assert_eq!(outcome, Err(RpcError::Timeout));
+}
+
+#[cfg(feature = "async")]
+mod future {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use flick_bridge::rpc::future::AsyncClient;
+
+    use super::*;
+
+    thread_local! {
+        /// Current time of the test.
+        static NOW: Cell<u64> = const { Cell::new(0) };
+    }
+
+    fn now() -> u64 {
+        NOW.with(Cell::get)
+    }
+
+    /// Waker counting how many times it was woken.
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn waker() -> (Arc<CountingWaker>, Waker) {
+        let counter = Arc::new(CountingWaker::default());
+        (counter.clone(), Waker::from(counter))
+    }
+
+    fn client() -> AsyncClient<1> {
+        NOW.with(|now| now.set(0));
+        AsyncClient::new(RpcClient::new(10, TIMEOUT), now)
+    }
+
+    #[test]
+    fn wakes_the_future_on_response() {
+        let client = client();
+        let device = MockDevice::default();
+        let (counter, waker) = waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut future = pin!(client.call(&device, Command::Heartbeat));
+        assert_eq!(future.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+
+        assert!(client
+            .receive(response(10, CommandResponse::Done))
+            .is_none());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            future.as_mut().poll(&mut cx),
+            Poll::Ready(Ok(CommandResponse::Done))
+        );
+    }
+
+    #[test]
+    fn times_out_the_future() {
+        let client = client();
+        let device = MockDevice::default();
+        let (counter, waker) = waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut future = pin!(client.call(&device, Command::Heartbeat));
+        assert_eq!(future.as_mut().poll(&mut cx), Poll::Pending);
+        NOW.with(|now| now.set(TIMEOUT));
+        client.update();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            future.as_mut().poll(&mut cx),
+            Poll::Ready(Err(RpcError::Timeout))
+        );
+    }
+
+    #[test]
+    fn cancels_the_request_when_the_future_is_dropped() {
+        let client = client();
+        let device = MockDevice::default();
+        let (_, waker) = waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut future = Box::pin(client.call(&device, Command::Heartbeat));
+        assert_eq!(future.as_mut().poll(&mut cx), Poll::Pending);
+        drop(future);
+
+        // The response is given back, and the request no longer takes room.
+        assert!(client
+            .receive(response(10, CommandResponse::Done))
+            .is_some());
+        let mut future = pin!(client.call(&device, Command::Heartbeat));
+        assert_eq!(future.as_mut().poll(&mut cx), Poll::Pending);
+    }
+
+    #[test]
+    fn fails_the_future_when_the_request_could_not_be_sent() {
+        let client = client();
+        let device = MockDevice::default();
+        let (_, waker) = waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let _first = client.call(&device, Command::Heartbeat);
+        let mut second = pin!(client.call(&device, Command::Heartbeat));
+        assert_eq!(
+            second.as_mut().poll(&mut cx),
+            Poll::Ready(Err(RpcError::TooManyRequests))
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Tests of the delta-compressed telemetry codec, over streams of simulated snapshots.
+
+use flick_bridge::telemetry::codec::{
+    CodecError, Quantization, TelemetryDecoder, TelemetryEncoder,
+};
+use flick_bridge::telemetry::TelemetrySnapshot;
+
+/// Header bit that marks a keyframe.
+const KEYFRAME_BIT: u8 = 0x80;
+
+/// Snapshot of a rocket climbing and slowly rolling, `i` samples of 10 ms into the flight.
+fn snapshot(i: u64) -> TelemetrySnapshot {
+    let t = i as f64 * 0.01;
+    let (sin, cos) = (t * 0.1).sin_cos();
+    TelemetrySnapshot {
+        time: i * 10_000,
+        state: (i / 100) as u32,
+        position: [0.2 * t, -0.1 * t, 4.9 * t * t],
+        orientation: [cos, 0.0, 0.0, sin],
+        velocity: [0.2, -0.1, 9.8 * t],
+        acceleration: [0.01 * sin, 0.0, 9.8],
+    }
+}
+
+/// Assert that every value is within the resolution of the expected one.
+fn assert_within(values: &[f64], expected: &[f64], resolution: f64) {
+    for (value, expected) in values.iter().zip(expected) {
+        assert!(
+            (value - expected).abs() <= resolution,
+            "{value} != {expected}"
+        );
+    }
+}
+
+/// Assert that a decoded snapshot is within the quantization of the encoded one.
+fn assert_close(decoded: &TelemetrySnapshot, snapshot: &TelemetrySnapshot) {
+    let quantization = Quantization::default();
+    assert_eq!(decoded.time, snapshot.time);
+    assert_eq!(decoded.state, snapshot.state);
+    assert_within(&decoded.position, &snapshot.position, quantization.position);
+    assert_within(
+        &decoded.orientation,
+        &snapshot.orientation,
+        quantization.orientation,
+    );
+    assert_within(&decoded.velocity, &snapshot.velocity, quantization.velocity);
+    assert_within(
+        &decoded.acceleration,
+        &snapshot.acceleration,
+        quantization.acceleration,
+    );
+}
+
+/// Encode the snapshot into a frame.
+fn encode(encoder: &mut TelemetryEncoder, i: u64) -> Vec<u8> {
+    let mut buf = [0; 256];
+    let len = encoder.encode(&snapshot(i), &mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+#[test]
+fn round_trips_keyframes_and_delta_frames() {
+    let mut encoder = TelemetryEncoder::new(Quantization::default(), 9);
+    let mut decoder = TelemetryDecoder::new(Quantization::default());
+
+    for i in 0..100 {
+        let frame = encode(&mut encoder, i);
+        // A keyframe, then 9 delta frames.
+        assert_eq!(frame[0] & KEYFRAME_BIT != 0, i % 10 == 0);
+        assert_close(&decoder.decode(&frame).unwrap(), &snapshot(i));
+    }
+}
+
+#[test]
+fn compresses_delta_frames() {
+    let mut encoder = TelemetryEncoder::new(Quantization::default(), 9);
+    let keyframe = encode(&mut encoder, 0);
+    let delta = encode(&mut encoder, 1);
+    assert!(delta.len() < keyframe.len());
+
+    // Nothing but the header and an empty mask when nothing changed.
+    let mut encoder = TelemetryEncoder::new(Quantization::default(), 9);
+    let mut buf = [0; 256];
+    encoder.encode(&snapshot(0), &mut buf).unwrap();
+    assert_eq!(encoder.encode(&snapshot(0), &mut buf), Ok(2));
+}
+
+#[test]
+fn wraps_the_sequence_number() {
+    let mut encoder = TelemetryEncoder::new(Quantization::default(), u16::MAX);
+    let mut decoder = TelemetryDecoder::new(Quantization::default());
+
+    for i in 0..300 {
+        let frame = encode(&mut encoder, i);
+        assert_eq!(frame[0] & !KEYFRAME_BIT, (i % 128) as u8);
+        assert_close(&decoder.decode(&frame).unwrap(), &snapshot(i));
+    }
+}
+
+#[test]
+fn rejects_delta_frames_after_a_gap_until_the_next_keyframe() {
+    let mut encoder = TelemetryEncoder::new(Quantization::default(), 9);
+    let mut decoder = TelemetryDecoder::new(Quantization::default());
+    for i in 0..3 {
+        decoder.decode(&encode(&mut encoder, i)).unwrap();
+    }
+
+    // Frame 3 is lost.
+    encode(&mut encoder, 3);
+    assert_eq!(
+        decoder.decode(&encode(&mut encoder, 4)),
+        Err(CodecError::SequenceGap)
+    );
+    for i in 5..10 {
+        assert_eq!(
+            decoder.decode(&encode(&mut encoder, i)),
+            Err(CodecError::MissingKeyframe)
+        );
+    }
+
+    let keyframe = encode(&mut encoder, 10);
+    assert_ne!(keyframe[0] & KEYFRAME_BIT, 0);
+    assert_close(&decoder.decode(&keyframe).unwrap(), &snapshot(10));
+    assert_close(
+        &decoder.decode(&encode(&mut encoder, 11)).unwrap(),
+        &snapshot(11),
+    );
+}
+
+#[test]
+fn rejects_delta_frames_after_a_lost_keyframe() {
+    let mut encoder = TelemetryEncoder::new(Quantization::default(), 9);
+    let mut decoder = TelemetryDecoder::new(Quantization::default());
+
+    // The first keyframe is lost.
+    encode(&mut encoder, 0);
+    for i in 1..10 {
+        assert_eq!(
+            decoder.decode(&encode(&mut encoder, i)),
+            Err(CodecError::MissingKeyframe)
+        );
+    }
+    assert_close(
+        &decoder.decode(&encode(&mut encoder, 10)).unwrap(),
+        &snapshot(10),
+    );
+}
+
+#[test]
+fn forces_a_keyframe() {
+    let mut encoder = TelemetryEncoder::new(Quantization::default(), 9);
+    let mut decoder = TelemetryDecoder::new(Quantization::default());
+    for i in 0..4 {
+        encode(&mut encoder, i);
+    }
+
+    // A receiver joining mid-stream picks it up from the forced keyframe.
+    encoder.force_keyframe();
+    let keyframe = encode(&mut encoder, 4);
+    assert_ne!(keyframe[0] & KEYFRAME_BIT, 0);
+    assert_close(&decoder.decode(&keyframe).unwrap(), &snapshot(4));
+
+    // The keyframe interval restarts from the forced keyframe.
+    for i in 5..14 {
+        let frame = encode(&mut encoder, i);
+        assert_eq!(frame[0] & KEYFRAME_BIT, 0);
+        assert_close(&decoder.decode(&frame).unwrap(), &snapshot(i));
+    }
+    assert_ne!(encode(&mut encoder, 14)[0] & KEYFRAME_BIT, 0);
+}
+
+#[test]
+fn rejects_malformed_frames() {
+    let mut encoder = TelemetryEncoder::new(Quantization::default(), 9);
+    let mut decoder = TelemetryDecoder::new(Quantization::default());
+
+    let keyframe = encode(&mut encoder, 0);
+    assert_eq!(
+        decoder.decode(&keyframe[..keyframe.len() - 1]),
+        Err(CodecError::Truncated)
+    );
+    let mut padded = keyframe.clone();
+    padded.push(0);
+    assert_eq!(decoder.decode(&padded), Err(CodecError::InvalidFrame));
+    assert_eq!(
+        encoder.encode(&snapshot(1), &mut [0; 4]),
+        Err(CodecError::BufferTooSmall)
+    );
+}
@@ -0,0 +1,176 @@
+//! Tests of the USB serial device, separating bridge frames from the log lines of the vehicle.
+
+#![cfg(feature = "std")]
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::rc::Rc;
+
+use flick_bridge::command::Command;
+use flick_bridge::device::Device;
+use flick_bridge::frame::stream::{self, StreamDecoder, DELIMITER, MAX_ENCODED_LEN};
+use flick_bridge::frame::{self, MAX_FRAME_LEN};
+use flick_bridge::host::usb::UsbSerialDevice;
+use flick_bridge::message::{Message, MessageFlags, MessageKind};
+
+/// Port reading the bytes queued by the test, and keeping what is written to it.
+#[derive(Default, Clone)]
+struct MockPort {
+    input: Rc<RefCell<VecDeque<u8>>>,
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl MockPort {
+    fn push(&self, bytes: &[u8]) {
+        self.input.borrow_mut().extend(bytes);
+    }
+}
+
+impl Read for MockPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = self.input.borrow_mut();
+        if input.is_empty() {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        input.read(buf)
+    }
+}
+
+impl Write for MockPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Log lines received by a device.
+type Logs = Rc<RefCell<Vec<String>>>;
+
+/// Device over a mock port, with the log lines it received.
+fn device() -> (UsbSerialDevice<MockPort>, MockPort, Logs) {
+    let port = MockPort::default();
+    let logs = Logs::default();
+    let device = UsbSerialDevice::new(port.clone(), {
+        let logs = logs.clone();
+        move |line: &str| logs.borrow_mut().push(line.to_owned())
+    });
+    (device, port, logs)
+}
+
+fn message(id: u64) -> Message {
+    Message::new(
+        id,
+        MessageKind::Request(Command::Heartbeat),
+        id * 1000,
+        MessageFlags::empty(),
+    )
+}
+
+/// Frame as the vehicle writes it, with an extra delimiter in front.
+fn vehicle_frame(message: &Message) -> Vec<u8> {
+    let mut buf = [0; MAX_FRAME_LEN];
+    let frame = frame::encode(message, &mut buf).unwrap();
+    let mut encoded = [0; MAX_ENCODED_LEN];
+    let len = stream::encode(frame, &mut encoded).unwrap();
+    [&[DELIMITER], &encoded[..len]].concat()
+}
+
+fn poll_id(device: &UsbSerialDevice<MockPort>) -> Option<u64> {
+    device.poll().unwrap().map(|message| message.id())
+}
+
+#[test]
+fn separates_interleaved_logs_and_frames() {
+    let (device, port, logs) = device();
+    port.push(b"[INFO flightctrl]: booted\r\n");
+    port.push(&vehicle_frame(&message(1)));
+    port.push(b"[WARN flightctrl]: low battery\r\n");
+    port.push(&vehicle_frame(&message(2)));
+    port.push(&vehicle_frame(&message(3)));
+    port.push(b"[INFO flightctrl]: armed\r\n");
+
+    assert_eq!(poll_id(&device), Some(1));
+    assert_eq!(poll_id(&device), Some(2));
+    assert_eq!(poll_id(&device), Some(3));
+    assert_eq!(poll_id(&device), None);
+    assert_eq!(
+        *logs.borrow(),
+        [
+            "[INFO flightctrl]: booted",
+            "[WARN flightctrl]: low battery",
+            "[INFO flightctrl]: armed",
+        ]
+    );
+}
+
+#[test]
+fn waits_for_the_rest_of_partial_frames_and_lines() {
+    let (device, port, logs) = device();
+    let frame = vehicle_frame(&message(1));
+    let (start, end) = frame.split_at(frame.len() / 2);
+
+    port.push(b"[INFO flightctrl]: ");
+    port.push(start);
+    assert_eq!(poll_id(&device), None);
+    port.push(end);
+    port.push(b"[INFO flightctrl]: armed");
+    assert_eq!(poll_id(&device), Some(1));
+    assert_eq!(poll_id(&device), None);
+    assert!(logs.borrow().is_empty());
+
+    port.push(b"\r\n");
+    assert_eq!(poll_id(&device), None);
+    assert_eq!(*logs.borrow(), ["[INFO flightctrl]: armed"]);
+}
+
+#[test]
+fn resynchronizes_after_a_delimiter_in_a_log_line() {
+    let (device, port, logs) = device();
+    // The text after the stray delimiter is taken for a frame, until the next frame starts.
+    port.push(b"[INFO flightctrl]: before\0after\r\n");
+    port.push(&vehicle_frame(&message(1)));
+    port.push(b"[INFO flightctrl]: armed\r\n");
+
+    assert!(device.poll().is_err());
+    assert_eq!(poll_id(&device), Some(1));
+    assert_eq!(poll_id(&device), None);
+    // Only the line with the delimiter is lost.
+    assert_eq!(*logs.borrow(), ["[INFO flightctrl]: armed"]);
+}
+
+#[test]
+fn resynchronizes_when_starting_in_the_middle_of_a_frame() {
+    let (device, port, logs) = device();
+    let frame = vehicle_frame(&message(1));
+    port.push(&frame[frame.len() / 2..]);
+    port.push(b"[INFO flightctrl]: lost\r\n");
+    port.push(&vehicle_frame(&message(2)));
+    port.push(b"[INFO flightctrl]: armed\r\n");
+
+    // The trailing delimiter of the first frame is taken for the start of one, so the log line
+    // that follows fails to decode as a frame.
+    assert!(device.poll().is_err());
+    assert_eq!(poll_id(&device), Some(2));
+    assert_eq!(poll_id(&device), None);
+    assert_eq!(*logs.borrow(), ["[INFO flightctrl]: armed"]);
+}
+
+#[test]
+fn transmits_delimited_frames() {
+    let (device, port, _) = device();
+    device.transmit(message(1)).unwrap();
+    device.transmit(message(2)).unwrap();
+
+    let mut decoder = StreamDecoder::new();
+    let mut ids = Vec::new();
+    for &byte in port.output.borrow().iter() {
+        if let Some(frame) = decoder.push(byte).unwrap() {
+            ids.push(frame::decode(frame).unwrap().id());
+        }
+    }
+    assert_eq!(ids, [1, 2]);
+}
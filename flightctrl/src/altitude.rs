@@ -0,0 +1,394 @@
+//! Barometric altitude, from the International Standard Atmosphere.
+//!
+//! In the troposphere, the standard atmosphere has the temperature fall linearly with altitude,
+//! which ties the pressure to the altitude. The altitude above ground is found from the pressure
+//! relative to the ground pressure, using the temperature measured on the ground instead of the
+//! standard one: this compensates for hot and cold days, which would otherwise put the altitude off
+//! by several percent. The temperature measured in flight isn't used, since the sensor sits in a
+//! warm avionics bay and lags the air.
+//!
+//! The ground reference is averaged from the barometer on the pad, window after window, so that
+//! it follows the weather until launch. Windows where the pressure moved too much, such as the one
+//! the launch happens in, are discarded. [`Altimeter::rezero`] starts over, such as after the
+//! vehicle was moved to the pad.
+
+use flick_bridge::command::{Command, CommandError, CommandResponse};
+use flick_bridge::param::Param;
+use flick_bridge::state::FlightState;
+
+use crate::device::{BarometerSample, Pressure, Temperature};
+use crate::params::Parameters;
+
+/// Standard pressure at sea level, in pascals.
+pub const SEA_LEVEL_PRESSURE: Pressure = Pressure(101_325.0);
+/// Standard temperature at sea level.
+pub const SEA_LEVEL_TEMPERATURE: Temperature = Temperature(15.0);
+/// Fall of the temperature with altitude in the troposphere, in kelvins per meter.
+pub const LAPSE_RATE: f32 = 0.0065;
+/// Exponent of the pressure ratio, `R L / (g M)`.
+const EXPONENT: f32 = 0.190_263;
+/// Zero degrees Celsius, in kelvins.
+const ZERO_CELSIUS: f32 = 273.15;
+
+/// Largest spread of the pressure within a window for it to be used as ground reference, in
+/// pascals. About 2.5 m.
+pub const MAX_GROUND_SPREAD: f32 = 30.0;
+
+/// Altitude of a pressure above a reference level, given the temperature at that level.
+pub fn altitude_above(pressure: Pressure, reference: Pressure, temperature: Temperature) -> f32 {
+    let temperature = temperature.0 + ZERO_CELSIUS;
+    temperature / LAPSE_RATE * (1.0 - num_traits::Float::powf(pressure.0 / reference.0, EXPONENT))
+}
+
+/// Altitude of a pressure above mean sea level in the standard atmosphere, also known as the
+/// pressure altitude.
+pub fn pressure_altitude(pressure: Pressure) -> f32 {
+    altitude_above(pressure, SEA_LEVEL_PRESSURE, SEA_LEVEL_TEMPERATURE)
+}
+
+/// Conditions on the ground that altitudes are measured from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroundReference {
+    pub pressure: Pressure,
+    pub temperature: Temperature,
+    /// Altitude of the ground above mean sea level, in meters.
+    pub elevation: f32,
+}
+
+/// Barometric altitude.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Altitude {
+    pub time: u64,
+    /// Altitude above ground level, in meters.
+    pub agl: f32,
+    /// Altitude above mean sea level, in meters.
+    pub msl: f32,
+}
+
+/// Configuration of the [`Altimeter`].
+#[derive(Debug, Clone, Copy)]
+pub struct AltimeterConfig {
+    /// Time that the barometer is averaged over for the ground reference, in microseconds.
+    pub averaging: u64,
+    /// Largest spread of the pressure within a window for it to be used, in pascals.
+    pub max_spread: f32,
+}
+
+impl From<&Parameters> for AltimeterConfig {
+    fn from(params: &Parameters) -> Self {
+        Self {
+            averaging: params.micros(Param::GroundAveragingTime),
+            max_spread: MAX_GROUND_SPREAD,
+        }
+    }
+}
+
+/// Barometer samples being averaged.
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    start: u64,
+    count: u32,
+    /// First pressure, which the others are summed relative to, to keep the precision.
+    first: f32,
+    pressure_sum: f32,
+    temperature_sum: f32,
+    min: f32,
+    max: f32,
+}
+
+impl Window {
+    fn new(sample: &BarometerSample) -> Self {
+        Self {
+            start: sample.time,
+            count: 0,
+            first: sample.pressure.0,
+            pressure_sum: 0.0,
+            temperature_sum: 0.0,
+            min: sample.pressure.0,
+            max: sample.pressure.0,
+        }
+    }
+
+    fn push(&mut self, sample: &BarometerSample) {
+        self.count += 1;
+        self.pressure_sum += sample.pressure.0 - self.first;
+        self.temperature_sum += sample.temperature.0;
+        self.min = self.min.min(sample.pressure.0);
+        self.max = self.max.max(sample.pressure.0);
+    }
+
+    fn pressure(&self) -> Pressure {
+        Pressure(self.first + self.pressure_sum / self.count as f32)
+    }
+
+    fn temperature(&self) -> Temperature {
+        Temperature(self.temperature_sum / self.count as f32)
+    }
+}
+
+/// Turns barometer samples into altitudes.
+#[derive(Debug, Clone)]
+pub struct Altimeter {
+    config: AltimeterConfig,
+    ground: Option<GroundReference>,
+    /// Known elevation of the ground, in meters.
+    elevation: Option<f32>,
+    window: Option<Window>,
+}
+
+impl Altimeter {
+    /// Instantiate a new [`Altimeter`], without a ground reference.
+    pub fn new(config: AltimeterConfig) -> Self {
+        Self {
+            config,
+            ground: None,
+            elevation: None,
+            window: None,
+        }
+    }
+
+    /// Current ground reference, once one was averaged.
+    pub fn ground(&self) -> Option<&GroundReference> {
+        self.ground.as_ref()
+    }
+
+    /// Set the elevation of the ground above mean sea level, in meters, such as from a survey or
+    /// the GNSS. Without one, the pressure altitude of the ground is used, which is off by as much
+    /// as the weather moves the pressure.
+    pub fn set_elevation(&mut self, elevation: Option<f32>) {
+        self.elevation = elevation;
+        if let Some(ground) = &mut self.ground {
+            ground.elevation = elevation.unwrap_or_else(|| pressure_altitude(ground.pressure));
+        }
+    }
+
+    /// Forget the ground reference, and average a new one.
+    pub fn rezero(&mut self) {
+        log::info!("rezeroing altitude");
+        self.ground = None;
+        self.window = None;
+    }
+
+    /// Altitude of a pressure, once there is a ground reference.
+    pub fn altitude(&self, time: u64, pressure: Pressure) -> Option<Altitude> {
+        let ground = self.ground?;
+        let agl = altitude_above(pressure, ground.pressure, ground.temperature);
+        Some(Altitude {
+            time,
+            agl,
+            msl: ground.elevation + agl,
+        })
+    }
+
+    /// Update with a new barometer sample, given the current flight state. The ground reference
+    /// follows the barometer until launch. Returns the altitude of the sample, once there is a
+    /// ground reference.
+    pub fn update(&mut self, sample: &BarometerSample, state: FlightState) -> Option<Altitude> {
+        if on_pad(state) {
+            self.average(sample);
+        } else {
+            self.window = None;
+        }
+
+        self.altitude(sample.time, sample.pressure)
+    }
+
+    /// Handle the commands that operate the altimeter, given the current flight state. Returns
+    /// `None` for every other command.
+    pub fn handle(&mut self, command: &Command, state: FlightState) -> Option<CommandResponse> {
+        match command {
+            Command::Rezero if on_pad(state) => {
+                self.rezero();
+                Some(CommandResponse::Done)
+            }
+            Command::Rezero => Some(CommandResponse::Failed(CommandError::InvalidState)),
+            _ => None,
+        }
+    }
+
+    fn average(&mut self, sample: &BarometerSample) {
+        let window = self.window.get_or_insert_with(|| Window::new(sample));
+        window.push(sample);
+        if sample.time.saturating_sub(window.start) < self.config.averaging {
+            return;
+        }
+
+        let window = *window;
+        self.window = None;
+        if window.max - window.min > self.config.max_spread {
+            log::debug!(
+                "ground reference discarded, pressure moved {} Pa",
+                window.max - window.min
+            );
+            return;
+        }
+
+        let pressure = window.pressure();
+        let ground = GroundReference {
+            pressure,
+            temperature: window.temperature(),
+            elevation: self
+                .elevation
+                .unwrap_or_else(|| pressure_altitude(pressure)),
+        };
+        if self.ground.is_none() {
+            log::info!(
+                "ground reference {} Pa, {} C, {} m",
+                ground.pressure.0,
+                ground.temperature.0,
+                ground.elevation
+            );
+        }
+        self.ground = Some(ground);
+    }
+}
+
+/// Whether the vehicle is on the pad, before launch. A vehicle in [`FlightState::Fault`] keeps
+/// its ground reference, since it may be anywhere.
+fn on_pad(state: FlightState) -> bool {
+    matches!(state, FlightState::Idle | FlightState::Armed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000;
+
+    fn altimeter() -> Altimeter {
+        Altimeter::new(AltimeterConfig {
+            averaging: SECOND,
+            max_spread: MAX_GROUND_SPREAD,
+        })
+    }
+
+    fn sample(time: u64, pressure: f32) -> BarometerSample {
+        BarometerSample {
+            time,
+            pressure: Pressure(pressure),
+            temperature: Temperature(20.0),
+        }
+    }
+
+    /// Feed a window of samples every 10 ms, alternating between two pressures.
+    fn feed(altimeter: &mut Altimeter, start: u64, pressures: [f32; 2], state: FlightState) {
+        for i in 0..=100 {
+            let sample = sample(start + i * SECOND / 100, pressures[i as usize % 2]);
+            altimeter.update(&sample, state);
+        }
+    }
+
+    fn assert_close(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (value - expected).abs() <= tolerance,
+            "{} != {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn matches_the_standard_atmosphere() {
+        assert_close(pressure_altitude(SEA_LEVEL_PRESSURE), 0.0, 0.01);
+        assert_close(pressure_altitude(Pressure(89_874.6)), 1_000.0, 0.5);
+        assert_close(pressure_altitude(Pressure(79_495.2)), 2_000.0, 0.5);
+        assert_close(pressure_altitude(Pressure(54_019.9)), 5_000.0, 1.0);
+        assert_close(pressure_altitude(Pressure(107_477.8)), -500.0, 0.5);
+
+        // Warmer air is less dense, so the same pressure drop spans more height.
+        let hot = altitude_above(Pressure(89_874.6), SEA_LEVEL_PRESSURE, Temperature(30.0));
+        assert_close(hot, 1_000.0 * (303.15 / 288.15), 0.5);
+    }
+
+    #[test]
+    fn averages_the_ground_reference_over_a_window() {
+        let mut altimeter = altimeter();
+        // No altitude until the first window ends.
+        assert!(altimeter
+            .update(&sample(0, 100_000.0), FlightState::Idle)
+            .is_none());
+        assert!(altimeter
+            .update(&sample(SECOND - 1, 100_000.0), FlightState::Idle)
+            .is_none());
+        altimeter.rezero();
+
+        feed(
+            &mut altimeter,
+            0,
+            [100_000.0, 100_010.0],
+            FlightState::Armed,
+        );
+        let ground = *altimeter.ground().unwrap();
+        assert_close(ground.pressure.0, 100_005.0, 0.5);
+        assert_close(ground.temperature.0, 20.0, 0.01);
+        assert_close(ground.elevation, pressure_altitude(ground.pressure), 0.01);
+
+        let altitude = altimeter.altitude(0, ground.pressure).unwrap();
+        assert_close(altitude.agl, 0.0, 0.01);
+        assert_close(altitude.msl, ground.elevation, 0.01);
+        altimeter.set_elevation(Some(400.0));
+        // About 11.6 Pa per meter near the ground at 20 C.
+        let altitude = altimeter.altitude(0, Pressure(98_800.0)).unwrap();
+        assert_close(altitude.agl, 103.9, 0.5);
+        assert_close(altitude.msl, 400.0 + altitude.agl, 0.01);
+    }
+
+    #[test]
+    fn discards_windows_where_the_pressure_moved() {
+        let mut altimeter = altimeter();
+        feed(&mut altimeter, 0, [100_000.0, 100_040.0], FlightState::Idle);
+        assert_eq!(altimeter.ground(), None);
+
+        feed(
+            &mut altimeter,
+            SECOND,
+            [100_000.0, 100_020.0],
+            FlightState::Idle,
+        );
+        assert_close(altimeter.ground().unwrap().pressure.0, 100_010.0, 0.5);
+        // A window that moved keeps the previous reference.
+        feed(
+            &mut altimeter,
+            2 * SECOND,
+            [99_000.0, 100_000.0],
+            FlightState::Idle,
+        );
+        assert_close(altimeter.ground().unwrap().pressure.0, 100_010.0, 0.5);
+    }
+
+    #[test]
+    fn keeps_the_ground_reference_after_launch() {
+        let mut altimeter = altimeter();
+        feed(&mut altimeter, 0, [100_000.0; 2], FlightState::Idle);
+        for state in [FlightState::Boost, FlightState::Landed, FlightState::Fault] {
+            feed(&mut altimeter, SECOND, [99_000.0; 2], state);
+            assert_close(altimeter.ground().unwrap().pressure.0, 100_000.0, 0.1);
+        }
+    }
+
+    #[test]
+    fn rezeroes_on_the_pad_only() {
+        let mut altimeter = altimeter();
+        feed(&mut altimeter, 0, [100_000.0; 2], FlightState::Idle);
+
+        for state in [FlightState::Boost, FlightState::Landed, FlightState::Fault] {
+            assert_eq!(
+                altimeter.handle(&Command::Rezero, state),
+                Some(CommandResponse::Failed(CommandError::InvalidState))
+            );
+            assert!(altimeter.ground().is_some());
+        }
+        assert_eq!(
+            altimeter.handle(&Command::Heartbeat, FlightState::Idle),
+            None
+        );
+
+        assert_eq!(
+            altimeter.handle(&Command::Rezero, FlightState::Armed),
+            Some(CommandResponse::Done)
+        );
+        assert_eq!(altimeter.ground(), None);
+        feed(&mut altimeter, SECOND, [99_000.0; 2], FlightState::Armed);
+        assert_close(altimeter.ground().unwrap().pressure.0, 99_000.0, 0.1);
+    }
+}
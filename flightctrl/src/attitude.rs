@@ -0,0 +1,327 @@
+//! Attitude estimation, with a Mahony filter.
+//!
+//! The orientation is the rotation from the forward-left-up body frame to the east-north-up earth
+//! frame, like [`TelemetrySnapshot::orientation`]. It is integrated from the gyroscope, whose
+//! drift is corrected by comparing where gravity and north are measured, by the accelerometer and
+//! the magnetometer, with where the orientation expects them. The correction is a proportional and
+//! integral feedback on the gyroscope, where the integral learns what remains of its bias.
+//!
+//! The accelerometer only measures gravity when nothing else pushes the vehicle. Under thrust, and
+//! in the free fall of the coast, it can't be used, so its correction is disabled in boost and
+//! coast, and whenever the acceleration is far from standard gravity. The gyroscope then carries
+//! the orientation alone. The magnetometer, when there is one, only corrects the heading.
+//!
+//! Samples are expected in the body frame, so calibrated (see [`crate::device::calibration`]).
+//!
+//! [`TelemetrySnapshot::orientation`]: flick_bridge::telemetry::TelemetrySnapshot::orientation
+
+use flick_bridge::state::FlightState;
+use nalgebra::{Matrix3, Rotation3, UnitQuaternion, Vector3};
+
+use crate::device::{ImuSample, MagnetometerSample, STANDARD_GRAVITY};
+
+/// Configuration of the [`AttitudeFilter`].
+#[derive(Debug, Clone, Copy)]
+pub struct AttitudeConfig {
+    /// Proportional gain of the correction, in radians per second per radian of error.
+    pub kp: f32,
+    /// Integral gain of the correction, learning the gyroscope bias.
+    pub ki: f32,
+    /// Largest difference between the measured acceleration and standard gravity for the
+    /// accelerometer to be used, in meters per second squared.
+    pub gravity_tolerance: f32,
+}
+
+impl Default for AttitudeConfig {
+    fn default() -> Self {
+        Self {
+            kp: 1.0,
+            ki: 0.02,
+            gravity_tolerance: 1.0,
+        }
+    }
+}
+
+/// Mahony attitude filter.
+#[derive(Debug, Clone)]
+pub struct AttitudeFilter {
+    config: AttitudeConfig,
+    orientation: UnitQuaternion<f32>,
+    /// Integral of the correction, added to the gyroscope, in radians per second.
+    integral: Vector3<f32>,
+    /// Time of the last sample, once there was one.
+    last_time: Option<u64>,
+}
+
+impl AttitudeFilter {
+    /// Instantiate a new [`AttitudeFilter`]. The orientation is aligned with the first sample.
+    pub fn new(config: AttitudeConfig) -> Self {
+        Self {
+            config,
+            orientation: UnitQuaternion::identity(),
+            integral: Vector3::zeros(),
+            last_time: None,
+        }
+    }
+
+    /// Estimated orientation, from the body frame to the earth frame.
+    pub fn orientation(&self) -> UnitQuaternion<f32> {
+        self.orientation
+    }
+
+    /// Estimated orientation, as given in telemetry.
+    pub fn telemetry_orientation(&self) -> [f64; 4] {
+        let q = self.orientation.quaternion();
+        [q.w, q.i, q.j, q.k].map(f64::from)
+    }
+
+    /// Gyroscope bias learnt by the filter, in radians per second.
+    pub fn gyro_bias(&self) -> Vector3<f32> {
+        -self.integral
+    }
+
+    /// Acceleration of a sample in the earth frame, without gravity, in meters per second
+    /// squared.
+    pub fn earth_acceleration(&self, sample: &ImuSample) -> Vector3<f32> {
+        self.orientation * sample.acceleration.0 - Vector3::z() * STANDARD_GRAVITY
+    }
+
+    /// Start over from the given samples, such as after the vehicle was moved on the pad.
+    pub fn reset(&mut self, imu: &ImuSample, magnetometer: Option<&MagnetometerSample>) {
+        self.integral = Vector3::zeros();
+        self.last_time = Some(imu.time);
+        self.orientation = Self::align(&imu.acceleration.0, magnetometer.map(|m| &m.field.0))
+            .unwrap_or_else(UnitQuaternion::identity);
+    }
+
+    /// Update with a new IMU sample, and the latest magnetometer sample if there is one, given
+    /// the current flight state. Returns the estimated orientation.
+    pub fn update(
+        &mut self,
+        imu: &ImuSample,
+        magnetometer: Option<&MagnetometerSample>,
+        state: FlightState,
+    ) -> UnitQuaternion<f32> {
+        let last_time = match self.last_time {
+            Some(last_time) => last_time,
+            None => {
+                self.reset(imu, magnetometer);
+                return self.orientation;
+            }
+        };
+        let dt = imu.time.saturating_sub(last_time) as f32 / 1e6;
+        self.last_time = Some(imu.time);
+
+        let mut error = Vector3::zeros();
+        let mut corrected = false;
+
+        let acceleration = imu.acceleration.0;
+        let tolerance = self.config.gravity_tolerance;
+        let gravity_observable = !matches!(state, FlightState::Boost | FlightState::Coast)
+            && (-tolerance..=tolerance).contains(&(acceleration.norm() - STANDARD_GRAVITY));
+        if gravity_observable {
+            // At rest, the accelerometer measures the reaction to gravity, which points up.
+            let expected = self.orientation.inverse_transform_vector(&Vector3::z());
+            error += acceleration.normalize().cross(&expected);
+            corrected = true;
+        }
+
+        if let Some(field) = magnetometer.and_then(|m| m.field.0.try_normalize(f32::EPSILON)) {
+            // Only the heading is corrected: the measured field is expected to point north
+            // horizontally, keeping its measured inclination.
+            let earth = self.orientation * field;
+            let horizontal = earth.xy().norm();
+            let expected = self
+                .orientation
+                .inverse_transform_vector(&Vector3::new(0.0, horizontal, earth.z));
+            let heading_error = field.cross(&expected);
+            // Keep the component around the vertical, so the tilt is left to the accelerometer.
+            let up = self.orientation.inverse_transform_vector(&Vector3::z());
+            error += up * heading_error.dot(&up);
+            corrected = true;
+        }
+
+        if corrected {
+            self.integral += error * self.config.ki * dt;
+        }
+        let rate = imu.angular_rate.0 + error * self.config.kp + self.integral;
+        self.orientation *= UnitQuaternion::from_scaled_axis(rate * dt);
+
+        self.orientation
+    }
+
+    /// Orientation matching the gravity measured by the accelerometer, and the heading measured
+    /// by the magnetometer if there is one.
+    fn align(
+        acceleration: &Vector3<f32>,
+        field: Option<&Vector3<f32>>,
+    ) -> Option<UnitQuaternion<f32>> {
+        let up = acceleration.try_normalize(f32::EPSILON)?;
+        let east = field.and_then(|field| field.cross(&up).try_normalize(f32::EPSILON));
+        let east = match east {
+            Some(east) => east,
+            None => return UnitQuaternion::rotation_between(&up, &Vector3::z()),
+        };
+        let north = up.cross(&east);
+
+        // The rows hold the earth axes in the body frame, which makes it the rotation from the
+        // body to the earth.
+        let rotation = Matrix3::from_rows(&[east.transpose(), north.transpose(), up.transpose()]);
+        Some(UnitQuaternion::from_rotation_matrix(
+            &Rotation3::from_matrix_unchecked(rotation),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{Acceleration, AngularRate, MagneticField};
+
+    /// Time between two samples, in microseconds.
+    const PERIOD: u64 = 10_000;
+
+    /// Field of the earth in the earth frame, in microteslas: north, and dipping down.
+    fn earth_field() -> Vector3<f32> {
+        Vector3::new(0.0, 20.0, -40.0)
+    }
+
+    fn sample(time: u64, acceleration: Vector3<f32>, angular_rate: Vector3<f32>) -> ImuSample {
+        ImuSample {
+            time,
+            acceleration: Acceleration(acceleration),
+            angular_rate: AngularRate(angular_rate),
+        }
+    }
+
+    /// Reading of the accelerometer at rest in the given orientation.
+    fn at_rest(orientation: UnitQuaternion<f32>) -> Vector3<f32> {
+        orientation.inverse_transform_vector(&Vector3::z()) * STANDARD_GRAVITY
+    }
+
+    /// Reading of the magnetometer in the given orientation.
+    fn field(time: u64, orientation: UnitQuaternion<f32>) -> MagnetometerSample {
+        MagnetometerSample {
+            time,
+            field: MagneticField(orientation.inverse_transform_vector(&earth_field())),
+        }
+    }
+
+    /// Filter started in the given orientation, at time zero.
+    fn filter(orientation: UnitQuaternion<f32>, heading: bool) -> AttitudeFilter {
+        let mut filter = AttitudeFilter::new(AttitudeConfig::default());
+        let magnetometer = field(0, orientation);
+        let imu = sample(0, at_rest(orientation), Vector3::zeros());
+        filter.update(&imu, heading.then_some(&magnetometer), FlightState::Armed);
+        filter
+    }
+
+    /// Feed samples for the given duration, in seconds, computed from the time, from the last one
+    /// fed. Returns the orientation at the end.
+    fn run(
+        filter: &mut AttitudeFilter,
+        duration: f32,
+        state: FlightState,
+        mut samples: impl FnMut(u64) -> (ImuSample, Option<MagnetometerSample>),
+    ) -> UnitQuaternion<f32> {
+        let start = filter.last_time.unwrap_or(0);
+        let end = start + (duration * 1e6) as u64;
+        for time in (start + PERIOD..=end).step_by(PERIOD as usize) {
+            let (imu, magnetometer) = samples(time);
+            filter.update(&imu, magnetometer.as_ref(), state);
+        }
+        filter.orientation()
+    }
+
+    /// Angle between the vertical of the body, as estimated and as it truly is.
+    fn tilt_error(estimated: UnitQuaternion<f32>, truth: UnitQuaternion<f32>) -> f32 {
+        (estimated * Vector3::z()).angle(&(truth * Vector3::z()))
+    }
+
+    #[test]
+    fn aligns_with_the_first_sample() {
+        let truth = UnitQuaternion::from_euler_angles(0.2, -0.4, 1.0);
+        let filter = filter(truth, true);
+        assert!(filter.orientation().angle_to(&truth) < 1e-4);
+    }
+
+    #[test]
+    fn integrates_a_constant_rate() {
+        let rate = Vector3::new(0.3, -0.2, 0.5);
+        let mut filter = filter(UnitQuaternion::identity(), false);
+
+        // In free fall, so only the gyroscope is used.
+        let orientation = run(&mut filter, 2.0, FlightState::Coast, |time| {
+            (sample(time, Vector3::zeros(), rate), None)
+        });
+
+        let expected = UnitQuaternion::from_scaled_axis(rate * 2.0);
+        assert!(orientation.angle_to(&expected) < 1e-3);
+    }
+
+    #[test]
+    fn corrects_a_wrong_tilt_at_rest() {
+        let truth = UnitQuaternion::from_euler_angles(0.1, 0.2, 0.0);
+        // Started from a bump on the pad, tilted the other way.
+        let mut filter = filter(UnitQuaternion::from_euler_angles(-0.3, 0.1, 0.0), false);
+        assert!(tilt_error(filter.orientation(), truth) > 0.3);
+
+        let orientation = run(&mut filter, 10.0, FlightState::Armed, |time| {
+            (sample(time, at_rest(truth), Vector3::zeros()), None)
+        });
+        assert!(tilt_error(orientation, truth) < 0.01);
+    }
+
+    #[test]
+    fn corrects_the_heading_from_the_magnetometer() {
+        let truth = UnitQuaternion::from_euler_angles(0.0, 0.1, 0.5);
+        let wrong = UnitQuaternion::from_euler_angles(0.0, 0.1, -0.3);
+        let samples = |heading: bool| {
+            move |time| {
+                let magnetometer = heading.then(|| field(time, truth));
+                (sample(time, at_rest(truth), Vector3::zeros()), magnetometer)
+            }
+        };
+
+        // Gravity says nothing of the heading.
+        let mut without = filter(wrong, true);
+        let orientation = run(&mut without, 20.0, FlightState::Armed, samples(false));
+        assert!(orientation.angle_to(&truth) > 0.7);
+
+        let mut with = filter(wrong, true);
+        let orientation = run(&mut with, 20.0, FlightState::Armed, samples(true));
+        assert!(orientation.angle_to(&truth) < 0.05);
+        // Only the heading is corrected, so the tilt stays close.
+        assert!(tilt_error(orientation, truth) < 0.01);
+        // What remains was learnt as a bias by the integral, which unwinds slowly.
+        let orientation = run(&mut with, 40.0, FlightState::Armed, samples(true));
+        assert!(orientation.angle_to(&truth) < 0.035);
+    }
+
+    #[test]
+    fn ignores_the_accelerometer_in_boost_and_coast() {
+        let tilted = at_rest(UnitQuaternion::from_euler_angles(0.3, 0.0, 0.0));
+        let thrust = Vector3::new(2.0, 0.0, 40.0);
+
+        for (state, acceleration) in [
+            (FlightState::Boost, thrust),
+            (FlightState::Boost, tilted),
+            (FlightState::Coast, tilted),
+            // Also when pushed, whatever the state.
+            (FlightState::Armed, thrust),
+        ] {
+            let mut filter = filter(UnitQuaternion::identity(), false);
+            let orientation = run(&mut filter, 2.0, state, |time| {
+                (sample(time, acceleration, Vector3::zeros()), None)
+            });
+            assert_eq!(orientation, UnitQuaternion::identity(), "{:?}", state);
+        }
+
+        // Measured at rest, the same tilt is corrected.
+        let mut filter = filter(UnitQuaternion::identity(), false);
+        let orientation = run(&mut filter, 2.0, FlightState::Landed, |time| {
+            (sample(time, tilted, Vector3::zeros()), None)
+        });
+        assert!(orientation.angle_to(&UnitQuaternion::identity()) > 0.1);
+    }
+}
@@ -0,0 +1,255 @@
+//! Configuration that persists across power cycles: the IMU calibration and the parameters.
+//!
+//! The configuration is kept as a single record at the start of a [`Storage`], such as a sector of
+//! flash or an EEPROM. The record starts with a magic number and a version, and ends with a CRC,
+//! so that erased, foreign or corrupted storage is never loaded as a configuration.
+
+use core::fmt;
+
+use flick_bridge::frame::crc16;
+use serde::{Deserialize, Serialize};
+
+use crate::device::calibration::ImuCalibration;
+use crate::params::Parameters;
+
+/// Magic number starting a configuration record.
+const MAGIC: [u8; 4] = *b"FLKC";
+/// Version of the configuration layout, bumped whenever [`Config`] changes.
+const VERSION: u8 = 2;
+/// Magic number, version and payload length.
+const HEADER_LEN: usize = 7;
+/// Largest payload of a configuration record.
+pub const MAX_PAYLOAD_LEN: usize = 256;
+
+/// Non-volatile memory holding the configuration.
+pub trait Storage {
+    type Error;
+
+    /// Read bytes from the given offset.
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Write bytes at the given offset, erasing whatever needs to be.
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Offset is out of a [`RamStorage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds;
+
+/// Storage in memory, which doesn't persist. Useful on the host, and on boards without storage.
+#[derive(Debug, Clone)]
+pub struct RamStorage<const N: usize>(pub [u8; N]);
+
+impl<const N: usize> RamStorage<N> {
+    /// Instantiate a new, erased [`RamStorage`].
+    pub fn new() -> Self {
+        Self([0xff; N])
+    }
+}
+
+impl<const N: usize> Default for RamStorage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Storage for RamStorage<N> {
+    type Error = OutOfBounds;
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let bytes = self.0.get(offset..offset + buf.len()).ok_or(OutOfBounds)?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Self::Error> {
+        let buf = self
+            .0
+            .get_mut(offset..offset + bytes.len())
+            .ok_or(OutOfBounds)?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Reason that the configuration could not be loaded or saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError<E> {
+    /// The storage failed.
+    Storage(E),
+    /// The storage holds no configuration, such as when it was never saved.
+    Missing,
+    /// The configuration was saved with another layout.
+    Version(u8),
+    /// The configuration doesn't match its CRC.
+    Corrupted,
+    /// The configuration couldn't be encoded or decoded, or holds parameters out of range.
+    Malformed,
+}
+
+impl<E: fmt::Debug> fmt::Display for ConfigError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Storage(err) => write!(f, "storage failed: {:?}", err)?,
+            ConfigError::Missing => write!(f, "no configuration saved")?,
+            ConfigError::Version(version) => {
+                write!(f, "configuration version {} unsupported", version)?
+            }
+            ConfigError::Corrupted => write!(f, "configuration corrupted")?,
+            ConfigError::Malformed => write!(f, "configuration malformed")?,
+        };
+
+        Ok(())
+    }
+}
+
+/// Persistent configuration.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub imu_calibration: ImuCalibration,
+    pub params: Parameters,
+}
+
+impl Config {
+    /// Load the configuration from the storage.
+    pub fn load<S: Storage>(storage: &mut S) -> Result<Self, ConfigError<S::Error>> {
+        let mut header = [0; HEADER_LEN];
+        storage.read(0, &mut header).map_err(ConfigError::Storage)?;
+        if header[..4] != MAGIC {
+            return Err(ConfigError::Missing);
+        }
+        if header[4] != VERSION {
+            return Err(ConfigError::Version(header[4]));
+        }
+        let len = u16::from_le_bytes([header[5], header[6]]) as usize;
+        if len > MAX_PAYLOAD_LEN {
+            return Err(ConfigError::Corrupted);
+        }
+
+        let mut buf = [0; MAX_PAYLOAD_LEN + 2];
+        let record = &mut buf[..len + 2];
+        storage
+            .read(HEADER_LEN, record)
+            .map_err(ConfigError::Storage)?;
+        let (payload, crc) = record.split_at(len);
+        if crc16(payload).to_le_bytes() != crc {
+            return Err(ConfigError::Corrupted);
+        }
+
+        let config: Self = postcard::from_bytes(payload).map_err(|_| ConfigError::Malformed)?;
+        if !config.params.valid() {
+            return Err(ConfigError::Malformed);
+        }
+
+        Ok(config)
+    }
+
+    /// Load the configuration from the storage, or the default one if it can't be.
+    pub fn load_or_default<S: Storage>(storage: &mut S) -> Self
+    where
+        S::Error: fmt::Debug,
+    {
+        Self::load(storage).unwrap_or_else(|err| {
+            log::warn!("using the default configuration: {}", err);
+            Self::default()
+        })
+    }
+
+    /// Save the configuration to the storage.
+    pub fn save<S: Storage>(&self, storage: &mut S) -> Result<(), ConfigError<S::Error>> {
+        let mut buf = [0; HEADER_LEN + MAX_PAYLOAD_LEN + 2];
+        let len = postcard::to_slice(self, &mut buf[HEADER_LEN..HEADER_LEN + MAX_PAYLOAD_LEN])
+            .map_err(|_| ConfigError::Malformed)?
+            .len();
+        let crc = crc16(&buf[HEADER_LEN..HEADER_LEN + len]);
+
+        buf[..4].copy_from_slice(&MAGIC);
+        buf[4] = VERSION;
+        buf[5..HEADER_LEN].copy_from_slice(&(len as u16).to_le_bytes());
+        buf[HEADER_LEN + len..HEADER_LEN + len + 2].copy_from_slice(&crc.to_le_bytes());
+
+        storage
+            .write(0, &buf[..HEADER_LEN + len + 2])
+            .map_err(ConfigError::Storage)?;
+        log::info!("configuration saved");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flick_bridge::param::Param;
+
+    use super::*;
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.imu_calibration.gyro_bias = [0.01, -0.02, 0.03];
+        config.imu_calibration.accel_scale = [1.01, 0.99, 1.0];
+        config.imu_calibration.set_mounting_euler(0.0, 0.0, 1.0);
+        config.params.set(Param::MainAltitude, 300.0).unwrap();
+        config
+    }
+
+    #[test]
+    fn loads_what_was_saved() {
+        let mut storage = RamStorage::<512>::new();
+        assert_eq!(Config::load(&mut storage), Err(ConfigError::Missing));
+        assert_eq!(Config::load_or_default(&mut storage), Config::default());
+
+        config().save(&mut storage).unwrap();
+        assert_eq!(Config::load(&mut storage), Ok(config()));
+    }
+
+    #[test]
+    fn rejects_corrupted_records() {
+        let mut storage = RamStorage::<512>::new();
+        config().save(&mut storage).unwrap();
+        storage.0[HEADER_LEN + 3] ^= 0x10;
+        assert_eq!(Config::load(&mut storage), Err(ConfigError::Corrupted));
+        assert_eq!(Config::load_or_default(&mut storage), Config::default());
+
+        // A length past the largest payload.
+        config().save(&mut storage).unwrap();
+        storage.0[5..HEADER_LEN].copy_from_slice(&(MAX_PAYLOAD_LEN as u16 + 1).to_le_bytes());
+        assert_eq!(Config::load(&mut storage), Err(ConfigError::Corrupted));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut storage = RamStorage::<512>::new();
+        config().save(&mut storage).unwrap();
+        storage.0[4] = VERSION - 1;
+        assert_eq!(
+            Config::load(&mut storage),
+            Err(ConfigError::Version(VERSION - 1))
+        );
+    }
+
+    #[test]
+    fn rejects_parameters_out_of_range() {
+        let mut storage = RamStorage::<512>::new();
+        let mut config = config();
+        config.params = Parameters::default();
+        // Serialized as is, since only setting a parameter checks its range.
+        let id = Param::MainAltitude.id() as usize;
+        let mut bytes = [0; MAX_PAYLOAD_LEN];
+        let mut values = postcard::to_slice(&config.params, &mut bytes)
+            .unwrap()
+            .to_vec();
+        values[id * 4..id * 4 + 4].copy_from_slice(&f32::NAN.to_le_bytes());
+        config.params = postcard::from_bytes(&values).unwrap();
+
+        config.save(&mut storage).unwrap();
+        assert_eq!(Config::load(&mut storage), Err(ConfigError::Malformed));
+    }
+
+    #[test]
+    fn fails_when_the_storage_is_too_small() {
+        let mut storage = RamStorage::<16>::new();
+        assert_eq!(
+            config().save(&mut storage),
+            Err(ConfigError::Storage(OutOfBounds))
+        );
+    }
+}
@@ -105,7 +105,7 @@ where
         // Calculate final gain matrix.
         self.k =
             Some(r.clone().try_inverse().expect("Couldn't compute inverse") * b.transpose() * hk);
-        return Ok(self.k.clone().unwrap());
+        Ok(self.k.clone().unwrap())
     }
 
     /// Returns the optimal feedback control based on the desired and current state vectors.
@@ -132,11 +132,11 @@ where
         if y_error.abs() < T::from_f64(1e-2).unwrap() {
             self.integral_error = T::zero();
         } else {
-            self.integral_error += y_error * self.ki.clone();
+            self.integral_error += y_error * self.ki;
         }
 
         let mut controls = &self.k.clone().unwrap() * error;
-        controls[0] -= self.integral_error.clone();
+        controls[0] -= self.integral_error;
 
         Ok(controls)
     }
@@ -0,0 +1,154 @@
+//! Apogee detection.
+//!
+//! Apogee is the zero crossing of the vertical velocity estimated by a [`VerticalKalman`] filter.
+//! The crossing must hold for a while before being confirmed, and is only looked for once enough
+//! time has passed since the launch recorded by the [`StateMachine`]. Around and above the speed
+//! of sound, the pressure around the vehicle is disturbed, so the detector locks the barometer out
+//! (see [`ApogeeDetector::lock_out`]) and the estimate relies on the accelerometer.
+//!
+//! [`StateMachine`]: crate::state::StateMachine
+
+use crate::kalman::VerticalKalman;
+use crate::params::Parameters;
+use flick_bridge::param::Param;
+
+/// Parameters of the [`ApogeeDetector`].
+#[derive(Debug, Clone, Copy)]
+pub struct ApogeeConfig {
+    /// Time after launch before apogee is looked for, in microseconds. Protects against pressure
+    /// spikes around mach.
+    pub min_time: u64,
+    /// Speed above which the barometer is locked out, in meters per second.
+    pub lockout_speed: f32,
+    /// Time that the barometer stays locked out after slowing down, in microseconds, so that
+    /// shocks have time to settle.
+    pub lockout_hold: u64,
+    /// Time that the velocity must stay at or below zero for apogee to be confirmed, in
+    /// microseconds.
+    pub confirm_time: u64,
+    /// Upward velocity that cancels a crossing that wasn't confirmed yet, in meters per second.
+    pub hysteresis: f32,
+}
+
+impl Default for ApogeeConfig {
+    fn default() -> Self {
+        Self {
+            min_time: 3_000_000,
+            // Mach 0.7 at sea level.
+            lockout_speed: 240.0,
+            lockout_hold: 1_000_000,
+            confirm_time: 200_000,
+            hysteresis: 2.0,
+        }
+    }
+}
+
+impl From<&Parameters> for ApogeeConfig {
+    fn from(params: &Parameters) -> Self {
+        Self {
+            min_time: params.micros(Param::ApogeeMinTime),
+            lockout_speed: params.get(Param::ApogeeLockoutSpeed),
+            confirm_time: params.micros(Param::ApogeeConfirmTime),
+            ..Default::default()
+        }
+    }
+}
+
+/// Detected apogee.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Apogee {
+    /// Time the velocity crossed zero, in microseconds.
+    pub time: u64,
+    /// Highest estimated altitude, in meters.
+    pub altitude: f32,
+}
+
+/// Detects apogee from the estimated altitude and vertical velocity.
+#[derive(Debug, Clone)]
+pub struct ApogeeDetector {
+    config: ApogeeConfig,
+    /// Time the barometer was last locked out at.
+    locked_out_at: Option<u64>,
+    /// Time and altitude of the crossing being confirmed.
+    crossing: Option<Apogee>,
+    /// Highest estimated altitude.
+    max_altitude: f32,
+    /// Whether apogee was already detected.
+    detected: bool,
+}
+
+impl ApogeeDetector {
+    /// Instantiate a new [`ApogeeDetector`].
+    pub fn new(config: ApogeeConfig) -> Self {
+        Self {
+            config,
+            locked_out_at: None,
+            crossing: None,
+            max_altitude: 0.0,
+            detected: false,
+        }
+    }
+
+    /// Whether the barometer is currently locked out.
+    pub fn locked_out(&self) -> bool {
+        self.locked_out_at.is_some()
+    }
+
+    /// Update the lockout of the barometer at the given time, from the estimated vertical
+    /// velocity. Returns whether the barometer is locked out, in which case its altitude must not
+    /// be fed to the estimate.
+    pub fn lock_out(&mut self, time: u64, velocity: f32) -> bool {
+        if velocity.abs() > self.config.lockout_speed {
+            if self.locked_out_at.is_none() {
+                log::info!("barometer locked out at {} m/s", velocity);
+            }
+            self.locked_out_at = Some(time);
+        } else if self
+            .locked_out_at
+            .is_some_and(|at| time.saturating_sub(at) >= self.config.lockout_hold)
+        {
+            log::info!("barometer lockout lifted");
+            self.locked_out_at = None;
+        }
+
+        self.locked_out()
+    }
+
+    /// Update the detector with the estimate at the given time, and the time of the launch once
+    /// launched. Returns the apogee once, when it is confirmed.
+    pub fn update(
+        &mut self,
+        time: u64,
+        estimate: &VerticalKalman<f32>,
+        launched_at: Option<u64>,
+    ) -> Option<Apogee> {
+        self.max_altitude = self.max_altitude.max(estimate.altitude());
+
+        let launched_at = launched_at?;
+        if self.detected || time.saturating_sub(launched_at) < self.config.min_time {
+            return None;
+        }
+
+        let velocity = estimate.velocity();
+        match self.crossing {
+            None if velocity <= 0.0 && self.locked_out_at.is_none() => {
+                self.crossing = Some(Apogee {
+                    time,
+                    altitude: self.max_altitude,
+                });
+            }
+            Some(_) if velocity > self.config.hysteresis => self.crossing = None,
+            Some(crossing) if time.saturating_sub(crossing.time) >= self.config.confirm_time => {
+                self.detected = true;
+                log::info!("apogee at {} m", crossing.altitude);
+                return Some(Apogee {
+                    altitude: self.max_altitude,
+                    ..crossing
+                });
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
@@ -0,0 +1,125 @@
+//! Motor burnout detection.
+//!
+//! Once the motor burns out, drag and gravity take over and the vehicle decelerates. Burnout is
+//! detected once the acceleration stays below a threshold for a while, so that thrust
+//! oscillations don't count.
+
+use crate::params::Parameters;
+use flick_bridge::param::Param;
+
+use super::{Sample, Sustained};
+
+/// Parameters of the [`BurnoutDetector`].
+#[derive(Debug, Clone, Copy)]
+pub struct BurnoutConfig {
+    /// Acceleration below which the motor may have burnt out, in meters per second squared.
+    pub acceleration: f32,
+    /// Time that the acceleration must stay below the threshold, in microseconds.
+    pub debounce: u64,
+}
+
+impl From<&Parameters> for BurnoutConfig {
+    fn from(params: &Parameters) -> Self {
+        Self {
+            acceleration: params.get(Param::BurnoutAcceleration),
+            debounce: params.micros(Param::BurnoutDebounce),
+        }
+    }
+}
+
+/// Detects motor burnout from the accelerometer. Only meant to be fed samples taken after
+/// launch.
+#[derive(Debug, Clone)]
+pub struct BurnoutDetector {
+    config: BurnoutConfig,
+    below: Sustained,
+    detected: bool,
+}
+
+impl BurnoutDetector {
+    /// Instantiate a new [`BurnoutDetector`].
+    pub fn new(config: BurnoutConfig) -> Self {
+        Self {
+            config,
+            below: Sustained::new(),
+            detected: false,
+        }
+    }
+
+    /// Update the detector with a sample. Returns the time of the burnout once, when it is
+    /// confirmed.
+    pub fn update(&mut self, sample: &Sample) -> Option<u64> {
+        if self.detected {
+            return None;
+        }
+
+        let below = sample.acceleration < self.config.acceleration;
+        let burnt_out_at = self
+            .below
+            .update(sample.time, below, self.config.debounce)?;
+        self.detected = true;
+        Some(burnt_out_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Time between two samples, in microseconds.
+    const PERIOD: u64 = 10_000;
+
+    fn detector() -> BurnoutDetector {
+        BurnoutDetector::new(BurnoutConfig::from(&Parameters::default()))
+    }
+
+    /// Feed a sample every [`PERIOD`] until `end`, with the acceleration at that time. Returns
+    /// what was detected, with the time of the sample it was detected on.
+    fn feed(
+        detector: &mut BurnoutDetector,
+        end: u64,
+        acceleration: impl Fn(u64) -> f32,
+    ) -> Vec<(u64, u64)> {
+        (0..end / PERIOD)
+            .filter_map(|i| {
+                let sample = Sample {
+                    time: i * PERIOD,
+                    altitude: 0.0,
+                    acceleration: acceleration(i * PERIOD),
+                };
+                detector.update(&sample).map(|time| (time, sample.time))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_deceleration_once_debounced() {
+        let mut detector = detector();
+        let detected = feed(&mut detector, 5_000_000, |time| match time {
+            0..=2_499_999 => 50.0,
+            _ => -12.0,
+        });
+        assert_eq!(detected, [(2_500_000, 2_600_000)]);
+    }
+
+    #[test]
+    fn ignores_thrust_oscillations() {
+        let mut detector = detector();
+        // Chuffing motor, dipping below zero for 50 ms at a time.
+        let detected = feed(&mut detector, 2_000_000, |time| match time % 200_000 {
+            0..=49_999 => -5.0,
+            _ => 30.0,
+        });
+        assert!(detected.is_empty());
+    }
+
+    #[test]
+    fn starts_the_debounce_over_when_interrupted() {
+        let mut detector = detector();
+        let detected = feed(&mut detector, 3_000_000, |time| match time {
+            1_000_000..=1_049_999 | 1_060_000.. => -10.0,
+            _ => 20.0,
+        });
+        assert_eq!(detected, [(1_060_000, 1_160_000)]);
+    }
+}
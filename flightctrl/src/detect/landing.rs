@@ -0,0 +1,212 @@
+//! Landing detection.
+//!
+//! Once on the ground, the altitude stops changing and the accelerometer only sees noise.
+//! Landing is detected once both stay stable for a while: the altitude within a window, and the
+//! variance of the acceleration below a threshold. Any sample breaking either starts the wait
+//! over.
+
+use crate::params::Parameters;
+use flick_bridge::param::Param;
+
+use super::Sample;
+
+/// Parameters of the [`LandingDetector`].
+#[derive(Debug, Clone, Copy)]
+pub struct LandingConfig {
+    /// Largest change of altitude while landed, in meters.
+    pub altitude_window: f32,
+    /// Largest variance of the acceleration while landed, in squared meters per second squared.
+    pub variance: f32,
+    /// Time that the altitude and acceleration must stay stable, in microseconds.
+    pub time: u64,
+}
+
+impl From<&Parameters> for LandingConfig {
+    fn from(params: &Parameters) -> Self {
+        Self {
+            altitude_window: params.get(Param::LandingAltitudeWindow),
+            variance: params.get(Param::LandingVariance),
+            time: params.micros(Param::LandingTime),
+        }
+    }
+}
+
+/// Statistics of the samples since the vehicle looked stable.
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    start: u64,
+    min_altitude: f32,
+    max_altitude: f32,
+    count: u32,
+    /// Running mean and sum of squared deviations of the acceleration (Welford's algorithm).
+    mean: f32,
+    deviations: f32,
+}
+
+impl Window {
+    fn new(sample: &Sample) -> Self {
+        Self {
+            start: sample.time,
+            min_altitude: sample.altitude,
+            max_altitude: sample.altitude,
+            count: 1,
+            mean: sample.acceleration,
+            deviations: 0.0,
+        }
+    }
+
+    fn push(&mut self, sample: &Sample) {
+        self.min_altitude = self.min_altitude.min(sample.altitude);
+        self.max_altitude = self.max_altitude.max(sample.altitude);
+        self.count += 1;
+        let delta = sample.acceleration - self.mean;
+        self.mean += delta / self.count as f32;
+        self.deviations += delta * (sample.acceleration - self.mean);
+    }
+
+    fn variance(&self) -> f32 {
+        self.deviations / self.count as f32
+    }
+}
+
+/// Detects landing from the barometer and accelerometer. Only meant to be fed samples taken
+/// after apogee.
+#[derive(Debug, Clone)]
+pub struct LandingDetector {
+    config: LandingConfig,
+    window: Option<Window>,
+    detected: bool,
+}
+
+impl LandingDetector {
+    /// Instantiate a new [`LandingDetector`].
+    pub fn new(config: LandingConfig) -> Self {
+        Self {
+            config,
+            window: None,
+            detected: false,
+        }
+    }
+
+    /// Update the detector with a sample. Returns the time that the vehicle became stable at
+    /// once, when landing is confirmed.
+    pub fn update(&mut self, sample: &Sample) -> Option<u64> {
+        if self.detected {
+            return None;
+        }
+
+        let window = match &mut self.window {
+            Some(window) => {
+                window.push(sample);
+                window
+            }
+            None => self.window.insert(Window::new(sample)),
+        };
+        if window.max_altitude - window.min_altitude > self.config.altitude_window
+            || window.variance() > self.config.variance
+        {
+            *window = Window::new(sample);
+            return None;
+        }
+
+        if sample.time.saturating_sub(window.start) < self.config.time {
+            return None;
+        }
+        self.detected = true;
+        Some(window.start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Time between two samples, in microseconds.
+    const PERIOD: u64 = 10_000;
+
+    fn detector() -> LandingDetector {
+        LandingDetector::new(LandingConfig::from(&Parameters::default()))
+    }
+
+    /// Feed a sample every [`PERIOD`] until `end`, with the altitude and acceleration at that
+    /// time. Returns what was detected, with the time of the sample it was detected on.
+    fn feed(
+        detector: &mut LandingDetector,
+        end: u64,
+        sample: impl Fn(u64) -> (f32, f32),
+    ) -> Vec<(u64, u64)> {
+        (0..end / PERIOD)
+            .filter_map(|i| {
+                let (altitude, acceleration) = sample(i * PERIOD);
+                let sample = Sample {
+                    time: i * PERIOD,
+                    altitude,
+                    acceleration,
+                };
+                detector.update(&sample).map(|time| (time, sample.time))
+            })
+            .collect()
+    }
+
+    /// Descent at 6 m/s from 60 m, landing at 10 s, then at rest with the given acceleration
+    /// noise.
+    fn descent(time: u64, noise: f32) -> (f32, f32) {
+        let t = time as f32 / 1e6;
+        let sign = if (time / PERIOD).is_multiple_of(2) {
+            1.0
+        } else {
+            -1.0
+        };
+        match t {
+            t if t < 10.0 => (60.0 - 6.0 * t, sign * 1.5),
+            _ => (0.0, sign * noise),
+        }
+    }
+
+    #[test]
+    fn detects_a_stable_vehicle_after_the_landing_time() {
+        let mut detector = detector();
+        let detected = feed(&mut detector, 30_000_000, |time| descent(time, 0.3));
+        // The default window lets the last 2 m of the descent in, and landing takes 5 s.
+        let (landed_at, detected_at) = detected[0];
+        assert_eq!(detected.len(), 1);
+        assert!(
+            (9_670_000..=10_000_000).contains(&landed_at),
+            "{}",
+            landed_at
+        );
+        assert_eq!(detected_at, landed_at + 5_000_000);
+    }
+
+    #[test]
+    fn waits_while_the_acceleration_varies() {
+        // A variance of 1, above the default 0.5, such as swinging under the parachute in a tree.
+        let detected = feed(&mut detector(), 30_000_000, |time| descent(time, 1.0));
+        assert!(detected.is_empty());
+
+        // Noise just below the threshold is fine.
+        let detected = feed(&mut detector(), 30_000_000, |time| descent(time, 0.7));
+        assert_eq!(detected.len(), 1);
+    }
+
+    #[test]
+    fn waits_while_the_altitude_drifts() {
+        let mut detector = detector();
+        // Drifting down at 0.5 m/s leaves the 2 m window after 4 s, before the 5 s are over.
+        let detected = feed(&mut detector, 60_000_000, |time| {
+            (100.0 - 0.5 * time as f32 / 1e6, 0.0)
+        });
+        assert!(detected.is_empty());
+    }
+
+    #[test]
+    fn starts_over_after_a_disturbance() {
+        let mut detector = detector();
+        let detected = feed(&mut detector, 30_000_000, |time| match time {
+            // Knocked over 3 s after landing.
+            13_000_000 => (0.0, 20.0),
+            time => descent(time, 0.3),
+        });
+        assert_eq!(detected, [(13_010_000, 18_010_000)]);
+    }
+}
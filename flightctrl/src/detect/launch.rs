@@ -0,0 +1,136 @@
+//! Launch detection.
+//!
+//! Launch is detected once the acceleration stays above a threshold for a while, so that bumps
+//! on the pad don't count.
+
+use crate::params::Parameters;
+use flick_bridge::param::Param;
+
+use super::{Sample, Sustained};
+
+/// Parameters of the [`LaunchDetector`].
+#[derive(Debug, Clone, Copy)]
+pub struct LaunchConfig {
+    /// Acceleration above which the vehicle may have launched, in meters per second squared.
+    pub acceleration: f32,
+    /// Time that the acceleration must stay above the threshold, in microseconds.
+    pub debounce: u64,
+}
+
+impl From<&Parameters> for LaunchConfig {
+    fn from(params: &Parameters) -> Self {
+        Self {
+            acceleration: params.get(Param::LaunchAcceleration),
+            debounce: params.micros(Param::LaunchDebounce),
+        }
+    }
+}
+
+/// Detects launch from the accelerometer.
+#[derive(Debug, Clone)]
+pub struct LaunchDetector {
+    config: LaunchConfig,
+    above: Sustained,
+    detected: bool,
+}
+
+impl LaunchDetector {
+    /// Instantiate a new [`LaunchDetector`].
+    pub fn new(config: LaunchConfig) -> Self {
+        Self {
+            config,
+            above: Sustained::new(),
+            detected: false,
+        }
+    }
+
+    /// Update the detector with a sample. Returns the time of the launch once, when it is
+    /// confirmed.
+    pub fn update(&mut self, sample: &Sample) -> Option<u64> {
+        if self.detected {
+            return None;
+        }
+
+        let above = sample.acceleration > self.config.acceleration;
+        let launched_at = self
+            .above
+            .update(sample.time, above, self.config.debounce)?;
+        self.detected = true;
+        Some(launched_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oorandom::Rand32;
+
+    use super::*;
+
+    /// Time between two samples, in microseconds.
+    const PERIOD: u64 = 10_000;
+
+    fn detector() -> LaunchDetector {
+        LaunchDetector::new(LaunchConfig::from(&Parameters::default()))
+    }
+
+    /// Feed a sample every [`PERIOD`] until `end`, with the acceleration at that time. Returns
+    /// what was detected, with the time of the sample it was detected on.
+    fn feed(
+        detector: &mut LaunchDetector,
+        end: u64,
+        acceleration: impl Fn(u64) -> f32,
+    ) -> Vec<(u64, u64)> {
+        (0..end / PERIOD)
+            .filter_map(|i| {
+                let sample = Sample {
+                    time: i * PERIOD,
+                    altitude: 0.0,
+                    acceleration: acceleration(i * PERIOD),
+                };
+                detector.update(&sample).map(|time| (time, sample.time))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_sustained_acceleration_once_debounced() {
+        let mut detector = detector();
+        let detected = feed(&mut detector, 3_000_000, |time| match time {
+            0..=999_999 => 0.0,
+            _ => 40.0,
+        });
+        // Reported once the default 0.1 s debounce is over, with the time it started.
+        assert_eq!(detected, [(1_000_000, 1_100_000)]);
+    }
+
+    #[test]
+    fn ignores_bumps_shorter_than_the_debounce() {
+        let mut detector = detector();
+        // 50 ms kicks every half second, such as handling on the pad.
+        let detected = feed(&mut detector, 10_000_000, |time| match time % 500_000 {
+            0..=49_999 => 60.0,
+            _ => 0.0,
+        });
+        assert!(detected.is_empty());
+    }
+
+    #[test]
+    fn rejects_noise_below_the_threshold() {
+        let mut detector = detector();
+        let rng = core::cell::RefCell::new(Rand32::new(1));
+        let detected = feed(&mut detector, 10_000_000, |_| {
+            (rng.borrow_mut().rand_float() - 0.5) * 36.0
+        });
+        assert!(detected.is_empty());
+    }
+
+    #[test]
+    fn starts_the_debounce_over_when_interrupted() {
+        let mut detector = detector();
+        let detected = feed(&mut detector, 2_000_000, |time| match time {
+            1_000_000..=1_079_999 | 1_090_000.. => 40.0,
+            _ => 0.0,
+        });
+        assert_eq!(detected, [(1_090_000, 1_190_000)]);
+    }
+}
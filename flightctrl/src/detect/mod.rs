@@ -0,0 +1,346 @@
+//! Detection of flight events from the sensors.
+//!
+//! Detectors are fed [`Sample`]s and have no hardware dependencies, so they can be run on the host
+//! against simulated or recorded flights. [`Detectors`] feeds every detector from the sensor
+//! pipeline (see [`crate::pipeline`]), only reporting the events that can follow the current state
+//! of the flight. It also estimates the altitude and vertical velocity with a [`VerticalKalman`]
+//! filter, which apogee and landing are detected from, and which the recovery deploys on.
+
+use flick_bridge::event::Detection;
+use flick_bridge::state::FlightState;
+
+use crate::kalman::{KalmanConfig, VerticalKalman};
+use crate::params::Parameters;
+use crate::state::StateMachine;
+use apogee::{ApogeeConfig, ApogeeDetector};
+use burnout::{BurnoutConfig, BurnoutDetector};
+use landing::{LandingConfig, LandingDetector};
+use launch::{LaunchConfig, LaunchDetector};
+
+pub mod apogee;
+pub mod burnout;
+pub mod landing;
+pub mod launch;
+
+/// Sensor readings used by the detectors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// Time of the readings, in microseconds.
+    pub time: u64,
+    /// Altitude above the pad from the barometer, in meters.
+    pub altitude: f32,
+    /// Vertical acceleration from the accelerometer, without gravity, in meters per second
+    /// squared. Positive upwards.
+    pub acceleration: f32,
+}
+
+/// Tracks how long a condition has held.
+#[derive(Debug, Clone, Copy)]
+struct Sustained {
+    /// Time the condition started holding at.
+    since: Option<u64>,
+}
+
+impl Sustained {
+    fn new() -> Self {
+        Self { since: None }
+    }
+
+    /// Update with whether the condition holds at the given time. Returns the time it started
+    /// holding at, once it held for at least `duration`.
+    fn update(&mut self, time: u64, holds: bool, duration: u64) -> Option<u64> {
+        if !holds {
+            self.since = None;
+            return None;
+        }
+
+        let since = *self.since.get_or_insert(time);
+        (time.saturating_sub(since) >= duration).then_some(since)
+    }
+}
+
+/// Events detected on a sample, with the time each happened at.
+pub type Detected = heapless::Vec<(Detection, u64), 2>;
+
+/// Every detector, fed from the sensor pipeline.
+#[derive(Debug, Clone)]
+pub struct Detectors {
+    estimate: VerticalKalman<f32>,
+    launch: LaunchDetector,
+    burnout: BurnoutDetector,
+    apogee: ApogeeDetector,
+    landing: LandingDetector,
+}
+
+impl Detectors {
+    /// Instantiate new [`Detectors`], configured from the parameters. Parameters changed
+    /// afterwards only apply to detectors created again.
+    pub fn new(params: &Parameters) -> Self {
+        Self {
+            estimate: VerticalKalman::new(KalmanConfig::default()),
+            launch: LaunchDetector::new(LaunchConfig::from(params)),
+            burnout: BurnoutDetector::new(BurnoutConfig::from(params)),
+            apogee: ApogeeDetector::new(ApogeeConfig::from(params)),
+            landing: LandingDetector::new(LandingConfig::from(params)),
+        }
+    }
+
+    /// Estimate of the altitude above the pad and of the vertical velocity.
+    pub fn estimate(&self) -> &VerticalKalman<f32> {
+        &self.estimate
+    }
+
+    /// Apogee detector, which also locks the barometer out of the estimate.
+    pub fn apogee(&self) -> &ApogeeDetector {
+        &self.apogee
+    }
+
+    /// Update the detectors with a sample. Returns the events detected among those that can
+    /// follow the state of the flight, in the order they happened, with the time each happened
+    /// at, which is the time to trigger the state machine with.
+    pub fn update(&mut self, sample: &Sample, flight: &StateMachine) -> Detected {
+        // The altitude and velocity are estimated in every state.
+        self.estimate
+            .update_acceleration(sample.time, sample.acceleration);
+        if !self.apogee.lock_out(sample.time, self.estimate.velocity()) {
+            self.estimate.update_altitude(sample.time, sample.altitude);
+        }
+        let apogee = self
+            .apogee
+            .update(sample.time, &self.estimate, flight.launched_at())
+            .map(|apogee| (Detection::Apogee, apogee.time));
+        let mut detected = Detected::new();
+        let mut push = |detection: Option<(Detection, u64)>| {
+            if let Some(detection) = detection {
+                // Never full, since at most two events are detected on a sample.
+                detected.push(detection).ok();
+            }
+        };
+        match flight.state() {
+            FlightState::Armed => {
+                push(
+                    self.launch
+                        .update(sample)
+                        .map(|time| (Detection::Launch, time)),
+                );
+            }
+            // A missed burnout must not prevent apogee from being detected, and apogee is only
+            // reported once, so both are reported when confirmed on the same sample.
+            FlightState::Boost => {
+                push(
+                    self.burnout
+                        .update(sample)
+                        .map(|time| (Detection::Burnout, time)),
+                );
+                push(apogee);
+            }
+            FlightState::Coast => push(apogee),
+            FlightState::Apogee | FlightState::DrogueDescent | FlightState::MainDescent => {
+                // The estimated altitude is much less noisy than the barometer.
+                let sample = Sample {
+                    altitude: self.estimate.altitude(),
+                    ..*sample
+                };
+                push(
+                    self.landing
+                        .update(&sample)
+                        .map(|time| (Detection::Landing, time)),
+                );
+            }
+            _ => {}
+        }
+
+        detected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flick_bridge::param::Param;
+
+    use super::*;
+    use crate::state::Trigger;
+
+    /// Standard gravity, in meters per second squared.
+    const GRAVITY: f32 = 9.80665;
+    /// Time between two samples, in microseconds.
+    const PERIOD: u64 = 10_000;
+    /// Descent rate under the parachute of [`profile`], in meters per second.
+    const DESCENT_RATE: f32 = 10.0;
+
+    /// Samples of a flight without drag: 1 s on the pad, then a motor accelerating at `thrust`
+    /// for `burn_time` seconds, then a ballistic coast to apogee. A parachute opens 1 s after
+    /// apogee, and the vehicle descends at [`DESCENT_RATE`] until it lands, after which it stays on
+    /// the ground for 10 s. Returns the samples and the time of the landing.
+    fn profile(thrust: f32, burn_time: f32) -> (Vec<Sample>, u64) {
+        let (mut altitude, mut velocity) = (0.0f32, 0.0f32);
+        let mut apogee = None;
+        let mut landed = None;
+        let dt = PERIOD as f32 / 1e6;
+        let mut samples = Vec::new();
+        for i in 0.. {
+            let time = i * PERIOD;
+            let t = time as f32 / 1e6;
+            if velocity < 0.0 {
+                apogee.get_or_insert(t);
+            }
+            let acceleration = match t {
+                _ if landed.is_some() => 0.0,
+                t if t < 1.0 => 0.0,
+                t if t < 1.0 + burn_time => thrust,
+                t if apogee.is_none_or(|apogee| t < apogee + 1.0) => -GRAVITY,
+                // The parachute opens at once.
+                _ => {
+                    velocity = -DESCENT_RATE;
+                    0.0
+                }
+            };
+            altitude += velocity * dt + 0.5 * acceleration * dt * dt;
+            velocity += acceleration * dt;
+            if apogee.is_some() && altitude <= 0.0 && landed.is_none() {
+                (altitude, velocity) = (0.0, 0.0);
+                landed = Some(time);
+            }
+            samples.push(Sample {
+                time,
+                altitude,
+                acceleration,
+            });
+            if landed.is_some_and(|landed| time >= landed + 10_000_000) {
+                break;
+            }
+        }
+
+        (samples, landed.unwrap())
+    }
+
+    /// Samples of the trace in `testdata/flight.csv`, recorded from a simulated flight with drag,
+    /// sensor noise, a bump on the pad, thrust oscillations, and the pressure spike of the drogue
+    /// ejection charge. The motor ignites at 5 s and burns out at 7.5 s, apogee is at 15.8 s, and
+    /// the vehicle lands at 61.62 s.
+    fn trace() -> Vec<Sample> {
+        include_str!("../../testdata/flight.csv")
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let mut fields = line.split(',');
+                let mut field = || fields.next().unwrap();
+                Sample {
+                    time: field().parse().unwrap(),
+                    altitude: field().parse().unwrap(),
+                    acceleration: field().parse().unwrap(),
+                }
+            })
+            .collect()
+    }
+
+    /// Feed the samples to the detectors, moving an armed state machine with what they detect.
+    fn fly(params: &Parameters, samples: &[Sample]) -> (StateMachine, Vec<(Detection, u64)>) {
+        run(Detectors::new(params), samples)
+    }
+
+    fn run(mut detectors: Detectors, samples: &[Sample]) -> (StateMachine, Vec<(Detection, u64)>) {
+        let mut machine = StateMachine::new(Default::default(), 0);
+        machine.request(FlightState::Armed.id(), 0).unwrap();
+
+        let mut detected = Vec::new();
+        for sample in samples {
+            for (detection, time) in detectors.update(sample, &machine) {
+                machine.trigger(Trigger::Detected(detection), time);
+                detected.push((detection, time));
+            }
+        }
+        (machine, detected)
+    }
+
+    /// Asserts that the time is within the given range, in seconds.
+    fn assert_between(time: u64, min: f32, max: f32) {
+        let seconds = time as f32 / 1e6;
+        assert!((min..=max).contains(&seconds), "{} s", seconds);
+    }
+
+    #[test]
+    fn detects_every_event_of_a_simulated_flight() {
+        // Apogee 10 s after burnout, 13 s after launch.
+        let (samples, landed_at) = profile(50.0, 2.0);
+        let (machine, detected) = fly(&Parameters::new(), &samples);
+        let events: Vec<_> = detected.iter().map(|(detection, _)| *detection).collect();
+        assert_eq!(
+            events,
+            [
+                Detection::Launch,
+                Detection::Burnout,
+                Detection::Apogee,
+                Detection::Landing
+            ]
+        );
+        assert_eq!(machine.state(), FlightState::Landed);
+
+        assert_eq!(detected[0].1, 1_000_000);
+        assert_eq!(detected[1].1, 3_000_000);
+        assert_between(detected[2].1, 12.9, 13.6);
+        let landed_at = landed_at as f32 / 1e6;
+        assert_between(detected[3].1, landed_at, landed_at + 3.0);
+    }
+
+    #[test]
+    fn detects_every_event_of_a_recorded_flight() {
+        let (machine, detected) = fly(&Parameters::new(), &trace());
+        let events: Vec<_> = detected.iter().map(|(detection, _)| *detection).collect();
+        assert_eq!(
+            events,
+            [
+                Detection::Launch,
+                Detection::Burnout,
+                Detection::Apogee,
+                Detection::Landing
+            ]
+        );
+        assert_eq!(machine.state(), FlightState::Landed);
+
+        // The bump on the pad isn't a launch.
+        assert_between(detected[0].1, 5.0, 5.1);
+        assert_between(detected[1].1, 7.5, 7.6);
+        assert_between(detected[2].1, 15.3, 16.6);
+        assert_between(detected[3].1, 61.6, 66.0);
+    }
+
+    #[test]
+    fn reports_burnout_and_apogee_on_the_same_sample() {
+        let (samples, _) = profile(50.0, 2.0);
+        let params = Parameters::new();
+
+        // Sample that apogee is confirmed on, when burnout is never detected.
+        let mut detectors = Detectors::new(&params);
+        detectors.burnout = BurnoutDetector::new(BurnoutConfig {
+            acceleration: 0.0,
+            debounce: u64::MAX,
+        });
+        let (_, detected) = run(detectors, &samples);
+        assert_eq!(detected[1].0, Detection::Apogee);
+        let confirmed = detected[1].1 + ApogeeConfig::from(&params).confirm_time;
+
+        // Burnout is confirmed on the same sample.
+        let mut detectors = Detectors::new(&params);
+        detectors.burnout = BurnoutDetector::new(BurnoutConfig {
+            acceleration: 0.0,
+            debounce: confirmed - 3_000_000,
+        });
+        let (machine, detected) = run(detectors, &samples);
+        assert_eq!(detected[1], (Detection::Burnout, 3_000_000));
+        assert_eq!(detected[2].0, Detection::Apogee);
+        assert!(detected[2].1 < confirmed);
+        assert_eq!(machine.state(), FlightState::Landed);
+    }
+
+    #[test]
+    fn locks_apogee_out_after_the_launch_of_the_state_machine() {
+        let (samples, _) = profile(50.0, 2.0);
+        let mut params = Parameters::new();
+        params.set(Param::ApogeeMinTime, 20.0).unwrap();
+        let (machine, detected) = fly(&params, &samples);
+        let (detection, apogee) = detected[2];
+        assert_eq!(detection, Detection::Apogee);
+        assert!(apogee >= machine.launched_at().unwrap() + 20_000_000);
+    }
+}
@@ -0,0 +1,37 @@
+//! [`Barometer`] for the BMP280 (see the `bmp280-ehal` crate).
+//!
+//! The driver ignores bus errors, so readings are checked for plausibility instead.
+
+use bmp280_ehal::BMP280;
+use embedded_hal::blocking::i2c::WriteRead;
+
+use super::{Barometer, BarometerSample, Pressure, Temperature};
+
+/// Range of plausible pressures, in pascals: from the upper limit of the sensor, down to around
+/// 30 km of altitude.
+const PRESSURE_RANGE: core::ops::RangeInclusive<f64> = 1_000.0..=110_000.0;
+/// Range of plausible temperatures, in degrees Celsius, which is what the sensor is rated for.
+const TEMPERATURE_RANGE: core::ops::RangeInclusive<f64> = -40.0..=85.0;
+
+/// The BMP280 gave a reading out of range, usually because the bus failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImplausibleReading;
+
+impl<I: WriteRead> Barometer for BMP280<I> {
+    type Error = ImplausibleReading;
+
+    fn read(&mut self, now: u64) -> Result<BarometerSample, Self::Error> {
+        // The temperature must be read first, since it compensates the pressure.
+        let temperature = self.temp();
+        let pressure = self.pressure();
+        if !PRESSURE_RANGE.contains(&pressure) || !TEMPERATURE_RANGE.contains(&temperature) {
+            return Err(ImplausibleReading);
+        }
+
+        Ok(BarometerSample {
+            time: now,
+            pressure: Pressure(pressure as f32),
+            temperature: Temperature(temperature as f32),
+        })
+    }
+}
@@ -0,0 +1,597 @@
+//! Calibration of the IMU.
+//!
+//! Raw readings carry a bias on every gyroscope axis, and an offset and scale error on every
+//! accelerometer axis. The gyroscope bias is the mean reading at rest. The accelerometer is fitted
+//! from six orientations, with each axis pointing up and then down: gravity reads as `+g` and `-g`,
+//! which gives the offset and the scale of that axis. Once corrected, vectors are rotated from the
+//! frame of the sensor into the frame of the body, since boards are mounted in any orientation.
+//!
+//! [`Calibrated`] wraps an [`Imu`] so that every reader downstream only ever sees calibrated
+//! samples, and [`Calibrator`] calibrates it from the raw ones. The coefficients are kept in the
+//! [`Config`](crate::config::Config).
+
+use core::fmt;
+
+use nalgebra::{Quaternion, UnitQuaternion, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::{Acceleration, AngularRate, Imu, ImuSample, STANDARD_GRAVITY};
+
+/// Calibration coefficients of an IMU.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ImuCalibration {
+    /// Gyroscope bias, in radians per second, subtracted from the readings.
+    pub gyro_bias: [f32; 3],
+    /// Accelerometer offset, in meters per second squared, subtracted from the readings.
+    pub accel_offset: [f32; 3],
+    /// Accelerometer scale, multiplying the readings once the offset is removed.
+    pub accel_scale: [f32; 3],
+    /// Orientation of the sensor in the body, as a `[w, i, j, k]` quaternion: it rotates vectors
+    /// from the sensor frame into the body frame.
+    pub mounting: [f32; 4],
+}
+
+impl Default for ImuCalibration {
+    fn default() -> Self {
+        Self {
+            gyro_bias: [0.0; 3],
+            accel_offset: [0.0; 3],
+            accel_scale: [1.0; 3],
+            mounting: [1.0, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+impl ImuCalibration {
+    /// Orientation of the sensor in the body.
+    pub fn mounting(&self) -> UnitQuaternion<f32> {
+        let [w, i, j, k] = self.mounting;
+        UnitQuaternion::from_quaternion(Quaternion::new(w, i, j, k))
+    }
+
+    /// Set the orientation of the sensor in the body.
+    pub fn set_mounting(&mut self, mounting: UnitQuaternion<f32>) {
+        let q = mounting.quaternion();
+        self.mounting = [q.w, q.i, q.j, q.k];
+    }
+
+    /// Set the orientation of the sensor in the body from roll, pitch and yaw, in radians.
+    pub fn set_mounting_euler(&mut self, roll: f32, pitch: f32, yaw: f32) {
+        self.set_mounting(UnitQuaternion::from_euler_angles(roll, pitch, yaw));
+    }
+
+    /// Calibrate a raw sample, giving it in the body frame.
+    pub fn apply(&self, sample: &ImuSample) -> ImuSample {
+        let mounting = self.mounting();
+        let acceleration = (sample.acceleration.0 - Vector3::from(self.accel_offset))
+            .component_mul(&Vector3::from(self.accel_scale));
+        let angular_rate = sample.angular_rate.0 - Vector3::from(self.gyro_bias);
+
+        ImuSample {
+            time: sample.time,
+            acceleration: Acceleration(mounting * acceleration),
+            angular_rate: AngularRate(mounting * angular_rate),
+        }
+    }
+}
+
+/// IMU whose samples are calibrated.
+#[derive(Debug, Clone)]
+pub struct Calibrated<I> {
+    imu: I,
+    calibration: ImuCalibration,
+}
+
+impl<I: Imu> Calibrated<I> {
+    /// Instantiate a new [`Calibrated`] IMU.
+    pub fn new(imu: I, calibration: ImuCalibration) -> Self {
+        Self { imu, calibration }
+    }
+
+    /// Current calibration.
+    pub fn calibration(&self) -> &ImuCalibration {
+        &self.calibration
+    }
+
+    /// Replace the calibration, such as after calibrating again.
+    pub fn set_calibration(&mut self, calibration: ImuCalibration) {
+        self.calibration = calibration;
+    }
+
+    /// Read the sensor without calibrating, to calibrate it.
+    pub fn read_raw(&mut self, now: u64) -> Result<ImuSample, I::Error> {
+        self.imu.read(now)
+    }
+
+    /// The wrapped sensor.
+    pub fn into_inner(self) -> I {
+        self.imu
+    }
+}
+
+impl<I: Imu> Imu for Calibrated<I> {
+    type Error = I::Error;
+
+    fn read(&mut self, now: u64) -> Result<ImuSample, Self::Error> {
+        Ok(self.calibration.apply(&self.imu.read(now)?))
+    }
+}
+
+/// Reason that a calibration could not be computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationError {
+    /// Not enough samples were taken at rest.
+    NotEnoughSamples,
+    /// The sensor moved while sampling.
+    Moved,
+    /// No samples were taken with this orientation.
+    MissingOrientation(Orientation),
+    /// The readings of an axis up and down give no sensible scale.
+    Degenerate,
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrationError::NotEnoughSamples => write!(f, "not enough samples at rest")?,
+            CalibrationError::Moved => write!(f, "sensor moved while calibrating")?,
+            CalibrationError::MissingOrientation(orientation) => {
+                write!(f, "no samples with {} up", orientation)?
+            }
+            CalibrationError::Degenerate => write!(f, "degenerate accelerometer readings")?,
+        };
+
+        Ok(())
+    }
+}
+
+/// Running mean and spread of vector samples.
+#[derive(Debug, Clone, Copy)]
+struct Mean {
+    count: u32,
+    mean: Vector3<f32>,
+    /// Sum of the squared differences to the mean, for the variance.
+    m2: Vector3<f32>,
+}
+
+impl Mean {
+    const fn new() -> Self {
+        Self {
+            count: 0,
+            mean: Vector3::new(0.0, 0.0, 0.0),
+            m2: Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn push(&mut self, value: Vector3<f32>) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta.component_mul(&(value - self.mean));
+    }
+
+    /// Largest standard deviation of an axis.
+    fn max_deviation(&self) -> f32 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        num_traits::Float::sqrt(self.m2.max() / (self.count - 1) as f32)
+    }
+}
+
+/// Estimates the gyroscope bias from raw samples taken at rest.
+#[derive(Debug, Clone)]
+pub struct GyroBiasEstimator {
+    /// Number of samples needed.
+    samples: u32,
+    /// Largest standard deviation of an axis at rest, in radians per second.
+    max_deviation: f32,
+    rates: Mean,
+}
+
+impl GyroBiasEstimator {
+    /// Instantiate a new [`GyroBiasEstimator`], needing the given number of samples. The sensor is
+    /// considered moving if the rate of an axis deviates more than `max_deviation`, in radians per
+    /// second.
+    pub fn new(samples: u32, max_deviation: f32) -> Self {
+        Self {
+            samples,
+            max_deviation,
+            rates: Mean::new(),
+        }
+    }
+
+    /// Add a raw sample. Returns whether enough samples were taken.
+    pub fn push(&mut self, sample: &ImuSample) -> bool {
+        self.rates.push(sample.angular_rate.0);
+        self.rates.count >= self.samples
+    }
+
+    /// Start over, such as after the sensor moved.
+    pub fn reset(&mut self) {
+        self.rates = Mean::new();
+    }
+
+    /// The estimated bias, in radians per second.
+    pub fn bias(&self) -> Result<[f32; 3], CalibrationError> {
+        if self.rates.count < self.samples {
+            return Err(CalibrationError::NotEnoughSamples);
+        }
+        if self.rates.max_deviation() > self.max_deviation {
+            return Err(CalibrationError::Moved);
+        }
+
+        Ok(self.rates.mean.into())
+    }
+}
+
+/// Orientation of the sensor during the six-point calibration, as the axis that points up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl Orientation {
+    /// Every orientation, in the order they are usually calibrated in.
+    pub const ALL: [Orientation; 6] = [
+        Orientation::PositiveX,
+        Orientation::NegativeX,
+        Orientation::PositiveY,
+        Orientation::NegativeY,
+        Orientation::PositiveZ,
+        Orientation::NegativeZ,
+    ];
+
+    /// Orientation of a raw acceleration: the axis measuring the most of gravity.
+    fn of(acceleration: &Vector3<f32>) -> Self {
+        let axis = acceleration.iamax();
+        let up = acceleration[axis] > 0.0;
+        Self::ALL[axis * 2 + usize::from(!up)]
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Orientation::PositiveX => write!(f, "+X")?,
+            Orientation::NegativeX => write!(f, "-X")?,
+            Orientation::PositiveY => write!(f, "+Y")?,
+            Orientation::NegativeY => write!(f, "-Y")?,
+            Orientation::PositiveZ => write!(f, "+Z")?,
+            Orientation::NegativeZ => write!(f, "-Z")?,
+        };
+
+        Ok(())
+    }
+}
+
+/// Fits the accelerometer offset and scale from raw samples taken at rest in six orientations,
+/// with each axis pointing up and then down. The orientation of every sample is recognized from the
+/// reading, so they can be taken in any order.
+#[derive(Debug, Clone)]
+pub struct SixPointCalibration {
+    /// Number of samples needed in each orientation.
+    samples: u32,
+    /// Largest standard deviation of an axis at rest, in meters per second squared.
+    max_deviation: f32,
+    orientations: [Mean; 6],
+}
+
+impl SixPointCalibration {
+    /// Instantiate a new [`SixPointCalibration`], needing the given number of samples in every
+    /// orientation. The sensor is considered moving if the acceleration of an axis deviates more
+    /// than `max_deviation`, in meters per second squared.
+    pub fn new(samples: u32, max_deviation: f32) -> Self {
+        Self {
+            samples,
+            max_deviation,
+            orientations: [Mean::new(); 6],
+        }
+    }
+
+    /// Add a raw sample. Returns the orientation it was taken in.
+    pub fn push(&mut self, sample: &ImuSample) -> Orientation {
+        let orientation = Orientation::of(&sample.acceleration.0);
+        self.orientations[orientation.index()].push(sample.acceleration.0);
+        orientation
+    }
+
+    /// Whether enough samples were taken in an orientation.
+    pub fn done(&self, orientation: Orientation) -> bool {
+        self.orientations[orientation.index()].count >= self.samples
+    }
+
+    /// Forget the samples of an orientation, such as after the sensor moved.
+    pub fn reset(&mut self, orientation: Orientation) {
+        self.orientations[orientation.index()] = Mean::new();
+    }
+
+    /// The fitted offset and scale of every axis.
+    pub fn fit(&self) -> Result<([f32; 3], [f32; 3]), CalibrationError> {
+        for orientation in Orientation::ALL {
+            let mean = &self.orientations[orientation.index()];
+            if mean.count < self.samples {
+                return Err(CalibrationError::MissingOrientation(orientation));
+            }
+            if mean.max_deviation() > self.max_deviation {
+                return Err(CalibrationError::Moved);
+            }
+        }
+
+        let mut offset = [0.0; 3];
+        let mut scale = [1.0; 3];
+        for axis in 0..3 {
+            // Pointing up reads `k g + o`, and down `-k g + o`.
+            let up = self.orientations[axis * 2].mean[axis];
+            let down = self.orientations[axis * 2 + 1].mean[axis];
+            if up - down <= STANDARD_GRAVITY {
+                return Err(CalibrationError::Degenerate);
+            }
+            offset[axis] = (up + down) / 2.0;
+            scale[axis] = 2.0 * STANDARD_GRAVITY / (up - down);
+        }
+
+        Ok((offset, scale))
+    }
+}
+
+/// Step of a [`Calibrator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// Sampling the gyroscope, with the sensor at rest in any orientation.
+    Gyroscope,
+    /// Sampling the accelerometer, with the given axis up.
+    Accelerometer(Orientation),
+    /// Every sample was taken, so the calibration is ready.
+    Done,
+}
+
+/// Calibrates an IMU from its raw samples: the gyroscope at rest first, then the accelerometer in
+/// every orientation. An orientation whose samples show that the sensor moved is sampled again,
+/// such as when it was turned to the next one.
+#[derive(Debug, Clone)]
+pub struct Calibrator {
+    gyro: GyroBiasEstimator,
+    accel: SixPointCalibration,
+    /// Gyroscope bias, once estimated.
+    bias: Option<[f32; 3]>,
+}
+
+impl Calibrator {
+    /// Instantiate a new [`Calibrator`], needing the given number of samples at rest for the
+    /// gyroscope and in every orientation for the accelerometer. The largest standard deviations
+    /// at rest are in radians per second and meters per second squared.
+    pub fn new(samples: u32, gyro_deviation: f32, accel_deviation: f32) -> Self {
+        Self {
+            gyro: GyroBiasEstimator::new(samples, gyro_deviation),
+            accel: SixPointCalibration::new(samples, accel_deviation),
+            bias: None,
+        }
+    }
+
+    /// Add a raw sample. Fails with [`CalibrationError::Moved`] when the samples of the current
+    /// step are thrown away, since the sensor moved.
+    pub fn push(&mut self, sample: &ImuSample) -> Result<Progress, CalibrationError> {
+        if self.bias.is_none() {
+            if !self.gyro.push(sample) {
+                return Ok(Progress::Gyroscope);
+            }
+            match self.gyro.bias() {
+                Ok(bias) => self.bias = Some(bias),
+                Err(err) => {
+                    self.gyro.reset();
+                    return Err(err);
+                }
+            }
+            return Ok(self.progress());
+        }
+
+        // Samples of a finished orientation are taken while turning to the next one.
+        let orientation = Orientation::of(&sample.acceleration.0);
+        if !self.accel.done(orientation) {
+            self.accel.push(sample);
+            let mean = &self.accel.orientations[orientation.index()];
+            if self.accel.done(orientation) && mean.max_deviation() > self.accel.max_deviation {
+                self.accel.reset(orientation);
+                return Err(CalibrationError::Moved);
+            }
+        }
+
+        Ok(self.progress())
+    }
+
+    /// Start over, forgetting every sample.
+    pub fn reset(&mut self) {
+        self.gyro.reset();
+        for orientation in Orientation::ALL {
+            self.accel.reset(orientation);
+        }
+        self.bias = None;
+    }
+
+    /// Current step: the gyroscope, then the first orientation not sampled yet.
+    pub fn progress(&self) -> Progress {
+        if self.bias.is_none() {
+            return Progress::Gyroscope;
+        }
+        match Orientation::ALL
+            .into_iter()
+            .find(|&orientation| !self.accel.done(orientation))
+        {
+            Some(orientation) => Progress::Accelerometer(orientation),
+            None => Progress::Done,
+        }
+    }
+
+    /// The calibration, with the mounting of the sensor taken from `current`.
+    pub fn calibration(
+        &self,
+        current: &ImuCalibration,
+    ) -> Result<ImuCalibration, CalibrationError> {
+        let gyro_bias = match self.bias {
+            Some(bias) => bias,
+            None => return Err(CalibrationError::NotEnoughSamples),
+        };
+        let (accel_offset, accel_scale) = self.accel.fit()?;
+
+        Ok(ImuCalibration {
+            gyro_bias,
+            accel_offset,
+            accel_scale,
+            mounting: current.mounting,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oorandom::Rand32;
+
+    use super::*;
+
+    const SAMPLES: u32 = 100;
+    const BIAS: [f32; 3] = [0.02, -0.01, 0.005];
+    const OFFSET: [f32; 3] = [0.3, -0.2, 0.5];
+    const SCALE: [f32; 3] = [1.02, 0.97, 1.01];
+
+    /// Raw sample of a sensor at rest, with its errors, the given axis up, and some noise.
+    fn raw(orientation: Orientation, rng: &mut Rand32) -> ImuSample {
+        let mut noise = || (rng.rand_float() - 0.5) * 0.02;
+        let index = orientation.index();
+        let sign = if index.is_multiple_of(2) { 1.0 } else { -1.0 };
+        let mut gravity = Vector3::zeros();
+        gravity[index / 2] = sign * STANDARD_GRAVITY;
+
+        let acceleration =
+            Vector3::from_fn(|axis, _| gravity[axis] / SCALE[axis] + OFFSET[axis] + noise());
+        let angular_rate = Vector3::from_fn(|axis, _| BIAS[axis] + noise());
+        ImuSample {
+            time: 0,
+            acceleration: Acceleration(acceleration),
+            angular_rate: AngularRate(angular_rate),
+        }
+    }
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3], tolerance: f32) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!((actual - expected).abs() < tolerance, "{:?}", actual);
+        }
+    }
+
+    #[test]
+    fn fits_the_offset_and_scale_in_any_order() {
+        let mut rng = Rand32::new(1);
+        let mut calibration = SixPointCalibration::new(SAMPLES, 0.1);
+        for orientation in Orientation::ALL.into_iter().rev() {
+            for _ in 0..SAMPLES {
+                assert_eq!(calibration.push(&raw(orientation, &mut rng)), orientation);
+            }
+            assert!(calibration.done(orientation));
+        }
+
+        let (offset, scale) = calibration.fit().unwrap();
+        assert_close(offset, OFFSET, 0.01);
+        assert_close(scale, SCALE, 0.001);
+
+        // Calibrated samples read gravity on the axis that is up.
+        let calibrated = ImuCalibration {
+            accel_offset: offset,
+            accel_scale: scale,
+            ..ImuCalibration::default()
+        }
+        .apply(&raw(Orientation::NegativeY, &mut rng));
+        let expected = -Vector3::y() * STANDARD_GRAVITY;
+        assert!((calibrated.acceleration.0 - expected).norm() < 0.05);
+    }
+
+    #[test]
+    fn needs_every_orientation() {
+        let mut rng = Rand32::new(2);
+        let mut calibration = SixPointCalibration::new(SAMPLES, 0.1);
+        for orientation in Orientation::ALL {
+            if orientation != Orientation::PositiveY {
+                for _ in 0..SAMPLES {
+                    calibration.push(&raw(orientation, &mut rng));
+                }
+            }
+        }
+
+        assert_eq!(
+            calibration.fit(),
+            Err(CalibrationError::MissingOrientation(Orientation::PositiveY))
+        );
+    }
+
+    #[test]
+    fn estimates_the_gyroscope_bias_at_rest() {
+        let mut rng = Rand32::new(3);
+        let mut estimator = GyroBiasEstimator::new(SAMPLES, 0.01);
+        for _ in 1..SAMPLES {
+            assert!(!estimator.push(&raw(Orientation::PositiveZ, &mut rng)));
+        }
+        assert_eq!(estimator.bias(), Err(CalibrationError::NotEnoughSamples));
+        assert!(estimator.push(&raw(Orientation::PositiveZ, &mut rng)));
+        assert_close(estimator.bias().unwrap(), BIAS, 0.002);
+
+        // Turning the sensor shows as a spread of the rates.
+        estimator.reset();
+        for i in 0..SAMPLES {
+            let mut sample = raw(Orientation::PositiveZ, &mut rng);
+            sample.angular_rate.0.x += if i < SAMPLES / 2 { 0.0 } else { 1.0 };
+            estimator.push(&sample);
+        }
+        assert_eq!(estimator.bias(), Err(CalibrationError::Moved));
+    }
+
+    #[test]
+    fn samples_again_the_orientation_the_sensor_moved_in() {
+        let mut rng = Rand32::new(4);
+        let mut calibrator = Calibrator::new(SAMPLES, 0.01, 0.1);
+        for _ in 0..SAMPLES {
+            calibrator
+                .push(&raw(Orientation::PositiveZ, &mut rng))
+                .unwrap();
+        }
+        assert_eq!(
+            calibrator.progress(),
+            Progress::Accelerometer(Orientation::PositiveX)
+        );
+
+        // The sensor is tilted halfway through the first orientation.
+        let results: Vec<_> = (0..SAMPLES)
+            .map(|i| {
+                let mut sample = raw(Orientation::PositiveX, &mut rng);
+                if i >= SAMPLES / 2 {
+                    sample.acceleration.0.y += 3.0;
+                }
+                calibrator.push(&sample)
+            })
+            .collect();
+        assert_eq!(results.last(), Some(&Err(CalibrationError::Moved)));
+        assert!(!calibrator.accel.done(Orientation::PositiveX));
+
+        for orientation in Orientation::ALL {
+            for _ in 0..SAMPLES {
+                calibrator.push(&raw(orientation, &mut rng)).unwrap();
+            }
+        }
+        assert_eq!(calibrator.progress(), Progress::Done);
+
+        let mut current = ImuCalibration::default();
+        current.set_mounting_euler(0.0, 0.0, core::f32::consts::FRAC_PI_2);
+        let calibration = calibrator.calibration(&current).unwrap();
+        assert_close(calibration.gyro_bias, BIAS, 0.002);
+        assert_close(calibration.accel_offset, OFFSET, 0.01);
+        assert_close(calibration.accel_scale, SCALE, 0.001);
+        assert_eq!(calibration.mounting, current.mounting);
+    }
+}
@@ -0,0 +1,205 @@
+//! Sensors fed from recorded or simulated data, for running the flight logic without hardware.
+
+use core::iter::Peekable;
+
+use super::{
+    Barometer, BarometerSample, Gnss, GnssSample, Imu, ImuSample, Magnetometer, MagnetometerSample,
+    Timestamped,
+};
+
+/// Reason that a [`Replay`] has no sample to give.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The first sample is later than the time that was read at.
+    NoData,
+    /// The time that was read at is past the last sample.
+    Ended,
+}
+
+/// Sensor replaying time-ordered samples, such as a recorded flight. Reading gives the latest
+/// sample at or before the time that is read at.
+#[derive(Debug, Clone)]
+pub struct Replay<I: Iterator> {
+    samples: Peekable<I>,
+    current: Option<I::Item>,
+    /// Whether the current sample was polled, for [`Gnss`].
+    polled: bool,
+}
+
+impl<I> Replay<I>
+where
+    I: Iterator,
+    I::Item: Timestamped + Copy,
+{
+    /// Instantiate a new [`Replay`] of the given samples.
+    pub fn new(samples: impl IntoIterator<IntoIter = I>) -> Self {
+        Self {
+            samples: samples.into_iter().peekable(),
+            current: None,
+            polled: false,
+        }
+    }
+
+    fn latest(&mut self, now: u64) -> Result<I::Item, ReplayError> {
+        while let Some(sample) = self.samples.next_if(|sample| sample.time() <= now) {
+            self.current = Some(sample);
+            self.polled = false;
+        }
+
+        let current = self.current.ok_or(ReplayError::NoData)?;
+        if self.samples.peek().is_none() && now > current.time() {
+            return Err(ReplayError::Ended);
+        }
+        Ok(current)
+    }
+}
+
+impl<I: Iterator<Item = ImuSample>> Imu for Replay<I> {
+    type Error = ReplayError;
+
+    fn read(&mut self, now: u64) -> Result<ImuSample, Self::Error> {
+        self.latest(now)
+    }
+}
+
+impl<I: Iterator<Item = BarometerSample>> Barometer for Replay<I> {
+    type Error = ReplayError;
+
+    fn read(&mut self, now: u64) -> Result<BarometerSample, Self::Error> {
+        self.latest(now)
+    }
+}
+
+impl<I: Iterator<Item = MagnetometerSample>> Magnetometer for Replay<I> {
+    type Error = ReplayError;
+
+    fn read(&mut self, now: u64) -> Result<MagnetometerSample, Self::Error> {
+        self.latest(now)
+    }
+}
+
+impl<I: Iterator<Item = GnssSample>> Gnss for Replay<I> {
+    type Error = ReplayError;
+
+    fn poll(&mut self, now: u64) -> Result<Option<GnssSample>, Self::Error> {
+        let sample = self.latest(now)?;
+        Ok((!core::mem::replace(&mut self.polled, true)).then_some(sample))
+    }
+}
+
+/// Sensor computing its samples from the time, such as a simulation.
+#[derive(Debug, Clone, Copy)]
+pub struct Simulated<F>(pub F);
+
+impl<F: FnMut(u64) -> ImuSample> Imu for Simulated<F> {
+    type Error = core::convert::Infallible;
+
+    fn read(&mut self, now: u64) -> Result<ImuSample, Self::Error> {
+        Ok((self.0)(now))
+    }
+}
+
+impl<F: FnMut(u64) -> BarometerSample> Barometer for Simulated<F> {
+    type Error = core::convert::Infallible;
+
+    fn read(&mut self, now: u64) -> Result<BarometerSample, Self::Error> {
+        Ok((self.0)(now))
+    }
+}
+
+impl<F: FnMut(u64) -> MagnetometerSample> Magnetometer for Simulated<F> {
+    type Error = core::convert::Infallible;
+
+    fn read(&mut self, now: u64) -> Result<MagnetometerSample, Self::Error> {
+        Ok((self.0)(now))
+    }
+}
+
+impl<F: FnMut(u64) -> Option<GnssSample>> Gnss for Simulated<F> {
+    type Error = core::convert::Infallible;
+
+    fn poll(&mut self, now: u64) -> Result<Option<GnssSample>, Self::Error> {
+        Ok((self.0)(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+    use crate::device::{Pressure, Temperature, Velocity};
+
+    fn barometer(time: u64, pressure: f32) -> BarometerSample {
+        BarometerSample {
+            time,
+            pressure: Pressure(pressure),
+            temperature: Temperature(20.0),
+        }
+    }
+
+    fn fix(time: u64) -> GnssSample {
+        GnssSample {
+            time,
+            latitude: 48.0,
+            longitude: 2.0,
+            altitude: 100.0,
+            velocity: Velocity(Vector3::zeros()),
+            satellites: 8,
+        }
+    }
+
+    #[test]
+    fn replays_the_latest_sample() {
+        let samples = [
+            barometer(1_000, 100.0),
+            barometer(2_000, 200.0),
+            barometer(3_000, 300.0),
+        ];
+        let mut replay = Replay::new(samples);
+
+        let mut read = |now| Barometer::read(&mut replay, now).map(|sample| sample.pressure.0);
+        assert_eq!(read(1_000), Ok(100.0));
+        assert_eq!(read(1_999), Ok(100.0));
+        assert_eq!(read(2_000), Ok(200.0));
+        // Samples that were skipped over are never given.
+        assert_eq!(read(3_000), Ok(300.0));
+    }
+
+    #[test]
+    fn has_no_data_before_the_first_sample_and_ends_after_the_last() {
+        let mut replay = Replay::new([barometer(1_000, 100.0), barometer(2_000, 200.0)]);
+        assert_eq!(replay.read(999), Err(ReplayError::NoData));
+        assert_eq!(replay.read(1_000).unwrap().time, 1_000);
+        assert_eq!(replay.read(2_000).unwrap().time, 2_000);
+        assert_eq!(replay.read(2_001), Err(ReplayError::Ended));
+
+        let mut empty = Replay::new(core::iter::empty::<BarometerSample>());
+        assert_eq!(empty.read(0), Err(ReplayError::NoData));
+    }
+
+    #[test]
+    fn polls_every_fix_once() {
+        let mut gnss = Replay::new([fix(1_000_000), fix(2_000_000)]);
+        assert_eq!(gnss.poll(500_000), Err(ReplayError::NoData));
+        assert_eq!(gnss.poll(1_000_000).unwrap().unwrap().time, 1_000_000);
+        assert_eq!(gnss.poll(1_500_000), Ok(None));
+        assert_eq!(gnss.poll(1_999_999), Ok(None));
+        assert_eq!(gnss.poll(2_000_000).unwrap().unwrap().time, 2_000_000);
+        assert_eq!(gnss.poll(2_000_000), Ok(None));
+        assert_eq!(gnss.poll(2_000_001), Err(ReplayError::Ended));
+    }
+
+    #[test]
+    fn simulates_from_the_time() {
+        let mut barometer = Simulated(|time| barometer(time, time as f32));
+        assert_eq!(
+            Barometer::read(&mut barometer, 42).unwrap().pressure.0,
+            42.0
+        );
+
+        let mut gnss = Simulated(|time| (time >= 1_000).then(|| fix(time)));
+        assert_eq!(gnss.poll(999), Ok(None));
+        assert_eq!(gnss.poll(1_000).unwrap().unwrap().time, 1_000);
+    }
+}
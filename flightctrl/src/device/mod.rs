@@ -0,0 +1,140 @@
+//! Hardware-agnostic sensors.
+//!
+//! Flight logic only sees the [`Imu`], [`Barometer`], [`Magnetometer`] and [`Gnss`] traits, never
+//! a concrete chip. Adapters implement them for the drivers of the chips on the board, and
+//! [`mock`] implements them from recorded or simulated data, for running the flight logic on the
+//! host. Sensors don't keep time themselves: every read is given the current time, in
+//! microseconds, which stamps the sample.
+//!
+//! Quantities are wrapped in types giving their unit, so that they can't be mixed up. Vectors are
+//! in the frame of the sensor, which the calibration turns into the body frame.
+
+use nalgebra::Vector3;
+
+pub mod bmp280;
+pub mod calibration;
+pub mod mock;
+pub mod mpu6050;
+
+/// Standard gravity, in meters per second squared.
+pub const STANDARD_GRAVITY: f32 = 9.80665;
+
+/// Acceleration, in meters per second squared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Acceleration(pub Vector3<f32>);
+
+impl Acceleration {
+    /// Acceleration given in multiples of standard gravity.
+    pub fn from_g(g: Vector3<f32>) -> Self {
+        Self(g * STANDARD_GRAVITY)
+    }
+}
+
+/// Angular rate, in radians per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AngularRate(pub Vector3<f32>);
+
+/// Magnetic field, in microteslas.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MagneticField(pub Vector3<f32>);
+
+/// Pressure, in pascals.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Pressure(pub f32);
+
+/// Temperature, in degrees Celsius.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Temperature(pub f32);
+
+/// Velocity, in meters per second along the east, north and up axes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Velocity(pub Vector3<f32>);
+
+/// Something that was sampled at a given time.
+pub trait Timestamped {
+    /// Time of the sample, in microseconds.
+    fn time(&self) -> u64;
+}
+
+/// Sample of an [`Imu`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImuSample {
+    pub time: u64,
+    /// Specific force: the acceleration minus gravity, so at rest this points up.
+    pub acceleration: Acceleration,
+    pub angular_rate: AngularRate,
+}
+
+/// Sample of a [`Barometer`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BarometerSample {
+    pub time: u64,
+    pub pressure: Pressure,
+    pub temperature: Temperature,
+}
+
+/// Sample of a [`Magnetometer`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MagnetometerSample {
+    pub time: u64,
+    pub field: MagneticField,
+}
+
+/// Position fix of a [`Gnss`] receiver.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GnssSample {
+    pub time: u64,
+    /// Latitude, in degrees.
+    pub latitude: f64,
+    /// Longitude, in degrees.
+    pub longitude: f64,
+    /// Altitude above mean sea level, in meters.
+    pub altitude: f32,
+    pub velocity: Velocity,
+    /// Number of satellites used in the fix.
+    pub satellites: u8,
+}
+
+macro_rules! timestamped {
+    ($($sample:ty),*) => {
+        $(impl Timestamped for $sample {
+            fn time(&self) -> u64 {
+                self.time
+            }
+        })*
+    };
+}
+
+timestamped!(ImuSample, BarometerSample, MagnetometerSample, GnssSample);
+
+/// Inertial measurement unit: an accelerometer and a gyroscope.
+pub trait Imu {
+    type Error;
+
+    /// Read the sensor.
+    fn read(&mut self, now: u64) -> Result<ImuSample, Self::Error>;
+}
+
+/// Barometer, which also measures its temperature.
+pub trait Barometer {
+    type Error;
+
+    /// Read the sensor.
+    fn read(&mut self, now: u64) -> Result<BarometerSample, Self::Error>;
+}
+
+/// Magnetometer.
+pub trait Magnetometer {
+    type Error;
+
+    /// Read the sensor.
+    fn read(&mut self, now: u64) -> Result<MagnetometerSample, Self::Error>;
+}
+
+/// Satellite navigation receiver. Fixes come at a low rate, on their own schedule.
+pub trait Gnss {
+    type Error;
+
+    /// Returns the latest fix if a new one came since the last poll.
+    fn poll(&mut self, now: u64) -> Result<Option<GnssSample>, Self::Error>;
+}
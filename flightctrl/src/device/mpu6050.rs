@@ -0,0 +1,21 @@
+//! [`Imu`] for the MPU6050 (see the `mpu6050` crate).
+
+use ::mpu6050::{Mpu6050, Mpu6050Error};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use super::{Acceleration, AngularRate, Imu, ImuSample};
+
+impl<I, E> Imu for Mpu6050<I>
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    type Error = Mpu6050Error<E>;
+
+    fn read(&mut self, now: u64) -> Result<ImuSample, Self::Error> {
+        Ok(ImuSample {
+            time: now,
+            acceleration: Acceleration::from_g(self.get_acc()?),
+            angular_rate: AngularRate(self.get_gyro()?),
+        })
+    }
+}
@@ -0,0 +1,5 @@
+pub mod nrf24;
+pub mod serial;
+pub mod sx127x;
+#[cfg(target_os = "none")]
+pub mod usb;
@@ -0,0 +1,911 @@
+//! Driver for Nordic nRF24L01+ transceivers.
+//!
+//! Payloads are limited to 32 bytes, so every message is sent as a CRC-checked frame split into
+//! fragments (see [`flick_bridge::frame`]). Fragments sent to the peer rely on the hardware
+//! auto-acknowledgement and retransmission of Enhanced ShockBurst when it is enabled, while pings
+//! are broadcast without acknowledgement.
+//!
+//! Every node of a network shares the upper four bytes of its address, and is identified by the
+//! lowest byte. Pipe 1 receives messages addressed to the node, pipe 2 receives broadcasts, and
+//! pipe 0 receives acknowledgements while transmitting. Dynamic payload lengths require
+//! auto-acknowledgement on every pipe, so it stays on, and packets that mustn't be acknowledged,
+//! such as broadcasts, are sent with the no-acknowledgement flag instead.
+//! See the nRF24L01+ Product Specification v1.0.
+
+use core::cell::{Cell, RefCell};
+
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+use flick_bridge::device::{Device, DeviceError};
+use flick_bridge::frame::fragment::{fragments, Reassembler, HEADER_LEN};
+use flick_bridge::frame::{self, MAX_FRAME_LEN};
+use flick_bridge::message::{Message, MessageFlags, MessageKind};
+
+const R_REGISTER: u8 = 0x00;
+const W_REGISTER: u8 = 0x20;
+const R_RX_PAYLOAD: u8 = 0x61;
+const R_RX_PL_WID: u8 = 0x60;
+const W_TX_PAYLOAD: u8 = 0xa0;
+const W_TX_PAYLOAD_NOACK: u8 = 0xb0;
+const FLUSH_TX: u8 = 0xe1;
+const FLUSH_RX: u8 = 0xe2;
+const ACTIVATE: u8 = 0x50;
+
+const REG_CONFIG: u8 = 0x00;
+const REG_EN_AA: u8 = 0x01;
+const REG_EN_RXADDR: u8 = 0x02;
+const REG_SETUP_AW: u8 = 0x03;
+const REG_SETUP_RETR: u8 = 0x04;
+const REG_RF_CH: u8 = 0x05;
+const REG_RF_SETUP: u8 = 0x06;
+const REG_STATUS: u8 = 0x07;
+const REG_OBSERVE_TX: u8 = 0x08;
+const REG_RPD: u8 = 0x09;
+const REG_RX_ADDR_P0: u8 = 0x0a;
+const REG_RX_ADDR_P1: u8 = 0x0b;
+const REG_RX_ADDR_P2: u8 = 0x0c;
+const REG_TX_ADDR: u8 = 0x10;
+const REG_FIFO_STATUS: u8 = 0x17;
+const REG_DYNPD: u8 = 0x1c;
+const REG_FEATURE: u8 = 0x1d;
+
+const CONFIG_EN_CRC: u8 = 0x08;
+const CONFIG_CRCO: u8 = 0x04;
+const CONFIG_PWR_UP: u8 = 0x02;
+const CONFIG_PRIM_RX: u8 = 0x01;
+const STATUS_RX_DR: u8 = 0x40;
+const STATUS_TX_DS: u8 = 0x20;
+const STATUS_MAX_RT: u8 = 0x10;
+const FIFO_RX_EMPTY: u8 = 0x01;
+const STATUS_RX_P_NO: u8 = 0x0e;
+const FEATURE_EN_DPL: u8 = 0x04;
+const FEATURE_EN_DYN_ACK: u8 = 0x01;
+/// Features of the transceiver in use.
+const FEATURES: u8 = FEATURE_EN_DPL | FEATURE_EN_DYN_ACK;
+const PIPE_0: u8 = 0x01;
+const PIPE_1: u8 = 0x02;
+const PIPE_2: u8 = 0x04;
+/// Pipes that receive data, each of which is reassembled separately.
+const DATA_PIPES: usize = 3;
+
+/// Largest payload of a packet.
+const MAX_PAYLOAD_LEN: usize = 32;
+/// Address byte that every node listens to.
+const BROADCAST: u8 = 0xff;
+/// Frequency of channel 0, in hertz.
+const BASE_FREQ: u64 = 2_400_000_000;
+/// Spacing between two channels, in hertz.
+const CHANNEL_SPACING: u64 = 1_000_000;
+/// Highest channel.
+const MAX_CHANNEL: u8 = 125;
+/// Longest time to wait for a packet to be sent and acknowledged, in microseconds.
+const TX_TIMEOUT_US: u32 = 100_000;
+
+/// Air data rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataRate {
+    Kbps250,
+    Mbps1,
+    Mbps2,
+}
+
+/// Output power.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Power {
+    /// -18 dBm.
+    Min = 0,
+    /// -12 dBm.
+    Low = 1,
+    /// -6 dBm.
+    High = 2,
+    /// 0 dBm.
+    Max = 3,
+}
+
+/// Configuration of the transceiver. Both ends of the link must use the same channel, data rate
+/// and network.
+#[derive(Debug, Clone, Copy)]
+pub struct NrfConfig {
+    /// RF channel, from 0 to 125. The frequency is 2400 MHz plus the channel in MHz.
+    pub channel: u8,
+    pub data_rate: DataRate,
+    pub power: Power,
+    /// Upper four bytes of every address on the network.
+    pub network: [u8; 4],
+    /// Address byte of this node.
+    pub node: u8,
+    /// Address byte of the node that messages are sent to.
+    pub peer: u8,
+    /// Whether to use hardware acknowledgements and retransmissions.
+    pub auto_ack: bool,
+    /// Delay between two retransmissions, in steps of 250 us, from 0 to 15.
+    pub retransmit_delay: u8,
+    /// Number of retransmissions before giving up, from 0 to 15.
+    pub retransmit_count: u8,
+}
+
+impl Default for NrfConfig {
+    fn default() -> Self {
+        Self {
+            channel: 76,
+            data_rate: DataRate::Mbps1,
+            power: Power::Max,
+            network: *b"FLCK",
+            node: 0x01,
+            peer: 0x02,
+            auto_ack: true,
+            retransmit_delay: 5,
+            retransmit_count: 15,
+        }
+    }
+}
+
+impl NrfConfig {
+    /// Full address of the given node.
+    fn address(&self, node: u8) -> [u8; 5] {
+        let [a, b, c, d] = self.network;
+        [node, a, b, c, d]
+    }
+}
+
+/// Statistics of the transmitted packets, as counted by the transceiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxStatistics {
+    /// Packets lost since the channel was last set, saturating at 15.
+    pub lost: u8,
+    /// Retransmissions of the last packet.
+    pub retransmits: u8,
+}
+
+/// SPI bus, chip-select, chip-enable and delay of the transceiver.
+struct Bus<SPI, CS, CE, DELAY> {
+    spi: SPI,
+    cs: CS,
+    ce: CE,
+    delay: DELAY,
+}
+
+impl<SPI, CS, CE, DELAY> Bus<SPI, CS, CE, DELAY>
+where
+    SPI: Transfer<u8>,
+    CS: OutputPin,
+    CE: OutputPin,
+{
+    /// Run a command, exchanging the bytes of the buffer. Returns the status register, which is
+    /// shifted out during the command byte.
+    fn command(&mut self, command: u8, buf: &mut [u8]) -> Result<u8, DeviceError> {
+        let mut status = [command];
+        self.cs.set_low().map_err(|_| DeviceError::Physical)?;
+        let result = self
+            .spi
+            .transfer(&mut status)
+            .map(|status| status[0])
+            .and_then(|status| self.spi.transfer(buf).map(|_| status));
+        self.cs.set_high().map_err(|_| DeviceError::Physical)?;
+        result.map_err(|_| DeviceError::Physical)
+    }
+
+    fn read(&mut self, reg: u8) -> Result<u8, DeviceError> {
+        let mut buf = [0];
+        self.command(R_REGISTER | reg, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn write(&mut self, reg: u8, value: u8) -> Result<(), DeviceError> {
+        self.command(W_REGISTER | reg, &mut [value]).map(|_| ())
+    }
+
+    fn write_address(&mut self, reg: u8, address: [u8; 5]) -> Result<(), DeviceError> {
+        let mut buf = address;
+        self.command(W_REGISTER | reg, &mut buf).map(|_| ())
+    }
+
+    fn status(&mut self) -> Result<u8, DeviceError> {
+        self.command(R_REGISTER | REG_STATUS, &mut [])
+    }
+
+    fn set_ce(&mut self, high: bool) -> Result<(), DeviceError> {
+        match high {
+            true => self.ce.set_high(),
+            false => self.ce.set_low(),
+        }
+        .map_err(|_| DeviceError::Physical)
+    }
+}
+
+/// A nRF24L01+ transceiver used as a bridge device.
+pub struct Nrf24<SPI, CS, CE, DELAY> {
+    bus: RefCell<Bus<SPI, CS, CE, DELAY>>,
+    config: NrfConfig,
+    /// Reassembler of every pipe, since fragments of a message to the node and of a broadcast may
+    /// arrive interleaved.
+    reassemblers: RefCell<[Reassembler; DATA_PIPES]>,
+    /// Sequence number of the next frame.
+    sequence: Cell<u8>,
+}
+
+impl<SPI, CS, CE, DELAY> Nrf24<SPI, CS, CE, DELAY>
+where
+    SPI: Transfer<u8>,
+    CS: OutputPin,
+    CE: OutputPin,
+    DELAY: DelayUs<u32>,
+{
+    /// Configure the transceiver, and start receiving.
+    pub fn new(
+        spi: SPI,
+        cs: CS,
+        ce: CE,
+        delay: DELAY,
+        config: NrfConfig,
+    ) -> Result<Self, DeviceError> {
+        if config.channel > MAX_CHANNEL {
+            return Err(DeviceError::InvalidFrequency);
+        }
+
+        let mut bus = Bus { spi, cs, ce, delay };
+        bus.cs.set_high().map_err(|_| DeviceError::Physical)?;
+        bus.set_ce(false)?;
+        // Give the transceiver time to power on.
+        bus.delay.delay_us(5_000);
+
+        // Dynamic payload lengths are needed for fragments shorter than a whole payload, and
+        // dynamic acknowledgements to send packets that mustn't be acknowledged. The original
+        // nRF24L01 only exposes them after being activated.
+        bus.write(REG_FEATURE, FEATURES)?;
+        if bus.read(REG_FEATURE)? != FEATURES {
+            bus.command(ACTIVATE, &mut [0x73])?;
+            bus.write(REG_FEATURE, FEATURES)?;
+            if bus.read(REG_FEATURE)? != FEATURES {
+                return Err(DeviceError::Physical);
+            }
+        }
+        // Dynamic payload lengths need auto-acknowledgement on the same pipes.
+        bus.write(REG_DYNPD, PIPE_0 | PIPE_1 | PIPE_2)?;
+        bus.write(REG_EN_AA, PIPE_0 | PIPE_1 | PIPE_2)?;
+
+        // 5-byte addresses.
+        bus.write(REG_SETUP_AW, 0x03)?;
+        bus.write_address(REG_RX_ADDR_P1, config.address(config.node))?;
+        bus.write(REG_RX_ADDR_P2, BROADCAST)?;
+
+        let retries = match config.auto_ack {
+            true => (config.retransmit_delay.min(15) << 4) | config.retransmit_count.min(15),
+            false => 0,
+        };
+        bus.write(REG_SETUP_RETR, retries)?;
+
+        let rate = match config.data_rate {
+            DataRate::Kbps250 => 0x20,
+            DataRate::Mbps1 => 0x00,
+            DataRate::Mbps2 => 0x08,
+        };
+        bus.write(REG_RF_SETUP, rate | ((config.power as u8) << 1))?;
+        bus.write(REG_RF_CH, config.channel)?;
+
+        bus.command(FLUSH_TX, &mut [])?;
+        bus.command(FLUSH_RX, &mut [])?;
+        bus.write(REG_STATUS, STATUS_RX_DR | STATUS_TX_DS | STATUS_MAX_RT)?;
+
+        let radio = Self {
+            bus: RefCell::new(bus),
+            config,
+            reassemblers: RefCell::new(core::array::from_fn(|_| Reassembler::new())),
+            sequence: Cell::new(0),
+        };
+        radio.listen(&mut radio.bus.borrow_mut())?;
+
+        Ok(radio)
+    }
+
+    /// Power up in receive mode. Pipe 0 is only needed for acknowledgements while transmitting.
+    fn listen(&self, bus: &mut Bus<SPI, CS, CE, DELAY>) -> Result<(), DeviceError> {
+        bus.write(REG_EN_RXADDR, PIPE_1 | PIPE_2)?;
+        bus.write(
+            REG_CONFIG,
+            CONFIG_EN_CRC | CONFIG_CRCO | CONFIG_PWR_UP | CONFIG_PRIM_RX,
+        )?;
+        bus.set_ce(true)?;
+        // Settling time of the receiver.
+        bus.delay.delay_us(130);
+        Ok(())
+    }
+
+    /// Send a frame to the given node.
+    fn send_frame(&self, frame: &[u8], node: u8) -> Result<(), DeviceError> {
+        let mut bus = self.bus.borrow_mut();
+        let address = self.config.address(node);
+        let acknowledged = self.config.auto_ack && node != BROADCAST;
+
+        bus.set_ce(false)?;
+        bus.write_address(REG_TX_ADDR, address)?;
+        // Acknowledgements come back on pipe 0 from the address that was sent to.
+        bus.write_address(REG_RX_ADDR_P0, address)?;
+        bus.write(REG_EN_RXADDR, PIPE_0 | PIPE_1 | PIPE_2)?;
+        bus.write(REG_CONFIG, CONFIG_EN_CRC | CONFIG_CRCO | CONFIG_PWR_UP)?;
+        let command = if acknowledged {
+            W_TX_PAYLOAD
+        } else {
+            W_TX_PAYLOAD_NOACK
+        };
+
+        let sequence = self.sequence.get();
+        self.sequence.set(sequence.wrapping_add(1));
+
+        let mut result = Ok(());
+        for (header, chunk) in fragments(frame, MAX_PAYLOAD_LEN, sequence)? {
+            let mut payload = [0; MAX_PAYLOAD_LEN];
+            payload[..HEADER_LEN].copy_from_slice(&header.encode());
+            payload[HEADER_LEN..HEADER_LEN + chunk.len()].copy_from_slice(chunk);
+
+            result =
+                Self::send_payload(&mut bus, command, &mut payload[..HEADER_LEN + chunk.len()]);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        self.listen(&mut bus)?;
+        result
+    }
+
+    /// Send a single payload with the given command, waiting for it to be sent (and
+    /// acknowledged, unless sent with [`W_TX_PAYLOAD_NOACK`]).
+    fn send_payload(
+        bus: &mut Bus<SPI, CS, CE, DELAY>,
+        command: u8,
+        payload: &mut [u8],
+    ) -> Result<(), DeviceError> {
+        bus.command(command, payload)?;
+        // A pulse of at least 10 us on CE starts the transmission.
+        bus.set_ce(true)?;
+        bus.delay.delay_us(15);
+        bus.set_ce(false)?;
+
+        let mut waited = 0;
+        loop {
+            let status = bus.status()?;
+            if status & STATUS_TX_DS != 0 {
+                bus.write(REG_STATUS, STATUS_TX_DS)?;
+                return Ok(());
+            }
+            if status & STATUS_MAX_RT != 0 {
+                bus.write(REG_STATUS, STATUS_MAX_RT)?;
+                bus.command(FLUSH_TX, &mut [])?;
+                return Err(DeviceError::NotAcknowledged);
+            }
+            if waited >= TX_TIMEOUT_US {
+                bus.command(FLUSH_TX, &mut [])?;
+                return Err(DeviceError::Physical);
+            }
+
+            bus.delay.delay_us(50);
+            waited += 50;
+        }
+    }
+
+    /// Whether a signal stronger than -64 dBm was present on the channel while receiving.
+    pub fn carrier_detected(&self) -> Result<bool, DeviceError> {
+        Ok(self.bus.borrow_mut().read(REG_RPD)? & 0x01 != 0)
+    }
+
+    /// Statistics of the transmitted packets.
+    pub fn tx_statistics(&self) -> Result<TxStatistics, DeviceError> {
+        let observe = self.bus.borrow_mut().read(REG_OBSERVE_TX)?;
+        Ok(TxStatistics {
+            lost: observe >> 4,
+            retransmits: observe & 0x0f,
+        })
+    }
+}
+
+impl<SPI, CS, CE, DELAY> Device for Nrf24<SPI, CS, CE, DELAY>
+where
+    SPI: Transfer<u8>,
+    CS: OutputPin,
+    CE: OutputPin,
+    DELAY: DelayUs<u32>,
+{
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let frame = frame::encode(&message, &mut buf)?;
+        self.send_frame(frame, self.config.peer)
+    }
+
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        let mut bus = self.bus.borrow_mut();
+        while bus.read(REG_FIFO_STATUS)? & FIFO_RX_EMPTY == 0 {
+            let mut len = [0];
+            let status = bus.command(R_RX_PL_WID, &mut len)?;
+            let pipe = ((status & STATUS_RX_P_NO) >> 1) as usize;
+            let len = len[0] as usize;
+            if len > MAX_PAYLOAD_LEN {
+                // The datasheet says the payload is corrupt and must be flushed.
+                bus.command(FLUSH_RX, &mut [])?;
+                return Err(DeviceError::InvalidMessage);
+            }
+
+            let mut payload = [0; MAX_PAYLOAD_LEN];
+            bus.command(R_RX_PAYLOAD, &mut payload[..len])?;
+            bus.write(REG_STATUS, STATUS_RX_DR)?;
+
+            let mut reassemblers = self.reassemblers.borrow_mut();
+            let reassembler = match reassemblers.get_mut(pipe) {
+                Some(reassembler) => reassembler,
+                // Pipes that aren't enabled never receive.
+                None => continue,
+            };
+            if let Some(frame) = reassembler.push(&payload[..len])? {
+                return frame::decode(frame).map(Some).map_err(DeviceError::from);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn ping(&self, id: u64, freq: u64) -> Result<(), DeviceError> {
+        channel(freq)?;
+
+        let message = Message::new(id, MessageKind::Ping(id, freq), 0, MessageFlags::empty());
+        let mut buf = [0; MAX_FRAME_LEN];
+        let frame = frame::encode(&message, &mut buf)?;
+        self.send_frame(frame, BROADCAST)
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        Ok(BASE_FREQ + self.config.channel as u64 * CHANNEL_SPACING)
+    }
+
+    fn set_freq(&mut self, freq: u64) -> Result<(), DeviceError> {
+        let channel = channel(freq)?;
+        self.bus.get_mut().write(REG_RF_CH, channel)?;
+        self.config.channel = channel;
+        Ok(())
+    }
+}
+
+/// Channel of the given frequency, in hertz.
+fn channel(freq: u64) -> Result<u8, DeviceError> {
+    let offset = freq
+        .checked_sub(BASE_FREQ)
+        .ok_or(DeviceError::InvalidFrequency)?;
+    if offset % CHANNEL_SPACING != 0 || offset / CHANNEL_SPACING > MAX_CHANNEL as u64 {
+        return Err(DeviceError::InvalidFrequency);
+    }
+
+    Ok((offset / CHANNEL_SPACING) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+    use std::rc::Rc;
+
+    use flick_bridge::command::{Command, CommandResponse};
+    use flick_bridge::telemetry::TelemetrySnapshot;
+
+    use super::*;
+
+    /// Packet sent by the emulated transceiver.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Sent {
+        address: [u8; 5],
+        payload: Vec<u8>,
+        acknowledged: bool,
+    }
+
+    /// Registers and FIFOs of an emulated transceiver, recording every command.
+    struct Radio {
+        registers: [u8; 0x20],
+        tx_address: [u8; 5],
+        rx_address_p0: [u8; 5],
+        rx_address_p1: [u8; 5],
+        /// Whether the features were activated, which the original nRF24L01 needs.
+        activated: bool,
+        ce: bool,
+        tx_fifo: VecDeque<(Vec<u8>, bool)>,
+        rx_fifo: VecDeque<(u8, Vec<u8>)>,
+        /// Command of the transaction in progress.
+        command: Option<u8>,
+        /// Commands, with the bytes written.
+        log: Vec<(u8, Vec<u8>)>,
+        sent: Vec<Sent>,
+        /// Whether acknowledgements arrive.
+        acknowledging: bool,
+    }
+
+    impl Radio {
+        fn new() -> Rc<RefCell<Self>> {
+            Rc::new(RefCell::new(Self {
+                registers: [0; 0x20],
+                tx_address: [0; 5],
+                rx_address_p0: [0; 5],
+                rx_address_p1: [0; 5],
+                activated: true,
+                ce: false,
+                tx_fifo: VecDeque::new(),
+                rx_fifo: VecDeque::new(),
+                command: None,
+                log: Vec::new(),
+                sent: Vec::new(),
+                acknowledging: true,
+            }))
+        }
+
+        fn status(&self) -> u8 {
+            let pipe = self.rx_fifo.front().map_or(0x07, |(pipe, _)| *pipe);
+            self.registers[REG_STATUS as usize] & 0x70 | (pipe << 1)
+        }
+
+        fn register(&self, reg: u8) -> u8 {
+            match reg {
+                REG_STATUS => self.status(),
+                REG_FIFO_STATUS => u8::from(self.rx_fifo.is_empty()),
+                _ => self.registers[reg as usize],
+            }
+        }
+
+        /// Values written to a register, command after command.
+        fn writes(&self, reg: u8) -> Vec<Vec<u8>> {
+            self.log
+                .iter()
+                .filter(|(command, _)| *command == W_REGISTER | reg)
+                .map(|(_, data)| data.clone())
+                .collect()
+        }
+
+        /// Run the data phase of a command.
+        fn exchange(&mut self, command: u8, data: &mut [u8]) {
+            self.log.push((command, data.to_vec()));
+            match command {
+                R_RX_PL_WID => data[0] = self.rx_fifo.front().map_or(0, |(_, p)| p.len() as u8),
+                R_RX_PAYLOAD => {
+                    let (_, payload) = self.rx_fifo.pop_front().unwrap();
+                    data.copy_from_slice(&payload[..data.len()]);
+                }
+                W_TX_PAYLOAD | W_TX_PAYLOAD_NOACK => self
+                    .tx_fifo
+                    .push_back((data.to_vec(), command == W_TX_PAYLOAD)),
+                FLUSH_TX => self.tx_fifo.clear(),
+                FLUSH_RX => self.rx_fifo.clear(),
+                ACTIVATE => self.activated = data == [0x73],
+                _ if command & 0xe0 == R_REGISTER && !data.is_empty() => {
+                    data[0] = self.register(command & 0x1f)
+                }
+                _ if command & 0xe0 == W_REGISTER => {
+                    let reg = command & 0x1f;
+                    match reg {
+                        REG_TX_ADDR => self.tx_address.copy_from_slice(data),
+                        REG_RX_ADDR_P0 => self.rx_address_p0.copy_from_slice(data),
+                        REG_RX_ADDR_P1 => self.rx_address_p1.copy_from_slice(data),
+                        // Flags are cleared by writing ones.
+                        REG_STATUS => self.registers[reg as usize] &= !data[0],
+                        REG_FEATURE if !self.activated => {}
+                        _ => self.registers[reg as usize] = data[0],
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        /// Send the next payload on a pulse of CE while transmitting.
+        fn pulse(&mut self) {
+            if self.registers[REG_CONFIG as usize] & CONFIG_PRIM_RX != 0 {
+                return;
+            }
+            let (payload, acknowledged) = match self.tx_fifo.pop_front() {
+                Some(packet) => packet,
+                None => return,
+            };
+            self.sent.push(Sent {
+                address: self.tx_address,
+                payload,
+                acknowledged,
+            });
+            let done = match !acknowledged || self.acknowledging {
+                true => STATUS_TX_DS,
+                false => STATUS_MAX_RT,
+            };
+            self.registers[REG_STATUS as usize] |= done;
+        }
+
+        /// Packets sent, reassembled into messages.
+        fn messages(&self) -> Vec<(Sent, Message)> {
+            let mut reassembler = Reassembler::new();
+            self.sent
+                .iter()
+                .filter_map(|sent| {
+                    let frame = reassembler.push(&sent.payload).unwrap()?;
+                    Some((sent.clone(), frame::decode(frame).unwrap()))
+                })
+                .collect()
+        }
+    }
+
+    struct MockSpi(Rc<RefCell<Radio>>);
+
+    impl Transfer<u8> for MockSpi {
+        type Error = Infallible;
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Infallible> {
+            let mut radio = self.0.borrow_mut();
+            match radio.command {
+                None => {
+                    radio.command = Some(words[0]);
+                    words[0] = radio.status();
+                }
+                Some(command) => radio.exchange(command, words),
+            }
+            Ok(words)
+        }
+    }
+
+    /// Chip-select, which delimits the commands.
+    struct MockCs(Rc<RefCell<Radio>>);
+
+    impl OutputPin for MockCs {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().command = None;
+            Ok(())
+        }
+    }
+
+    /// Chip-enable, whose pulses start transmissions.
+    struct MockCe(Rc<RefCell<Radio>>);
+
+    impl OutputPin for MockCe {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            let mut radio = self.0.borrow_mut();
+            if radio.ce {
+                radio.pulse();
+            }
+            radio.ce = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().ce = true;
+            Ok(())
+        }
+    }
+
+    struct MockDelay;
+
+    impl DelayUs<u32> for MockDelay {
+        fn delay_us(&mut self, _us: u32) {}
+    }
+
+    type Radio24 = Nrf24<MockSpi, MockCs, MockCe, MockDelay>;
+
+    fn radio_with(state: &Rc<RefCell<Radio>>, config: NrfConfig) -> Result<Radio24, DeviceError> {
+        Nrf24::new(
+            MockSpi(state.clone()),
+            MockCs(state.clone()),
+            MockCe(state.clone()),
+            MockDelay,
+            config,
+        )
+    }
+
+    fn radio(config: NrfConfig) -> (Radio24, Rc<RefCell<Radio>>) {
+        let state = Radio::new();
+        let radio = radio_with(&state, config).unwrap();
+        state.borrow_mut().log.clear();
+        (radio, state)
+    }
+
+    fn message(id: u64, kind: MessageKind) -> Message {
+        Message::new(id, kind, 1_000, MessageFlags::empty())
+    }
+
+    /// Payloads of a message, as fragmented by the peer.
+    fn payloads(message: &Message, sequence: u8) -> Vec<Vec<u8>> {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let frame = frame::encode(message, &mut buf).unwrap();
+        fragments(frame, MAX_PAYLOAD_LEN, sequence)
+            .unwrap()
+            .map(|(header, chunk)| [&header.encode()[..], chunk].concat())
+            .collect()
+    }
+
+    #[test]
+    fn configures_the_transceiver() {
+        let state = Radio::new();
+        radio_with(&state, NrfConfig::default()).unwrap();
+        let state = state.borrow();
+
+        assert_eq!(state.registers[REG_FEATURE as usize], 0x05);
+        // Every pipe with dynamic payload lengths has auto-acknowledgement.
+        assert_eq!(state.registers[REG_DYNPD as usize], 0x07);
+        assert_eq!(state.registers[REG_EN_AA as usize], 0x07);
+        assert_eq!(state.registers[REG_SETUP_AW as usize], 0x03);
+        assert_eq!(state.rx_address_p1, *b"\x01FLCK");
+        assert_eq!(state.registers[REG_RX_ADDR_P2 as usize], BROADCAST);
+        assert_eq!(state.registers[REG_SETUP_RETR as usize], 0x5f);
+        assert_eq!(state.registers[REG_RF_SETUP as usize], 0x06);
+        assert_eq!(state.registers[REG_RF_CH as usize], 76);
+        assert_eq!(state.registers[REG_EN_RXADDR as usize], PIPE_1 | PIPE_2);
+        assert_eq!(state.registers[REG_CONFIG as usize], 0x0f);
+        assert!(state.ce);
+        assert!(!state.log.iter().any(|(command, _)| *command == ACTIVATE));
+    }
+
+    #[test]
+    fn activates_the_original_transceiver() {
+        let state = Radio::new();
+        state.borrow_mut().activated = false;
+        radio_with(&state, NrfConfig::default()).unwrap();
+        let state = state.borrow();
+
+        assert!(state.log.contains(&(ACTIVATE, vec![0x73])));
+        assert_eq!(state.registers[REG_FEATURE as usize], 0x05);
+    }
+
+    #[test]
+    fn keeps_auto_acknowledgement_without_retransmissions() {
+        let (radio, state) = radio(NrfConfig {
+            auto_ack: false,
+            ..NrfConfig::default()
+        });
+        {
+            let state = state.borrow();
+            assert_eq!(state.registers[REG_EN_AA as usize], 0x07);
+            assert_eq!(state.registers[REG_SETUP_RETR as usize], 0x00);
+        }
+
+        radio
+            .transmit(message(1, MessageKind::Request(Command::Heartbeat)))
+            .unwrap();
+        let state = state.borrow();
+        assert!(state.sent.iter().all(|sent| !sent.acknowledged));
+        assert!(state.writes(REG_EN_AA).is_empty());
+    }
+
+    #[test]
+    fn transmits_fragments_to_the_peer() {
+        let (radio, state) = radio(NrfConfig::default());
+        let kind = MessageKind::Response(3, CommandResponse::Done);
+        radio.transmit(message(42, kind)).unwrap();
+
+        let state = state.borrow();
+        assert_eq!(state.writes(REG_TX_ADDR), [b"\x02FLCK".to_vec()]);
+        // Acknowledgements come back on pipe 0.
+        assert_eq!(state.writes(REG_RX_ADDR_P0), [b"\x02FLCK".to_vec()]);
+        assert!(state.writes(REG_EN_AA).is_empty());
+        assert!(state
+            .sent
+            .iter()
+            .all(|sent| sent.acknowledged && sent.payload.len() <= MAX_PAYLOAD_LEN));
+
+        let messages = state.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].1.id(), 42);
+
+        // Back to receiving.
+        assert_eq!(state.registers[REG_CONFIG as usize], 0x0f);
+        assert_eq!(state.registers[REG_EN_RXADDR as usize], PIPE_1 | PIPE_2);
+        assert!(state.ce);
+    }
+
+    #[test]
+    fn broadcasts_pings_without_acknowledgement() {
+        let (radio, state) = radio(NrfConfig::default());
+        radio.ping(5, 2_450_000_000).unwrap();
+
+        let state = state.borrow();
+        assert!(!state.sent.is_empty());
+        assert!(state
+            .sent
+            .iter()
+            .all(|sent| !sent.acknowledged && sent.address == *b"\xffFLCK"));
+        assert!(state
+            .log
+            .iter()
+            .any(|(command, _)| *command == W_TX_PAYLOAD_NOACK));
+        assert!(state.writes(REG_EN_AA).is_empty());
+        assert!(matches!(
+            state.messages()[0].1.kind(),
+            MessageKind::Ping(5, 2_450_000_000)
+        ));
+    }
+
+    #[test]
+    fn reports_unacknowledged_fragments() {
+        let (radio, state) = radio(NrfConfig::default());
+        state.borrow_mut().acknowledging = false;
+
+        let result = radio.transmit(message(1, MessageKind::Request(Command::Heartbeat)));
+        assert_eq!(result, Err(DeviceError::NotAcknowledged));
+
+        let state = state.borrow();
+        // The rest of the message isn't sent.
+        assert_eq!(state.sent.len(), 1);
+        assert!(state.log.iter().any(|(command, _)| *command == FLUSH_TX));
+        assert_eq!(state.status() & (STATUS_MAX_RT | STATUS_TX_DS), 0);
+        assert_eq!(state.registers[REG_CONFIG as usize], 0x0f);
+    }
+
+    #[test]
+    fn reassembles_every_pipe_separately() {
+        let (radio, state) = radio(NrfConfig::default());
+        let snapshot = TelemetrySnapshot {
+            time: 1_000_000,
+            ..TelemetrySnapshot::default()
+        };
+        let direct = message(
+            1,
+            MessageKind::Response(0, CommandResponse::Telemetry(snapshot)),
+        );
+        let broadcast = Message::new(
+            u64::MAX,
+            MessageKind::Ping(u64::MAX, u64::MAX),
+            u64::MAX,
+            MessageFlags::empty(),
+        );
+        let direct = payloads(&direct, 0);
+        let broadcast = payloads(&broadcast, 0);
+        assert!(direct.len() > 1 && broadcast.len() > 1);
+
+        // Fragments of both messages arrive interleaved.
+        {
+            let mut state = state.borrow_mut();
+            let mut direct = direct.into_iter();
+            let mut broadcast = broadcast.into_iter();
+            loop {
+                let (a, b) = (direct.next(), broadcast.next());
+                if a.is_none() && b.is_none() {
+                    break;
+                }
+                state.rx_fifo.extend(a.map(|payload| (1, payload)));
+                state.rx_fifo.extend(b.map(|payload| (2, payload)));
+            }
+        }
+
+        let mut ids = Vec::new();
+        while let Some(message) = radio.poll().unwrap() {
+            ids.push(message.id());
+        }
+        ids.sort();
+        assert_eq!(ids, [1, u64::MAX]);
+        assert!(state.borrow().rx_fifo.is_empty());
+    }
+
+    #[test]
+    fn flushes_corrupted_payloads() {
+        let (radio, state) = radio(NrfConfig::default());
+        state.borrow_mut().rx_fifo.push_back((1, vec![0; 33]));
+
+        assert_eq!(radio.poll().unwrap_err(), DeviceError::InvalidMessage);
+        let state = state.borrow();
+        assert!(state.rx_fifo.is_empty());
+        assert!(state.log.iter().any(|(command, _)| *command == FLUSH_RX));
+    }
+
+    #[test]
+    fn sets_the_channel() {
+        let (mut radio, state) = radio(NrfConfig::default());
+        radio.set_freq(2_480_000_000).unwrap();
+        assert_eq!(radio.freq(), Ok(2_480_000_000));
+        assert_eq!(state.borrow().writes(REG_RF_CH), [vec![80]]);
+
+        assert_eq!(
+            radio.set_freq(2_480_500_000),
+            Err(DeviceError::InvalidFrequency)
+        );
+        assert_eq!(
+            radio.set_freq(2_600_000_000),
+            Err(DeviceError::InvalidFrequency)
+        );
+    }
+}
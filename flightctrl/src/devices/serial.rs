@@ -0,0 +1,270 @@
+//! Bridge device over a serial port, for transparent serial modems such as XBee, RFD900 or SiK
+//! radios.
+//!
+//! Every message is sent as a CRC-checked frame delimited with [`flick_bridge::frame::stream`]
+//! encoding, so the receiver resynchronizes after lost or corrupted bytes. The modem is
+//! configured separately, so pings are sent as broadcasts on the current link and the frequency
+//! can't be changed.
+
+use core::cell::RefCell;
+
+use embedded_hal::serial::{Read, Write};
+use flick_bridge::device::{Device, DeviceError};
+use flick_bridge::frame::stream::{self, StreamDecoder, MAX_ENCODED_LEN};
+use flick_bridge::frame::{self, MAX_FRAME_LEN};
+use flick_bridge::message::{Message, MessageFlags, MessageKind};
+
+/// A serial port used as a bridge device.
+pub struct SerialDevice<S> {
+    serial: RefCell<S>,
+    decoder: RefCell<StreamDecoder>,
+    /// Frequency the modem was configured with, in hertz.
+    freq: u64,
+}
+
+impl<S> SerialDevice<S>
+where
+    S: Read<u8> + Write<u8>,
+{
+    /// Instantiate a new [`SerialDevice`] over a serial port. `freq` is the frequency the modem
+    /// was configured with, in hertz, which is only reported by [`Device::freq`].
+    pub fn new(serial: S, freq: u64) -> Self {
+        Self {
+            serial: RefCell::new(serial),
+            decoder: RefCell::new(StreamDecoder::new()),
+            freq,
+        }
+    }
+
+    /// Release the serial port.
+    pub fn release(self) -> S {
+        self.serial.into_inner()
+    }
+
+    /// Send a message, blocking until it was written to the serial port.
+    fn send(&self, message: &Message) -> Result<(), DeviceError> {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let frame = frame::encode(message, &mut buf)?;
+        let mut encoded = [0; MAX_ENCODED_LEN];
+        let len = stream::encode(frame, &mut encoded)?;
+
+        let mut serial = self.serial.borrow_mut();
+        for &byte in &encoded[..len] {
+            nb::block!(serial.write(byte)).map_err(|_| DeviceError::Physical)?;
+        }
+        nb::block!(serial.flush()).map_err(|_| DeviceError::Physical)
+    }
+}
+
+impl<S> Device for SerialDevice<S>
+where
+    S: Read<u8> + Write<u8>,
+{
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        self.send(&message)
+    }
+
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        let mut serial = self.serial.borrow_mut();
+        let mut decoder = self.decoder.borrow_mut();
+        loop {
+            let byte = match serial.read() {
+                Ok(byte) => byte,
+                Err(nb::Error::WouldBlock) => return Ok(None),
+                Err(nb::Error::Other(_)) => return Err(DeviceError::Physical),
+            };
+
+            if let Some(frame) = decoder.push(byte)? {
+                return frame::decode(frame).map(Some).map_err(DeviceError::from);
+            }
+        }
+    }
+
+    fn ping(&self, id: u64, freq: u64) -> Result<(), DeviceError> {
+        let message = Message::new(id, MessageKind::Ping(id, freq), 0, MessageFlags::empty());
+        self.send(&message)
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        Ok(self.freq)
+    }
+
+    fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+        // The frequency is part of the modem's own configuration.
+        Err(DeviceError::InvalidFrequency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+    use std::rc::Rc;
+
+    use flick_bridge::command::Command;
+
+    use super::*;
+
+    /// Serial port whose written bytes can be read back, such as by a second port on the other
+    /// end of the link.
+    #[derive(Clone, Default)]
+    struct Loopback {
+        rx: Rc<RefCell<VecDeque<u8>>>,
+        tx: Rc<RefCell<VecDeque<u8>>>,
+        flushed: Rc<RefCell<usize>>,
+    }
+
+    impl Loopback {
+        /// Two ports wired to each other.
+        fn pair() -> (Self, Self) {
+            let a = Self::default();
+            let b = Self {
+                rx: a.tx.clone(),
+                tx: a.rx.clone(),
+                flushed: Default::default(),
+            };
+            (a, b)
+        }
+    }
+
+    impl Read<u8> for Loopback {
+        type Error = Infallible;
+
+        fn read(&mut self) -> nb::Result<u8, Infallible> {
+            self.rx
+                .borrow_mut()
+                .pop_front()
+                .ok_or(nb::Error::WouldBlock)
+        }
+    }
+
+    impl Write<u8> for Loopback {
+        type Error = Infallible;
+
+        fn write(&mut self, word: u8) -> nb::Result<(), Infallible> {
+            self.tx.borrow_mut().push_back(word);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> nb::Result<(), Infallible> {
+            *self.flushed.borrow_mut() += 1;
+            Ok(())
+        }
+    }
+
+    fn message(id: u64) -> Message {
+        Message::new(
+            id,
+            MessageKind::Request(Command::FirePyro(2)),
+            123_456,
+            MessageFlags::REQUIRES_ACK,
+        )
+    }
+
+    #[test]
+    fn round_trips_messages() {
+        let (a, b) = Loopback::pair();
+        let flushed = a.flushed.clone();
+        let vehicle = SerialDevice::new(a, 915_000_000);
+        let ground = SerialDevice::new(b, 915_000_000);
+
+        assert!(ground.poll().unwrap().is_none());
+        vehicle.transmit(message(1)).unwrap();
+        vehicle.transmit(message(2)).unwrap();
+        assert_eq!(*flushed.borrow(), 2);
+
+        for id in [1, 2] {
+            let received = ground.poll().unwrap().unwrap();
+            assert_eq!(received.id(), id);
+            assert_eq!(received.send_time(), 123_456);
+            assert_eq!(received.flags(), MessageFlags::REQUIRES_ACK);
+            assert!(matches!(
+                received.kind(),
+                MessageKind::Request(Command::FirePyro(2))
+            ));
+        }
+        assert!(ground.poll().unwrap().is_none());
+    }
+
+    #[test]
+    fn frames_with_cobs() {
+        let (a, b) = Loopback::pair();
+        let vehicle = SerialDevice::new(a, 0);
+        vehicle.transmit(message(1)).unwrap();
+
+        let mut buf = [0; MAX_FRAME_LEN];
+        let frame = frame::encode(&message(1), &mut buf).unwrap();
+        let mut encoded = [0; MAX_ENCODED_LEN];
+        let len = stream::encode(frame, &mut encoded).unwrap();
+        let written: Vec<u8> = b.rx.borrow().iter().copied().collect();
+        assert_eq!(written, encoded[..len]);
+        // The delimiter is the only zero.
+        assert_eq!(written.iter().filter(|&&byte| byte == 0).count(), 1);
+    }
+
+    #[test]
+    fn receives_frames_split_across_polls() {
+        let (a, b) = Loopback::pair();
+        let vehicle = SerialDevice::new(a, 0);
+        let ground = SerialDevice::new(b.clone(), 0);
+        vehicle.transmit(message(7)).unwrap();
+
+        // Only part of the frame arrived yet.
+        let rest: Vec<u8> = {
+            let mut rx = b.rx.borrow_mut();
+            let half = rx.len() / 2;
+            rx.drain(half..).collect()
+        };
+        assert!(ground.poll().unwrap().is_none());
+
+        b.rx.borrow_mut().extend(rest);
+        assert_eq!(ground.poll().unwrap().unwrap().id(), 7);
+    }
+
+    #[test]
+    fn resynchronizes_after_corruption() {
+        let (a, b) = Loopback::pair();
+        let vehicle = SerialDevice::new(a, 0);
+        let ground = SerialDevice::new(b.clone(), 0);
+
+        // Noise up to a delimiter before the first frame, and a corrupted byte in the second.
+        b.rx.borrow_mut().extend([0x42, 0x17, 0x99, 0x00]);
+        vehicle.transmit(message(1)).unwrap();
+        let start = b.rx.borrow().len();
+        vehicle.transmit(message(2)).unwrap();
+        b.rx.borrow_mut()[start + 4] ^= 0x01;
+        vehicle.transmit(message(3)).unwrap();
+
+        let mut ids = Vec::new();
+        let mut errors = 0;
+        loop {
+            match ground.poll() {
+                Ok(Some(message)) => ids.push(message.id()),
+                Ok(None) => break,
+                Err(DeviceError::InvalidMessage) => errors += 1,
+                Err(err) => panic!("unexpected error: {}", err),
+            }
+        }
+        assert_eq!(ids, [1, 3]);
+        assert!(errors >= 1);
+    }
+
+    #[test]
+    fn pings_on_the_current_link() {
+        let (a, b) = Loopback::pair();
+        let mut vehicle = SerialDevice::new(a, 433_000_000);
+        let ground = SerialDevice::new(b, 433_000_000);
+
+        vehicle.ping(9, 433_000_000).unwrap();
+        assert!(matches!(
+            ground.poll().unwrap().unwrap().kind(),
+            MessageKind::Ping(9, 433_000_000)
+        ));
+        assert_eq!(vehicle.freq(), Ok(433_000_000));
+        assert_eq!(
+            vehicle.set_freq(915_000_000),
+            Err(DeviceError::InvalidFrequency)
+        );
+    }
+}
@@ -0,0 +1,962 @@
+//! Driver for Semtech SX1276/SX1278 LoRa transceivers (as found on the RFM95/RFM98 modules).
+//!
+//! Every bridge message is sent as a single LoRa packet, relying on the hardware CRC to discard
+//! corrupted packets. Transmission doesn't block: [`Device::transmit`] starts sending the packet,
+//! and the radio goes back to receiving once [`Device::poll`] sees that it was sent.
+//! See the SX1276/77/78/79 datasheet, section 4.1 (LoRa modem).
+
+use core::cell::{Cell, RefCell};
+
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::OutputPin;
+use flick_bridge::device::{Device, DeviceError, SignalQuality};
+use flick_bridge::message::{Message, MessageFlags, MessageKind, MAX_MESSAGE_LEN};
+
+const REG_FIFO: u8 = 0x00;
+const REG_OP_MODE: u8 = 0x01;
+const REG_FRF_MSB: u8 = 0x06;
+const REG_PA_CONFIG: u8 = 0x09;
+const REG_LNA: u8 = 0x0c;
+const REG_FIFO_ADDR_PTR: u8 = 0x0d;
+const REG_FIFO_TX_BASE_ADDR: u8 = 0x0e;
+const REG_FIFO_RX_BASE_ADDR: u8 = 0x0f;
+const REG_FIFO_RX_CURRENT_ADDR: u8 = 0x10;
+const REG_IRQ_FLAGS: u8 = 0x12;
+const REG_RX_NB_BYTES: u8 = 0x13;
+const REG_PKT_SNR_VALUE: u8 = 0x19;
+const REG_PKT_RSSI_VALUE: u8 = 0x1a;
+const REG_RSSI_VALUE: u8 = 0x1b;
+const REG_MODEM_CONFIG_1: u8 = 0x1d;
+const REG_MODEM_CONFIG_2: u8 = 0x1e;
+const REG_PREAMBLE_MSB: u8 = 0x20;
+const REG_PAYLOAD_LENGTH: u8 = 0x22;
+const REG_MODEM_CONFIG_3: u8 = 0x26;
+const REG_DETECTION_OPTIMIZE: u8 = 0x31;
+const REG_DETECTION_THRESHOLD: u8 = 0x37;
+const REG_SYNC_WORD: u8 = 0x39;
+const REG_VERSION: u8 = 0x42;
+const REG_PA_DAC: u8 = 0x4d;
+
+const MODE_LONG_RANGE: u8 = 0x80;
+const MODE_SLEEP: u8 = 0x00;
+const MODE_STANDBY: u8 = 0x01;
+const MODE_TX: u8 = 0x03;
+const MODE_RX_CONTINUOUS: u8 = 0x05;
+
+const IRQ_TX_DONE: u8 = 0x08;
+const IRQ_PAYLOAD_CRC_ERROR: u8 = 0x20;
+const IRQ_RX_DONE: u8 = 0x40;
+
+/// Silicon revision reported by every SX1276/77/78/79.
+const VERSION: u8 = 0x12;
+/// Frequency of the crystal oscillator.
+const OSCILLATOR_FREQ: u64 = 32_000_000;
+/// Largest payload of a packet.
+const MAX_PACKET_LEN: usize = 255;
+/// Longest time to wait for a previous packet to be sent, in milliseconds.
+const TX_TIMEOUT_MS: u32 = 5_000;
+
+/// Model of the transceiver, which determines the frequencies it can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    /// SX1276, covering 137 to 1020 MHz.
+    Sx1276,
+    /// SX1278, covering 137 to 525 MHz.
+    Sx1278,
+}
+
+impl Chip {
+    /// Whether the chip can use the given frequency, in hertz.
+    fn supports(&self, freq: u64) -> bool {
+        match self {
+            Chip::Sx1276 => (137_000_000..=1_020_000_000).contains(&freq),
+            Chip::Sx1278 => (137_000_000..=525_000_000).contains(&freq),
+        }
+    }
+}
+
+/// Spreading factor. Higher spreading factors reach further, but take longer to send.
+///
+/// Spreading factor 6 isn't supported: it only works with the implicit header, which needs every
+/// packet to have the same length, while messages don't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpreadingFactor {
+    Sf7 = 7,
+    Sf8 = 8,
+    Sf9 = 9,
+    Sf10 = 10,
+    Sf11 = 11,
+    Sf12 = 12,
+}
+
+/// Signal bandwidth. Narrower bandwidths reach further, but take longer to send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bandwidth {
+    Khz7_8,
+    Khz10_4,
+    Khz15_6,
+    Khz20_8,
+    Khz31_25,
+    Khz41_7,
+    Khz62_5,
+    Khz125,
+    Khz250,
+    Khz500,
+}
+
+impl Bandwidth {
+    /// Bandwidth in hertz.
+    fn hz(&self) -> u32 {
+        match self {
+            Bandwidth::Khz7_8 => 7_800,
+            Bandwidth::Khz10_4 => 10_400,
+            Bandwidth::Khz15_6 => 15_600,
+            Bandwidth::Khz20_8 => 20_800,
+            Bandwidth::Khz31_25 => 31_250,
+            Bandwidth::Khz41_7 => 41_700,
+            Bandwidth::Khz62_5 => 62_500,
+            Bandwidth::Khz125 => 125_000,
+            Bandwidth::Khz250 => 250_000,
+            Bandwidth::Khz500 => 500_000,
+        }
+    }
+}
+
+/// Forward error correction coding rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodingRate {
+    Cr4_5 = 1,
+    Cr4_6 = 2,
+    Cr4_7 = 3,
+    Cr4_8 = 4,
+}
+
+/// Configuration of the LoRa modem. Both ends of the link must use the same configuration.
+#[derive(Debug, Clone, Copy)]
+pub struct LoraConfig {
+    /// Carrier frequency, in hertz.
+    pub freq: u64,
+    pub spreading_factor: SpreadingFactor,
+    pub bandwidth: Bandwidth,
+    pub coding_rate: CodingRate,
+    /// Output power on the PA_BOOST pin, from 2 to 17 dBm, or 20 dBm.
+    pub tx_power: u8,
+    /// Number of preamble symbols.
+    pub preamble_len: u16,
+    /// Sync word, used to ignore packets from other LoRa networks.
+    pub sync_word: u8,
+}
+
+impl Default for LoraConfig {
+    fn default() -> Self {
+        Self {
+            freq: 433_000_000,
+            spreading_factor: SpreadingFactor::Sf9,
+            bandwidth: Bandwidth::Khz125,
+            coding_rate: CodingRate::Cr4_5,
+            tx_power: 17,
+            preamble_len: 8,
+            sync_word: 0x12,
+        }
+    }
+}
+
+/// SPI bus, chip-select and delay of the transceiver.
+struct Bus<SPI, CS, DELAY> {
+    spi: SPI,
+    cs: CS,
+    delay: DELAY,
+}
+
+impl<SPI, CS, DELAY, E> Bus<SPI, CS, DELAY>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: OutputPin,
+{
+    fn read(&mut self, reg: u8) -> Result<u8, DeviceError> {
+        let mut buf = [0];
+        self.read_burst(reg, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn write(&mut self, reg: u8, value: u8) -> Result<(), DeviceError> {
+        self.write_burst(reg, &[value])
+    }
+
+    fn read_burst(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), DeviceError> {
+        self.cs.set_low().map_err(|_| DeviceError::Physical)?;
+        let result = self
+            .spi
+            .write(&[reg & 0x7f])
+            .and_then(|_| self.spi.transfer(buf).map(|_| ()));
+        self.cs.set_high().map_err(|_| DeviceError::Physical)?;
+        result.map_err(|_| DeviceError::Physical)
+    }
+
+    fn write_burst(&mut self, reg: u8, data: &[u8]) -> Result<(), DeviceError> {
+        self.cs.set_low().map_err(|_| DeviceError::Physical)?;
+        let result = self
+            .spi
+            .write(&[reg | 0x80])
+            .and_then(|_| self.spi.write(data));
+        self.cs.set_high().map_err(|_| DeviceError::Physical)?;
+        result.map_err(|_| DeviceError::Physical)
+    }
+
+    fn set_mode(&mut self, mode: u8) -> Result<(), DeviceError> {
+        self.write(REG_OP_MODE, MODE_LONG_RANGE | mode)
+    }
+}
+
+/// A SX1276/SX1278 transceiver used as a bridge device.
+pub struct Sx127x<SPI, CS, DELAY> {
+    bus: RefCell<Bus<SPI, CS, DELAY>>,
+    chip: Chip,
+    config: LoraConfig,
+    /// Whether a packet is being sent.
+    transmitting: Cell<bool>,
+    /// Quality of the last received packet.
+    quality: Cell<Option<SignalQuality>>,
+}
+
+impl<SPI, CS, DELAY, E> Sx127x<SPI, CS, DELAY>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: OutputPin,
+    DELAY: DelayMs<u32>,
+{
+    /// Reset and configure the transceiver, and start receiving.
+    pub fn new<RESET: OutputPin>(
+        spi: SPI,
+        cs: CS,
+        mut reset: RESET,
+        delay: DELAY,
+        chip: Chip,
+        config: LoraConfig,
+    ) -> Result<Self, DeviceError> {
+        let mut bus = Bus { spi, cs, delay };
+        bus.cs.set_high().map_err(|_| DeviceError::Physical)?;
+
+        reset.set_low().map_err(|_| DeviceError::Physical)?;
+        bus.delay.delay_ms(1);
+        reset.set_high().map_err(|_| DeviceError::Physical)?;
+        bus.delay.delay_ms(10);
+
+        if bus.read(REG_VERSION)? != VERSION {
+            return Err(DeviceError::Physical);
+        }
+
+        let mut radio = Self {
+            bus: RefCell::new(bus),
+            chip,
+            config,
+            transmitting: Cell::new(false),
+            quality: Cell::new(None),
+        };
+        radio.configure()?;
+
+        Ok(radio)
+    }
+
+    /// Apply the whole configuration, once the packet being sent is done. The modem can only be
+    /// switched to LoRa while sleeping.
+    fn configure(&mut self) -> Result<(), DeviceError> {
+        let config = self.config;
+        if !self.chip.supports(config.freq) {
+            return Err(DeviceError::InvalidFrequency);
+        }
+
+        self.finish_transmit(&mut self.bus.borrow_mut())?;
+        let bus = self.bus.get_mut();
+        bus.set_mode(MODE_SLEEP)?;
+        bus.set_mode(MODE_STANDBY)?;
+        Self::write_freq(bus, config.freq)?;
+        bus.write(REG_FIFO_TX_BASE_ADDR, 0)?;
+        bus.write(REG_FIFO_RX_BASE_ADDR, 0)?;
+
+        // Maximum LNA gain with boost for the high-frequency port.
+        let lna = bus.read(REG_LNA)?;
+        bus.write(REG_LNA, lna | 0x03)?;
+
+        bus.write(
+            REG_MODEM_CONFIG_1,
+            (bandwidth_bits(config.bandwidth) << 4) | ((config.coding_rate as u8) << 1),
+        )?;
+        // Payload CRC on.
+        bus.write(
+            REG_MODEM_CONFIG_2,
+            ((config.spreading_factor as u8) << 4) | 0x04,
+        )?;
+        // Automatic gain control, and low data rate optimization when symbols are longer than
+        // 16 ms, as required by the datasheet.
+        let symbol_us =
+            (1_000_000u64 << config.spreading_factor as u8) / config.bandwidth.hz() as u64;
+        let low_data_rate = if symbol_us > 16_000 { 0x08 } else { 0 };
+        bus.write(REG_MODEM_CONFIG_3, 0x04 | low_data_rate)?;
+
+        // Detection settings of spreading factors 7 to 12.
+        bus.write(REG_DETECTION_OPTIMIZE, 0xc3)?;
+        bus.write(REG_DETECTION_THRESHOLD, 0x0a)?;
+
+        bus.write_burst(REG_PREAMBLE_MSB, &config.preamble_len.to_be_bytes())?;
+        bus.write(REG_SYNC_WORD, config.sync_word)?;
+
+        // 20 dBm needs the high-power DAC, and is otherwise limited to 17 dBm on PA_BOOST.
+        let power = config.tx_power.clamp(2, 20);
+        if power > 17 {
+            bus.write(REG_PA_DAC, 0x87)?;
+            bus.write(REG_PA_CONFIG, 0x80 | (power - 5))?;
+        } else {
+            bus.write(REG_PA_DAC, 0x84)?;
+            bus.write(REG_PA_CONFIG, 0x80 | (power - 2))?;
+        }
+
+        bus.set_mode(MODE_RX_CONTINUOUS)?;
+        self.transmitting.set(false);
+        Ok(())
+    }
+
+    fn write_freq(bus: &mut Bus<SPI, CS, DELAY>, freq: u64) -> Result<(), DeviceError> {
+        let frf = (freq << 19) / OSCILLATOR_FREQ;
+        bus.write_burst(REG_FRF_MSB, &(frf as u32).to_be_bytes()[1..])
+    }
+
+    /// Change the spreading factor, bandwidth and coding rate.
+    pub fn set_modulation(
+        &mut self,
+        spreading_factor: SpreadingFactor,
+        bandwidth: Bandwidth,
+        coding_rate: CodingRate,
+    ) -> Result<(), DeviceError> {
+        self.config.spreading_factor = spreading_factor;
+        self.config.bandwidth = bandwidth;
+        self.config.coding_rate = coding_rate;
+        self.configure()
+    }
+
+    /// Current configuration of the modem.
+    pub fn config(&self) -> &LoraConfig {
+        &self.config
+    }
+
+    /// Current received signal strength on the channel, in dBm.
+    pub fn rssi(&self) -> Result<i16, DeviceError> {
+        let value = self.bus.borrow_mut().read(REG_RSSI_VALUE)?;
+        Ok(self.rssi_offset() + value as i16)
+    }
+
+    /// Offset of RSSI readings, which depends on the port used by the frequency.
+    fn rssi_offset(&self) -> i16 {
+        if self.config.freq > 525_000_000 {
+            -157
+        } else {
+            -164
+        }
+    }
+
+    /// Check whether the packet being sent is done, and if so, go back to receiving. Returns
+    /// whether the radio is still transmitting.
+    fn check_transmit(&self, bus: &mut Bus<SPI, CS, DELAY>) -> Result<bool, DeviceError> {
+        if !self.transmitting.get() {
+            return Ok(false);
+        }
+        if bus.read(REG_IRQ_FLAGS)? & IRQ_TX_DONE == 0 {
+            return Ok(true);
+        }
+
+        bus.write(REG_IRQ_FLAGS, IRQ_TX_DONE)?;
+        bus.set_mode(MODE_RX_CONTINUOUS)?;
+        self.transmitting.set(false);
+        Ok(false)
+    }
+
+    /// Wait for the packet being sent to be done, if there is one.
+    fn finish_transmit(&self, bus: &mut Bus<SPI, CS, DELAY>) -> Result<(), DeviceError> {
+        let mut waited = 0;
+        while self.check_transmit(bus)? {
+            if waited >= TX_TIMEOUT_MS {
+                return Err(DeviceError::Physical);
+            }
+            bus.delay.delay_ms(1);
+            waited += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Send a raw packet, waiting for the previous one to be sent first.
+    fn send_packet(&self, packet: &[u8]) -> Result<(), DeviceError> {
+        let mut bus = self.bus.borrow_mut();
+        self.finish_transmit(&mut bus)?;
+
+        bus.set_mode(MODE_STANDBY)?;
+        bus.write(REG_FIFO_ADDR_PTR, 0)?;
+        bus.write_burst(REG_FIFO, packet)?;
+        bus.write(REG_PAYLOAD_LENGTH, packet.len() as u8)?;
+        bus.set_mode(MODE_TX)?;
+        self.transmitting.set(true);
+
+        Ok(())
+    }
+}
+
+impl<SPI, CS, DELAY, E> Device for Sx127x<SPI, CS, DELAY>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: OutputPin,
+    DELAY: DelayMs<u32>,
+{
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let packet = message.encode(&mut buf)?;
+        if packet.len() > MAX_PACKET_LEN {
+            return Err(DeviceError::InvalidMessage);
+        }
+
+        self.send_packet(packet)
+    }
+
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        let mut bus = self.bus.borrow_mut();
+        if self.check_transmit(&mut bus)? {
+            return Ok(None);
+        }
+
+        let flags = bus.read(REG_IRQ_FLAGS)?;
+        if flags & IRQ_RX_DONE == 0 {
+            return Ok(None);
+        }
+        bus.write(REG_IRQ_FLAGS, IRQ_RX_DONE | IRQ_PAYLOAD_CRC_ERROR)?;
+        if flags & IRQ_PAYLOAD_CRC_ERROR != 0 {
+            return Err(DeviceError::InvalidMessage);
+        }
+
+        let len = bus.read(REG_RX_NB_BYTES)? as usize;
+        let addr = bus.read(REG_FIFO_RX_CURRENT_ADDR)?;
+        bus.write(REG_FIFO_ADDR_PTR, addr)?;
+        let mut buf = [0; MAX_PACKET_LEN];
+        bus.read_burst(REG_FIFO, &mut buf[..len])?;
+
+        let snr = bus.read(REG_PKT_SNR_VALUE)? as i8;
+        let rssi = bus.read(REG_PKT_RSSI_VALUE)?;
+        self.quality.set(Some(SignalQuality {
+            rssi: self.rssi_offset() + rssi as i16,
+            snr,
+        }));
+
+        Message::decode(&buf[..len]).map(Some)
+    }
+
+    fn ping(&self, id: u64, freq: u64) -> Result<(), DeviceError> {
+        if !self.chip.supports(freq) {
+            return Err(DeviceError::InvalidFrequency);
+        }
+
+        let message = Message::new(id, MessageKind::Ping(id, freq), 0, MessageFlags::empty());
+        self.transmit(message)
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        Ok(self.config.freq)
+    }
+
+    fn set_freq(&mut self, freq: u64) -> Result<(), DeviceError> {
+        if !self.chip.supports(freq) {
+            return Err(DeviceError::InvalidFrequency);
+        }
+
+        // Changing the frequency would cut the packet being sent short.
+        self.finish_transmit(&mut self.bus.borrow_mut())?;
+        let bus = self.bus.get_mut();
+        bus.set_mode(MODE_STANDBY)?;
+        Self::write_freq(bus, freq)?;
+        bus.set_mode(MODE_RX_CONTINUOUS)?;
+        self.config.freq = freq;
+        Ok(())
+    }
+
+    fn signal_quality(&self) -> Option<SignalQuality> {
+        self.quality.get()
+    }
+}
+
+/// Value of the bandwidth field of `REG_MODEM_CONFIG_1`.
+fn bandwidth_bits(bandwidth: Bandwidth) -> u8 {
+    match bandwidth {
+        Bandwidth::Khz7_8 => 0,
+        Bandwidth::Khz10_4 => 1,
+        Bandwidth::Khz15_6 => 2,
+        Bandwidth::Khz20_8 => 3,
+        Bandwidth::Khz31_25 => 4,
+        Bandwidth::Khz41_7 => 5,
+        Bandwidth::Khz62_5 => 6,
+        Bandwidth::Khz125 => 7,
+        Bandwidth::Khz250 => 8,
+        Bandwidth::Khz500 => 9,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::convert::Infallible;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// Register access of a single SPI transaction.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Access {
+        Read(u8, Vec<u8>),
+        Write(u8, Vec<u8>),
+    }
+
+    /// Registers and FIFO of an emulated transceiver, recording every access.
+    struct Radio {
+        registers: [u8; 0x80],
+        fifo: [u8; 256],
+        /// Access of the transaction in progress.
+        access: Option<Access>,
+        /// Register that the transaction in progress is at.
+        address: u8,
+        log: Vec<Access>,
+        /// Milliseconds until a packet being sent is done, or `None` if it never is.
+        tx_time: Option<u32>,
+        tx_elapsed: u32,
+        /// Packets sent.
+        sent: Vec<Vec<u8>>,
+    }
+
+    impl Radio {
+        fn new() -> Rc<RefCell<Self>> {
+            let mut registers = [0; 0x80];
+            registers[REG_VERSION as usize] = VERSION;
+            Rc::new(RefCell::new(Self {
+                registers,
+                fifo: [0; 256],
+                access: None,
+                address: 0,
+                log: Vec::new(),
+                tx_time: Some(0),
+                tx_elapsed: 0,
+                sent: Vec::new(),
+            }))
+        }
+
+        fn mode(&self) -> u8 {
+            self.registers[REG_OP_MODE as usize] & 0x07
+        }
+
+        /// Data written to a register, transaction after transaction.
+        fn writes(&self, reg: u8) -> Vec<Vec<u8>> {
+            self.log
+                .iter()
+                .filter_map(|access| match access {
+                    Access::Write(r, data) if *r == reg => Some(data.clone()),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        /// Position in the log of the first write of a value to a register.
+        fn position(&self, reg: u8, data: &[u8]) -> Option<usize> {
+            self.log
+                .iter()
+                .position(|access| *access == Access::Write(reg, data.to_vec()))
+        }
+
+        /// Byte read from or written to a register, advancing the FIFO pointer.
+        fn byte(&mut self, reg: u8) -> &mut u8 {
+            if reg == REG_FIFO {
+                let ptr = &mut self.registers[REG_FIFO_ADDR_PTR as usize];
+                let addr = *ptr;
+                *ptr = ptr.wrapping_add(1);
+                &mut self.fifo[addr as usize]
+            } else {
+                &mut self.registers[reg as usize]
+            }
+        }
+
+        fn write(&mut self, reg: u8, value: u8) {
+            match reg {
+                // Flags are cleared by writing ones.
+                REG_IRQ_FLAGS => self.registers[reg as usize] &= !value,
+                REG_OP_MODE => {
+                    self.registers[reg as usize] = value;
+                    if value & 0x07 == MODE_TX {
+                        let len = self.registers[REG_PAYLOAD_LENGTH as usize] as usize;
+                        self.sent.push(self.fifo[..len].to_vec());
+                        self.tx_elapsed = 0;
+                        self.update_tx();
+                    }
+                }
+                _ => *self.byte(reg) = value,
+            }
+        }
+
+        fn update_tx(&mut self) {
+            if self.mode() == MODE_TX && self.tx_time.is_some_and(|time| self.tx_elapsed >= time) {
+                self.registers[REG_IRQ_FLAGS as usize] |= IRQ_TX_DONE;
+                self.registers[REG_OP_MODE as usize] =
+                    self.registers[REG_OP_MODE as usize] & !0x07 | MODE_STANDBY;
+            }
+        }
+    }
+
+    struct MockSpi(Rc<RefCell<Radio>>);
+
+    impl Write<u8> for MockSpi {
+        type Error = Infallible;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+            let mut radio = self.0.borrow_mut();
+            let mut words = words;
+            if radio.access.is_none() {
+                let reg = words[0] & 0x7f;
+                radio.access = Some(match words[0] & 0x80 {
+                    0 => Access::Read(reg, Vec::new()),
+                    _ => Access::Write(reg, Vec::new()),
+                });
+                radio.address = reg;
+                words = &words[1..];
+            }
+            for &word in words {
+                match radio.access.as_mut() {
+                    Some(Access::Write(_, data)) => data.push(word),
+                    _ => panic!("data written in a read"),
+                }
+                let reg = radio.address;
+                radio.write(reg, word);
+                // Bursts go on to the next register, except for the FIFO.
+                if reg != REG_FIFO {
+                    radio.address += 1;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Transfer<u8> for MockSpi {
+        type Error = Infallible;
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Infallible> {
+            let mut radio = self.0.borrow_mut();
+            if !matches!(radio.access, Some(Access::Read(..))) {
+                panic!("transfer outside of a read");
+            }
+            for word in words.iter_mut() {
+                let reg = radio.address;
+                *word = *radio.byte(reg);
+                if let Some(Access::Read(_, data)) = radio.access.as_mut() {
+                    data.push(*word);
+                }
+                if reg != REG_FIFO {
+                    radio.address += 1;
+                }
+            }
+            Ok(words)
+        }
+    }
+
+    /// Chip-select, which delimits the transactions.
+    struct MockCs(Rc<RefCell<Radio>>);
+
+    impl OutputPin for MockCs {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            let mut radio = self.0.borrow_mut();
+            if let Some(access) = radio.access.take() {
+                radio.log.push(access);
+            }
+            Ok(())
+        }
+    }
+
+    struct MockPin;
+
+    impl OutputPin for MockPin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    /// Delay, during which the packet being sent goes on.
+    struct MockDelay(Rc<RefCell<Radio>>);
+
+    impl DelayMs<u32> for MockDelay {
+        fn delay_ms(&mut self, ms: u32) {
+            let mut radio = self.0.borrow_mut();
+            radio.tx_elapsed += ms;
+            radio.update_tx();
+        }
+    }
+
+    type Radio127x = Sx127x<MockSpi, MockCs, MockDelay>;
+
+    fn radio(config: LoraConfig) -> (Radio127x, Rc<RefCell<Radio>>) {
+        let state = Radio::new();
+        let radio = Sx127x::new(
+            MockSpi(state.clone()),
+            MockCs(state.clone()),
+            MockPin,
+            MockDelay(state.clone()),
+            Chip::Sx1276,
+            config,
+        )
+        .unwrap();
+        state.borrow_mut().log.clear();
+        (radio, state)
+    }
+
+    fn message(id: u64) -> Message {
+        Message::new(
+            id,
+            MessageKind::Request(flick_bridge::command::Command::Heartbeat),
+            1_000,
+            MessageFlags::empty(),
+        )
+    }
+
+    #[test]
+    fn configures_the_modem() {
+        let state = Radio::new();
+        Sx127x::new(
+            MockSpi(state.clone()),
+            MockCs(state.clone()),
+            MockPin,
+            MockDelay(state.clone()),
+            Chip::Sx1276,
+            LoraConfig::default(),
+        )
+        .unwrap();
+        let state = state.borrow();
+
+        assert_eq!(state.log[0], Access::Read(REG_VERSION, vec![VERSION]));
+        // LoRa is switched on while sleeping, and the radio ends up receiving.
+        assert_eq!(
+            state.writes(REG_OP_MODE),
+            [[0x80], [0x81], [0x85]].map(|mode| mode.to_vec())
+        );
+        // 433 MHz in steps of 32 MHz / 2^19.
+        assert_eq!(state.writes(REG_FRF_MSB), [vec![0x6c, 0x40, 0x00]]);
+        // 125 kHz, 4/5, explicit header.
+        assert_eq!(state.writes(REG_MODEM_CONFIG_1), [vec![0x72]]);
+        // Spreading factor 9, payload CRC on.
+        assert_eq!(state.writes(REG_MODEM_CONFIG_2), [vec![0x94]]);
+        // Automatic gain control, without the low data rate optimization.
+        assert_eq!(state.writes(REG_MODEM_CONFIG_3), [vec![0x04]]);
+        assert_eq!(state.writes(REG_DETECTION_OPTIMIZE), [vec![0xc3]]);
+        assert_eq!(state.writes(REG_DETECTION_THRESHOLD), [vec![0x0a]]);
+        assert_eq!(state.writes(REG_PREAMBLE_MSB), [vec![0x00, 0x08]]);
+        assert_eq!(state.writes(REG_SYNC_WORD), [vec![0x12]]);
+        // 17 dBm on PA_BOOST.
+        assert_eq!(state.writes(REG_PA_DAC), [vec![0x84]]);
+        assert_eq!(state.writes(REG_PA_CONFIG), [vec![0x8f]]);
+        assert_eq!(state.registers[REG_LNA as usize], 0x03);
+    }
+
+    #[test]
+    fn configures_slow_modulations() {
+        let (mut radio, state) = radio(LoraConfig {
+            tx_power: 20,
+            ..LoraConfig::default()
+        });
+        radio
+            .set_modulation(SpreadingFactor::Sf12, Bandwidth::Khz125, CodingRate::Cr4_8)
+            .unwrap();
+        let state = state.borrow();
+
+        assert_eq!(state.writes(REG_MODEM_CONFIG_1), [vec![0x78]]);
+        assert_eq!(state.writes(REG_MODEM_CONFIG_2), [vec![0xc4]]);
+        // Symbols of 32 ms need the low data rate optimization.
+        assert_eq!(state.writes(REG_MODEM_CONFIG_3), [vec![0x0c]]);
+        // 20 dBm needs the high-power DAC.
+        assert_eq!(state.writes(REG_PA_DAC), [vec![0x87]]);
+        assert_eq!(state.writes(REG_PA_CONFIG), [vec![0x8f]]);
+    }
+
+    #[test]
+    fn rejects_unsupported_frequencies() {
+        let state = Radio::new();
+        let result = Sx127x::new(
+            MockSpi(state.clone()),
+            MockCs(state.clone()),
+            MockPin,
+            MockDelay(state.clone()),
+            Chip::Sx1278,
+            LoraConfig {
+                freq: 915_000_000,
+                ..LoraConfig::default()
+            },
+        );
+        assert!(matches!(result, Err(DeviceError::InvalidFrequency)));
+
+        let (mut radio, state) = radio(LoraConfig::default());
+        assert_eq!(
+            radio.set_freq(1_100_000_000),
+            Err(DeviceError::InvalidFrequency)
+        );
+        assert!(state.borrow().log.is_empty());
+    }
+
+    #[test]
+    fn sets_the_frequency() {
+        let (mut radio, state) = radio(LoraConfig::default());
+        radio.set_freq(434_000_000).unwrap();
+
+        let state = state.borrow();
+        assert_eq!(
+            state.log,
+            [
+                Access::Write(REG_OP_MODE, vec![0x81]),
+                Access::Write(REG_FRF_MSB, vec![0x6c, 0x80, 0x00]),
+                Access::Write(REG_OP_MODE, vec![0x85]),
+            ]
+        );
+        assert_eq!(radio.freq(), Ok(434_000_000));
+    }
+
+    #[test]
+    fn sets_the_frequency_once_the_packet_is_sent() {
+        let (mut radio, state) = radio(LoraConfig::default());
+        state.borrow_mut().tx_time = Some(3);
+        radio.transmit(message(1)).unwrap();
+        radio.set_freq(434_000_000).unwrap();
+
+        let state = state.borrow();
+        let tx_done = state
+            .position(REG_IRQ_FLAGS, &[IRQ_TX_DONE])
+            .expect("TxDone wasn't cleared");
+        let freq = state
+            .position(REG_FRF_MSB, &[0x6c, 0x80, 0x00])
+            .expect("frequency wasn't set");
+        assert!(tx_done < freq);
+        assert_eq!(state.sent.len(), 1);
+        assert_eq!(state.tx_elapsed, 3);
+    }
+
+    #[test]
+    fn keeps_the_frequency_if_the_packet_is_never_sent() {
+        let (mut radio, state) = radio(LoraConfig::default());
+        state.borrow_mut().tx_time = None;
+        radio.transmit(message(1)).unwrap();
+
+        assert_eq!(radio.set_freq(434_000_000), Err(DeviceError::Physical));
+        assert_eq!(radio.freq(), Ok(433_000_000));
+        assert!(state.borrow().writes(REG_FRF_MSB).is_empty());
+    }
+
+    #[test]
+    fn transmits_packets() {
+        let (radio, state) = radio(LoraConfig::default());
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let packet = message(7).encode(&mut buf).unwrap().to_vec();
+
+        radio.transmit(message(7)).unwrap();
+        {
+            let state = state.borrow();
+            assert_eq!(
+                state.log,
+                [
+                    Access::Write(REG_OP_MODE, vec![0x81]),
+                    Access::Write(REG_FIFO_ADDR_PTR, vec![0]),
+                    Access::Write(REG_FIFO, packet.clone()),
+                    Access::Write(REG_PAYLOAD_LENGTH, vec![packet.len() as u8]),
+                    Access::Write(REG_OP_MODE, vec![0x83]),
+                ]
+            );
+            assert_eq!(state.sent.len(), 1);
+            assert_eq!(state.sent[0], packet);
+        }
+
+        // Once sent, the radio goes back to receiving.
+        state.borrow_mut().log.clear();
+        assert!(radio.poll().unwrap().is_none());
+        let state = state.borrow();
+        assert_eq!(
+            &state.log[..3],
+            [
+                Access::Read(REG_IRQ_FLAGS, vec![IRQ_TX_DONE]),
+                Access::Write(REG_IRQ_FLAGS, vec![IRQ_TX_DONE]),
+                Access::Write(REG_OP_MODE, vec![0x85]),
+            ]
+        );
+    }
+
+    #[test]
+    fn transmits_after_the_previous_packet() {
+        let (radio, state) = radio(LoraConfig::default());
+        state.borrow_mut().tx_time = Some(2);
+
+        radio.transmit(message(1)).unwrap();
+        radio.transmit(message(2)).unwrap();
+        let state = state.borrow();
+        assert_eq!(state.sent.len(), 2);
+        assert_eq!(state.writes(REG_IRQ_FLAGS), [vec![IRQ_TX_DONE]]);
+    }
+
+    /// Put a received packet in the FIFO.
+    fn receive(state: &Rc<RefCell<Radio>>, packet: &[u8], flags: u8) {
+        let mut state = state.borrow_mut();
+        state.fifo[0x40..0x40 + packet.len()].copy_from_slice(packet);
+        state.registers[REG_FIFO_RX_CURRENT_ADDR as usize] = 0x40;
+        state.registers[REG_RX_NB_BYTES as usize] = packet.len() as u8;
+        state.registers[REG_PKT_SNR_VALUE as usize] = (-8i8) as u8;
+        state.registers[REG_PKT_RSSI_VALUE as usize] = 100;
+        state.registers[REG_IRQ_FLAGS as usize] = flags;
+    }
+
+    #[test]
+    fn receives_packets() {
+        let (radio, state) = radio(LoraConfig::default());
+        assert!(radio.poll().unwrap().is_none());
+
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let packet = message(9).encode(&mut buf).unwrap().to_vec();
+        receive(&state, &packet, IRQ_RX_DONE);
+        state.borrow_mut().log.clear();
+
+        let received = radio.poll().unwrap().unwrap();
+        assert_eq!(received.id(), 9);
+        assert_eq!(
+            radio.signal_quality(),
+            Some(SignalQuality { rssi: -64, snr: -8 })
+        );
+
+        let state = state.borrow();
+        assert_eq!(
+            &state.log[..5],
+            [
+                Access::Read(REG_IRQ_FLAGS, vec![IRQ_RX_DONE]),
+                Access::Write(REG_IRQ_FLAGS, vec![IRQ_RX_DONE | IRQ_PAYLOAD_CRC_ERROR]),
+                Access::Read(REG_RX_NB_BYTES, vec![packet.len() as u8]),
+                Access::Read(REG_FIFO_RX_CURRENT_ADDR, vec![0x40]),
+                Access::Write(REG_FIFO_ADDR_PTR, vec![0x40]),
+            ]
+        );
+        assert_eq!(state.log[5], Access::Read(REG_FIFO, packet));
+        assert_eq!(state.registers[REG_IRQ_FLAGS as usize], 0);
+    }
+
+    #[test]
+    fn drops_corrupted_packets() {
+        let (radio, state) = radio(LoraConfig::default());
+        receive(&state, &[1, 2, 3], IRQ_RX_DONE | IRQ_PAYLOAD_CRC_ERROR);
+
+        assert_eq!(radio.poll().unwrap_err(), DeviceError::InvalidMessage);
+        assert_eq!(state.borrow().registers[REG_IRQ_FLAGS as usize], 0);
+        assert!(radio.poll().unwrap().is_none());
+    }
+}
@@ -0,0 +1,97 @@
+//! Bridge device over the USB serial port, shared with the log output.
+//!
+//! Log lines are plain text, so frames are told apart by being delimited with zero bytes on both
+//! ends: every frame is [`flick_bridge::frame::stream`] encoded and preceded by an extra
+//! delimiter. A serial terminal still shows the logs, and
+//! [`flick_bridge::host::usb::UsbSerialDevice`] splits the two again on the host. Frames received
+//! from the host are only delimited by their trailing zero.
+
+use core::cell::RefCell;
+
+use flick_bridge::device::{Device, DeviceError};
+use flick_bridge::frame::stream::{self, StreamDecoder, DELIMITER, MAX_ENCODED_LEN};
+use flick_bridge::frame::{self, MAX_FRAME_LEN};
+use flick_bridge::message::Message;
+use teensy4_bsp as bsp;
+
+use crate::logging;
+
+/// Size of the buffer used to read from the USB serial port.
+const READ_LEN: usize = 64;
+
+/// The USB serial port used as a bridge device.
+pub struct UsbDevice {
+    reader: RefCell<bsp::usb::Reader>,
+    decoder: RefCell<StreamDecoder>,
+    /// Bytes read from the port that weren't decoded yet.
+    received: RefCell<heapless::Deque<u8, READ_LEN>>,
+}
+
+impl UsbDevice {
+    /// Instantiate a new [`UsbDevice`] from the reader returned by [`logging::init`].
+    pub fn new(reader: bsp::usb::Reader) -> Self {
+        Self {
+            reader: RefCell::new(reader),
+            decoder: RefCell::new(StreamDecoder::new()),
+            received: RefCell::new(heapless::Deque::new()),
+        }
+    }
+
+    fn send(&self, message: &Message) -> Result<(), DeviceError> {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let frame = frame::encode(message, &mut buf)?;
+        let mut encoded = [0; MAX_ENCODED_LEN + 1];
+        encoded[0] = DELIMITER;
+        let len = stream::encode(frame, (&mut encoded[1..]).try_into().unwrap())?;
+
+        logging::write(&encoded[..len + 1]).map_err(|_| DeviceError::Physical)
+    }
+}
+
+impl Device for UsbDevice {
+    fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+        self.send(&message)
+    }
+
+    fn poll(&self) -> Result<Option<Message>, DeviceError> {
+        let mut received = self.received.borrow_mut();
+        let mut decoder = self.decoder.borrow_mut();
+        loop {
+            if received.is_empty() {
+                let mut buf = [0; READ_LEN];
+                let len = match self.reader.borrow_mut().read(&mut buf) {
+                    Ok(len) => len,
+                    // Nothing can arrive until the host configured the device.
+                    Err(bsp::usb::Error::NotConfigured) => 0,
+                    Err(_) => return Err(DeviceError::Physical),
+                };
+                if len == 0 {
+                    return Ok(None);
+                }
+                for &byte in &buf[..len] {
+                    // Can't fail since the buffer was empty.
+                    let _ = received.push_back(byte);
+                }
+            }
+
+            while let Some(byte) = received.pop_front() {
+                if let Some(frame) = decoder.push(byte)? {
+                    return frame::decode(frame).map(Some).map_err(DeviceError::from);
+                }
+            }
+        }
+    }
+
+    /// The host is the only peer on the cable, so this does nothing.
+    fn ping(&self, _id: u64, _freq: u64) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn freq(&self) -> Result<u64, DeviceError> {
+        Ok(0)
+    }
+
+    fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+        Err(DeviceError::InvalidFrequency)
+    }
+}
@@ -0,0 +1,208 @@
+//! Onboard event log, and reporting of events to the ground.
+
+use flick_bridge::device::{Device, DeviceError};
+use flick_bridge::event::{Severity, VehicleEvent};
+use flick_bridge::message::{Message, MessageFlags, MessageKind};
+use flick_bridge::reliable::ReliableSender;
+use heapless::Deque;
+
+/// Log of the most recent `N` events. Once full, the oldest events are overwritten.
+#[derive(Debug, Clone)]
+pub struct EventLog<const N: usize> {
+    events: Deque<VehicleEvent, N>,
+    /// Number of events that were overwritten.
+    dropped: u32,
+}
+
+impl<const N: usize> EventLog<N> {
+    /// Instantiate a new, empty [`EventLog`].
+    pub fn new() -> Self {
+        Self {
+            events: Deque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Record an event, overwriting the oldest one if the log is full.
+    pub fn record(&mut self, event: VehicleEvent) {
+        match event.severity {
+            Severity::Debug => log::debug!("{:?}", event),
+            Severity::Info => log::info!("{:?}", event),
+            Severity::Warning => log::warn!("{:?}", event),
+            Severity::Critical => log::error!("{:?}", event),
+        }
+
+        if self.events.is_full() {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        // Can't fail since room was just made.
+        let _ = self.events.push_back(event);
+    }
+
+    /// Iterate over the recorded events, from oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item = &VehicleEvent> {
+        self.events.iter()
+    }
+
+    /// Number of events that were overwritten since the log was created.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+}
+
+impl<const N: usize> Default for EventLog<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Records events to the onboard log, and reliably sends them to the ground.
+#[derive(Debug, Clone)]
+pub struct EventReporter<const L: usize, const P: usize> {
+    log: EventLog<L>,
+    sender: ReliableSender<P>,
+}
+
+impl<const L: usize, const P: usize> EventReporter<L, P> {
+    /// Instantiate a new [`EventReporter`]. See [`ReliableSender::new`] for the arguments.
+    pub fn new(retry_interval: u64, max_attempts: u8) -> Self {
+        Self {
+            log: EventLog::new(),
+            sender: ReliableSender::new(retry_interval, max_attempts),
+        }
+    }
+
+    /// Onboard log of the events.
+    pub fn log(&self) -> &EventLog<L> {
+        &self.log
+    }
+
+    /// Record an event and send it to the ground in a message with the given ID. The event is
+    /// always recorded, and retransmitted by [`update`](Self::update) if it couldn't be sent.
+    pub fn report<D: Device>(
+        &mut self,
+        device: &D,
+        id: u64,
+        event: VehicleEvent,
+        now: u64,
+    ) -> Result<(), DeviceError> {
+        self.log.record(event);
+        let message = Message::new(
+            id,
+            MessageKind::Event(event),
+            now,
+            MessageFlags::REQUIRES_ACK,
+        );
+        self.sender.send(device, message, now)
+    }
+
+    /// Handle a message received from the ground. Returns whether it acknowledged an event.
+    pub fn receive(&mut self, message: &Message) -> bool {
+        self.sender.receive(message)
+    }
+
+    /// Retransmit events that weren't acknowledged in time. See [`ReliableSender::update`].
+    pub fn update<D: Device>(&mut self, device: &D, now: u64) -> Result<(), DeviceError> {
+        self.sender.update(device, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::{Cell, RefCell};
+
+    use flick_bridge::event::EventKind;
+
+    use super::*;
+
+    /// Device that records what it sends, and fails to transmit while it is down.
+    #[derive(Default)]
+    struct MockDevice {
+        sent: RefCell<Vec<Message>>,
+        down: Cell<bool>,
+    }
+
+    impl Device for MockDevice {
+        fn transmit(&self, message: Message) -> Result<(), DeviceError> {
+            if self.down.get() {
+                return Err(DeviceError::Physical);
+            }
+            self.sent.borrow_mut().push(message);
+            Ok(())
+        }
+
+        fn poll(&self) -> Result<Option<Message>, DeviceError> {
+            Ok(None)
+        }
+
+        fn ping(&self, _id: u64, _freq: u64) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn freq(&self) -> Result<u64, DeviceError> {
+            Ok(0)
+        }
+
+        fn set_freq(&mut self, _freq: u64) -> Result<(), DeviceError> {
+            Ok(())
+        }
+    }
+
+    fn event(time: u64) -> VehicleEvent {
+        VehicleEvent::new(time, EventKind::LowBattery(3300))
+    }
+
+    #[test]
+    fn overwrites_the_oldest_events() {
+        let mut log = EventLog::<3>::new();
+        for time in 0..5 {
+            log.record(event(time));
+        }
+
+        let times: Vec<_> = log.iter().map(|event| event.time).collect();
+        assert_eq!(times, [2, 3, 4]);
+        assert_eq!(log.dropped(), 2);
+    }
+
+    #[test]
+    fn reports_events_until_acknowledged() {
+        let device = MockDevice::default();
+        let mut reporter = EventReporter::<8, 4>::new(100_000, 3);
+        reporter.report(&device, 1, event(10), 10).unwrap();
+
+        let sent = device.sent.take();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].flags().contains(MessageFlags::REQUIRES_ACK));
+        assert!(matches!(sent[0].kind(), MessageKind::Event(e) if *e == event(10)));
+
+        reporter.update(&device, 100_010).unwrap();
+        assert_eq!(device.sent.take().len(), 1);
+
+        let ack = Message::new(7, MessageKind::Ack(1), 0, MessageFlags::empty());
+        assert!(reporter.receive(&ack));
+        reporter.update(&device, 1_000_000).unwrap();
+        assert!(device.sent.take().is_empty());
+    }
+
+    #[test]
+    fn records_events_that_could_not_be_sent() {
+        let device = MockDevice::default();
+        let mut reporter = EventReporter::<8, 1>::new(100_000, 3);
+
+        device.down.set(true);
+        reporter.report(&device, 1, event(10), 10).unwrap();
+        assert_eq!(
+            reporter.report(&device, 2, event(20), 20),
+            Err(DeviceError::QueueFull)
+        );
+        assert_eq!(reporter.log().iter().count(), 2);
+
+        // The queued event goes out once the device is back.
+        device.down.set(false);
+        reporter.update(&device, 30).unwrap();
+        let sent = device.sent.take();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].id(), 1);
+    }
+}
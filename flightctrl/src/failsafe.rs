@@ -0,0 +1,333 @@
+//! Link-loss failsafe.
+//!
+//! Watches heartbeats from the ground station, and decides what the vehicle should do when the
+//! link is lost. What is done depends on the phase of the flight, since disarming pyros is only
+//! safe on the pad, while in flight it is better to carry on and hold the current state.
+
+use flick_bridge::event::{EventKind, VehicleEvent};
+use flick_bridge::heartbeat::{HeartbeatMonitor, LinkEvent, LinkState};
+
+/// Phase of the flight, used to pick which actions to take when the link is lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlightPhase {
+    /// On the pad, before launch.
+    Pad,
+    /// Between launch and landing.
+    Flight,
+    /// After landing.
+    Landed,
+}
+
+/// Actions taken by the vehicle when the link is lost.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkLossActions {
+    /// Disarm every pyro channel.
+    pub disarm_pyros: bool,
+    /// Reject state changes that weren't triggered by the vehicle itself.
+    pub hold_state: bool,
+    /// Switch the radio to beacon mode so the vehicle can be found.
+    pub beacon: bool,
+}
+
+/// Configuration of the failsafe.
+#[derive(Debug, Clone, Copy)]
+pub struct FailsafeConfig {
+    /// Time between two heartbeats sent to the ground, in microseconds.
+    pub heartbeat_interval: u64,
+    /// Time without hearing from the ground after which the link is lost, in microseconds.
+    pub timeout: u64,
+    /// Actions taken when the link is lost on the pad.
+    pub on_pad: LinkLossActions,
+    /// Actions taken when the link is lost in flight.
+    pub in_flight: LinkLossActions,
+    /// Actions taken when the link is lost after landing.
+    pub after_landing: LinkLossActions,
+}
+
+impl Default for FailsafeConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: 500_000,
+            timeout: 3_000_000,
+            on_pad: LinkLossActions {
+                disarm_pyros: true,
+                ..Default::default()
+            },
+            in_flight: LinkLossActions {
+                hold_state: true,
+                ..Default::default()
+            },
+            after_landing: LinkLossActions {
+                beacon: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl FailsafeConfig {
+    /// Actions to take when the link is lost in the given phase.
+    pub fn actions(&self, phase: FlightPhase) -> LinkLossActions {
+        match phase {
+            FlightPhase::Pad => self.on_pad,
+            FlightPhase::Flight => self.in_flight,
+            FlightPhase::Landed => self.after_landing,
+        }
+    }
+}
+
+/// Event raised by the failsafe that should be reported to the ground.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailsafeEvent {
+    /// The link was lost and the given actions were taken.
+    LinkLost {
+        last_heard: u64,
+        actions: LinkLossActions,
+    },
+    /// The link came back. Since the loss couldn't be reported while the link was down, this
+    /// also carries every action taken during the outage.
+    LinkRestored {
+        lost_at: u64,
+        restored_at: u64,
+        actions: LinkLossActions,
+    },
+}
+
+impl FailsafeEvent {
+    /// Event reporting this to the ground.
+    pub fn event(&self, now: u64) -> VehicleEvent {
+        match *self {
+            FailsafeEvent::LinkLost { last_heard, .. } => {
+                VehicleEvent::new(now, EventKind::LinkLost(last_heard))
+            }
+            FailsafeEvent::LinkRestored {
+                lost_at,
+                restored_at,
+                ..
+            } => VehicleEvent::new(restored_at, EventKind::LinkRestored(lost_at)),
+        }
+    }
+}
+
+/// Link-loss failsafe of the vehicle.
+#[derive(Debug, Clone)]
+pub struct Failsafe {
+    config: FailsafeConfig,
+    monitor: HeartbeatMonitor,
+    /// Actions currently in effect.
+    active: LinkLossActions,
+    /// Every action taken since the link was lost, to report once it comes back.
+    taken: LinkLossActions,
+}
+
+impl Failsafe {
+    /// Instantiate a new [`Failsafe`].
+    pub fn new(config: FailsafeConfig) -> Self {
+        Self {
+            monitor: HeartbeatMonitor::new(config.heartbeat_interval, config.timeout),
+            config,
+            active: LinkLossActions::default(),
+            taken: LinkLossActions::default(),
+        }
+    }
+
+    /// Actions currently in effect. While the link is lost, these follow the phase of the flight,
+    /// until the link comes back.
+    pub fn active(&self) -> LinkLossActions {
+        self.active
+    }
+
+    /// Current state of the link.
+    pub fn link_state(&self) -> LinkState {
+        self.monitor.state()
+    }
+
+    /// Records that a message was received from the ground.
+    pub fn heard(&mut self, now: u64) -> Option<FailsafeEvent> {
+        match self.monitor.heard(now)? {
+            LinkEvent::Restored {
+                lost_at,
+                restored_at,
+            } => {
+                self.active = LinkLossActions::default();
+                let actions = core::mem::take(&mut self.taken);
+                log::info!(
+                    "link restored after {} us",
+                    restored_at.saturating_sub(lost_at)
+                );
+                Some(FailsafeEvent::LinkRestored {
+                    lost_at,
+                    restored_at,
+                    actions,
+                })
+            }
+            LinkEvent::Lost { .. } => None,
+        }
+    }
+
+    /// Checks whether the link timed out. While it is lost, the actions in effect are the ones
+    /// configured for the current phase of the flight, so that pyros are only disarmed on the pad.
+    pub fn update(&mut self, now: u64, phase: FlightPhase) -> Option<FailsafeEvent> {
+        let event = self.monitor.update(now);
+        if self.monitor.state() != LinkState::Lost {
+            return None;
+        }
+
+        let actions = self.config.actions(phase);
+        if actions != self.active {
+            log::warn!("link lost in {:?}, taking {:?}", phase, actions);
+            self.active = actions;
+            self.taken.disarm_pyros |= actions.disarm_pyros;
+            self.taken.hold_state |= actions.hold_state;
+            self.taken.beacon |= actions.beacon;
+        }
+
+        match event? {
+            LinkEvent::Lost { last_heard } => Some(FailsafeEvent::LinkLost {
+                last_heard,
+                actions,
+            }),
+            LinkEvent::Restored { .. } => None,
+        }
+    }
+
+    /// Returns whether a heartbeat should be sent to the ground.
+    pub fn heartbeat_due(&mut self, now: u64) -> bool {
+        self.monitor.heartbeat_due(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000;
+
+    /// Failsafe that heard from the ground at time 0.
+    fn failsafe() -> Failsafe {
+        let mut failsafe = Failsafe::new(FailsafeConfig::default());
+        assert_eq!(failsafe.heard(0), None);
+        failsafe
+    }
+
+    #[test]
+    fn disarms_on_the_pad_when_the_link_is_lost() {
+        let mut failsafe = failsafe();
+        assert_eq!(failsafe.update(3 * SECOND, FlightPhase::Pad), None);
+        assert_eq!(failsafe.active(), LinkLossActions::default());
+
+        let lost = failsafe.update(3 * SECOND + 1, FlightPhase::Pad);
+        let actions = LinkLossActions {
+            disarm_pyros: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            lost,
+            Some(FailsafeEvent::LinkLost {
+                last_heard: 0,
+                actions
+            })
+        );
+        assert_eq!(failsafe.active(), actions);
+        assert_eq!(failsafe.link_state(), LinkState::Lost);
+        // The loss is only reported once.
+        assert_eq!(failsafe.update(4 * SECOND, FlightPhase::Pad), None);
+    }
+
+    #[test]
+    fn takes_the_actions_of_each_phase() {
+        let config = FailsafeConfig::default();
+        for phase in [FlightPhase::Pad, FlightPhase::Flight, FlightPhase::Landed] {
+            let mut failsafe = failsafe();
+            match failsafe.update(4 * SECOND, phase) {
+                Some(FailsafeEvent::LinkLost { actions, .. }) => {
+                    assert_eq!(actions, config.actions(phase))
+                }
+                event => panic!("unexpected {:?}", event),
+            }
+            assert_eq!(failsafe.active(), config.actions(phase));
+        }
+        // Pyros are never disarmed in flight.
+        assert!(!config.actions(FlightPhase::Flight).disarm_pyros);
+        assert!(config.actions(FlightPhase::Flight).hold_state);
+        assert!(config.actions(FlightPhase::Landed).beacon);
+    }
+
+    #[test]
+    fn follows_the_phase_while_the_link_is_lost() {
+        let config = FailsafeConfig::default();
+        let mut failsafe = failsafe();
+        assert!(failsafe.update(4 * SECOND, FlightPhase::Pad).is_some());
+
+        assert_eq!(failsafe.update(5 * SECOND, FlightPhase::Flight), None);
+        assert_eq!(failsafe.active(), config.in_flight);
+        assert_eq!(failsafe.update(6 * SECOND, FlightPhase::Landed), None);
+        assert_eq!(failsafe.active(), config.after_landing);
+    }
+
+    #[test]
+    fn reports_every_action_taken_once_the_link_is_restored() {
+        let mut failsafe = failsafe();
+        failsafe.update(4 * SECOND, FlightPhase::Pad);
+        failsafe.update(5 * SECOND, FlightPhase::Flight);
+        failsafe.update(6 * SECOND, FlightPhase::Landed);
+
+        let restored = failsafe.heard(7 * SECOND).unwrap();
+        assert_eq!(
+            restored,
+            FailsafeEvent::LinkRestored {
+                lost_at: 4 * SECOND,
+                restored_at: 7 * SECOND,
+                actions: LinkLossActions {
+                    disarm_pyros: true,
+                    hold_state: true,
+                    beacon: true,
+                },
+            }
+        );
+        assert_eq!(
+            restored.event(8 * SECOND),
+            VehicleEvent::new(7 * SECOND, EventKind::LinkRestored(4 * SECOND))
+        );
+        assert_eq!(failsafe.active(), LinkLossActions::default());
+        assert_eq!(failsafe.link_state(), LinkState::Connected);
+
+        // The next outage only reports its own actions.
+        failsafe.update(11 * SECOND, FlightPhase::Landed);
+        match failsafe.heard(12 * SECOND) {
+            Some(FailsafeEvent::LinkRestored { actions, .. }) => {
+                assert_eq!(actions, FailsafeConfig::default().after_landing)
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+
+    #[test]
+    fn times_out_a_link_that_never_came_up() {
+        let mut failsafe = Failsafe::new(FailsafeConfig::default());
+        assert_eq!(failsafe.update(10 * SECOND, FlightPhase::Pad), None);
+        assert_eq!(failsafe.link_state(), LinkState::Waiting);
+        assert_eq!(failsafe.update(13 * SECOND, FlightPhase::Pad), None);
+
+        match failsafe.update(13 * SECOND + 1, FlightPhase::Pad) {
+            Some(event @ FailsafeEvent::LinkLost { last_heard, .. }) => {
+                assert_eq!(last_heard, 10 * SECOND);
+                assert_eq!(
+                    event.event(13 * SECOND + 1),
+                    VehicleEvent::new(13 * SECOND + 1, EventKind::LinkLost(10 * SECOND))
+                );
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+        assert!(failsafe.active().disarm_pyros);
+    }
+
+    #[test]
+    fn sends_heartbeats_at_the_interval() {
+        let mut failsafe = failsafe();
+        assert!(failsafe.heartbeat_due(0));
+        assert!(!failsafe.heartbeat_due(SECOND / 2 - 1));
+        assert!(failsafe.heartbeat_due(SECOND / 2));
+        assert!(!failsafe.heartbeat_due(SECOND / 2));
+    }
+}
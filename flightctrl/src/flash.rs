@@ -0,0 +1,180 @@
+//! [`Storage`] in the flash of the Teensy 4.0.
+//!
+//! The configuration is kept in the first 4 KiB sector after the firmware image, which the linker
+//! script of the board limits to 1984 KiB. This is the start of the region that Teensyduino uses to
+//! emulate an EEPROM, which is never programmed by the bootloader.
+//!
+//! The flash is erased and programmed through the FlexSPI NOR driver in the boot ROM of the
+//! i.MX RT1062 (see the "ROM APIs" chapter of its reference manual). The driver runs from the ROM,
+//! but the firmware runs from the same flash, so interrupts are disabled while it works: nothing
+//! may be fetched from the flash until it is done.
+
+use crate::config::Storage;
+
+/// Offset of the sector holding the configuration, from the start of the flash.
+const SECTOR_OFFSET: u32 = 0x1F_0000;
+/// Size of a sector, the smallest erasable unit.
+const SECTOR_LEN: usize = 4096;
+/// Size of a page, the largest programmable unit.
+const PAGE_LEN: usize = 256;
+/// Size of the configuration block of the flash, at its start.
+const NOR_CONFIG_LEN: usize = 512;
+/// Start of the flash in the memory map.
+const FLASH_BASE: usize = 0x6000_0000;
+/// Address of the pointer to the API tree of the boot ROM.
+const ROM_API_TREE: usize = 0x0020_001C;
+/// FlexSPI instance of the flash, as numbered by the boot ROM.
+const INSTANCE: u32 = 0;
+/// Status returned by the boot ROM on success.
+const STATUS_SUCCESS: i32 = 0;
+
+/// Configuration block of a FlexSPI NOR flash (`flexspi_nor_config_t`).
+#[repr(C, align(4))]
+struct NorConfig([u8; NOR_CONFIG_LEN]);
+
+/// FlexSPI NOR driver of the boot ROM (`flexspi_nor_driver_interface_t`).
+// Every entry is declared for the layout, even those unused.
+#[allow(dead_code)]
+#[repr(C)]
+struct NorDriver {
+    version: u32,
+    init: unsafe extern "C" fn(u32, *mut NorConfig) -> i32,
+    program: unsafe extern "C" fn(u32, *mut NorConfig, u32, *const u32) -> i32,
+    erase_all: unsafe extern "C" fn(u32, *mut NorConfig) -> i32,
+    erase: unsafe extern "C" fn(u32, *mut NorConfig, u32, u32) -> i32,
+    read: unsafe extern "C" fn(u32, *mut NorConfig, *mut u32, u32, u32) -> i32,
+    clear_cache: unsafe extern "C" fn(u32),
+}
+
+/// API tree of the boot ROM (`bootloader_api_entry_t`), up to the NOR driver.
+#[allow(dead_code)]
+#[repr(C)]
+struct RomApi {
+    version: u32,
+    copyright: *const u8,
+    run_bootloader: unsafe extern "C" fn(*mut u8),
+    reserved: *const u32,
+    nor_driver: *const NorDriver,
+}
+
+/// Reason that the flash could not be read or written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashError {
+    /// The bytes don't fit in the sector.
+    OutOfBounds,
+    /// The boot ROM failed, with the given status.
+    Rom(i32),
+}
+
+/// Storage in a sector of the flash of the Teensy 4.0.
+pub struct FlashStorage {
+    /// Configuration block of the flash, copied from its start since the driver may modify it.
+    config: NorConfig,
+}
+
+impl FlashStorage {
+    /// Instantiate a new [`FlashStorage`].
+    ///
+    /// # Safety
+    ///
+    /// Only one may exist, and nothing else may program the flash.
+    pub unsafe fn new() -> Self {
+        let mut config = NorConfig([0; NOR_CONFIG_LEN]);
+        let fcb = core::slice::from_raw_parts(FLASH_BASE as *const u8, NOR_CONFIG_LEN);
+        config.0.copy_from_slice(fcb);
+        Self { config }
+    }
+
+    fn driver() -> &'static NorDriver {
+        // SAFETY: the boot ROM of the i.MX RT1062 always holds its API tree at this address.
+        unsafe {
+            let api = &**(ROM_API_TREE as *const *const RomApi);
+            &*api.nor_driver
+        }
+    }
+
+    fn check(offset: usize, len: usize) -> Result<(), FlashError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= SECTOR_LEN => Ok(()),
+            _ => Err(FlashError::OutOfBounds),
+        }
+    }
+}
+
+fn status(status: i32) -> Result<(), FlashError> {
+    if status == STATUS_SUCCESS {
+        Ok(())
+    } else {
+        Err(FlashError::Rom(status))
+    }
+}
+
+impl Storage for FlashStorage {
+    type Error = FlashError;
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error> {
+        Self::check(offset, buf.len())?;
+
+        // Read through the driver rather than the memory map, which the caches may hold stale.
+        let mut words = [0u32; 16];
+        // Leaves room for the bytes before the first one, in its word.
+        let chunk_len = words.len() * 4 - 4;
+        for (i, chunk) in buf.chunks_mut(chunk_len).enumerate() {
+            let start = offset + i * chunk_len;
+            // Whole words from the one holding the first byte.
+            let skip = start % 4;
+            let len = (skip + chunk.len()).div_ceil(4) * 4;
+            let addr = SECTOR_OFFSET + (start - skip) as u32;
+            // SAFETY: the buffer holds enough words, and reading never disturbs the firmware.
+            status(unsafe {
+                (Self::driver().read)(
+                    INSTANCE,
+                    &mut self.config,
+                    words.as_mut_ptr(),
+                    addr,
+                    len as u32,
+                )
+            })?;
+            for (j, byte) in chunk.iter_mut().enumerate() {
+                let k = skip + j;
+                *byte = words[k / 4].to_le_bytes()[k % 4];
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Self::Error> {
+        Self::check(offset, bytes.len())?;
+
+        // The sector is erased as a whole, so whatever else it holds is written back.
+        let mut sector = [0u32; SECTOR_LEN / 4];
+        let mut buf = [0u8; SECTOR_LEN];
+        self.read(0, &mut buf)?;
+        buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+        for (word, bytes) in sector.iter_mut().zip(buf.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        let driver = Self::driver();
+        let config = &mut self.config;
+        cortex_m::interrupt::free(|_| {
+            // SAFETY: interrupts are disabled, so nothing runs from the flash while the driver
+            // erases and programs it, and the sector is out of the firmware image.
+            unsafe {
+                status((driver.erase)(
+                    INSTANCE,
+                    config,
+                    SECTOR_OFFSET,
+                    SECTOR_LEN as u32,
+                ))?;
+                for (i, page) in sector.chunks_exact(PAGE_LEN / 4).enumerate() {
+                    let addr = SECTOR_OFFSET + (i * PAGE_LEN) as u32;
+                    status((driver.program)(INSTANCE, config, addr, page.as_ptr()))?;
+                }
+                (driver.clear_cache)(INSTANCE);
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,365 @@
+//! Vertical-channel Kalman filter, estimating the altitude, vertical velocity and vertical
+//! acceleration.
+//!
+//! The state follows a constant acceleration model, whose changes (the jerk) are the process
+//! noise. The filter fuses the barometric altitude with the vertical acceleration in the earth
+//! frame, such as given by [`AttitudeFilter::earth_acceleration`], each at whatever rate it comes.
+//!
+//! Around mach 1, shock waves over the vehicle disturb the static pressure, so the barometer is
+//! trusted much less in the transonic region. Altitudes too far from the prediction for their noise
+//! are rejected as outliers, such as the pressure spike of an ejection charge, unless too many in a
+//! row are, which means the estimate itself went off: its altitude and velocity are then reset to
+//! what the measurements give.
+//!
+//! Like the [`LqrController`], the filter is generic over the scalar, so that it runs in `f32` on
+//! the Teensy.
+//!
+//! [`AttitudeFilter::earth_acceleration`]: crate::attitude::AttitudeFilter::earth_acceleration
+//! [`LqrController`]: crate::control::lqr::LqrController
+
+use nalgebra::{Matrix3, RealField, RowVector3, Vector3};
+
+/// Configuration of the [`VerticalKalman`] filter.
+#[derive(Debug, Clone, Copy)]
+pub struct KalmanConfig<T> {
+    /// Spectral density of the jerk, in squared meters per second to the fifth.
+    pub jerk_noise: T,
+    /// Variance of the barometric altitude, in squared meters.
+    pub altitude_noise: T,
+    /// Variance of the vertical acceleration, in squared meters per second squared.
+    pub acceleration_noise: T,
+    /// Speed of sound, in meters per second.
+    pub speed_of_sound: T,
+    /// Lowest mach number of the transonic region.
+    pub transonic_min: T,
+    /// Highest mach number of the transonic region.
+    pub transonic_max: T,
+    /// Factor on the variance of the barometric altitude in the transonic region.
+    pub transonic_factor: T,
+    /// Largest innovation of an accepted altitude, in standard deviations.
+    pub outlier_threshold: T,
+    /// Number of altitude outliers in a row after which the estimate is considered off.
+    pub max_outliers: u32,
+}
+
+impl<T: RealField + Copy> Default for KalmanConfig<T> {
+    fn default() -> Self {
+        Self {
+            jerk_noise: constant(100.0),
+            altitude_noise: constant(1.0),
+            acceleration_noise: constant(0.25),
+            speed_of_sound: constant(343.0),
+            transonic_min: constant(0.8),
+            transonic_max: constant(1.2),
+            transonic_factor: constant(100.0),
+            outlier_threshold: constant(5.0),
+            max_outliers: 20,
+        }
+    }
+}
+
+/// Outcome of an altitude measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Measurement {
+    /// The measurement was used.
+    Accepted,
+    /// The measurement was rejected as an outlier.
+    Outlier,
+    /// Too many outliers came in a row, so the altitude estimate was reset to the measurement, and
+    /// the velocity is learnt again.
+    Reset,
+}
+
+/// Kalman filter of the vertical channel. The state holds the altitude, the vertical velocity and
+/// the vertical acceleration.
+#[derive(Debug, Clone)]
+pub struct VerticalKalman<T: RealField + Copy> {
+    config: KalmanConfig<T>,
+    state: Vector3<T>,
+    covariance: Matrix3<T>,
+    /// Time of the state, once there was a measurement.
+    time: Option<u64>,
+    /// Altitude outliers in a row.
+    outliers: u32,
+}
+
+impl<T: RealField + Copy> VerticalKalman<T> {
+    /// Instantiate a new [`VerticalKalman`] filter. The estimate is uncertain until the first
+    /// measurements come.
+    pub fn new(config: KalmanConfig<T>) -> Self {
+        Self {
+            config,
+            state: Vector3::zeros(),
+            covariance: Self::initial_covariance(),
+            time: None,
+            outliers: 0,
+        }
+    }
+
+    /// Estimated altitude, in meters.
+    pub fn altitude(&self) -> T {
+        self.state[0]
+    }
+
+    /// Estimated vertical velocity, in meters per second.
+    pub fn velocity(&self) -> T {
+        self.state[1]
+    }
+
+    /// Estimated vertical acceleration, in meters per second squared.
+    pub fn acceleration(&self) -> T {
+        self.state[2]
+    }
+
+    /// Covariance of the estimate.
+    pub fn covariance(&self) -> &Matrix3<T> {
+        &self.covariance
+    }
+
+    /// Whether the estimated speed is in the transonic region.
+    pub fn transonic(&self) -> bool {
+        let mach = self.state[1].abs() / self.config.speed_of_sound;
+        mach >= self.config.transonic_min && mach <= self.config.transonic_max
+    }
+
+    /// Move the estimate forward to the given time, in microseconds.
+    pub fn predict(&mut self, time: u64) {
+        let last_time = *self.time.get_or_insert(time);
+        if time <= last_time {
+            return;
+        }
+        self.time = Some(time);
+
+        let dt = constant::<T>((time - last_time) as f64 / 1e6);
+        let dt2 = dt * dt;
+        let dt3 = dt2 * dt;
+        let half = constant::<T>(0.5);
+        let transition = Matrix3::new(
+            T::one(),
+            dt,
+            half * dt2,
+            T::zero(),
+            T::one(),
+            dt,
+            T::zero(),
+            T::zero(),
+            T::one(),
+        );
+        // Jerk as white noise, integrated over the step.
+        let (q11, q12, q13) = (
+            dt3 * dt2 / constant(20.0),
+            dt2 * dt2 / constant(8.0),
+            dt3 / constant(6.0),
+        );
+        let (q22, q23, q33) = (dt3 / constant(3.0), half * dt2, dt);
+        let process =
+            Matrix3::new(q11, q12, q13, q12, q22, q23, q13, q23, q33) * self.config.jerk_noise;
+
+        self.state = transition * self.state;
+        self.covariance = transition * self.covariance * transition.transpose() + process;
+    }
+
+    /// Update with a barometric altitude, in meters, measured at the given time.
+    pub fn update_altitude(&mut self, time: u64, altitude: T) -> Measurement {
+        self.predict(time);
+
+        // Shock waves may hold the barometer off for the whole transonic region, which must not
+        // be taken for the estimate going off.
+        let transonic = self.transonic();
+        let mut noise = self.config.altitude_noise;
+        if transonic {
+            noise *= self.config.transonic_factor;
+        }
+
+        let mut outcome = Measurement::Accepted;
+        let innovation = altitude - self.state[0];
+        let threshold = self.config.outlier_threshold;
+        if innovation * innovation > threshold * threshold * (self.covariance[(0, 0)] + noise) {
+            if transonic || self.outliers < self.config.max_outliers {
+                self.outliers += u32::from(!transonic);
+                return Measurement::Outlier;
+            }
+            // The altitude drifted off with a wrong velocity, such as after a landing without an
+            // impact in the acceleration, so both are learnt again. The acceleration is kept.
+            log::warn!("altitude estimate reset after {} outliers", self.outliers);
+            let initial = Self::initial_covariance();
+            for i in 0..2 {
+                self.covariance.row_mut(i).fill(T::zero());
+                self.covariance.column_mut(i).fill(T::zero());
+                self.covariance[(i, i)] = initial[(i, i)];
+            }
+            outcome = Measurement::Reset;
+        }
+
+        self.outliers = 0;
+        self.correct(
+            RowVector3::new(T::one(), T::zero(), T::zero()),
+            altitude,
+            noise,
+        );
+        outcome
+    }
+
+    /// Update with a vertical acceleration in the earth frame, in meters per second squared,
+    /// measured at the given time. The acceleration is never rejected, since ignition and burnout
+    /// change it in steps far larger than its noise.
+    pub fn update_acceleration(&mut self, time: u64, acceleration: T) {
+        self.predict(time);
+        self.correct(
+            RowVector3::new(T::zero(), T::zero(), T::one()),
+            acceleration,
+            self.config.acceleration_noise,
+        );
+    }
+
+    /// Correct the estimate with a measurement of `observation * state`.
+    fn correct(&mut self, observation: RowVector3<T>, measurement: T, noise: T) {
+        let innovation = measurement - (observation * self.state)[0];
+        let variance = (observation * self.covariance * observation.transpose())[0] + noise;
+        let gain = self.covariance * observation.transpose() / variance;
+        self.state += gain * innovation;
+        // Joseph form, which keeps the covariance symmetric and positive in single precision.
+        let correction = Matrix3::identity() - gain * observation;
+        self.covariance =
+            correction * self.covariance * correction.transpose() + gain * gain.transpose() * noise;
+    }
+
+    fn initial_covariance() -> Matrix3<T> {
+        Matrix3::from_diagonal(&Vector3::new(constant(1e6), constant(1e4), constant(1e4)))
+    }
+}
+
+/// Scalar of the given value.
+fn constant<T: RealField>(value: f64) -> T {
+    T::from_f64(value).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use oorandom::Rand32;
+
+    use super::*;
+
+    /// Time between two samples, in microseconds.
+    const PERIOD: u64 = 10_000;
+
+    fn filter() -> VerticalKalman<f32> {
+        VerticalKalman::new(KalmanConfig::default())
+    }
+
+    /// Feed the altitude and acceleration of `profile` at every sample over the given times, in
+    /// seconds, with some noise on the altitude. Returns the outcome of every altitude.
+    fn feed(
+        filter: &mut VerticalKalman<f32>,
+        seconds: core::ops::Range<f32>,
+        rng: &mut Rand32,
+        profile: impl Fn(f32) -> (f32, f32),
+    ) -> Vec<Measurement> {
+        let start = (seconds.start * 1e6) as u64;
+        let end = (seconds.end * 1e6) as u64;
+        (start..end)
+            .step_by(PERIOD as usize)
+            .map(|time| {
+                let (altitude, acceleration) = profile(time as f32 / 1e6);
+                filter.update_acceleration(time, acceleration);
+                let noise = (rng.rand_float() - 0.5) * 2.0;
+                filter.update_altitude(time, altitude + noise)
+            })
+            .collect()
+    }
+
+    /// At rest at the given altitude.
+    fn rest(altitude: f32) -> impl Fn(f32) -> (f32, f32) {
+        move |_| (altitude, 0.0)
+    }
+
+    #[test]
+    fn predicts_with_a_constant_acceleration() {
+        let mut rng = Rand32::new(1);
+        let mut filter = filter();
+        let profile = |t: f32| (10.0 + 20.0 * t + 2.5 * t * t, 5.0);
+        let outcomes = feed(&mut filter, 0.0..5.0, &mut rng, profile);
+        assert!(outcomes
+            .iter()
+            .all(|&outcome| outcome == Measurement::Accepted));
+        assert!((filter.altitude() - profile(4.99).0).abs() < 0.5);
+        assert!((filter.velocity() - 44.95).abs() < 0.5);
+        assert!((filter.acceleration() - 5.0).abs() < 0.1);
+
+        // A second later without measurements, the estimate followed the model but is less sure.
+        let variance = filter.covariance()[(0, 0)];
+        filter.predict(5_990_000);
+        assert!((filter.altitude() - profile(5.99).0).abs() < 1.0);
+        assert!((filter.velocity() - 49.95).abs() < 0.5);
+        assert!(filter.covariance()[(0, 0)] > 10.0 * variance);
+
+        // Going back in time changes nothing.
+        let altitude = filter.altitude();
+        filter.predict(5_000_000);
+        assert_eq!(filter.altitude(), altitude);
+    }
+
+    #[test]
+    fn rejects_outliers() {
+        let mut rng = Rand32::new(2);
+        let mut filter = filter();
+        feed(&mut filter, 0.0..5.0, &mut rng, rest(100.0));
+
+        // The pressure spike of an ejection charge, shorter than what resets the estimate.
+        let outcomes = feed(&mut filter, 5.0..5.1, &mut rng, rest(150.0));
+        assert!(outcomes
+            .iter()
+            .all(|&outcome| outcome == Measurement::Outlier));
+        assert!((filter.altitude() - 100.0).abs() < 0.5);
+
+        let outcomes = feed(&mut filter, 5.1..6.0, &mut rng, rest(100.0));
+        assert!(outcomes
+            .iter()
+            .all(|&outcome| outcome == Measurement::Accepted));
+        assert!((filter.altitude() - 100.0).abs() < 0.5);
+        assert!(filter.velocity().abs() < 0.5);
+    }
+
+    #[test]
+    fn resets_after_too_many_outliers_in_a_row() {
+        let mut rng = Rand32::new(3);
+        let mut filter = filter();
+        feed(&mut filter, 0.0..5.0, &mut rng, rest(100.0));
+
+        // The estimate went off, such as after the barometer came back from a lockout.
+        let outcomes = feed(&mut filter, 5.0..7.0, &mut rng, rest(300.0));
+        let max_outliers = KalmanConfig::<f32>::default().max_outliers as usize;
+        assert!(outcomes[..max_outliers]
+            .iter()
+            .all(|&outcome| outcome == Measurement::Outlier));
+        assert_eq!(outcomes[max_outliers], Measurement::Reset);
+        assert!(outcomes[max_outliers + 1..]
+            .iter()
+            .all(|&outcome| outcome == Measurement::Accepted));
+        assert!((filter.altitude() - 300.0).abs() < 0.5);
+        assert!(filter.velocity().abs() < 0.5);
+    }
+
+    #[test]
+    fn never_resets_in_the_transonic_region() {
+        let mut rng = Rand32::new(4);
+        let mut filter = filter();
+        let profile = |offset: f32| move |t: f32| (340.0 * t + offset, 0.0);
+        feed(&mut filter, 0.0..5.0, &mut rng, profile(0.0));
+        assert!(filter.transonic());
+
+        // Shocks hold the barometer off for longer than what would reset the estimate.
+        let outcomes = feed(&mut filter, 5.0..7.0, &mut rng, profile(300.0));
+        assert!(outcomes
+            .iter()
+            .all(|&outcome| outcome == Measurement::Outlier));
+        assert!((filter.altitude() - 340.0 * 6.99).abs() < 1.0);
+
+        // Smaller disturbances are accepted, but barely move the estimate.
+        let outcomes = feed(&mut filter, 7.0..7.5, &mut rng, profile(20.0));
+        assert!(outcomes
+            .iter()
+            .all(|&outcome| outcome == Measurement::Accepted));
+        assert!((filter.altitude() - 340.0 * 7.49).abs() < 10.0);
+    }
+}
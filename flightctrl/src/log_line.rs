@@ -0,0 +1,86 @@
+//! Formatting of the log lines written to the USB serial port by [`logging`](crate::logging).
+//!
+//! Lines share the port with bridge frames, which are delimited by zero bytes, so a line never
+//! contains one.
+
+use core::fmt::{self, Write as _};
+
+/// Longest log line, longer lines are truncated.
+const MAX_LINE_LEN: usize = 256;
+
+/// Format a record into a line of text, ending with `\r\n`.
+pub fn format(record: &log::Record) -> heapless::String<MAX_LINE_LEN> {
+    let mut line = Line(heapless::String::new());
+    // Lines that don't fit are truncated rather than dropped.
+    let _ = write!(
+        line,
+        "[{} {}]: {}",
+        record.level(),
+        record.target(),
+        record.args()
+    );
+    let mut line = line.0;
+    while line.len() > MAX_LINE_LEN - 2 {
+        line.pop();
+    }
+    let _ = line.push_str("\r\n");
+    line
+}
+
+/// Log line being formatted, replacing zero bytes so they can't be mistaken for a delimiter.
+struct Line(heapless::String<MAX_LINE_LEN>);
+
+impl fmt::Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.0
+                .push(if c == '\0' {
+                    char::REPLACEMENT_CHARACTER
+                } else {
+                    c
+                })
+                .map_err(|_| fmt::Error)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(message: &str) -> heapless::String<MAX_LINE_LEN> {
+        format(
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .target("flightctrl")
+                .args(format_args!("{message}"))
+                .build(),
+        )
+    }
+
+    #[test]
+    fn formats_the_level_and_target() {
+        assert_eq!(line("armed"), "[WARN flightctrl]: armed\r\n");
+    }
+
+    #[test]
+    fn replaces_the_frame_delimiter() {
+        let replaced = line("before\0after");
+        assert!(!replaced.as_bytes().contains(&0));
+        assert_eq!(replaced, "[WARN flightctrl]: before\u{fffd}after\r\n");
+    }
+
+    #[test]
+    fn truncates_long_lines() {
+        let ascii = line(&"x".repeat(2 * MAX_LINE_LEN));
+        assert_eq!(ascii.len(), MAX_LINE_LEN);
+        assert!(ascii.ends_with("x\r\n"));
+
+        // Truncation never splits a character.
+        let accented = line(&"é".repeat(MAX_LINE_LEN));
+        assert!(accented.len() <= MAX_LINE_LEN);
+        assert!(accented.ends_with("é\r\n"));
+    }
+}
@@ -1,8 +1,12 @@
 //! USB logging support
 //!
+//! Log lines share the USB serial port with bridge frames sent by
+//! [`UsbDevice`](crate::devices::usb::UsbDevice), so the logger and the device write through the
+//! same [`write`] function, which never splits a line or a frame.
+//!
 //! If you don't want USB logging, remove
 //!
-//! - this module
+//! - this module, and [`UsbDevice`](crate::devices::usb::UsbDevice)
 //! - the `log` dependency in Cargo.toml
 
 use core::cell::RefCell;
@@ -12,6 +16,8 @@ use bsp::hal::ral::usb::USB1;
 use bsp::interrupt;
 use teensy4_bsp as bsp;
 
+use crate::log_line;
+
 /// Specify any logging filters here
 ///
 /// See the BSP docs for more information on logging filters.
@@ -21,25 +27,43 @@ const FILTERS: &[bsp::usb::Filter] = &[
     // ("{{crate_name}}", None),
 ];
 
+/// Writer of the USB serial port, shared by the logger and the bridge device.
+static WRITER: Mutex<RefCell<Option<bsp::usb::Writer>>> = Mutex::new(RefCell::new(None));
+
+static LOGGER: Logger = Logger;
+
 /// Initialize the USB logging system, and prepares the USB ISR with the poller
 ///
 /// When `init` returns, the USB interrupt will be enabled, and the host may begin to interface the
-/// device. You should only call this once.
+/// device. The returned reader is used to receive bridge frames. You should only call this once.
 ///
 /// # Panics
 /// Panics if the imxrt-ral USB1 instance is already taken.
 pub fn init() -> Result<bsp::usb::Reader, bsp::usb::Error> {
     let inst = USB1::take().unwrap();
-    bsp::usb::init(
-        inst,
-        bsp::usb::LoggingConfig {
-            filters: FILTERS,
-            ..Default::default()
-        },
-    )
-    .map(|(poller, reader)| {
-        setup(poller);
-        reader
+    let (poller, reader, writer) = bsp::usb::split(inst)?;
+    cortex_m::interrupt::free(|cs| *WRITER.borrow(cs).borrow_mut() = Some(writer));
+
+    log::set_logger(&LOGGER)?;
+    log::set_max_level(log::STATIC_MAX_LEVEL);
+    setup(poller);
+    Ok(reader)
+}
+
+/// Write bytes to the USB serial port in one go, so they aren't interleaved with other writes.
+pub fn write(bytes: &[u8]) -> Result<(), bsp::usb::Error> {
+    cortex_m::interrupt::free(|cs| {
+        let mut writer = WRITER.borrow(cs).borrow_mut();
+        let writer = writer.as_mut().ok_or(bsp::usb::Error::NotConfigured)?;
+
+        let mut written = 0;
+        while written < bytes.len() {
+            match writer.write(&bytes[written..])? {
+                0 => return Err(bsp::usb::Error::Io),
+                len => written += len,
+            }
+        }
+        writer.flush()
     })
 }
 
@@ -65,3 +89,36 @@ fn setup(poller: bsp::usb::Poller) {
         unsafe { cortex_m::peripheral::NVIC::unmask(bsp::interrupt::USB_OTG1) };
     });
 }
+
+/// Logger writing a line of text for every record.
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level() && filtered(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = log_line::format(record);
+        // Nothing can be done if the host isn't listening.
+        let _ = write(line.as_bytes());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Whether a record passes the [`FILTERS`].
+fn filtered(metadata: &log::Metadata) -> bool {
+    if FILTERS.is_empty() {
+        return true;
+    }
+
+    FILTERS
+        .iter()
+        .find(|(target, _)| *target == metadata.target())
+        .is_some_and(|(_, level)| level.is_none_or(|level| metadata.level() <= level))
+}
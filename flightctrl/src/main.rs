@@ -1,21 +1,52 @@
-//! The starter code slowly blinks the LED, and sets up USB logging.
+//! The starter code slowly blinks the LED, and sets up USB logging. It loads the configuration from
+//! the flash, and calibrates the IMU if no calibration was saved.
 
-#![no_std]
-#![no_main]
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+// On the host, nothing is reachable from `main`, only from the tests.
+#![cfg_attr(not(target_os = "none"), allow(dead_code))]
 
+#[cfg(target_os = "none")]
 use core::time::Duration;
+#[cfg(target_os = "none")]
 use teensy4_bsp as bsp;
+#[cfg(target_os = "none")]
 use teensy4_panic as _;
 
+mod altitude;
+mod attitude;
+mod config;
 mod control;
+mod detect;
+mod device;
+mod devices;
+mod events;
+mod failsafe;
+#[cfg(target_os = "none")]
+mod flash;
+mod kalman;
+mod log_line;
+#[cfg(target_os = "none")]
 mod logging;
+mod params;
+mod pipeline;
+mod pyro;
+mod recovery;
+mod schedule;
 mod simulation;
+mod state;
 
+#[cfg(target_os = "none")]
 const LED_PERIOD: Duration = Duration::from_millis(1_000);
+/// Time between two samples while calibrating the IMU, in milliseconds.
+#[cfg(target_os = "none")]
+const CALIBRATION_PERIOD_MS: u32 = 10;
 /// The GPT output compare register we're using for tracking time. This is the first register,
 /// since we're using reset mode.
+#[cfg(target_os = "none")]
 const GPT_OCR: bsp::hal::gpt::OutputCompareRegister = bsp::hal::gpt::OutputCompareRegister::One;
 
+#[cfg(target_os = "none")]
 #[cortex_m_rt::entry]
 fn main() -> ! {
     let mut periphs = bsp::Peripherals::take().unwrap();
@@ -53,8 +84,43 @@ fn main() -> ! {
         gpt1
     };
 
-    // See the `logging` module docs for more info.
-    assert!(logging::init().is_ok());
+    // See the `logging` module docs for more info. Bridge messages share the USB serial port with
+    // the logs.
+    let _bridge = devices::usb::UsbDevice::new(logging::init().unwrap());
+
+    let mut systick = cortex_m::delay::Delay::with_source(
+        cortex_m::Peripherals::take().unwrap().SYST,
+        bsp::EXT_SYSTICK_HZ,
+        cortex_m::peripheral::syst::SystClkSource::External,
+    );
+    // The IMU is on the first I2C bus, on pins 19 (SCL) and 18 (SDA).
+    let (i2c1_builder, _, _, _) = periphs.i2c.clock(
+        &mut periphs.ccm.handle,
+        bsp::hal::ccm::i2c::ClockSelect::OSC,
+        bsp::hal::ccm::i2c::PrescalarSelect::DIVIDE_3,
+    );
+    let mut i2c1 = i2c1_builder.build(pins.p19, pins.p18);
+    if let Err(err) = i2c1.set_clock_speed(bsp::hal::i2c::ClockSpeed::KHz400) {
+        log::warn!("failed to set the I2C clock speed: {:?}", err);
+    }
+    let mut mpu6050 = mpu6050::Mpu6050::new(i2c1);
+    if let Err(err) = mpu6050.init(&mut systick) {
+        log::error!("failed to initialize the IMU: {:?}", err);
+    }
+
+    // SAFETY: this is the only storage, and nothing else programs the flash.
+    let mut storage = unsafe { flash::FlashStorage::new() };
+    let (mut config, saved) = match config::Config::load(&mut storage) {
+        Ok(config) => (config, true),
+        Err(err) => {
+            log::warn!("using the default configuration: {}", err);
+            (config::Config::default(), false)
+        }
+    };
+    let mut imu = device::calibration::Calibrated::new(mpu6050, config.imu_calibration);
+    if !saved {
+        calibrate(&mut imu, &mut config, &mut storage, &mut systick);
+    }
 
     timer.set_enable(true);
     loop {
@@ -65,3 +131,42 @@ fn main() -> ! {
         timer.output_compare_status(GPT_OCR).clear();
     }
 }
+
+/// Calibrate the IMU, which is held at rest and then turned with each axis up and down in turn.
+#[cfg(target_os = "none")]
+fn calibrate<I: device::Imu, S: config::Storage>(
+    imu: &mut device::calibration::Calibrated<I>,
+    config: &mut config::Config,
+    storage: &mut S,
+    delay: &mut cortex_m::delay::Delay,
+) where
+    I::Error: core::fmt::Debug,
+    S::Error: core::fmt::Debug,
+{
+    use device::calibration::{Calibrator, Progress};
+
+    let mut calibrator = Calibrator::new(200, 0.01, 0.1);
+    let mut progress = None;
+    let mut now = 0;
+    loop {
+        match pipeline::calibrate(&mut calibrator, imu, config, storage, now) {
+            Ok(Progress::Done) => return,
+            Ok(step) if progress != Some(step) => {
+                log::info!("calibrating: {:?}", step);
+                progress = Some(step);
+            }
+            Ok(_) => (),
+            Err(pipeline::CalibrateError::Config(err)) => {
+                log::error!("failed to save the calibration: {}", err);
+                return;
+            }
+            Err(err) => log::warn!("{}", err),
+        }
+        delay.delay_ms(CALIBRATION_PERIOD_MS);
+        now += u64::from(CALIBRATION_PERIOD_MS) * 1_000;
+    }
+}
+
+/// On the host, only the tests of the modules are built.
+#[cfg(not(target_os = "none"))]
+fn main() {}
@@ -0,0 +1,129 @@
+//! Onboard values of the tunable parameters (see [`flick_bridge::param`]).
+
+use flick_bridge::command::{Command, CommandError, CommandResponse};
+use flick_bridge::param::Param;
+use serde::{Deserialize, Serialize};
+
+/// Default value and range of a parameter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParamInfo {
+    pub default: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl ParamInfo {
+    const fn new(default: f32, min: f32, max: f32) -> Self {
+        Self { default, min, max }
+    }
+}
+
+/// Default value and range of the given parameter.
+pub fn info(param: Param) -> ParamInfo {
+    match param {
+        Param::LaunchAcceleration => ParamInfo::new(20.0, 5.0, 200.0),
+        Param::LaunchDebounce => ParamInfo::new(0.1, 0.0, 2.0),
+        Param::BurnoutAcceleration => ParamInfo::new(0.0, -20.0, 20.0),
+        Param::BurnoutDebounce => ParamInfo::new(0.1, 0.0, 2.0),
+        Param::ApogeeMinTime => ParamInfo::new(3.0, 0.0, 120.0),
+        Param::ApogeeLockoutSpeed => ParamInfo::new(240.0, 50.0, 1000.0),
+        Param::ApogeeConfirmTime => ParamInfo::new(0.2, 0.0, 5.0),
+        Param::LandingAltitudeWindow => ParamInfo::new(2.0, 0.1, 50.0),
+        Param::LandingVariance => ParamInfo::new(0.5, 0.0, 100.0),
+        Param::LandingTime => ParamInfo::new(5.0, 0.5, 60.0),
+        Param::DrogueChannel => ParamInfo::new(0.0, -1.0, 15.0),
+        Param::DrogueBackupChannel => ParamInfo::new(2.0, -1.0, 15.0),
+        Param::DrogueDelay => ParamInfo::new(0.0, 0.0, 10.0),
+        Param::DrogueBackupTime => ParamInfo::new(0.0, 0.0, 300.0),
+        Param::MainChannel => ParamInfo::new(1.0, -1.0, 15.0),
+        Param::MainBackupChannel => ParamInfo::new(3.0, -1.0, 15.0),
+        Param::MainAltitude => ParamInfo::new(150.0, 30.0, 3000.0),
+        Param::MainBackupTime => ParamInfo::new(0.0, 0.0, 600.0),
+        Param::RedundancyDelay => ParamInfo::new(1.0, 0.0, 10.0),
+        Param::GroundAveragingTime => ParamInfo::new(1.0, 0.1, 60.0),
+    }
+}
+
+/// Current values of every parameter.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Parameters {
+    values: [f32; Param::ALL.len()],
+}
+
+impl Parameters {
+    /// Instantiate new [`Parameters`], with their default values.
+    pub fn new() -> Self {
+        Self {
+            values: Param::ALL.map(|param| info(param).default),
+        }
+    }
+
+    /// Value of a parameter.
+    pub fn get(&self, param: Param) -> f32 {
+        self.values[param.id() as usize]
+    }
+
+    /// Value of a parameter given in seconds, in microseconds.
+    pub fn micros(&self, param: Param) -> u64 {
+        (self.get(param) * 1e6) as u64
+    }
+
+    /// Value of a parameter giving a pyro channel, or `None` if it is negative.
+    pub fn channel(&self, param: Param) -> Option<u16> {
+        let value = self.get(param);
+        (value >= 0.0).then_some(value as u16)
+    }
+
+    /// Value of a parameter given in seconds, in microseconds, or `None` if it is zero.
+    pub fn timer(&self, param: Param) -> Option<u64> {
+        Some(self.micros(param)).filter(|&micros| micros > 0)
+    }
+
+    /// Whether every value is in its range, such as after loading them.
+    pub fn valid(&self) -> bool {
+        Param::ALL
+            .iter()
+            .all(|&param| (info(param).min..=info(param).max).contains(&self.get(param)))
+    }
+
+    /// Set a parameter. Fails with [`CommandError::InvalidArgument`] if the value is out of range.
+    pub fn set(&mut self, param: Param, value: f32) -> Result<(), CommandError> {
+        let info = info(param);
+        // Also rejects NaN.
+        if !(info.min..=info.max).contains(&value) {
+            return Err(CommandError::InvalidArgument);
+        }
+
+        log::info!("{} = {}", param, value);
+        self.values[param.id() as usize] = value;
+        Ok(())
+    }
+
+    /// Handle the commands that read and write parameters. Returns `None` for every other
+    /// command.
+    pub fn handle(&mut self, command: &Command) -> Option<CommandResponse> {
+        let (id, value) = match *command {
+            Command::GetParameter(id) => (id, None),
+            Command::SetParameter(id, value) => (id, Some(value)),
+            _ => return None,
+        };
+
+        let param = match Param::from_id(id) {
+            Some(param) => param,
+            None => return Some(CommandResponse::Failed(CommandError::InvalidArgument)),
+        };
+        if let Some(value) = value {
+            if let Err(err) = self.set(param, value) {
+                return Some(CommandResponse::Failed(err));
+            }
+        }
+
+        Some(CommandResponse::Parameter(id, self.get(param)))
+    }
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,392 @@
+//! Sensor pipeline feeding the flight logic.
+//!
+//! The [`Pipeline`] reads the sensors through the [`Imu`] and [`Barometer`] traits, turns the
+//! pressure into an altitude above the pad with the [`Altimeter`], and the specific force into a
+//! vertical acceleration with the [`AttitudeFilter`]. Together they make the [`Sample`]s that feed
+//! the [`Detectors`], whose detections move the [`StateMachine`].
+//!
+//! Nothing is detected until the altimeter has a ground reference, since altitudes are measured
+//! from it.
+//!
+//! Before that, [`calibrate`] runs the [`Calibrator`] over the raw samples of the IMU, then uses
+//! the calibration and saves it in the [`Config`].
+
+use core::fmt;
+
+use crate::altitude::{Altimeter, AltimeterConfig, Altitude};
+use crate::attitude::{AttitudeConfig, AttitudeFilter};
+use crate::config::{Config, ConfigError, Storage};
+use crate::detect::{Detectors, Sample};
+use crate::device::calibration::{Calibrated, CalibrationError, Calibrator, Progress};
+use crate::device::{Barometer, Imu};
+use crate::params::Parameters;
+use crate::state::{StateMachine, Transition, Trigger};
+
+/// Transitions caused by a step of the [`Pipeline`].
+pub type Transitions = heapless::Vec<Transition, 2>;
+
+/// Sensor that failed to be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineError<I, B> {
+    Imu(I),
+    Barometer(B),
+}
+
+impl<I: fmt::Debug, B: fmt::Debug> fmt::Display for PipelineError<I, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::Imu(err) => write!(f, "IMU failed: {:?}", err)?,
+            PipelineError::Barometer(err) => write!(f, "barometer failed: {:?}", err)?,
+        };
+
+        Ok(())
+    }
+}
+
+/// Reason that a step of the calibration failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrateError<I, S> {
+    Imu(I),
+    /// The samples of the step were thrown away, since the sensor moved.
+    Calibration(CalibrationError),
+    /// The calibration is in use, but couldn't be saved.
+    Config(ConfigError<S>),
+}
+
+impl<I: fmt::Debug, S: fmt::Debug> fmt::Display for CalibrateError<I, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrateError::Imu(err) => write!(f, "IMU failed: {:?}", err)?,
+            CalibrateError::Calibration(err) => write!(f, "calibration failed: {}", err)?,
+            CalibrateError::Config(err) => write!(f, "calibration not saved: {}", err)?,
+        };
+
+        Ok(())
+    }
+}
+
+/// Feed a raw sample of the IMU to the calibrator at the given time. Once done, the IMU is
+/// calibrated with the result, which is saved in the configuration. Returns the current step.
+///
+/// Saving is tried again on the next call if it failed. If the samples give no sensible
+/// calibration, the calibrator starts over.
+pub fn calibrate<I: Imu, S: Storage>(
+    calibrator: &mut Calibrator,
+    imu: &mut Calibrated<I>,
+    config: &mut Config,
+    storage: &mut S,
+    now: u64,
+) -> Result<Progress, CalibrateError<I::Error, S::Error>> {
+    let sample = imu.read_raw(now).map_err(CalibrateError::Imu)?;
+    let progress = calibrator
+        .push(&sample)
+        .map_err(CalibrateError::Calibration)?;
+    if progress != Progress::Done {
+        return Ok(progress);
+    }
+
+    let calibration = match calibrator.calibration(imu.calibration()) {
+        Ok(calibration) => calibration,
+        Err(err) => {
+            calibrator.reset();
+            return Err(CalibrateError::Calibration(err));
+        }
+    };
+    log::info!("IMU calibrated: {:?}", calibration);
+    imu.set_calibration(calibration);
+    config.imu_calibration = calibration;
+    config.save(storage).map_err(CalibrateError::Config)?;
+    Ok(progress)
+}
+
+/// Sensor pipeline, from the sensors to the state machine.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    altimeter: Altimeter,
+    attitude: AttitudeFilter,
+    detectors: Detectors,
+    /// Latest sample fed to the detectors.
+    sample: Option<Sample>,
+}
+
+impl Pipeline {
+    /// Instantiate a new [`Pipeline`], configured from the parameters.
+    pub fn new(params: &Parameters) -> Self {
+        Self {
+            altimeter: Altimeter::new(AltimeterConfig::from(params)),
+            attitude: AttitudeFilter::new(AttitudeConfig::default()),
+            detectors: Detectors::new(params),
+            sample: None,
+        }
+    }
+
+    /// Altimeter, such as for handling its commands.
+    pub fn altimeter(&mut self) -> &mut Altimeter {
+        &mut self.altimeter
+    }
+
+    /// Estimate of the orientation.
+    pub fn attitude(&self) -> &AttitudeFilter {
+        &self.attitude
+    }
+
+    /// Detectors, which also estimate the altitude and vertical velocity.
+    pub fn detectors(&self) -> &Detectors {
+        &self.detectors
+    }
+
+    /// Latest sample fed to the detectors, once there was one.
+    pub fn sample(&self) -> Option<&Sample> {
+        self.sample.as_ref()
+    }
+
+    /// Read the sensors at the given time, update the estimates and the detectors, and trigger
+    /// the state machine with what was detected. Returns the transitions that it caused.
+    pub fn update<I: Imu, B: Barometer>(
+        &mut self,
+        imu: &mut I,
+        barometer: &mut B,
+        flight: &mut StateMachine,
+        now: u64,
+    ) -> Result<Transitions, PipelineError<I::Error, B::Error>> {
+        let imu = imu.read(now).map_err(PipelineError::Imu)?;
+        let barometer = barometer.read(now).map_err(PipelineError::Barometer)?;
+
+        let state = flight.state();
+        self.attitude.update(&imu, None, state);
+        let altitude = self.altimeter.update(&barometer, state);
+
+        let mut transitions = Transitions::new();
+        let agl = match altitude {
+            Some(Altitude { agl, .. }) => agl,
+            None => return Ok(transitions),
+        };
+        let sample = Sample {
+            time: imu.time,
+            altitude: agl,
+            acceleration: self.attitude.earth_acceleration(&imu).z,
+        };
+        self.sample = Some(sample);
+
+        for (detection, time) in self.detectors.update(&sample, flight) {
+            log::info!("detected {:?}", detection);
+            if let Some(transition) = flight.trigger(Trigger::Detected(detection), time) {
+                // Never full, since at most two events are detected on a sample.
+                transitions.push(transition).ok();
+            }
+        }
+
+        Ok(transitions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flick_bridge::state::FlightState;
+    use nalgebra::Vector3;
+
+    use super::*;
+    use crate::altitude::{LAPSE_RATE, SEA_LEVEL_PRESSURE, SEA_LEVEL_TEMPERATURE};
+    use crate::config::{OutOfBounds, RamStorage};
+    use crate::device::calibration::{ImuCalibration, Orientation};
+    use crate::device::mock::{Replay, ReplayError, Simulated};
+    use crate::device::{
+        Acceleration, AngularRate, BarometerSample, ImuSample, Pressure, STANDARD_GRAVITY,
+    };
+
+    /// Time between two samples, in microseconds.
+    const PERIOD: u64 = 10_000;
+
+    /// Altitude and vertical acceleration of a flight without drag: 2 s on the pad, then a motor
+    /// accelerating at 50 m/s² for 2 s, then a ballistic coast.
+    fn flight(time: u64) -> (f32, f32) {
+        let t = time as f32 / 1e6;
+        match t {
+            t if t < 2.0 => (0.0, 0.0),
+            t if t < 4.0 => (25.0 * (t - 2.0) * (t - 2.0), 50.0),
+            t => {
+                let t = t - 4.0;
+                (
+                    100.0 + 100.0 * t - 0.5 * STANDARD_GRAVITY * t * t,
+                    -STANDARD_GRAVITY,
+                )
+            }
+        }
+    }
+
+    /// Pressure at the given altitude above sea level, in the standard atmosphere.
+    fn pressure(altitude: f32) -> Pressure {
+        let temperature = SEA_LEVEL_TEMPERATURE.0 + 273.15;
+        let ratio = 1.0 - LAPSE_RATE * altitude / temperature;
+        Pressure(SEA_LEVEL_PRESSURE.0 * num_traits::Float::powf(ratio, 1.0 / 0.190_263))
+    }
+
+    #[test]
+    fn moves_the_state_machine_from_the_sensors() {
+        let mut imu = Simulated(|time| ImuSample {
+            time,
+            // The vehicle stands upright, and the specific force includes the reaction to
+            // gravity.
+            acceleration: Acceleration(Vector3::z() * (flight(time).1 + STANDARD_GRAVITY)),
+            angular_rate: AngularRate(Vector3::zeros()),
+        });
+        let mut barometer = Simulated(|time| BarometerSample {
+            time,
+            pressure: pressure(flight(time).0),
+            temperature: SEA_LEVEL_TEMPERATURE,
+        });
+        let mut machine = StateMachine::new(Default::default(), 0);
+        machine.request(FlightState::Armed.id(), 0).unwrap();
+        let mut pipeline = Pipeline::new(&Parameters::default());
+
+        let mut transitions = Vec::new();
+        for now in (0..20_000_000).step_by(PERIOD as usize) {
+            let caused = pipeline
+                .update(&mut imu, &mut barometer, &mut machine, now)
+                .unwrap();
+            transitions.extend(
+                caused
+                    .iter()
+                    .map(|transition| (transition.to, transition.time)),
+            );
+            // Nothing is fed to the detectors until there is a ground reference.
+            assert_eq!(pipeline.sample().is_some(), now >= 1_000_000);
+        }
+
+        assert_eq!(transitions[0], (FlightState::Boost, 2_000_000));
+        assert_eq!(transitions[1], (FlightState::Coast, 4_000_000));
+        let (state, apogee) = transitions[2];
+        assert_eq!(state, FlightState::Apogee);
+        // Apogee is 10.2 s after burnout.
+        assert!((14_000_000..15_000_000).contains(&apogee), "{}", apogee);
+        let altitude = pipeline.sample().unwrap().altitude;
+        assert!(
+            (altitude - flight(19_990_000).0).abs() < 1.0,
+            "{}",
+            altitude
+        );
+    }
+
+    #[test]
+    fn replays_a_recorded_flight() {
+        // The trace gives the altitude and vertical acceleration of an upright vehicle.
+        let trace: Vec<_> = include_str!("../testdata/flight.csv")
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split(',').collect();
+                let time: u64 = fields[0].parse().unwrap();
+                let altitude: f32 = fields[1].parse().unwrap();
+                let acceleration: f32 = fields[2].parse().unwrap();
+                (time, altitude, acceleration)
+            })
+            .collect();
+        let mut imu = Replay::new(trace.iter().map(|&(time, _, acceleration)| ImuSample {
+            time,
+            acceleration: Acceleration(Vector3::z() * (acceleration + STANDARD_GRAVITY)),
+            angular_rate: AngularRate(Vector3::zeros()),
+        }));
+        let mut barometer = Replay::new(trace.iter().map(|&(time, altitude, _)| BarometerSample {
+            time,
+            pressure: pressure(altitude),
+            temperature: SEA_LEVEL_TEMPERATURE,
+        }));
+        let mut machine = StateMachine::new(Default::default(), 0);
+        machine.request(FlightState::Armed.id(), 0).unwrap();
+        let mut pipeline = Pipeline::new(&Parameters::default());
+
+        let mut states = Vec::new();
+        let err = (0..)
+            .map(|i| i * PERIOD)
+            .find_map(
+                |now| match pipeline.update(&mut imu, &mut barometer, &mut machine, now) {
+                    Ok(caused) => {
+                        states.extend(caused.iter().map(|transition| transition.to));
+                        None
+                    }
+                    Err(err) => Some(err),
+                },
+            )
+            .unwrap();
+
+        assert_eq!(err, PipelineError::Imu(ReplayError::Ended));
+        assert_eq!(
+            states,
+            [
+                FlightState::Boost,
+                FlightState::Coast,
+                FlightState::Apogee,
+                FlightState::Landed
+            ]
+        );
+    }
+
+    /// Raw samples of a sensor with an accelerometer offset and a gyroscope bias, held at rest
+    /// with its axes up and down in turn, a second each.
+    fn turned(time: u64) -> ImuSample {
+        let orientation = Orientation::ALL[(time / 1_000_000) as usize % 6];
+        let axis = orientation as usize / 2;
+        let sign = if (orientation as usize).is_multiple_of(2) {
+            1.0
+        } else {
+            -1.0
+        };
+        let mut acceleration = Vector3::new(0.4, -0.3, 0.2);
+        acceleration[axis] += sign * STANDARD_GRAVITY;
+        ImuSample {
+            time,
+            acceleration: Acceleration(acceleration),
+            angular_rate: AngularRate(Vector3::new(0.01, 0.02, -0.03)),
+        }
+    }
+
+    /// Calibrate until done, returning the time it was done at, or the first error.
+    fn run_calibration<S: Storage>(
+        imu: &mut Calibrated<impl Imu<Error = core::convert::Infallible>>,
+        config: &mut Config,
+        storage: &mut S,
+    ) -> Result<u64, CalibrateError<core::convert::Infallible, S::Error>> {
+        let mut calibrator = Calibrator::new(50, 0.01, 0.1);
+        for now in (0..20_000_000).step_by(PERIOD as usize) {
+            if calibrate(&mut calibrator, imu, config, storage, now)? == Progress::Done {
+                return Ok(now);
+            }
+        }
+
+        panic!("calibration never done");
+    }
+
+    #[test]
+    fn calibrates_the_imu_and_saves_the_calibration() {
+        let mut imu = Calibrated::new(Simulated(turned), ImuCalibration::default());
+        let mut storage = RamStorage::<512>::new();
+        let mut config = Config::default();
+
+        let done = run_calibration(&mut imu, &mut config, &mut storage).unwrap();
+        // The gyroscope is sampled in the first half of the first orientation, then every
+        // orientation in its own half.
+        assert_eq!(done, 5_490_000);
+
+        assert_eq!(config.imu_calibration, *imu.calibration());
+        assert_eq!(Config::load(&mut storage), Ok(config));
+        let sample = imu.read(done).unwrap();
+        assert!(sample.angular_rate.0.norm() < 1e-4);
+        let expected = -Vector3::z() * STANDARD_GRAVITY;
+        assert!((sample.acceleration.0 - expected).norm() < 1e-3);
+    }
+
+    #[test]
+    fn keeps_the_calibration_it_failed_to_save() {
+        let mut imu = Calibrated::new(Simulated(turned), ImuCalibration::default());
+        let mut storage = RamStorage::<16>::new();
+        let mut config = Config::default();
+
+        let err = run_calibration(&mut imu, &mut config, &mut storage).unwrap_err();
+        assert_eq!(
+            err,
+            CalibrateError::Config(ConfigError::Storage(OutOfBounds))
+        );
+        assert_ne!(*imu.calibration(), ImuCalibration::default());
+        assert_eq!(config.imu_calibration, *imu.calibration());
+    }
+}
@@ -0,0 +1,514 @@
+//! Pyro channels, used to deploy parachutes.
+//!
+//! Each channel drives an e-match through an output pin, and senses its continuity through an ADC
+//! pin: an intact e-match lets a small current through, which goes away once it burnt. A channel
+//! only fires when it is armed and the flight state unlocks the pyros, and then stays on for a
+//! fixed pulse. Every firing is recorded along with the continuity before and after.
+
+use core::fmt;
+
+use embedded_hal::adc::{Channel, OneShot};
+use embedded_hal::digital::v2::OutputPin;
+use flick_bridge::command::{Command, CommandError, CommandResponse};
+use flick_bridge::state::FlightState;
+use heapless::Deque;
+
+/// Number of firings kept in the record.
+pub const MAX_FIRINGS: usize = 16;
+
+/// Reason that a pyro channel could not be operated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PyroError {
+    /// The channel doesn't exist.
+    InvalidChannel,
+    /// The channel isn't armed.
+    NotArmed,
+    /// The flight state doesn't allow firing.
+    LockedOut,
+    /// The channel is already firing.
+    Firing,
+    /// A pin or the ADC failed.
+    Hardware,
+}
+
+impl fmt::Display for PyroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PyroError::InvalidChannel => write!(f, "no such pyro channel")?,
+            PyroError::NotArmed => write!(f, "pyro channel not armed")?,
+            PyroError::LockedOut => write!(f, "pyros locked out in this state")?,
+            PyroError::Firing => write!(f, "pyro channel already firing")?,
+            PyroError::Hardware => write!(f, "pyro hardware failure")?,
+        };
+
+        Ok(())
+    }
+}
+
+impl From<PyroError> for CommandError {
+    fn from(err: PyroError) -> Self {
+        match err {
+            PyroError::InvalidChannel => CommandError::InvalidArgument,
+            PyroError::NotArmed | PyroError::LockedOut | PyroError::Firing => {
+                CommandError::InvalidState
+            }
+            PyroError::Hardware => CommandError::Hardware,
+        }
+    }
+}
+
+/// Configuration of the pyro channels.
+#[derive(Debug, Clone, Copy)]
+pub struct PyroConfig {
+    /// Time that a channel stays on when fired, in microseconds.
+    pub pulse: u64,
+    /// ADC reading above which a channel has continuity.
+    pub continuity_threshold: u16,
+    /// Returns whether the pyros may fire in the given state.
+    pub unlocked: fn(FlightState) -> bool,
+}
+
+impl Default for PyroConfig {
+    fn default() -> Self {
+        Self {
+            pulse: 500_000,
+            continuity_threshold: 512,
+            unlocked: |state| {
+                matches!(
+                    state,
+                    FlightState::Coast
+                        | FlightState::Apogee
+                        | FlightState::DrogueDescent
+                        | FlightState::MainDescent
+                )
+            },
+        }
+    }
+}
+
+/// Record of a channel firing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Firing {
+    pub channel: u16,
+    /// Time the channel was turned on.
+    pub time: u64,
+    /// Time the channel was turned off, once it was.
+    pub ended_at: Option<u64>,
+    /// State of the flight when fired.
+    pub state: FlightState,
+    /// Continuity sensed last before firing, if it was ever sensed.
+    pub continuity_before: Option<bool>,
+    /// Continuity sensed first after firing, once it was.
+    pub continuity_after: Option<bool>,
+}
+
+/// State of a single channel.
+#[derive(Debug, Clone, Copy, Default)]
+struct ChannelState {
+    armed: bool,
+    /// Time the channel was turned on, while firing.
+    firing_since: Option<u64>,
+    /// Last sensed continuity.
+    continuity: Option<bool>,
+}
+
+/// `N` pyro channels.
+pub struct Pyros<'a, E, const N: usize> {
+    config: PyroConfig,
+    outputs: [&'a mut dyn OutputPin<Error = E>; N],
+    channels: [ChannelState; N],
+    firings: Deque<Firing, MAX_FIRINGS>,
+}
+
+impl<'a, E, const N: usize> Pyros<'a, E, N> {
+    /// Instantiate new [`Pyros`], disarmed, turning every output off.
+    pub fn new(
+        config: PyroConfig,
+        mut outputs: [&'a mut dyn OutputPin<Error = E>; N],
+    ) -> Result<Self, PyroError> {
+        for output in outputs.iter_mut() {
+            output.set_low().map_err(|_| PyroError::Hardware)?;
+        }
+
+        Ok(Self {
+            config,
+            outputs,
+            channels: [ChannelState::default(); N],
+            firings: Deque::new(),
+        })
+    }
+
+    /// Whether a channel is armed.
+    pub fn armed(&self, channel: u16) -> Result<bool, PyroError> {
+        Ok(self.channel(channel)?.armed)
+    }
+
+    /// Whether a channel is firing.
+    pub fn firing(&self, channel: u16) -> Result<bool, PyroError> {
+        Ok(self.channel(channel)?.firing_since.is_some())
+    }
+
+    /// Last sensed continuity of a channel, if it was ever sensed.
+    pub fn continuity(&self, channel: u16) -> Result<Option<bool>, PyroError> {
+        Ok(self.channel(channel)?.continuity)
+    }
+
+    /// Iterate over the recorded firings, from oldest to newest.
+    pub fn firings(&self) -> impl Iterator<Item = &Firing> {
+        self.firings.iter()
+    }
+
+    /// Most recent firing of a channel.
+    pub fn last_firing(&self, channel: u16) -> Option<&Firing> {
+        self.firings
+            .iter()
+            .rev()
+            .find(|firing| firing.channel == channel)
+    }
+
+    /// Arm or disarm a channel. Disarming a channel that is firing turns it off right away.
+    pub fn set_armed(&mut self, channel: u16, armed: bool, now: u64) -> Result<(), PyroError> {
+        self.channel(channel)?;
+        if !armed {
+            self.turn_off(channel as usize, now)?;
+        }
+
+        log::info!("pyro {} armed: {}", channel, armed);
+        self.channels[channel as usize].armed = armed;
+        Ok(())
+    }
+
+    /// Disarm every channel, turning them off.
+    pub fn disarm_all(&mut self, now: u64) -> Result<(), PyroError> {
+        let mut result = Ok(());
+        for channel in 0..N {
+            // Keep disarming the other channels if one fails.
+            if let Err(err) = self.set_armed(channel as u16, false, now) {
+                result = Err(err);
+            }
+        }
+
+        result
+    }
+
+    /// Fire a channel for the configured pulse.
+    pub fn fire(&mut self, channel: u16, now: u64, state: FlightState) -> Result<(), PyroError> {
+        let current = self.channel(channel)?;
+        if !current.armed {
+            return Err(PyroError::NotArmed);
+        }
+        if !(self.config.unlocked)(state) {
+            return Err(PyroError::LockedOut);
+        }
+        if current.firing_since.is_some() {
+            return Err(PyroError::Firing);
+        }
+
+        self.outputs[channel as usize]
+            .set_high()
+            .map_err(|_| PyroError::Hardware)?;
+        self.channels[channel as usize].firing_since = Some(now);
+        log::warn!("pyro {} fired in {}", channel, state);
+
+        if self.firings.is_full() {
+            self.firings.pop_front();
+        }
+        // Can't fail since room was just made.
+        let _ = self.firings.push_back(Firing {
+            channel,
+            time: now,
+            ended_at: None,
+            state,
+            continuity_before: current.continuity,
+            continuity_after: None,
+        });
+        Ok(())
+    }
+
+    /// Turn off the channels whose pulse is over.
+    pub fn update(&mut self, now: u64) -> Result<(), PyroError> {
+        let mut result = Ok(());
+        for channel in 0..N {
+            let over = self.channels[channel]
+                .firing_since
+                .is_some_and(|since| now.saturating_sub(since) >= self.config.pulse);
+            if over {
+                if let Err(err) = self.turn_off(channel, now) {
+                    result = Err(err);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Sense the continuity of a channel through the ADC pin wired to it. Returns whether the
+    /// channel has continuity.
+    pub fn sense<ADC, A, S>(
+        &mut self,
+        channel: u16,
+        adc: &mut A,
+        pin: &mut S,
+    ) -> Result<bool, PyroError>
+    where
+        A: OneShot<ADC, u16, S>,
+        S: Channel<ADC>,
+    {
+        let current = self.channel(channel)?;
+        // The firing current would read as continuity.
+        if current.firing_since.is_some() {
+            return Err(PyroError::Firing);
+        }
+
+        let reading = nb::block!(adc.read(pin)).map_err(|_| PyroError::Hardware)?;
+        let continuity = reading > self.config.continuity_threshold;
+        self.channels[channel as usize].continuity = Some(continuity);
+
+        let last_firing = self
+            .firings
+            .iter_mut()
+            .rev()
+            .find(|firing| firing.channel == channel);
+        if let Some(firing) = last_firing {
+            if firing.continuity_after.is_none() {
+                firing.continuity_after = Some(continuity);
+            }
+        }
+
+        Ok(continuity)
+    }
+
+    /// Handle the commands that operate the pyros, given the current flight state. Returns `None`
+    /// for every other command. Toggling a channel arms or disarms it.
+    pub fn handle(
+        &mut self,
+        command: &Command,
+        now: u64,
+        state: FlightState,
+    ) -> Option<CommandResponse> {
+        let result = match *command {
+            Command::FirePyro(channel) => self.fire(channel, now, state),
+            Command::TogglePyro(channel) => self
+                .armed(channel)
+                .and_then(|armed| self.set_armed(channel, !armed, now)),
+            _ => return None,
+        };
+
+        Some(match result {
+            Ok(()) => CommandResponse::Done,
+            Err(err) => CommandResponse::Failed(err.into()),
+        })
+    }
+
+    fn channel(&self, channel: u16) -> Result<ChannelState, PyroError> {
+        self.channels
+            .get(channel as usize)
+            .copied()
+            .ok_or(PyroError::InvalidChannel)
+    }
+
+    fn turn_off(&mut self, channel: usize, now: u64) -> Result<(), PyroError> {
+        self.outputs[channel]
+            .set_low()
+            .map_err(|_| PyroError::Hardware)?;
+
+        if self.channels[channel].firing_since.take().is_some() {
+            let last_firing = self
+                .firings
+                .iter_mut()
+                .rev()
+                .find(|firing| firing.channel as usize == channel);
+            if let Some(firing) = last_firing {
+                firing.ended_at = Some(now);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::convert::Infallible;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// Pulse of the channels, in microseconds.
+    const PULSE: u64 = 300_000;
+
+    /// Output pin whose level is shared with the test.
+    #[derive(Clone, Default)]
+    struct Pin(Rc<Cell<bool>>);
+
+    impl OutputPin for Pin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    /// ADC whose reading of every channel is set by the test.
+    #[derive(Clone, Default)]
+    struct Adc(Rc<RefCell<[u16; 2]>>);
+
+    /// ADC pin wired to a channel.
+    struct Sense(usize);
+
+    impl Channel<Adc> for Sense {
+        type ID = u8;
+
+        fn channel() -> u8 {
+            0
+        }
+    }
+
+    impl OneShot<Adc, u16, Sense> for Adc {
+        type Error = Infallible;
+
+        fn read(&mut self, pin: &mut Sense) -> nb::Result<u16, Infallible> {
+            Ok(self.0.borrow()[pin.0])
+        }
+    }
+
+    /// Run the test with two channels, whose outputs are given too.
+    fn with_pyros(test: impl FnOnce(&mut Pyros<'_, Infallible, 2>, [Pin; 2])) {
+        let pins = [Pin::default(), Pin::default()];
+        let [mut a, mut b] = pins.clone();
+        let config = PyroConfig {
+            pulse: PULSE,
+            ..PyroConfig::default()
+        };
+        let mut pyros = Pyros::new(config, [&mut a, &mut b]).unwrap();
+        test(&mut pyros, pins);
+    }
+
+    #[test]
+    fn refuses_to_fire_unarmed_or_locked_out() {
+        with_pyros(|pyros, pins| {
+            assert_eq!(
+                pyros.fire(0, 0, FlightState::Coast),
+                Err(PyroError::NotArmed)
+            );
+
+            pyros.set_armed(0, true, 0).unwrap();
+            for state in [FlightState::Armed, FlightState::Boost, FlightState::Landed] {
+                assert_eq!(pyros.fire(0, 0, state), Err(PyroError::LockedOut));
+            }
+            assert_eq!(
+                pyros.fire(2, 0, FlightState::Coast),
+                Err(PyroError::InvalidChannel)
+            );
+            assert!(!pins[0].0.get());
+            assert_eq!(pyros.firings().count(), 0);
+
+            // Refusals map to the errors reported to the ground.
+            let response = pyros.handle(&Command::FirePyro(1), 0, FlightState::Coast);
+            assert!(matches!(
+                response,
+                Some(CommandResponse::Failed(CommandError::InvalidState))
+            ));
+        });
+    }
+
+    #[test]
+    fn ends_the_pulse_in_update() {
+        with_pyros(|pyros, pins| {
+            let response = pyros.handle(&Command::TogglePyro(1), 0, FlightState::Armed);
+            assert!(matches!(response, Some(CommandResponse::Done)));
+            assert!(pyros.armed(1).unwrap());
+
+            pyros.fire(1, 1_000, FlightState::Apogee).unwrap();
+            assert!(pins[1].0.get());
+            assert!(!pins[0].0.get());
+            assert_eq!(
+                pyros.fire(1, 2_000, FlightState::Apogee),
+                Err(PyroError::Firing)
+            );
+
+            pyros.update(1_000 + PULSE - 1).unwrap();
+            assert!(pyros.firing(1).unwrap());
+            pyros.update(1_000 + PULSE).unwrap();
+            assert!(!pyros.firing(1).unwrap());
+            assert!(!pins[1].0.get());
+
+            let firing = pyros.last_firing(1).unwrap();
+            assert_eq!(firing.time, 1_000);
+            assert_eq!(firing.ended_at, Some(1_000 + PULSE));
+            assert_eq!(firing.state, FlightState::Apogee);
+        });
+    }
+
+    #[test]
+    fn turns_off_when_disarmed_during_the_pulse() {
+        with_pyros(|pyros, pins| {
+            pyros.set_armed(0, true, 0).unwrap();
+            pyros.fire(0, 1_000, FlightState::Coast).unwrap();
+            assert!(pins[0].0.get());
+
+            pyros.set_armed(0, false, 2_000).unwrap();
+            assert!(!pins[0].0.get());
+            assert!(!pyros.firing(0).unwrap());
+            assert_eq!(pyros.last_firing(0).unwrap().ended_at, Some(2_000));
+
+            // The end of the pulse doesn't record it again.
+            pyros.update(1_000 + PULSE).unwrap();
+            assert_eq!(pyros.last_firing(0).unwrap().ended_at, Some(2_000));
+        });
+    }
+
+    #[test]
+    fn records_continuity_before_and_after_firing() {
+        with_pyros(|pyros, _| {
+            let adc = Adc::default();
+            adc.0.replace([900, 100]);
+            assert_eq!(pyros.sense(0, &mut adc.clone(), &mut Sense(0)), Ok(true));
+            assert_eq!(pyros.sense(1, &mut adc.clone(), &mut Sense(1)), Ok(false));
+
+            pyros.set_armed(0, true, 0).unwrap();
+            pyros.fire(0, 1_000, FlightState::Apogee).unwrap();
+            // The firing current would read as continuity.
+            assert_eq!(
+                pyros.sense(0, &mut adc.clone(), &mut Sense(0)),
+                Err(PyroError::Firing)
+            );
+
+            pyros.update(1_000 + PULSE).unwrap();
+            adc.0.replace([50, 100]);
+            assert_eq!(pyros.sense(0, &mut adc.clone(), &mut Sense(0)), Ok(false));
+            // Only the first reading after firing is kept.
+            adc.0.replace([900, 100]);
+            assert_eq!(pyros.sense(0, &mut adc.clone(), &mut Sense(0)), Ok(true));
+
+            let firing = pyros.last_firing(0).unwrap();
+            assert_eq!(firing.continuity_before, Some(true));
+            assert_eq!(firing.continuity_after, Some(false));
+            assert_eq!(pyros.continuity(0), Ok(Some(true)));
+        });
+    }
+
+    #[test]
+    fn evicts_the_oldest_firings() {
+        with_pyros(|pyros, _| {
+            pyros.set_armed(0, true, 0).unwrap();
+            let count = MAX_FIRINGS as u64 + 3;
+            for i in 0..count {
+                let now = i * PULSE;
+                pyros.update(now).unwrap();
+                pyros.fire(0, now, FlightState::DrogueDescent).unwrap();
+            }
+
+            let times: Vec<_> = pyros.firings().map(|firing| firing.time).collect();
+            let expected: Vec<_> = (3..count).map(|i| i * PULSE).collect();
+            assert_eq!(times, expected);
+            assert_eq!(pyros.last_firing(0).unwrap().time, (count - 1) * PULSE);
+        });
+    }
+}
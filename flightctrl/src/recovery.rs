@@ -0,0 +1,512 @@
+//! Dual-deploy recovery: a drogue parachute at apogee, and a main parachute at a lower altitude.
+//!
+//! Each parachute has a primary and an optional redundant pyro channel. The drogue deploys a
+//! delay after apogee, and the main once the vehicle descends below its altitude. Backup timers
+//! deploy either one if their event never comes. The redundant channel fires if the primary
+//! couldn't fire, or if it showed no continuity change a while after firing, meaning its e-match
+//! may not have burnt. A parachute only counts as deployed once one of its channels fired, so both
+//! are tried again until one does. Channels are only sensed when the caller runs [`Pyros::sense`], so without
+//! continuity sensing the redundant channel always fires.
+//!
+//! The configuration is read from the [`Parameters`] on every update, so that it can be set over
+//! the bridge at any time. The altitude is taken from the [`VerticalKalman`] estimate of the
+//! detectors (see [`Detectors::estimate`]), never from the barometer alone, whose noise and
+//! ejection spikes could fire the main early.
+//!
+//! [`Detectors::estimate`]: crate::detect::Detectors::estimate
+
+use flick_bridge::param::Param;
+use flick_bridge::state::FlightState;
+
+use crate::kalman::VerticalKalman;
+use crate::params::Parameters;
+use crate::pyro::{PyroError, Pyros};
+use crate::state::{Transition, Trigger};
+
+/// Pyro channels of a parachute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Channels {
+    pub primary: Option<u16>,
+    pub redundant: Option<u16>,
+}
+
+/// Configuration of the recovery.
+#[derive(Debug, Clone, Copy)]
+pub struct RecoveryConfig {
+    pub drogue: Channels,
+    /// Time between apogee and the drogue deployment, in microseconds.
+    pub drogue_delay: u64,
+    /// Time after launch at which the drogue deploys if apogee wasn't detected, in microseconds.
+    pub drogue_backup_time: Option<u64>,
+    pub main: Channels,
+    /// Altitude above the pad at which the main deploys, in meters.
+    pub main_altitude: f32,
+    /// Time after apogee, or the drogue deployment if apogee wasn't detected, at which the main
+    /// deploys if it didn't yet, in microseconds.
+    pub main_backup_time: Option<u64>,
+    /// Time after firing a primary channel before firing its redundant channel, in microseconds.
+    pub redundancy_delay: u64,
+}
+
+impl From<&Parameters> for RecoveryConfig {
+    fn from(params: &Parameters) -> Self {
+        Self {
+            drogue: Channels {
+                primary: params.channel(Param::DrogueChannel),
+                redundant: params.channel(Param::DrogueBackupChannel),
+            },
+            drogue_delay: params.micros(Param::DrogueDelay),
+            drogue_backup_time: params.timer(Param::DrogueBackupTime),
+            main: Channels {
+                primary: params.channel(Param::MainChannel),
+                redundant: params.channel(Param::MainBackupChannel),
+            },
+            main_altitude: params.get(Param::MainAltitude),
+            main_backup_time: params.timer(Param::MainBackupTime),
+            redundancy_delay: params.micros(Param::RedundancyDelay),
+        }
+    }
+}
+
+/// Parachute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parachute {
+    Drogue,
+    Main,
+}
+
+/// Pyro channel fired by the recovery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deployment {
+    pub parachute: Parachute,
+    pub channel: u16,
+    /// Whether this is the redundant channel of the parachute.
+    pub redundant: bool,
+}
+
+impl Deployment {
+    /// Trigger of the state machine for this deployment.
+    pub fn trigger(&self) -> Trigger {
+        match self.parachute {
+            Parachute::Drogue => Trigger::DrogueDeployed,
+            Parachute::Main => Trigger::MainDeployed,
+        }
+    }
+}
+
+/// Progress of the deployment of a parachute.
+#[derive(Debug, Clone, Copy, Default)]
+struct Stage {
+    /// Time that a channel of the parachute first fired at.
+    fired_at: Option<u64>,
+    /// Whether the redundant channel was dealt with.
+    redundant_done: bool,
+}
+
+/// Recovery controller.
+#[derive(Debug, Clone, Default)]
+pub struct Recovery {
+    launched_at: Option<u64>,
+    apogee_at: Option<u64>,
+    drogue: Stage,
+    main: Stage,
+}
+
+impl Recovery {
+    /// Instantiate a new [`Recovery`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a transition of the flight state machine.
+    pub fn transition(&mut self, transition: &Transition) {
+        match transition.to {
+            FlightState::Boost => self.launched_at = Some(transition.time),
+            FlightState::Apogee => self.apogee_at = Some(transition.time),
+            _ => {}
+        }
+    }
+
+    /// Fire whatever is due, given the current parameters and the estimate of the altitude above
+    /// the pad. Returns the channel that was fired, if any. At most one channel fires per update.
+    pub fn update<E, const N: usize>(
+        &mut self,
+        params: &Parameters,
+        now: u64,
+        estimate: &VerticalKalman<f32>,
+        state: FlightState,
+        pyros: &mut Pyros<'_, E, N>,
+    ) -> Option<Deployment> {
+        let config = RecoveryConfig::from(params);
+        let since = |time: Option<u64>| time.map(|time| now.saturating_sub(time));
+
+        let drogue_due = since(self.apogee_at) >= Some(config.drogue_delay)
+            || config.drogue_backup_time.is_some()
+                && since(self.launched_at) >= config.drogue_backup_time;
+        // If apogee wasn't detected, the vehicle is descending once the drogue deployed.
+        let descending_since = self.apogee_at.or(self.drogue.fired_at);
+        let main_due = descending_since.is_some() && estimate.altitude() <= config.main_altitude
+            || config.main_backup_time.is_some()
+                && since(descending_since) >= config.main_backup_time;
+
+        if drogue_due {
+            let deployed =
+                self.drogue
+                    .deploy(Parachute::Drogue, config.drogue, &config, now, state, pyros);
+            if deployed.is_some() {
+                return deployed;
+            }
+        }
+        if main_due {
+            return self
+                .main
+                .deploy(Parachute::Main, config.main, &config, now, state, pyros);
+        }
+
+        None
+    }
+}
+
+impl Stage {
+    /// Fire the next channel of a parachute that is due, if any.
+    fn deploy<E, const N: usize>(
+        &mut self,
+        parachute: Parachute,
+        channels: Channels,
+        config: &RecoveryConfig,
+        now: u64,
+        state: FlightState,
+        pyros: &mut Pyros<'_, E, N>,
+    ) -> Option<Deployment> {
+        let fire = |pyros: &mut Pyros<'_, E, N>, channel: Option<u16>, redundant: bool| {
+            let channel = channel.ok_or(PyroError::InvalidChannel)?;
+            pyros.fire(channel, now, state).inspect_err(|err| {
+                log::error!("{:?} pyro {} failed to fire: {}", parachute, channel, err);
+            })?;
+            Ok(Deployment {
+                parachute,
+                channel,
+                redundant,
+            })
+        };
+
+        let fired_at = match self.fired_at {
+            Some(fired_at) => fired_at,
+            None => {
+                let deployment = match fire(pyros, channels.primary, false) {
+                    // Wait for the state to unlock the pyros.
+                    Err(PyroError::LockedOut) => return None,
+                    // Without a working primary, the redundant channel fires right away. If it
+                    // can't either, both are tried again on the next update.
+                    Err(_) => {
+                        let deployment = fire(pyros, channels.redundant, true).ok()?;
+                        self.redundant_done = true;
+                        deployment
+                    }
+                    Ok(deployment) => deployment,
+                };
+                self.fired_at = Some(now);
+                return Some(deployment);
+            }
+        };
+
+        if self.redundant_done || now.saturating_sub(fired_at) < config.redundancy_delay {
+            return None;
+        }
+
+        // The e-match of the primary went from continuity to none if it burnt.
+        let burnt = channels
+            .primary
+            .and_then(|primary| pyros.last_firing(primary))
+            .is_some_and(|firing| {
+                firing.continuity_before == Some(true) && firing.continuity_after == Some(false)
+            });
+        if burnt {
+            self.redundant_done = true;
+            return None;
+        }
+
+        let result = fire(pyros, channels.redundant, true);
+        if result != Err(PyroError::LockedOut) {
+            self.redundant_done = true;
+            log::warn!("{:?} primary showed no continuity change", parachute);
+        }
+        result.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::convert::Infallible;
+    use std::rc::Rc;
+
+    use embedded_hal::adc::{Channel, OneShot};
+    use embedded_hal::digital::v2::OutputPin;
+
+    use super::*;
+    use crate::kalman::KalmanConfig;
+    use crate::pyro::PyroConfig;
+
+    const DROGUE: u16 = 0;
+    const MAIN: u16 = 1;
+    const DROGUE_BACKUP: u16 = 2;
+    const MAIN_BACKUP: u16 = 3;
+
+    /// Output pin that does nothing.
+    struct Pin;
+
+    impl OutputPin for Pin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    /// ADC reading whatever the test sets, on every channel.
+    #[derive(Clone, Default)]
+    struct Adc(Rc<RefCell<u16>>);
+
+    struct Sense;
+
+    impl Channel<Adc> for Sense {
+        type ID = u8;
+
+        fn channel() -> u8 {
+            0
+        }
+    }
+
+    impl OneShot<Adc, u16, Sense> for Adc {
+        type Error = Infallible;
+
+        fn read(&mut self, _pin: &mut Sense) -> nb::Result<u16, Infallible> {
+            Ok(*self.0.borrow())
+        }
+    }
+
+    /// Flight driving the recovery, with the default channels armed.
+    struct Flight {
+        params: Parameters,
+        recovery: Recovery,
+        state: FlightState,
+        /// Channels fired so far, with the time they fired at.
+        fired: Vec<(Deployment, u64)>,
+    }
+
+    impl Flight {
+        fn new() -> Self {
+            let mut recovery = Recovery::new();
+            recovery.transition(&Transition {
+                from: FlightState::Armed,
+                to: FlightState::Boost,
+                time: 0,
+            });
+            Self {
+                params: Parameters::default(),
+                recovery,
+                state: FlightState::Coast,
+                fired: Vec::new(),
+            }
+        }
+
+        fn params(mut self, params: &[(Param, f32)]) -> Self {
+            for &(param, value) in params {
+                self.params.set(param, value).unwrap();
+            }
+            self
+        }
+
+        fn apogee(&mut self, time: u64) {
+            self.state = FlightState::Apogee;
+            self.recovery.transition(&Transition {
+                from: FlightState::Coast,
+                to: FlightState::Apogee,
+                time,
+            });
+        }
+
+        /// Update the recovery every 10 ms over the given times, with the altitude estimated at
+        /// the given one.
+        fn run<E>(
+            &mut self,
+            pyros: &mut Pyros<'_, E, 4>,
+            times: core::ops::Range<u64>,
+            altitude: f32,
+        ) {
+            for now in times.step_by(10_000) {
+                pyros.update(now).ok();
+                // A first measurement is taken as is, so that the filter has no say here.
+                let mut estimate = VerticalKalman::new(KalmanConfig::default());
+                estimate.update_altitude(now, altitude);
+                let deployment =
+                    self.recovery
+                        .update(&self.params, now, &estimate, self.state, pyros);
+                if let Some(deployment) = deployment {
+                    self.fired.push((deployment, now));
+                }
+            }
+        }
+
+        /// Channels fired so far.
+        fn channels(&self) -> Vec<u16> {
+            self.fired
+                .iter()
+                .map(|(deployment, _)| deployment.channel)
+                .collect()
+        }
+    }
+
+    /// Run the test with the four default channels, armed as given.
+    fn with_pyros(armed: &[u16], test: impl FnOnce(&mut Pyros<'_, Infallible, 4>)) {
+        let [mut a, mut b, mut c, mut d] = [Pin, Pin, Pin, Pin];
+        let mut pyros =
+            Pyros::new(PyroConfig::default(), [&mut a, &mut b, &mut c, &mut d]).unwrap();
+        for &channel in armed {
+            pyros.set_armed(channel, true, 0).unwrap();
+        }
+        test(&mut pyros);
+    }
+
+    const ALL: [u16; 4] = [DROGUE, MAIN, DROGUE_BACKUP, MAIN_BACKUP];
+
+    #[test]
+    fn deploys_at_apogee_and_at_the_main_altitude() {
+        with_pyros(&ALL, |pyros| {
+            let mut flight = Flight::new().params(&[(Param::DrogueDelay, 1.0)]);
+            flight.apogee(10_000_000);
+            flight.run(pyros, 10_000_000..11_000_000, 500.0);
+            assert!(flight.fired.is_empty());
+            flight.run(pyros, 11_000_000..11_010_000, 500.0);
+            assert_eq!(flight.fired[0].0.parachute, Parachute::Drogue);
+            assert_eq!(flight.fired[0].0.trigger(), Trigger::DrogueDeployed);
+            assert_eq!(flight.channels(), [DROGUE]);
+
+            // Without continuity sensing, the redundant channel fires after the delay.
+            flight.run(pyros, 11_010_000..12_010_000, 400.0);
+            assert_eq!(flight.channels(), [DROGUE, DROGUE_BACKUP]);
+            assert_eq!(flight.fired[1].1, 12_000_000);
+            assert!(flight.fired[1].0.redundant);
+
+            flight.run(pyros, 12_010_000..20_000_000, 151.0);
+            assert_eq!(flight.fired.len(), 2);
+            flight.run(pyros, 20_000_000..20_010_000, 150.0);
+            assert_eq!(flight.fired[2].0.parachute, Parachute::Main);
+            flight.run(pyros, 20_010_000..30_000_000, 100.0);
+            assert_eq!(
+                flight.channels(),
+                [DROGUE, DROGUE_BACKUP, MAIN, MAIN_BACKUP]
+            );
+        });
+    }
+
+    #[test]
+    fn applies_parameters_set_in_flight() {
+        with_pyros(&ALL, |pyros| {
+            let mut flight = Flight::new().params(&[
+                (Param::DrogueDelay, 5.0),
+                (Param::DrogueBackupChannel, -1.0),
+            ]);
+            flight.apogee(10_000_000);
+            flight.run(pyros, 10_000_000..12_000_000, 500.0);
+            assert!(flight.fired.is_empty());
+
+            // Shortened over the bridge while waiting.
+            flight.params.set(Param::DrogueDelay, 2.0).unwrap();
+            flight.params.set(Param::MainAltitude, 300.0).unwrap();
+            flight.run(pyros, 12_000_000..15_000_000, 500.0);
+            assert_eq!(flight.channels(), [DROGUE]);
+            assert_eq!(flight.fired[0].1, 12_000_000);
+            flight.run(pyros, 15_000_000..15_010_000, 300.0);
+            assert_eq!(flight.channels(), [DROGUE, MAIN]);
+        });
+    }
+
+    #[test]
+    fn skips_the_redundant_channel_once_the_primary_burnt() {
+        with_pyros(&ALL, |pyros| {
+            let adc = Adc::default();
+            adc.0.replace(900);
+            pyros.sense(DROGUE, &mut adc.clone(), &mut Sense).unwrap();
+
+            let mut flight = Flight::new();
+            flight.apogee(10_000_000);
+            flight.run(pyros, 10_000_000..10_600_000, 500.0);
+            adc.0.replace(0);
+            pyros.sense(DROGUE, &mut adc.clone(), &mut Sense).unwrap();
+            flight.run(pyros, 10_600_000..15_000_000, 500.0);
+            assert_eq!(flight.channels(), [DROGUE]);
+        });
+    }
+
+    #[test]
+    fn fires_the_redundant_channel_when_the_primary_is_unarmed() {
+        with_pyros(&[DROGUE_BACKUP, MAIN], |pyros| {
+            let mut flight = Flight::new();
+            flight.apogee(10_000_000);
+            flight.run(pyros, 10_000_000..15_000_000, 500.0);
+            assert_eq!(flight.channels(), [DROGUE_BACKUP]);
+            assert_eq!(flight.fired[0].1, 10_000_000);
+            assert!(flight.fired[0].0.redundant);
+        });
+    }
+
+    #[test]
+    fn waits_for_a_channel_that_can_fire() {
+        with_pyros(&[MAIN], |pyros| {
+            let mut flight = Flight::new().params(&[
+                (Param::DrogueBackupTime, 20.0),
+                (Param::MainBackupTime, 5.0),
+            ]);
+            flight.run(pyros, 0..30_000_000, 800.0);
+            // Neither drogue channel is armed, so the drogue isn't deployed, and the main backup
+            // timer doesn't start either.
+            assert!(flight.fired.is_empty());
+
+            pyros.set_armed(DROGUE, true, 30_000_000).unwrap();
+            flight.run(pyros, 30_000_000..30_010_000, 800.0);
+            assert_eq!(flight.channels(), [DROGUE]);
+            assert_eq!(flight.fired[0].1, 30_000_000);
+        });
+    }
+
+    #[test]
+    fn deploys_on_the_backup_timers() {
+        with_pyros(&ALL, |pyros| {
+            let mut flight = Flight::new().params(&[
+                (Param::DrogueBackupTime, 20.0),
+                (Param::MainBackupTime, 30.0),
+                (Param::DrogueBackupChannel, -1.0),
+                (Param::MainBackupChannel, -1.0),
+            ]);
+            // Apogee is never detected, and the altitude never falls below the main altitude.
+            flight.run(pyros, 0..20_000_000, 800.0);
+            assert!(flight.fired.is_empty());
+            flight.state = FlightState::DrogueDescent;
+            flight.run(pyros, 20_000_000..50_000_000, 800.0);
+            assert_eq!(flight.channels(), [DROGUE]);
+            assert_eq!(flight.fired[0].1, 20_000_000);
+            flight.run(pyros, 50_000_000..60_000_000, 800.0);
+            assert_eq!(flight.channels(), [DROGUE, MAIN]);
+            assert_eq!(flight.fired[1].1, 50_000_000);
+        });
+    }
+
+    #[test]
+    fn waits_for_the_state_to_unlock_the_pyros() {
+        with_pyros(&ALL, |pyros| {
+            let mut flight = Flight::new().params(&[(Param::DrogueBackupTime, 5.0)]);
+            flight.state = FlightState::Boost;
+            flight.run(pyros, 0..10_000_000, 800.0);
+            assert!(flight.fired.is_empty());
+            flight.state = FlightState::Coast;
+            flight.run(pyros, 10_000_000..10_010_000, 800.0);
+            assert_eq!(flight.channels(), [DROGUE]);
+        });
+    }
+}
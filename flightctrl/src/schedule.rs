@@ -0,0 +1,239 @@
+//! Onboard queue of time-tagged commands (see [`flick_bridge::command::schedule`]).
+
+use flick_bridge::command::schedule::{
+    ScheduleList, ScheduledAction, ScheduledCommand, Trigger, MAX_LISTED,
+};
+use flick_bridge::command::{Command, CommandError, CommandResponse};
+use flick_bridge::event::Detection;
+
+/// Holds up to `N` scheduled commands until they are due.
+#[derive(Debug, Clone)]
+pub struct CommandQueue<const N: usize> {
+    commands: heapless::Vec<ScheduledCommand, N>,
+    /// Identifier of the next scheduled command.
+    next_id: u32,
+    /// Time that each flight event was detected at, indexed by [`index`].
+    detected: [Option<u64>; 4],
+}
+
+impl<const N: usize> CommandQueue<N> {
+    /// Instantiate a new, empty [`CommandQueue`].
+    pub fn new() -> Self {
+        Self {
+            commands: heapless::Vec::new(),
+            next_id: 0,
+            detected: [None; 4],
+        }
+    }
+
+    /// Handle the commands that manage the queue. Returns `None` for every other command.
+    pub fn handle(&mut self, command: &Command) -> Option<CommandResponse> {
+        let response = match *command {
+            Command::Schedule(trigger, action) => match self.schedule(trigger, action) {
+                Some(id) => CommandResponse::Scheduled(id),
+                None => CommandResponse::Failed(CommandError::InvalidState),
+            },
+            Command::ListScheduled(start) => CommandResponse::ScheduleList(self.list(start)),
+            Command::CancelScheduled(id) => match self.cancel(id) {
+                true => CommandResponse::Done,
+                false => CommandResponse::Failed(CommandError::InvalidArgument),
+            },
+            _ => return None,
+        };
+
+        Some(response)
+    }
+
+    /// Queue an action, returning its identifier, or `None` if the queue is full.
+    pub fn schedule(&mut self, trigger: Trigger, action: ScheduledAction) -> Option<u32> {
+        let id = self.next_id;
+        self.commands
+            .push(ScheduledCommand {
+                id,
+                trigger,
+                action,
+            })
+            .ok()?;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        log::info!("scheduled command {}: {:?} {:?}", id, trigger, action);
+        Some(id)
+    }
+
+    /// Up to [`MAX_LISTED`] queued commands, starting at the given index.
+    pub fn list(&self, start: u16) -> ScheduleList {
+        self.commands
+            .iter()
+            .skip(start as usize)
+            .take(MAX_LISTED)
+            .copied()
+            .collect()
+    }
+
+    /// Remove a queued command. Returns whether it was queued.
+    pub fn cancel(&mut self, id: u32) -> bool {
+        match self.commands.iter().position(|command| command.id == id) {
+            Some(index) => {
+                self.commands.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Record that a flight event was detected, which starts the countdown of the commands
+    /// triggered by it. Only the first detection of every event counts.
+    pub fn detected(&mut self, detection: Detection, now: u64) {
+        self.detected[index(detection)].get_or_insert(now);
+    }
+
+    /// Forget the flight events detected so far, when a new flight starts. Queued commands stay,
+    /// and those triggered by an event wait for it to be detected in the new flight.
+    pub fn reset(&mut self) {
+        self.detected = [None; 4];
+    }
+
+    /// Remove and return the next command that is due.
+    pub fn due(&mut self, now: u64) -> Option<ScheduledCommand> {
+        let index = self.commands.iter().position(|command| {
+            let at = match command.trigger {
+                Trigger::At(at) => Some(at),
+                Trigger::After(detection, delay) => {
+                    self.detected[index(detection)].map(|detected| detected.saturating_add(delay))
+                }
+            };
+            at.is_some_and(|at| now >= at)
+        })?;
+
+        Some(self.commands.remove(index))
+    }
+}
+
+impl<const N: usize> Default for CommandQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Index of a flight event in [`CommandQueue::detected`].
+fn index(detection: Detection) -> usize {
+    match detection {
+        Detection::Launch => 0,
+        Detection::Burnout => 1,
+        Detection::Apogee => 2,
+        Detection::Landing => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000;
+
+    fn ids(queue: &CommandQueue<8>) -> heapless::Vec<u32, MAX_LISTED> {
+        queue.list(0).iter().map(|command| command.id).collect()
+    }
+
+    #[test]
+    fn runs_commands_in_the_order_they_are_due() {
+        let mut queue = CommandQueue::<8>::new();
+        let late = queue
+            .schedule(Trigger::At(3 * SECOND), ScheduledAction::FirePyro(1))
+            .unwrap();
+        let early = queue
+            .schedule(Trigger::At(SECOND), ScheduledAction::FirePyro(0))
+            .unwrap();
+
+        assert_eq!(queue.due(SECOND - 1), None);
+        assert_eq!(queue.due(SECOND).map(|command| command.id), Some(early));
+        assert_eq!(queue.due(2 * SECOND), None);
+        // Commands that are overdue still run.
+        let command = queue.due(10 * SECOND).unwrap();
+        assert_eq!(command.id, late);
+        assert!(matches!(Command::from(command.action), Command::FirePyro(1)));
+        assert_eq!(queue.due(10 * SECOND), None);
+    }
+
+    #[test]
+    fn counts_down_from_the_first_detection() {
+        let mut queue = CommandQueue::<8>::new();
+        let id = queue
+            .schedule(
+                Trigger::After(Detection::Launch, 2 * SECOND),
+                ScheduledAction::TogglePyro(2),
+            )
+            .unwrap();
+        assert_eq!(queue.due(100 * SECOND), None);
+
+        queue.detected(Detection::Apogee, 5 * SECOND);
+        assert_eq!(queue.due(100 * SECOND), None);
+        queue.detected(Detection::Launch, 10 * SECOND);
+        queue.detected(Detection::Launch, 11 * SECOND);
+        assert_eq!(queue.due(12 * SECOND - 1), None);
+        assert_eq!(queue.due(12 * SECOND).map(|command| command.id), Some(id));
+    }
+
+    #[test]
+    fn forgets_detections_when_a_new_flight_starts() {
+        let mut queue = CommandQueue::<8>::new();
+        queue.detected(Detection::Launch, 10 * SECOND);
+        queue.reset();
+        let id = queue
+            .schedule(
+                Trigger::After(Detection::Launch, SECOND),
+                ScheduledAction::ChangeState(0),
+            )
+            .unwrap();
+
+        assert_eq!(queue.due(100 * SECOND), None);
+        queue.detected(Detection::Launch, 200 * SECOND);
+        assert_eq!(queue.due(201 * SECOND).map(|command| command.id), Some(id));
+    }
+
+    #[test]
+    fn lists_and_cancels_commands() {
+        let mut queue = CommandQueue::<8>::new();
+        for channel in 0..4 {
+            queue
+                .schedule(Trigger::At(SECOND), ScheduledAction::FirePyro(channel))
+                .unwrap();
+        }
+
+        assert!(queue.cancel(1));
+        assert!(!queue.cancel(1));
+        assert_eq!(ids(&queue), [0, 2, 3]);
+        assert_eq!(queue.list(1)[0].action, ScheduledAction::FirePyro(2));
+        assert!(queue.list(3).is_empty());
+        // Identifiers aren't reused.
+        assert_eq!(
+            queue.schedule(Trigger::At(SECOND), ScheduledAction::FirePyro(1)),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn handles_the_commands_that_manage_the_queue() {
+        let mut queue = CommandQueue::<1>::new();
+        let schedule = Command::Schedule(Trigger::At(SECOND), ScheduledAction::FirePyro(0));
+        assert_eq!(queue.handle(&schedule), Some(CommandResponse::Scheduled(0)));
+        assert_eq!(
+            queue.handle(&schedule),
+            Some(CommandResponse::Failed(CommandError::InvalidState))
+        );
+
+        match queue.handle(&Command::ListScheduled(0)) {
+            Some(CommandResponse::ScheduleList(list)) => assert_eq!(list.len(), 1),
+            response => panic!("unexpected response {:?}", response),
+        }
+        assert_eq!(
+            queue.handle(&Command::CancelScheduled(1)),
+            Some(CommandResponse::Failed(CommandError::InvalidArgument))
+        );
+        assert_eq!(
+            queue.handle(&Command::CancelScheduled(0)),
+            Some(CommandResponse::Done)
+        );
+        assert_eq!(queue.handle(&Command::Heartbeat), None);
+    }
+}
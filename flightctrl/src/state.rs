@@ -0,0 +1,355 @@
+//! Flight state machine (see [`flick_bridge::state::FlightState`]).
+//!
+//! The flight moves forward on [`Trigger`]s derived from the sensors and the pyros. The ground can
+//! arm, disarm and abort on the pad through [`Command::ChangeState`], and push a flying vehicle to
+//! the next state if a detection was missed. Every transition is guarded, so triggers that make no
+//! sense in the current state are ignored.
+//!
+//! The time of the launch is the time that [`FlightState::Boost`] was entered at, which the
+//! detectors and the recovery all take from here. Apogee is only looked for once its lockout after
+//! that time is over (see [`ApogeeConfig::min_time`]).
+//!
+//! [`ApogeeConfig::min_time`]: crate::detect::apogee::ApogeeConfig::min_time
+//!
+//! [`Command::ChangeState`]: flick_bridge::command::Command::ChangeState
+
+use flick_bridge::command::CommandError;
+use flick_bridge::event::{Detection, EventKind, VehicleEvent};
+use flick_bridge::state::FlightState;
+
+use crate::failsafe::FlightPhase;
+
+/// Something that happened onboard which may move the flight to another state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// A flight event was detected from the sensors.
+    Detected(Detection),
+    /// The drogue parachute was deployed.
+    DrogueDeployed,
+    /// The main parachute was deployed.
+    MainDeployed,
+    /// Something makes it unsafe to fly, such as a sensor failing on the pad. Ignored in flight,
+    /// where the recovery must carry on regardless.
+    Fault,
+}
+
+/// Guards of the state machine.
+#[derive(Debug, Clone, Copy)]
+pub struct StateConfig {
+    /// Time after launch before landing is accepted, in microseconds.
+    pub landing_lockout: u64,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        Self {
+            landing_lockout: 10_000_000,
+        }
+    }
+}
+
+/// Change of state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub from: FlightState,
+    pub to: FlightState,
+    /// Time of the change, in microseconds.
+    pub time: u64,
+}
+
+impl Transition {
+    /// Event reporting this to the ground.
+    pub fn event(&self) -> VehicleEvent {
+        VehicleEvent::new(
+            self.time,
+            EventKind::StateChanged {
+                from: self.from.id(),
+                to: self.to.id(),
+            },
+        )
+    }
+}
+
+/// State machine of the flight.
+#[derive(Debug, Clone)]
+pub struct StateMachine {
+    config: StateConfig,
+    state: FlightState,
+    /// Time that the current state was entered at.
+    entered_at: u64,
+    /// Time of the launch, once launched.
+    launched_at: Option<u64>,
+}
+
+impl StateMachine {
+    /// Instantiate a new [`StateMachine`], idle on the pad.
+    pub fn new(config: StateConfig, now: u64) -> Self {
+        Self {
+            config,
+            state: FlightState::Idle,
+            entered_at: now,
+            launched_at: None,
+        }
+    }
+
+    /// Current state.
+    pub fn state(&self) -> FlightState {
+        self.state
+    }
+
+    /// Time that the current state was entered at.
+    pub fn entered_at(&self) -> u64 {
+        self.entered_at
+    }
+
+    /// Time of the launch, once launched.
+    pub fn launched_at(&self) -> Option<u64> {
+        self.launched_at
+    }
+
+    /// Phase of the flight, for the failsafe.
+    pub fn phase(&self) -> FlightPhase {
+        match self.state {
+            FlightState::Idle | FlightState::Armed | FlightState::Fault => FlightPhase::Pad,
+            FlightState::Landed => FlightPhase::Landed,
+            _ => FlightPhase::Flight,
+        }
+    }
+
+    /// Handle a trigger that happened at the given time, returning the transition it caused, if
+    /// any.
+    pub fn trigger(&mut self, trigger: Trigger, now: u64) -> Option<Transition> {
+        use FlightState::*;
+
+        let since_launch = self
+            .launched_at
+            .map(|launched_at| now.saturating_sub(launched_at));
+        let next = match (self.state, trigger) {
+            (Armed, Trigger::Detected(Detection::Launch)) => Boost,
+            (Boost, Trigger::Detected(Detection::Burnout)) => Coast,
+            // A missed burnout must not prevent the recovery.
+            (Boost | Coast, Trigger::Detected(Detection::Apogee)) => Apogee,
+            // The drogue may be deployed by a backup timer, without apogee being detected.
+            (Coast | Apogee, Trigger::DrogueDeployed) => DrogueDescent,
+            // The main may be deployed without the drogue, if the drogue failed.
+            (Apogee | DrogueDescent, Trigger::MainDeployed) => MainDescent,
+            (Apogee | DrogueDescent | MainDescent, Trigger::Detected(Detection::Landing))
+                if since_launch >= Some(self.config.landing_lockout) =>
+            {
+                Landed
+            }
+            (Idle | Armed, Trigger::Fault) => Fault,
+            _ => {
+                log::debug!("ignored {:?} in {}", trigger, self.state);
+                return None;
+            }
+        };
+
+        Some(self.enter(next, now))
+    }
+
+    /// Handle a [`Command::ChangeState`](flick_bridge::command::Command::ChangeState) from the
+    /// ground. Allowed requests are arming and disarming, aborting on the pad, clearing a fault,
+    /// and moving a flying vehicle to the state that follows its current one.
+    pub fn request(&mut self, id: u32, now: u64) -> Result<Transition, CommandError> {
+        use FlightState::*;
+
+        let requested = FlightState::from_id(id).ok_or(CommandError::InvalidArgument)?;
+        let allowed = match (self.state, requested) {
+            (Idle, Armed) | (Armed, Idle) | (Fault, Idle) | (Idle | Armed, Fault) => true,
+            (state, requested) => state.next_in_flight() == Some(requested),
+        };
+        if !allowed {
+            return Err(CommandError::InvalidState);
+        }
+
+        log::warn!("ground requested {} in {}", requested, self.state);
+        Ok(self.enter(requested, now))
+    }
+
+    fn enter(&mut self, state: FlightState, now: u64) -> Transition {
+        let transition = Transition {
+            from: self.state,
+            to: state,
+            time: now,
+        };
+        log::info!("{} -> {}", transition.from, transition.to);
+
+        if state == FlightState::Boost {
+            self.launched_at = Some(now);
+        }
+        self.state = state;
+        self.entered_at = now;
+        transition
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use FlightState::*;
+
+    const LAUNCH: Trigger = Trigger::Detected(Detection::Launch);
+    const BURNOUT: Trigger = Trigger::Detected(Detection::Burnout);
+    const APOGEE: Trigger = Trigger::Detected(Detection::Apogee);
+    const LANDING: Trigger = Trigger::Detected(Detection::Landing);
+
+    /// State machine in the given state, launched at 1 s if flying or landed.
+    fn in_state(state: FlightState) -> StateMachine {
+        let mut machine = StateMachine::new(StateConfig::default(), 0);
+        machine.state = state;
+        if state.in_flight() || state == Landed {
+            machine.launched_at = Some(1_000_000);
+        }
+        machine
+    }
+
+    /// State that the trigger moves to from the given state, at the given time.
+    fn next(state: FlightState, trigger: Trigger, now: u64) -> Option<FlightState> {
+        let mut machine = in_state(state);
+        let transition = machine.trigger(trigger, now)?;
+        assert_eq!(transition.from, state);
+        assert_eq!(transition.time, now);
+        assert_eq!(machine.state(), transition.to);
+        assert_eq!(machine.entered_at(), now);
+        Some(transition.to)
+    }
+
+    #[test]
+    fn moves_through_a_flight() {
+        let mut machine = StateMachine::new(StateConfig::default(), 0);
+        machine.request(Armed.id(), 0).unwrap();
+        let triggers = [
+            (LAUNCH, 1_000_000, Boost),
+            (BURNOUT, 3_000_000, Coast),
+            (APOGEE, 15_000_000, Apogee),
+            (Trigger::DrogueDeployed, 15_000_000, DrogueDescent),
+            (Trigger::MainDeployed, 40_000_000, MainDescent),
+            (LANDING, 60_000_000, Landed),
+        ];
+        for (trigger, now, state) in triggers {
+            assert_eq!(machine.trigger(trigger, now).unwrap().to, state);
+        }
+        assert_eq!(machine.launched_at(), Some(1_000_000));
+        assert_eq!(machine.phase(), FlightPhase::Landed);
+    }
+
+    #[test]
+    fn guards_every_transition() {
+        let now = 20_000_000;
+        for state in FlightState::ALL {
+            let expected = match state {
+                Armed => Some(Boost),
+                _ => None,
+            };
+            assert_eq!(next(state, LAUNCH, now), expected, "launch in {}", state);
+
+            let expected = match state {
+                Boost => Some(Coast),
+                _ => None,
+            };
+            assert_eq!(next(state, BURNOUT, now), expected, "burnout in {}", state);
+
+            let expected = match state {
+                Boost | Coast => Some(Apogee),
+                _ => None,
+            };
+            assert_eq!(next(state, APOGEE, now), expected, "apogee in {}", state);
+
+            let expected = match state {
+                Coast | Apogee => Some(DrogueDescent),
+                _ => None,
+            };
+            let drogue = next(state, Trigger::DrogueDeployed, now);
+            assert_eq!(drogue, expected, "drogue in {}", state);
+
+            let expected = match state {
+                Apogee | DrogueDescent => Some(MainDescent),
+                _ => None,
+            };
+            let main = next(state, Trigger::MainDeployed, now);
+            assert_eq!(main, expected, "main in {}", state);
+
+            let expected = match state {
+                Apogee | DrogueDescent | MainDescent => Some(Landed),
+                _ => None,
+            };
+            assert_eq!(next(state, LANDING, now), expected, "landing in {}", state);
+
+            let expected = match state {
+                Idle | Armed => Some(Fault),
+                _ => None,
+            };
+            assert_eq!(
+                next(state, Trigger::Fault, now),
+                expected,
+                "fault in {}",
+                state
+            );
+        }
+    }
+
+    #[test]
+    fn locks_landing_out_after_launch() {
+        let lockout = StateConfig::default().landing_lockout;
+        for state in [Apogee, DrogueDescent, MainDescent] {
+            assert_eq!(next(state, LANDING, 1_000_000 + lockout - 1), None);
+            assert_eq!(next(state, LANDING, 1_000_000 + lockout), Some(Landed));
+        }
+    }
+
+    #[test]
+    fn records_the_time_of_the_launch() {
+        let mut machine = in_state(Armed);
+        assert_eq!(machine.launched_at(), None);
+        machine.trigger(LAUNCH, 1_234_000).unwrap();
+        assert_eq!(machine.launched_at(), Some(1_234_000));
+
+        // Later transitions keep it.
+        machine.trigger(BURNOUT, 4_000_000).unwrap();
+        assert_eq!(machine.launched_at(), Some(1_234_000));
+    }
+
+    #[test]
+    fn allows_requests_of_the_ground() {
+        for state in FlightState::ALL {
+            for requested in FlightState::ALL {
+                let allowed = matches!(
+                    (state, requested),
+                    (Idle, Armed)
+                        | (Armed, Idle)
+                        | (Fault, Idle)
+                        | (Idle, Fault)
+                        | (Armed, Fault)
+                        | (Boost, Coast)
+                        | (Coast, Apogee)
+                        | (Apogee, DrogueDescent)
+                        | (DrogueDescent, MainDescent)
+                        | (MainDescent, Landed)
+                );
+
+                let mut machine = in_state(state);
+                match machine.request(requested.id(), 5_000_000) {
+                    Ok(transition) => {
+                        assert!(allowed, "{} -> {} allowed", state, requested);
+                        assert_eq!(transition.to, requested);
+                        assert_eq!(machine.state(), requested);
+                    }
+                    Err(err) => {
+                        assert!(!allowed, "{} -> {} refused", state, requested);
+                        assert_eq!(err, CommandError::InvalidState);
+                        assert_eq!(machine.state(), state);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_states() {
+        let mut machine = in_state(Idle);
+        assert_eq!(machine.request(99, 0), Err(CommandError::InvalidArgument));
+        assert_eq!(machine.state(), Idle);
+    }
+}